@@ -1,7 +1,7 @@
-use crate::{packet::NtpLeapIndicator, time_types::NtpDuration};
+use crate::{algorithm::SystemSourceChoice, packet::NtpLeapIndicator, time_types::NtpDuration};
 
 use super::{
-    config::AlgorithmConfig,
+    config::{AlgorithmConfig, SystemSourceTieBreak},
     matrix::{Matrix, Vector},
     sqr, SourceSnapshot,
 };
@@ -12,13 +12,25 @@ pub(super) struct Combine<Index: Copy> {
     pub sources: Vec<Index>,
     pub delay: NtpDuration,
     pub leap_indicator: Option<NtpLeapIndicator>,
+    /// Root mean square of the offsets of the selected sources from the
+    /// combined offset, in seconds
+    pub selection_jitter: f64,
+    /// Why the first of the sources was chosen as system source
+    pub system_source_choice: SystemSourceChoice,
 }
 
 fn vote_leap<Index: Copy>(selection: &[SourceSnapshot<Index>]) -> Option<NtpLeapIndicator> {
+    // Only sources trusted with leap seconds get a vote, unless none of the
+    // selected sources is trusted.
+    let mut voters: Vec<_> = selection.iter().filter(|s| s.leap_trusted).collect();
+    if voters.is_empty() {
+        voters = selection.iter().collect();
+    }
+
     let mut votes_59 = 0;
     let mut votes_61 = 0;
     let mut votes_none = 0;
-    for snapshot in selection {
+    for snapshot in voters.iter() {
         match snapshot.leap_indicator {
             NtpLeapIndicator::NoWarning => votes_none += 1,
             NtpLeapIndicator::Leap61 => votes_61 += 1,
@@ -28,11 +40,11 @@ fn vote_leap<Index: Copy>(selection: &[SourceSnapshot<Index>]) -> Option<NtpLeap
             }
         }
     }
-    if votes_none * 2 > selection.len() {
+    if votes_none * 2 > voters.len() {
         Some(NtpLeapIndicator::NoWarning)
-    } else if votes_59 * 2 > selection.len() {
+    } else if votes_59 * 2 > voters.len() {
         Some(NtpLeapIndicator::Leap59)
-    } else if votes_61 * 2 > selection.len() {
+    } else if votes_61 * 2 > voters.len() {
         Some(NtpLeapIndicator::Leap61)
     } else {
         None
@@ -43,38 +55,63 @@ pub(super) fn combine<Index: Copy>(
     selection: &[SourceSnapshot<Index>],
     algo_config: &AlgorithmConfig,
 ) -> Option<Combine<Index>> {
+    // Weights only set the relative share of the sources, so they are
+    // normalized to an average of 1. Otherwise they would also scale the
+    // combined uncertainty, misrepresenting how precise the time is.
+    let mean_weight = selection
+        .iter()
+        .map(|snapshot| snapshot.weight)
+        .sum::<f64>()
+        / selection.len() as f64;
+
     selection.first().map(|first| {
         let mut estimate = first.state;
-        let mut uncertainty = if algo_config.ignore_server_dispersion {
-            first.uncertainty
-        } else {
-            first.uncertainty
-                + Matrix::new([[sqr(first.source_uncertainty.to_seconds()), 0.], [0., 0.]])
-        };
+        let mut uncertainty = (mean_weight / first.weight)
+            * if algo_config.ignore_server_dispersion {
+                first.uncertainty
+            } else {
+                first.uncertainty
+                    + Matrix::new([[sqr(first.source_uncertainty.to_seconds()), 0.], [0., 0.]])
+            };
 
-        let mut used_sources = vec![(first.index, uncertainty.determinant())];
+        let mut used_sources = vec![(first.index, uncertainty.determinant(), first.stratum)];
 
         for snapshot in selection.iter().skip(1) {
             let source_estimate = snapshot.state;
-            let source_uncertainty = if algo_config.ignore_server_dispersion {
-                snapshot.uncertainty
-            } else {
-                snapshot.uncertainty
-                    + Matrix::new([
-                        [sqr(snapshot.source_uncertainty.to_seconds()), 0.],
-                        [0., 0.],
-                    ])
-            };
-
-            used_sources.push((snapshot.index, source_uncertainty.determinant()));
+            // A larger weight makes the source count as more precise, increasing
+            // its share in the combined estimate
+            let source_uncertainty = (mean_weight / snapshot.weight)
+                * if algo_config.ignore_server_dispersion {
+                    snapshot.uncertainty
+                } else {
+                    snapshot.uncertainty
+                        + Matrix::new([
+                            [sqr(snapshot.source_uncertainty.to_seconds()), 0.],
+                            [0., 0.],
+                        ])
+                };
+
+            used_sources.push((
+                snapshot.index,
+                source_uncertainty.determinant(),
+                snapshot.stratum,
+            ));
 
             // Merge measurements
             let mixer = (uncertainty + source_uncertainty).inverse();
             estimate = estimate + uncertainty * mixer * (source_estimate - estimate);
-            uncertainty = uncertainty * mixer * source_uncertainty;
+            uncertainty = clamp_variances(uncertainty * mixer * source_uncertainty);
         }
 
         used_sources.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let system_source_choice = break_system_source_tie(&mut used_sources, algo_config);
+
+        let selection_jitter = (selection
+            .iter()
+            .map(|snapshot| sqr(snapshot.state.ventry(0) - estimate.ventry(0)))
+            .sum::<f64>()
+            / selection.len() as f64)
+            .sqrt();
 
         Combine {
             estimate,
@@ -86,10 +123,54 @@ pub(super) fn combine<Index: Copy>(
                 .min()
                 .unwrap_or(NtpDuration::from_seconds(first.delay) + first.source_delay),
             leap_indicator: vote_leap(selection),
+            selection_jitter,
+            system_source_choice,
         }
     })
 }
 
+/// Sources with (nearly) noiseless measurements have degenerate uncertainty
+/// matrices, for which merging can produce tiny negative variances through
+/// roundoff. Those would turn into NaN once their square root is taken.
+fn clamp_variances(uncertainty: Matrix<2, 2>) -> Matrix<2, 2> {
+    Matrix::new([
+        [uncertainty.entry(0, 0).max(0.), uncertainty.entry(0, 1)],
+        [uncertainty.entry(1, 0), uncertainty.entry(1, 1).max(0.)],
+    ])
+}
+
+/// Move the preferred source among those with about the lowest uncertainty
+/// to the front. The sources must be sorted by uncertainty.
+fn break_system_source_tie<Index: Copy>(
+    used_sources: &mut [(Index, f64, u8)],
+    algo_config: &AlgorithmConfig,
+) -> SystemSourceChoice {
+    let Some(&(_, lowest_uncertainty, stratum)) = used_sources.first() else {
+        return SystemSourceChoice::LowestUncertainty;
+    };
+
+    match algo_config.system_source_tie_break {
+        SystemSourceTieBreak::Uncertainty => SystemSourceChoice::LowestUncertainty,
+        SystemSourceTieBreak::Stratum => {
+            let margin = lowest_uncertainty * algo_config.system_source_tie_margin;
+            let preferred = used_sources
+                .iter()
+                .take_while(|(_, uncertainty, _)| *uncertainty <= margin)
+                .enumerate()
+                .min_by_key(|(_, (_, _, stratum))| *stratum)
+                .map(|(position, _)| position)
+                .unwrap_or(0);
+
+            if used_sources[preferred].2 < stratum {
+                used_sources[..=preferred].rotate_right(1);
+                SystemSourceChoice::LowestStratum
+            } else {
+                SystemSourceChoice::LowestUncertainty
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::time_types::NtpTimestamp;
@@ -110,6 +191,10 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            stratum: 1,
+            precision: -20,
+            leap_trusted: true,
         }
     }
 
@@ -134,6 +219,7 @@ mod tests {
         let result = combine(&selected, &algconfig).unwrap();
         assert!((result.uncertainty.entry(0, 0) - 2e-6).abs() < 1e-12);
         assert!((result.uncertainty.entry(0, 0) - 2e-6).abs() < 1e-12);
+        assert_eq!(result.selection_jitter, 0.0);
 
         let algconfig = AlgorithmConfig {
             ignore_server_dispersion: true,
@@ -166,6 +252,8 @@ mod tests {
         assert!(result.estimate.ventry(1).abs() < 1e-8);
         assert!((result.uncertainty.entry(0, 0) - 1e-6).abs() < 1e-12);
         assert!((result.uncertainty.entry(1, 1) - 5e-13).abs() < 1e-16);
+        // both sources are 0.5ms from the combined offset
+        assert!((result.selection_jitter - 5e-4).abs() < 1e-8);
 
         let algconfig = AlgorithmConfig {
             ignore_server_dispersion: true,
@@ -178,6 +266,86 @@ mod tests {
         assert!((result.uncertainty.entry(1, 1) - 5e-13).abs() < 1e-16);
     }
 
+    #[test]
+    fn test_degenerate() {
+        // a source without measurement noise has no offset variance left,
+        // merging it must not produce a negative variance through roundoff
+        let selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([
+                    [0.0, 8.271806125530277e-25],
+                    [8.271806125530277e-25, 3.3333333895412784e-17],
+                ]),
+                0.0,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([0.5, 0.0]),
+                Matrix::new([
+                    [1.0224996479711496e-8, 1.0000000052328308e-8],
+                    [1.0000000052328308e-8, 1.00000001e-8],
+                ]),
+                0.0,
+            ),
+        ];
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!(result.uncertainty.entry(0, 0) >= 0.0);
+        assert!(result.uncertainty.entry(1, 1) >= 0.0);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+        ];
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let unweighted = combine(&selected, &algconfig).unwrap();
+
+        selected[1].weight = 3.0;
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.ventry(0) - 7.5e-4).abs() < 1e-8);
+        assert!(result.estimate.ventry(1).abs() < 1e-8);
+
+        // the weight is relative, scaling all sources equally changes nothing
+        selected[0].weight = 2.0;
+        selected[1].weight = 6.0;
+        let scaled = combine(&selected, &algconfig).unwrap();
+        assert!((scaled.estimate.ventry(0) - 7.5e-4).abs() < 1e-8);
+        assert!((scaled.uncertainty.entry(0, 0) - result.uncertainty.entry(0, 0)).abs() < 1e-18);
+
+        // and so does giving every source the same weight
+        selected[0].weight = 2.0;
+        selected[1].weight = 2.0;
+        let uniform = combine(&selected, &algconfig).unwrap();
+        assert!((uniform.estimate.ventry(0) - unweighted.estimate.ventry(0)).abs() < 1e-12);
+        for (row, column) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            assert!(
+                (uniform.uncertainty.entry(row, column)
+                    - unweighted.uncertainty.entry(row, column))
+                .abs()
+                    < 1e-24
+            );
+        }
+    }
+
     #[test]
     fn test_sort_order() {
         let mut selected = vec![
@@ -223,6 +391,63 @@ mod tests {
         assert_eq!(result.sources, vec![1, 0]);
     }
 
+    #[test]
+    fn test_system_source_tie_break() {
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1.2e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+        ];
+        selected[0].index = 0;
+        selected[0].stratum = 2;
+        selected[1].index = 1;
+        selected[1].stratum = 1;
+
+        // about equally good, so the lower stratum wins
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.sources, vec![1, 0]);
+        assert_eq!(
+            result.system_source_choice,
+            SystemSourceChoice::LowestStratum
+        );
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            system_source_tie_break: SystemSourceTieBreak::Uncertainty,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.sources, vec![0, 1]);
+        assert_eq!(
+            result.system_source_choice,
+            SystemSourceChoice::LowestUncertainty
+        );
+
+        // outside of the margin the uncertainty decides
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            system_source_tie_margin: 1.1,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.sources, vec![0, 1]);
+        assert_eq!(
+            result.system_source_choice,
+            SystemSourceChoice::LowestUncertainty
+        );
+    }
+
     fn snapshot_for_leap(leap: NtpLeapIndicator) -> SourceSnapshot<usize> {
         SourceSnapshot {
             index: 0,
@@ -233,6 +458,10 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.0),
             leap_indicator: leap,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            stratum: 1,
+            precision: -20,
+            leap_trusted: true,
         }
     }
 
@@ -287,4 +516,33 @@ mod tests {
         let result = combine(&selected, &algconfig).unwrap();
         assert_eq!(result.leap_indicator, None);
     }
+
+    #[test]
+    fn test_leap_vote_trusted() {
+        let algconfig = AlgorithmConfig::default();
+
+        let untrusted = |leap| SourceSnapshot {
+            leap_trusted: false,
+            ..snapshot_for_leap(leap)
+        };
+
+        // a single trusted source outweighs any number of untrusted ones
+        let selected = vec![
+            untrusted(NtpLeapIndicator::NoWarning),
+            untrusted(NtpLeapIndicator::NoWarning),
+            untrusted(NtpLeapIndicator::NoWarning),
+            snapshot_for_leap(NtpLeapIndicator::Leap61),
+        ];
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::Leap61));
+
+        // without trusted sources, everyone gets a vote
+        let selected = vec![
+            untrusted(NtpLeapIndicator::NoWarning),
+            untrusted(NtpLeapIndicator::NoWarning),
+            untrusted(NtpLeapIndicator::Leap61),
+        ];
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::NoWarning));
+    }
 }
@@ -1,6 +1,6 @@
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 
-use crate::time_types::NtpDuration;
+use crate::time_types::{NtpDuration, PollInterval};
 
 #[derive(Debug, Copy, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
@@ -38,6 +38,11 @@ pub struct AlgorithmConfig {
     /// going on and we need to do more measurements. (probability, 0-1)
     #[serde(default = "default_poll_interval_step_threshold")]
     pub poll_interval_step_threshold: f64,
+    /// Largest poll interval that is reached by growing the poll interval
+    /// because measurements are stable. Other logic may still use the
+    /// maximum of the poll interval limits. (log2 seconds)
+    #[serde(default)]
+    pub max_stable_poll_interval: Option<PollInterval>,
 
     /// Threshold (in number of standard deviations) above which
     /// measurements with a significantly larger network delay
@@ -45,6 +50,16 @@ pub struct AlgorithmConfig {
     #[serde(default = "default_delay_outlier_threshold")]
     pub delay_outlier_threshold: f64,
 
+    /// Threshold (in number of median absolute deviations from the
+    /// median) above which offset samples in the initial measurement
+    /// window are discarded before estimating the offset of a source.
+    /// (median absolute deviations, 1+)
+    #[serde(
+        default = "default_offset_outlier_mad_multiplier",
+        deserialize_with = "deserialize_offset_outlier_mad_multiplier"
+    )]
+    pub offset_outlier_mad_multiplier: f64,
+
     /// Initial estimate of the clock wander of the combination
     /// of our local clock and that of the source. (s/s^2)
     #[serde(default = "default_initial_wander")]
@@ -94,10 +109,28 @@ pub struct AlgorithmConfig {
     /// What is the minimum duration of a slew (s)
     #[serde(default = "default_slew_minimum_duration")]
     pub slew_minimum_duration: f64,
+    /// Never step the clock, correcting every offset by adjusting the
+    /// frequency, including at startup.
+    #[serde(default)]
+    pub freq_only: bool,
+    /// Longest time correcting an offset by adjusting the frequency may
+    /// take in frequency only mode before we raise an alarm. (seconds, 0+)
+    #[serde(default = "default_freq_only_max_correction_time")]
+    pub freq_only_max_correction_time: f64,
 
     /// Absolute maximum frequency correction (s/s)
     #[serde(default = "default_maximum_frequency_steer")]
     pub maximum_frequency_steer: f64,
+    /// Minimum time between two updates of the clock. Measurements
+    /// arriving in between are still processed, and are applied together
+    /// once the interval has passed. (seconds, 0+)
+    #[serde(default)]
+    pub min_clock_update_interval: f64,
+
+    /// Rate at which the root dispersion of a source grows in the time
+    /// since its last measurement (s/s)
+    #[serde(default = "default_dispersion_growth_rate")]
+    pub dispersion_growth_rate: f64,
 
     /// Ignore a servers advertised dispersion when synchronizing.
     /// Can improve synchronization quality with servers reporting
@@ -105,9 +138,82 @@ pub struct AlgorithmConfig {
     #[serde(default)]
     pub ignore_server_dispersion: bool,
 
-    /// Threshold for detecting external clock meddling
+    /// Threshold for detecting external clock meddling: the largest
+    /// difference in progress between the system and monotonic clock
+    /// between measurements before we conclude someone else stepped the
+    /// clock. (seconds, 0+)
     #[serde(default = "default_meddling_threshold")]
     pub meddling_threshold: NtpDuration,
+
+    /// Poll interval used by the low power profile once synchronized (log2 seconds)
+    #[serde(default = "default_low_power_poll_interval")]
+    pub low_power_poll_interval: PollInterval,
+    /// Offset uncertainty below which the low power profile switches to
+    /// its long poll interval. (seconds, 0+)
+    #[serde(default = "default_low_power_enter_uncertainty")]
+    pub low_power_enter_uncertainty: f64,
+    /// Offset above which the low power profile returns to the regular
+    /// poll interval. Should be well above the enter uncertainty to avoid
+    /// switching back and forth. (seconds, 0+)
+    #[serde(default = "default_low_power_exit_offset")]
+    pub low_power_exit_offset: f64,
+
+    /// Falseticker score above which a source is temporarily excluded
+    /// from selection. Every selection round in which the source is a
+    /// falseticker adds one to its score. None disables exclusion. (count, 1+)
+    #[serde(default)]
+    pub falseticker_exclusion_threshold: Option<f64>,
+    /// Factor by which the falseticker score of a source decays every
+    /// selection round. (weight, 0-1)
+    #[serde(default = "default_falseticker_score_decay")]
+    pub falseticker_score_decay: f64,
+    /// Duration of the first exclusion of a source. Every further exclusion
+    /// of the same source lasts twice as long as the previous. (seconds, 0+)
+    #[serde(default = "default_falseticker_exclusion_duration")]
+    pub falseticker_exclusion_duration: f64,
+    /// Upper bound on the duration of an exclusion. (seconds, 0+)
+    #[serde(default = "default_falseticker_exclusion_max_duration")]
+    pub falseticker_exclusion_max_duration: f64,
+
+    /// How to choose the system source among selected sources that are
+    /// about equally good.
+    #[serde(default)]
+    pub system_source_tie_break: SystemSourceTieBreak,
+    /// Selected sources whose uncertainty is at most this factor above the
+    /// lowest uncertainty count as equally good when choosing the system
+    /// source. (factor, 1+)
+    #[serde(default = "default_system_source_tie_margin")]
+    pub system_source_tie_margin: f64,
+
+    /// Number of sources that must agree on a large offset to step the clock
+    /// right away, instead of waiting for the filters to converge to the new
+    /// offset. At least half of the reachable sources that may be selected
+    /// must agree as well. None disables consensus stepping. (count, 1+)
+    #[serde(default)]
+    pub consensus_step_quorum: Option<usize>,
+    /// Offset from which a measurement counts towards a consensus step.
+    /// (seconds, 0+)
+    #[serde(default = "default_consensus_step_threshold")]
+    pub consensus_step_threshold: f64,
+    /// Largest difference between the offsets of the sources agreeing on a
+    /// consensus step. A spike of a single source is not matched by the
+    /// others, whereas a shift of the local clock shows up in all of them
+    /// alike. (seconds, 0+)
+    #[serde(default = "default_consensus_step_max_spread")]
+    pub consensus_step_max_spread: f64,
+}
+
+/// Preference among about equally good sources when choosing the system
+/// source
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SystemSourceTieBreak {
+    /// Prefer the source with the lowest stratum, closest to a reference
+    /// clock
+    #[default]
+    Stratum,
+    /// Always prefer the source with the lowest uncertainty
+    Uncertainty,
 }
 
 impl Default for AlgorithmConfig {
@@ -122,8 +228,10 @@ impl Default for AlgorithmConfig {
             poll_interval_high_weight: default_poll_interval_high_weight(),
             poll_interval_hysteresis: default_poll_interval_hysteresis(),
             poll_interval_step_threshold: default_poll_interval_step_threshold(),
+            max_stable_poll_interval: None,
 
             delay_outlier_threshold: default_delay_outlier_threshold(),
+            offset_outlier_mad_multiplier: default_offset_outlier_mad_multiplier(),
 
             initial_wander: default_initial_wander(),
             initial_frequency_uncertainty: default_initial_frequency_uncertainty(),
@@ -139,12 +247,32 @@ impl Default for AlgorithmConfig {
             step_threshold: default_step_threshold(),
             slew_maximum_frequency_offset: default_slew_maximum_frequency_offset(),
             slew_minimum_duration: default_slew_minimum_duration(),
+            freq_only: false,
+            freq_only_max_correction_time: default_freq_only_max_correction_time(),
 
             maximum_frequency_steer: default_maximum_frequency_steer(),
+            min_clock_update_interval: 0.0,
+            dispersion_growth_rate: default_dispersion_growth_rate(),
 
             ignore_server_dispersion: false,
 
             meddling_threshold: default_meddling_threshold(),
+
+            low_power_poll_interval: default_low_power_poll_interval(),
+            low_power_enter_uncertainty: default_low_power_enter_uncertainty(),
+            low_power_exit_offset: default_low_power_exit_offset(),
+
+            falseticker_exclusion_threshold: None,
+            falseticker_score_decay: default_falseticker_score_decay(),
+            falseticker_exclusion_duration: default_falseticker_exclusion_duration(),
+            falseticker_exclusion_max_duration: default_falseticker_exclusion_max_duration(),
+
+            system_source_tie_break: SystemSourceTieBreak::default(),
+            system_source_tie_margin: default_system_source_tie_margin(),
+
+            consensus_step_quorum: None,
+            consensus_step_threshold: default_consensus_step_threshold(),
+            consensus_step_max_spread: default_consensus_step_max_spread(),
         }
     }
 }
@@ -185,6 +313,24 @@ fn default_delay_outlier_threshold() -> f64 {
     5.
 }
 
+fn default_offset_outlier_mad_multiplier() -> f64 {
+    5.
+}
+
+fn deserialize_offset_outlier_mad_multiplier<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let multiplier = f64::deserialize(deserializer)?;
+    if multiplier.is_finite() && multiplier > 0.0 {
+        Ok(multiplier)
+    } else {
+        Err(de::Error::custom(
+            "offset-outlier-mad-multiplier should be a positive, finite number",
+        ))
+    }
+}
+
 fn default_initial_wander() -> f64 {
     1e-8
 }
@@ -233,10 +379,55 @@ fn default_maximum_frequency_steer() -> f64 {
     495e-6
 }
 
+fn default_dispersion_growth_rate() -> f64 {
+    15e-6
+}
+
 fn default_slew_minimum_duration() -> f64 {
     8.0
 }
 
+fn default_freq_only_max_correction_time() -> f64 {
+    3600.0
+}
+
 fn default_meddling_threshold() -> NtpDuration {
     NtpDuration::from_seconds(5.)
 }
+
+fn default_low_power_poll_interval() -> PollInterval {
+    // 2^12 seconds, a little over an hour
+    PollInterval::from_byte(12)
+}
+
+fn default_low_power_enter_uncertainty() -> f64 {
+    1e-3
+}
+
+fn default_low_power_exit_offset() -> f64 {
+    10e-3
+}
+
+fn default_falseticker_score_decay() -> f64 {
+    0.9
+}
+
+fn default_falseticker_exclusion_duration() -> f64 {
+    3600.0
+}
+
+fn default_falseticker_exclusion_max_duration() -> f64 {
+    86400.0
+}
+
+fn default_system_source_tie_margin() -> f64 {
+    2.0
+}
+
+fn default_consensus_step_threshold() -> f64 {
+    1.0
+}
+
+fn default_consensus_step_max_spread() -> f64 {
+    0.050
+}
@@ -0,0 +1,160 @@
+//! Extrapolation of the uncertainty of the combined estimate, to predict
+//! how long it takes until the clock is within a target accuracy.
+//!
+//! The covariance of the estimate evolves as in the filters of the sources:
+//! between polls it grows with the clock wander, and every poll a
+//! measurement with the estimated noise shrinks it again. Meanwhile the
+//! remaining offset is slewed away. The state itself is not extrapolated, as
+//! its expected evolution is exactly what steering the clock takes care of.
+
+use std::time::Duration;
+
+use crate::{
+    algorithm::ConvergenceEstimate,
+    time_types::{NtpDuration, PollInterval},
+};
+
+use super::matrix::Matrix;
+
+/// Number of polls after which the uncertainty is considered settled
+const MAX_POLLS: u32 = 10_000;
+/// Relative change in uncertainty over a poll below which it is settled
+const SETTLED_TOLERANCE: f64 = 1e-9;
+
+/// State of the combined estimate after a clock update, and the parameters
+/// of its future evolution
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ConvergenceModel {
+    /// Remaining offset of the clock (seconds)
+    pub offset: f64,
+    pub uncertainty: Matrix<2, 2>,
+    /// Clock wander of the combination of the selected sources (s/s^2)
+    pub wander: f64,
+    /// Variance of the combination of a measurement of each selected
+    /// source (s^2)
+    pub measurement_variance: f64,
+    pub poll_interval: PollInterval,
+    /// Rate at which the remaining offset is slewed away (s/s)
+    pub slew_rate: f64,
+}
+
+impl ConvergenceModel {
+    fn poll(&self, uncertainty: Matrix<2, 2>) -> Matrix<2, 2> {
+        let delta_t = self.poll_interval.as_system_duration().as_secs_f64();
+        let update = Matrix::new([[1.0, delta_t], [0.0, 1.0]]);
+        let process_noise = Matrix::new([
+            [
+                self.wander * delta_t * delta_t * delta_t / 3.,
+                self.wander * delta_t * delta_t / 2.,
+            ],
+            [self.wander * delta_t * delta_t / 2., self.wander * delta_t],
+        ]);
+        let uncertainty = update * uncertainty * update.transpose() + process_noise;
+
+        let measurement_transform = Matrix::new([[1., 0.]]);
+        let measurement_noise = Matrix::new([[self.measurement_variance]]);
+        let difference_covariance =
+            measurement_transform * uncertainty * measurement_transform.transpose()
+                + measurement_noise;
+        let update_strength =
+            uncertainty * measurement_transform.transpose() * difference_covariance.inverse();
+        ((Matrix::unit() - update_strength * measurement_transform) * uncertainty).symmetrize()
+    }
+
+    /// Bound on the error of the clock after the given time, with the given
+    /// uncertainty of the estimate
+    fn error(&self, elapsed: f64, uncertainty: Matrix<2, 2>) -> f64 {
+        let remaining_offset = (self.offset.abs() - self.slew_rate * elapsed).max(0.0);
+        remaining_offset + uncertainty.entry(0, 0).sqrt()
+    }
+
+    pub fn estimate(&self, target: NtpDuration) -> ConvergenceEstimate {
+        let target_seconds = target.to_seconds();
+        let poll_seconds = self.poll_interval.as_system_duration().as_secs_f64();
+
+        let mut uncertainty = self.uncertainty;
+        let mut time_to_target =
+            (self.error(0.0, uncertainty) <= target_seconds).then_some(Duration::ZERO);
+        for polls in 1..=MAX_POLLS {
+            let next = self.poll(uncertainty);
+            let elapsed = polls as f64 * poll_seconds;
+            if time_to_target.is_none() && self.error(elapsed, next) <= target_seconds {
+                time_to_target = Some(Duration::from_secs_f64(elapsed));
+            }
+
+            let settled = (next.entry(0, 0) - uncertainty.entry(0, 0)).abs()
+                <= SETTLED_TOLERANCE * uncertainty.entry(0, 0);
+            uncertainty = next;
+            // once settled the remaining offset is all that still changes
+            if settled {
+                break;
+            }
+        }
+
+        let uncertainty_floor = uncertainty.entry(0, 0).sqrt();
+        if time_to_target.is_none() && uncertainty_floor < target_seconds && self.slew_rate > 0.0 {
+            // only the slew is left, which takes longer than we extrapolated
+            let slew = (self.offset.abs() - (target_seconds - uncertainty_floor)) / self.slew_rate;
+            time_to_target = Some(Duration::from_secs_f64(slew.max(0.0)));
+        }
+
+        ConvergenceEstimate {
+            target,
+            current_error: NtpDuration::from_seconds(self.error(0.0, self.uncertainty)),
+            time_to_target,
+            uncertainty_floor: NtpDuration::from_seconds(uncertainty_floor),
+            poll_interval: self.poll_interval,
+            measurement_noise: NtpDuration::from_seconds(self.measurement_variance.sqrt()),
+            wander: self.wander,
+            slew_rate: self.slew_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(offset: f64, offset_uncertainty: f64) -> ConvergenceModel {
+        ConvergenceModel {
+            offset,
+            uncertainty: Matrix::new([[offset_uncertainty * offset_uncertainty, 0.], [0., 1e-14]]),
+            wander: 1e-16,
+            measurement_variance: 1e-8,
+            poll_interval: PollInterval::from_byte(4),
+            slew_rate: 1e-3,
+        }
+    }
+
+    #[test]
+    fn already_converged() {
+        let estimate = model(0.0, 1e-5).estimate(NtpDuration::from_seconds(1e-3));
+        assert_eq!(estimate.time_to_target, Some(Duration::ZERO));
+        assert!(estimate.uncertainty_floor < NtpDuration::from_seconds(1e-4));
+    }
+
+    #[test]
+    fn converges_as_uncertainty_shrinks() {
+        let estimate = model(0.0, 1e-2).estimate(NtpDuration::from_seconds(1e-3));
+        let time = estimate.time_to_target.unwrap();
+        assert!(time > Duration::ZERO);
+        // a whole number of polls
+        assert_eq!(time.as_secs() % 16, 0);
+        assert!(estimate.current_error > NtpDuration::from_seconds(1e-3));
+    }
+
+    #[test]
+    fn large_offset_waits_for_slew() {
+        // slewing away 1s at 1ms/s takes about 1000s
+        let estimate = model(1.0, 1e-5).estimate(NtpDuration::from_seconds(1e-3));
+        let time = estimate.time_to_target.unwrap().as_secs_f64();
+        assert!((990.0..=1010.0).contains(&time), "{time}");
+    }
+
+    #[test]
+    fn unreachable_target() {
+        let estimate = model(0.0, 1e-2).estimate(NtpDuration::from_seconds(1e-6));
+        assert_eq!(estimate.time_to_target, None);
+        assert!(estimate.uncertainty_floor > NtpDuration::from_seconds(1e-6));
+    }
+}
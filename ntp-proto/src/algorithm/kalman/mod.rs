@@ -1,31 +1,53 @@
 use std::{collections::HashMap, fmt::Debug, hash::Hash, time::Duration};
 
-use tracing::{error, info, instrument};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument, warn};
 
 use crate::{
-    clock::NtpClock,
-    config::{SourceDefaultsConfig, SynchronizationConfig},
+    clock::{measure_precision, NtpClock},
+    config::{LeapTrust, PowerProfile, SourceDefaultsConfig, SynchronizationConfig},
     packet::NtpLeapIndicator,
     source::Measurement,
     system::TimeSnapshot,
-    time_types::{NtpDuration, NtpTimestamp},
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp},
 };
 
 use self::{
-    combiner::combine,
+    combiner::{combine, Combine},
     config::AlgorithmConfig,
+    convergence::ConvergenceModel,
     matrix::{Matrix, Vector},
     source::SourceState,
 };
 
-use super::{ObservableSourceTimedata, StateUpdate, TimeSyncController};
+pub use self::source::SourceFilterState;
+
+use super::{
+    ConvergenceEstimate, ObservableSourceFilter, ObservableSourceTimedata, SelectionStatus,
+    SourceOptions, StateUpdate, TimeSyncController,
+};
 
 mod combiner;
 pub(super) mod config;
+mod convergence;
 mod matrix;
 mod select;
+#[cfg(any(test, feature = "__internal-test"))]
+pub(super) mod simulation;
 mod source;
 
+/// Amount in seconds by which the system clock must advance beyond the
+/// monotonic clock for us to conclude the system was suspended
+const SUSPEND_DETECTION_THRESHOLD: f64 = 10.0;
+/// Allowed relative difference in rate between the system and monotonic
+/// clock, covering our own frequency steering
+const SUSPEND_DETECTION_RATE_TOLERANCE: f64 = 1e-3;
+
+/// A clock before this time (2020-01-01) was not set from a working real
+/// time clock, so we never trust it to be close to correct
+const RTC_VALID_SINCE: NtpTimestamp =
+    NtpTimestamp::from_seconds_nanos_since_ntp_era(3_786_825_600, 0);
+
 fn sqr(x: f64) -> f64 {
     x * x
 }
@@ -42,6 +64,15 @@ struct SourceSnapshot<Index: Copy> {
     leap_indicator: NtpLeapIndicator,
 
     last_update: NtpTimestamp,
+
+    // Configured trust in the source, scaling its influence when combining
+    weight: f64,
+
+    stratum: u8,
+    // Precision advertised by the source (log2 seconds)
+    precision: i8,
+    // Whether the leap indicator of the source counts in the leap second vote
+    leap_trusted: bool,
 }
 
 impl<Index: Copy> SourceSnapshot<Index> {
@@ -60,14 +91,129 @@ impl<Index: Copy> SourceSnapshot<Index> {
             delay: NtpDuration::from_seconds(self.delay),
             remote_delay: self.source_delay,
             remote_uncertainty: self.source_uncertainty,
+            remote_precision: Some(self.precision),
             last_update: self.last_update,
+            weight: self.weight,
+            selection: Default::default(),
+            falseticker_score: 0.0,
+            excluded_until: None,
+            no_step: false,
+        }
+    }
+}
+
+/// How often a source was recently a falseticker, and whether it is
+/// excluded from selection because of that
+#[derive(Debug, Clone, Copy, Default)]
+struct FalsetickerState {
+    score: f64,
+    /// Number of times the source was excluded, determining the duration
+    /// of the next exclusion
+    exclusions: u32,
+    excluded_until: Option<NtpTimestamp>,
+}
+
+/// Everything the controller keeps track of for a single source
+#[derive(Debug, Clone)]
+struct SourceEntry {
+    state: SourceState,
+    /// Whether the source is reachable and synchronized itself
+    usable: bool,
+    options: SourceOptions,
+    falseticker: FalsetickerState,
+    /// Offset of the last measurement, when it was beyond the consensus
+    /// step threshold
+    large_offset: Option<f64>,
+    /// Number of measurements received from the source
+    measurement_count: u32,
+}
+
+impl SourceEntry {
+    fn new(options: SourceOptions) -> Self {
+        SourceEntry {
+            state: SourceState::new(),
+            usable: false,
+            options,
+            falseticker: FalsetickerState::default(),
+            large_offset: None,
+            measurement_count: 0,
+        }
+    }
+
+    fn is_excluded(&self) -> bool {
+        self.falseticker.excluded_until.is_some()
+    }
+}
+
+/// Latest version of the format of exported controller state
+pub const CONTROLLER_STATE_VERSION: u32 = 1;
+
+/// Full state of the controller, to hand over disciplining the clock to
+/// another instance, for example across an upgrade of the daemon, without
+/// having to converge again.
+///
+/// Source ids are only meaningful within a single process, so the state of
+/// the sources is keyed by something that identifies them across instances,
+/// such as their address.
+///
+/// New fields must have a default, so state exported by older versions can
+/// still be imported. The version is only bumped for changes that can't
+/// follow that rule.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KalmanControllerState<SourceKey> {
+    pub version: u32,
+    /// Long-term frequency correction of the clock, excluding any temporary
+    /// adjustment used for slewing
+    pub frequency: f64,
+    /// Running total of clock steps for the accumulated step threshold
+    #[serde(default)]
+    pub accumulated_steps: NtpDuration,
+    /// Whether the controller was still in its startup phase
+    #[serde(default)]
+    pub in_startup: bool,
+    /// Filter state of the sources out of their initial phase
+    #[serde(default)]
+    pub sources: Vec<(SourceKey, SourceFilterState)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStateError {
+    /// The state was exported in a newer format than we understand
+    UnsupportedVersion(u32),
+    /// The clock could not be read to resume the source filters from
+    ClockUnreadable,
+}
+
+impl std::fmt::Display for ImportStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportStateError::UnsupportedVersion(version) => write!(
+                f,
+                "controller state version {version} is newer than the supported version {CONTROLLER_STATE_VERSION}"
+            ),
+            ImportStateError::ClockUnreadable => {
+                write!(f, "could not read the clock to import the controller state")
+            }
         }
     }
 }
 
+impl std::error::Error for ImportStateError {}
+
 #[derive(Debug, Clone)]
 pub struct KalmanClockController<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> {
-    sources: HashMap<SourceId, (SourceState, bool)>,
+    sources: HashMap<SourceId, SourceEntry>,
+    selection: HashMap<SourceId, SelectionStatus>,
+    pinned: Option<SourceId>,
+    /// Whether all sources selected in the last clock update are no_step
+    /// sources, in which case offsets are slewed instead of stepped
+    only_no_step_selected: bool,
+    /// Pair of candidate sources whose offsets differed most in the last
+    /// selection round, and that difference in seconds
+    divergence: Option<(SourceId, SourceId, f64)>,
+    /// Combined estimate of the last clock update, to extrapolate how long
+    /// until the clock converges
+    convergence: Option<ConvergenceModel>,
     clock: C,
     synchronization_config: SynchronizationConfig,
     source_defaults_config: SourceDefaultsConfig,
@@ -76,17 +222,40 @@ pub struct KalmanClockController<C: NtpClock, SourceId: Hash + Eq + Copy + Debug
     timedata: TimeSnapshot,
     desired_freq: f64,
     in_startup: bool,
+    in_low_power: bool,
+    /// Monotonic and system time of the last measurement, used to detect a
+    /// resume from suspend
+    last_measurement_time: Option<(NtpInstant, NtpTimestamp)>,
+    /// Whether the offset grew beyond what we can correct in reasonable
+    /// time in frequency only mode
+    freq_only_diverged: bool,
+    /// Whether the last frequency correction exceeded the maximum steer, so
+    /// the warning is only logged when clamping starts
+    frequency_clamped: bool,
+    /// Local time of the last update of the clock
+    last_clock_update: Option<NtpTimestamp>,
+    /// Whether measurements arrived that have not been applied to the clock
+    /// yet, because the minimum clock update interval had not passed
+    pending_clock_update: bool,
+    /// Leap indicator that takes precedence over those of the sources
+    leap_indicator: Option<NtpLeapIndicator>,
+    /// Source of the most recent measurement, which triggers the clock
+    /// updates following it
+    last_measured_source: Option<SourceId>,
+    /// Precision of the local clock as measured at startup, added to the
+    /// uncertainty of every source. Zero when it was not measured.
+    local_precision: NtpDuration,
 }
 
 impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, SourceId> {
     #[instrument(skip(self))]
     fn update_source(&mut self, id: SourceId, measurement: Measurement) -> bool {
-        self.sources.get_mut(&id).map(|state| {
-            state.0.update_self_using_measurement(
+        self.sources.get_mut(&id).map(|source| {
+            source.state.update_self_using_measurement(
                 &self.source_defaults_config,
                 &self.algo_config,
                 measurement,
-            ) && state.1
+            ) && source.usable
         }) == Some(true)
     }
 
@@ -94,8 +263,8 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         // ensure all filters represent the same (current) time
         if self
             .sources
-            .iter()
-            .filter_map(|(_, (state, _))| state.get_filtertime())
+            .values()
+            .filter_map(|source| source.state.get_filtertime())
             .any(|sourcetime| time - sourcetime < NtpDuration::ZERO)
         {
             return StateUpdate {
@@ -104,24 +273,75 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 next_update: None,
             };
         }
-        for (_, (state, _)) in self.sources.iter_mut() {
-            state.progress_filtertime(time);
+        for source in self.sources.values_mut() {
+            source.state.progress_filtertime(time);
+        }
+        self.last_clock_update = Some(time);
+        self.pending_clock_update = false;
+
+        self.selection.clear();
+        self.end_exclusions(time);
+        let mut candidates = vec![];
+        for (index, source) in self.sources.iter() {
+            let state = &source.state;
+            if source.options.no_select {
+                self.selection.insert(*index, SelectionStatus::NoSelect);
+            } else if source.is_excluded() {
+                self.selection.insert(*index, SelectionStatus::Excluded);
+            } else if !source.usable {
+                self.selection.insert(*index, SelectionStatus::Unreachable);
+            } else if !state.has_samples(self.synchronization_config.minimum_samples) {
+                self.selection
+                    .insert(*index, SelectionStatus::TooFewSamples);
+            } else if let Some(snapshot) = state.snapshot_with_config(*index, &self.algo_config) {
+                if self
+                    .synchronization_config
+                    .min_server_precision
+                    .map_or(false, |min_precision| snapshot.precision > min_precision)
+                {
+                    self.selection.insert(*index, SelectionStatus::LowPrecision);
+                    continue;
+                }
+                let leap_trusted = match self.synchronization_config.leap_trust {
+                    LeapTrust::All => true,
+                    LeapTrust::MaxStratum(max_stratum) => snapshot.stratum <= max_stratum,
+                    LeapTrust::Trusted => source.options.trust_leap,
+                };
+                let (source_delay, source_uncertainty) = if source.options.ignore_root {
+                    (
+                        NtpDuration::from_seconds(snapshot.delay),
+                        NtpDuration::from_seconds(snapshot.offset_uncertainty()),
+                    )
+                } else {
+                    (snapshot.source_delay, snapshot.source_uncertainty)
+                };
+                candidates.push(SourceSnapshot {
+                    weight: source.options.weight,
+                    leap_trusted,
+                    source_delay,
+                    source_uncertainty: source_uncertainty + self.local_precision,
+                    ..snapshot
+                });
+            } else {
+                self.selection
+                    .insert(*index, SelectionStatus::TooFewSamples);
+            }
         }
 
-        let selection = select::select(
-            &self.synchronization_config,
-            &self.algo_config,
-            self.sources
-                .iter()
-                .filter_map(|(index, (state, usable))| {
-                    if *usable {
-                        state.snapshot(*index)
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-        );
+        self.divergence = max_divergence(&candidates);
+
+        let (selection, status) = match self.pinned {
+            Some(pinned) => select_pinned(pinned, candidates),
+            None => select::select(&self.synchronization_config, &self.algo_config, candidates),
+        };
+        self.update_falseticker_scores(time, &status);
+        self.selection.extend(status);
+        self.only_no_step_selected = !selection.is_empty()
+            && selection.iter().all(|snapshot| {
+                self.sources
+                    .get(&snapshot.index)
+                    .map_or(false, |source| source.options.no_step)
+            });
 
         if let Some(combined) = combine(&selection, &self.algo_config) {
             info!(
@@ -168,21 +388,28 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 None
             };
 
+            self.convergence = self.convergence_model(&selection, &combined);
+
+            self.timedata.system_offset = NtpDuration::from_seconds(offset_delta);
+            self.timedata.system_jitter =
+                NtpDuration::from_seconds(offset_uncertainty.hypot(combined.selection_jitter));
             self.timedata.root_delay = combined.delay;
+            self.timedata.system_source_choice = combined.system_source_choice;
             self.timedata.root_dispersion =
                 NtpDuration::from_seconds(combined.uncertainty.entry(0, 0).sqrt());
             self.clock
                 .error_estimate_update(self.timedata.root_dispersion, self.timedata.root_delay)
                 .expect("Cannot update clock");
 
-            if let Some(leap) = combined.leap_indicator {
-                self.clock.status_update(leap).expect("Cannot update clock");
-                self.timedata.leap_indicator = leap;
+            if let Some(leap) = self.leap_indicator.or(combined.leap_indicator) {
+                self.apply_leap_indicator(leap);
             }
 
             // After a succesfull measurement we are out of startup.
             self.in_startup = false;
 
+            self.update_power_mode(offset_delta, offset_uncertainty);
+
             StateUpdate {
                 used_sources: Some(combined.sources),
                 time_snapshot: Some(self.timedata),
@@ -190,6 +417,7 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
             }
         } else {
             info!("No consensus cluster found");
+            self.convergence = None;
             StateUpdate {
                 used_sources: None,
                 time_snapshot: Some(self.timedata),
@@ -198,6 +426,165 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         }
     }
 
+    /// Parameters for extrapolating the combined estimate, from the noise
+    /// estimates of the selected sources. Offsets are only corrected when
+    /// they stand out from the uncertainty, so the offset remaining to be
+    /// corrected is that of an ongoing slew.
+    fn convergence_model(
+        &self,
+        selection: &[SourceSnapshot<SourceId>],
+        combined: &Combine<SourceId>,
+    ) -> Option<ConvergenceModel> {
+        let noise_estimates: Vec<_> = selection
+            .iter()
+            .filter_map(|snapshot| self.sources.get(&snapshot.index)?.state.noise_estimates())
+            .collect();
+        if noise_estimates.is_empty() {
+            return None;
+        }
+
+        let wander = noise_estimates
+            .iter()
+            .map(|(wander, _)| wander)
+            .sum::<f64>()
+            / noise_estimates.len() as f64;
+        let measurement_variance = 1.0
+            / noise_estimates
+                .iter()
+                .map(|(_, variance)| 1.0 / variance)
+                .sum::<f64>();
+        let (offset, slew_rate) = if self.desired_freq != 0.0 {
+            (combined.estimate.ventry(0), self.desired_freq.abs())
+        } else {
+            (0.0, self.algo_config.slew_maximum_frequency_offset)
+        };
+
+        Some(ConvergenceModel {
+            offset,
+            uncertainty: combined.uncertainty,
+            wander,
+            measurement_variance,
+            poll_interval: self.timedata.poll_interval,
+            slew_rate,
+        })
+    }
+
+    /// Keep track of how often sources are falsetickers, and exclude those
+    /// that are so too often from selection for a while. Their measurements
+    /// are still processed, so they can be selected again afterwards.
+    fn update_falseticker_scores(
+        &mut self,
+        time: NtpTimestamp,
+        status: &[(SourceId, SelectionStatus)],
+    ) {
+        let Some(threshold) = self.algo_config.falseticker_exclusion_threshold else {
+            return;
+        };
+
+        for (index, status) in status {
+            let Some(source) = self.sources.get_mut(index) else {
+                continue;
+            };
+            let state = &mut source.falseticker;
+            state.score *= self.algo_config.falseticker_score_decay;
+            if *status == SelectionStatus::Falseticker {
+                state.score += 1.0;
+            }
+
+            if state.score > threshold {
+                let duration = (self.algo_config.falseticker_exclusion_duration
+                    * 2f64.powi(state.exclusions.min(32) as i32))
+                .min(self.algo_config.falseticker_exclusion_max_duration);
+                warn!(
+                    source = ?index,
+                    score = state.score,
+                    "Source is repeatedly a falseticker, excluding it from selection for {}s",
+                    duration
+                );
+                state.exclusions += 1;
+                state.excluded_until = Some(time + NtpDuration::from_seconds(duration));
+            }
+        }
+    }
+
+    fn end_exclusions(&mut self, time: NtpTimestamp) {
+        for (index, source) in self.sources.iter_mut() {
+            let state = &mut source.falseticker;
+            if state
+                .excluded_until
+                .map_or(false, |until| !time.is_before(until))
+            {
+                info!(source = ?index, "Exclusion of falseticker ended, returning it to selection");
+                state.excluded_until = None;
+                state.score = 0.0;
+            }
+        }
+    }
+
+    /// Time that still needs to pass before the clock may be updated again
+    fn clock_update_holdoff(&self, time: NtpTimestamp) -> Option<Duration> {
+        let interval = self.algo_config.min_clock_update_interval;
+        let elapsed = (time - self.last_clock_update?).to_seconds();
+        // a clock that went backwards should not delay us beyond the interval
+        (elapsed < interval).then(|| Duration::from_secs_f64((interval - elapsed).min(interval)))
+    }
+
+    fn apply_leap_indicator(&mut self, leap: NtpLeapIndicator) {
+        self.clock.status_update(leap).expect("Cannot update clock");
+        self.timedata.leap_indicator = leap;
+    }
+
+    /// The monotonic clock does not advance while the system is suspended,
+    /// whereas the system clock does. When the latter got ahead considerably
+    /// since the previous measurement we were most likely suspended, and treat
+    /// the next step like one at startup. Any other divergence beyond the
+    /// meddling threshold means someone else stepped the clock.
+    fn detect_clock_jump(&mut self, monotime: NtpInstant, localtime: NtpTimestamp) {
+        if let Some((last_monotime, last_localtime)) = self.last_measurement_time {
+            // measurements of different sources may arrive slightly out of order
+            if monotime < last_monotime {
+                return;
+            }
+
+            let monotonic_elapsed = monotime.abs_diff(last_monotime).to_seconds();
+            let system_elapsed = (localtime - last_localtime).to_seconds();
+            let divergence = system_elapsed - monotonic_elapsed;
+            let threshold =
+                SUSPEND_DETECTION_THRESHOLD + SUSPEND_DETECTION_RATE_TOLERANCE * monotonic_elapsed;
+            if divergence > threshold
+                && self.synchronization_config.resume_grace
+                && !self.in_startup
+            {
+                info!(
+                    suspended = divergence,
+                    "Detected resume from suspend, allowing a startup step"
+                );
+                self.in_startup = true;
+            } else if divergence.abs() > self.algo_config.meddling_threshold.to_seconds() {
+                self.reset_after_external_step(divergence);
+            }
+        }
+
+        self.last_measurement_time = Some((monotime, localtime));
+    }
+
+    /// Another process stepped the clock, so the offsets in the filters no
+    /// longer apply. Rather than fighting the change, measure afresh.
+    fn reset_after_external_step(&mut self, jump: f64) {
+        warn!(
+            jump_ms = jump * 1e3,
+            "Detected an external step of the clock, resetting the source filters"
+        );
+        if self.desired_freq != 0.0 {
+            self.change_desired_frequency(0.0, 0.0);
+        }
+        for source in self.sources.values_mut() {
+            source.state = SourceState::new();
+            source.large_offset = None;
+        }
+        self.pending_clock_update = false;
+    }
+
     fn check_offset_steer(&mut self, change: f64) {
         let change = NtpDuration::from_seconds(change);
         if self.in_startup {
@@ -233,15 +620,71 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         }
     }
 
+    /// At startup, a small offset means the real time clock the system time
+    /// was initialized from is plausibly correct, in which case we'd rather
+    /// slew than jump.
+    fn trust_rtc(&self, change: f64) -> bool {
+        let Some(bound) = self.synchronization_config.rtc_trusted_offset else {
+            return false;
+        };
+
+        let Ok(now) = self.clock.now() else {
+            return false;
+        };
+
+        let pivot = self.synchronization_config.era_pivot;
+        self.in_startup
+            && change.abs() <= bound.to_seconds()
+            && now.cmp_with_pivot(RTC_VALID_SINCE, pivot).is_ge()
+    }
+
     fn steer_offset(&mut self, change: f64, freq_delta: f64) -> Option<Duration> {
-        if change.abs() > self.algo_config.step_threshold {
+        let trust_rtc = change.abs() > self.algo_config.step_threshold && self.trust_rtc(change);
+        if trust_rtc {
+            info!(
+                "Offset of {}ms at startup is within the trusted real time clock offset, slewing instead of stepping",
+                change * 1e3
+            );
+        }
+
+        let no_step = change.abs() > self.algo_config.step_threshold
+            && !trust_rtc
+            && self.only_no_step_selected;
+        if no_step {
+            info!(
+                "Offset of {}ms is only supported by sources that may not step the clock, slewing instead of stepping",
+                change * 1e3
+            );
+        }
+
+        if change.abs() > self.algo_config.step_threshold
+            && !self.algo_config.freq_only
+            && !trust_rtc
+            && !no_step
+            && self.too_few_polls_to_step()
+        {
+            info!(
+                "Offset of {}ms is based on too few measurements of the source to step the clock, treating it as a spike",
+                change * 1e3
+            );
+            return None;
+        }
+
+        if change.abs() > self.algo_config.step_threshold
+            && !self.algo_config.freq_only
+            && !trust_rtc
+            && !no_step
+        {
             // jump
             self.check_offset_steer(change);
             self.clock
                 .step_clock(NtpDuration::from_seconds(change))
                 .expect("Cannot adjust clock");
-            for (state, _) in self.sources.values_mut() {
-                state.process_offset_steering(change);
+            // our own step should not be mistaken for a suspend
+            self.last_measurement_time = None;
+            for source in self.sources.values_mut() {
+                source.state.process_offset_steering(change);
+                source.large_offset = None;
             }
             info!("Jumped offset by {}ms", change * 1e3);
             None
@@ -252,6 +695,9 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 .slew_maximum_frequency_offset
                 .min(change.abs() / self.algo_config.slew_minimum_duration);
             let duration = Duration::from_secs_f64(change.abs() / freq);
+            if self.algo_config.freq_only {
+                self.check_correction_time(change, duration);
+            }
             info!(
                 "Slewing by {}ms over {}s",
                 change * 1e3,
@@ -262,25 +708,138 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         }
     }
 
+    /// Whether the source that triggered the clock update has too few
+    /// measurements for the update to step the clock. The step at startup
+    /// is always allowed.
+    fn too_few_polls_to_step(&self) -> bool {
+        let min_polls = self.synchronization_config.min_polls_before_step;
+        if self.in_startup || min_polls == 0 {
+            return false;
+        }
+
+        self.last_measured_source
+            .and_then(|id| self.sources.get(&id))
+            .map_or(false, |source| source.measurement_count < min_polls)
+    }
+
+    /// When enough of the selected sources agree on a large offset, the
+    /// clock itself most likely shifted (for example across a suspend), so
+    /// step right away instead of waiting for the filters to converge on the
+    /// new offset. A spike of a single source is not matched by the other
+    /// sources, so a small spread of the offsets tells the two apart.
+    fn consensus_step(&mut self) -> Option<StateUpdate<SourceId>> {
+        let quorum = self.algo_config.consensus_step_quorum?;
+        if self.algo_config.freq_only {
+            return None;
+        }
+
+        // The selection itself is upset by the shift, as the first sources to
+        // report it are outvoted by those that didn't yet, so count all
+        // sources that could be selected.
+        let survivors: Vec<&SourceEntry> = self
+            .sources
+            .values()
+            .filter(|source| {
+                source.usable
+                    && !source.options.no_select
+                    && !source.options.no_step
+                    && !source.is_excluded()
+            })
+            .collect();
+        let mut offsets: Vec<f64> = survivors
+            .iter()
+            .filter_map(|source| source.large_offset)
+            .collect();
+        if offsets.len() < quorum.max(1) || 2 * offsets.len() < survivors.len() {
+            return None;
+        }
+
+        offsets.sort_by(f64::total_cmp);
+        let spread = offsets[offsets.len() - 1] - offsets[0];
+        if spread > self.algo_config.consensus_step_max_spread {
+            return None;
+        }
+        let change = offsets[offsets.len() / 2];
+
+        info!(
+            sources = offsets.len(),
+            spread_ms = spread * 1e3,
+            "Selected sources agree on a large offset, stepping right away"
+        );
+        if self.desired_freq != 0.0 {
+            self.change_desired_frequency(0.0, 0.0);
+        }
+        self.check_offset_steer(change);
+        self.clock
+            .step_clock(NtpDuration::from_seconds(change))
+            .expect("Cannot adjust clock");
+        info!("Jumped offset by {}ms", change * 1e3);
+
+        // The filters still hold the offset from before the shift, which
+        // would be off by the step now, so start them afresh.
+        self.last_measurement_time = None;
+        self.pending_clock_update = false;
+        for source in self.sources.values_mut() {
+            source.state = SourceState::new();
+            source.large_offset = None;
+        }
+
+        Some(StateUpdate {
+            time_snapshot: Some(self.timedata),
+            ..StateUpdate::default()
+        })
+    }
+
+    /// In frequency only mode we can't step away large offsets, so raise an
+    /// alarm when correcting the offset takes unreasonably long
+    fn check_correction_time(&mut self, change: f64, duration: Duration) {
+        let diverged = duration.as_secs_f64() > self.algo_config.freq_only_max_correction_time;
+        if diverged && !self.freq_only_diverged {
+            error!(
+                "Offset of {}ms can not be corrected within {}s by adjusting the frequency, correcting it will take {}s",
+                change * 1e3,
+                self.algo_config.freq_only_max_correction_time,
+                duration.as_secs_f64(),
+            );
+        } else if !diverged && self.freq_only_diverged {
+            info!("Offset can be corrected in reasonable time by adjusting the frequency again");
+        }
+        self.freq_only_diverged = diverged;
+    }
+
     fn change_desired_frequency(&mut self, new_freq: f64, freq_delta: f64) -> NtpTimestamp {
         let change = self.desired_freq - new_freq + freq_delta;
         self.desired_freq = new_freq;
+        self.timedata.slewing = new_freq != 0.0;
         self.steer_frequency(change)
     }
 
     fn steer_frequency(&mut self, change: f64) -> NtpTimestamp {
-        let new_freq_offset = ((1.0 + self.freq_offset) * (1.0 + change) - 1.0).clamp(
-            -self.algo_config.maximum_frequency_steer,
-            self.algo_config.maximum_frequency_steer,
-        );
+        let maximum = self.algo_config.maximum_frequency_steer;
+        let desired_freq_offset = (1.0 + self.freq_offset) * (1.0 + change) - 1.0;
+        let clamped = desired_freq_offset.abs() > maximum;
+        if clamped && !self.frequency_clamped {
+            warn!(
+                "Frequency correction of {}ppm exceeds the maximum of {}ppm, clamping",
+                desired_freq_offset * 1e6,
+                maximum * 1e6,
+            );
+        } else if !clamped && self.frequency_clamped {
+            info!("Frequency correction is within the maximum again");
+        }
+        self.frequency_clamped = clamped;
+        let new_freq_offset = desired_freq_offset.clamp(-maximum, maximum);
         let actual_change = (1.0 + new_freq_offset) / (1.0 + self.freq_offset) - 1.0;
         self.freq_offset = new_freq_offset;
         let freq_update = self
             .clock
             .set_frequency(self.freq_offset)
             .expect("Cannot adjust clock");
-        for (state, _) in self.sources.values_mut() {
-            state.process_frequency_steering(freq_update, actual_change);
+        self.timedata.frequency_ppm = self.freq_offset * 1e6;
+        for source in self.sources.values_mut() {
+            source
+                .state
+                .process_frequency_steering(freq_update, actual_change);
         }
         info!(
             "Changed frequency, current steer {}ppm, desired freq {}ppm",
@@ -290,16 +849,129 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         freq_update
     }
 
+    fn update_power_mode(&mut self, offset: f64, offset_uncertainty: f64) {
+        if self.synchronization_config.power_profile != PowerProfile::LowPower {
+            return;
+        }
+
+        // The thresholds for entering and leaving differ, so that we don't
+        // wake up frequently when the offset hovers around a single threshold.
+        if !self.in_low_power && offset_uncertainty < self.algo_config.low_power_enter_uncertainty {
+            info!("Synchronized, switching to low power polling");
+            self.in_low_power = true;
+            self.update_desired_poll();
+        } else if self.in_low_power && offset.abs() > self.algo_config.low_power_exit_offset {
+            info!(
+                "Offset of {}ms too large, switching to regular polling",
+                offset * 1e3
+            );
+            self.in_low_power = false;
+            self.update_desired_poll();
+        }
+    }
+
     fn update_desired_poll(&mut self) {
+        if self.in_low_power {
+            self.timedata.poll_interval = self.algo_config.low_power_poll_interval;
+            return;
+        }
+
         self.timedata.poll_interval = self
             .sources
             .values()
-            .map(|(state, _)| {
-                state.get_desired_poll(&self.source_defaults_config.poll_interval_limits)
+            .map(|source| {
+                source
+                    .state
+                    .get_desired_poll(&self.source_defaults_config.poll_interval_limits)
             })
             .min()
             .unwrap_or(self.source_defaults_config.poll_interval_limits.max);
     }
+
+    /// Precision of the local clock, as measured at startup
+    pub fn precision(&self) -> NtpDuration {
+        self.timedata.precision
+    }
+
+    /// Export the full state of the controller, to continue disciplining
+    /// the clock from it in another instance with [`Self::import_state`].
+    /// Sources are exported under the key returned by `source_key`, sources
+    /// without a key are left out.
+    pub fn export_state<SourceKey>(
+        &self,
+        source_key: impl Fn(SourceId) -> Option<SourceKey>,
+    ) -> KalmanControllerState<SourceKey> {
+        KalmanControllerState {
+            version: CONTROLLER_STATE_VERSION,
+            frequency: self.frequency(),
+            accumulated_steps: self.timedata.accumulated_steps,
+            in_startup: self.in_startup,
+            sources: self
+                .sources
+                .iter()
+                .filter_map(|(id, source)| Some((source_key(*id)?, source.state.export()?)))
+                .collect(),
+        }
+    }
+
+    /// Continue from state exported by another instance. The filter state
+    /// is only taken over for sources that were already added, and whose
+    /// key as returned by `source_key` matches the key they were exported
+    /// under. Other sources start afresh as usual.
+    pub fn import_state<SourceKey: PartialEq + Debug>(
+        &mut self,
+        state: KalmanControllerState<SourceKey>,
+        source_key: impl Fn(SourceId) -> Option<SourceKey>,
+    ) -> Result<(), ImportStateError> {
+        if state.version > CONTROLLER_STATE_VERSION {
+            return Err(ImportStateError::UnsupportedVersion(state.version));
+        }
+
+        let now = self.clock.now().map_err(|error| {
+            error!(
+                ?error,
+                "Could not read the clock to import the controller state"
+            );
+            ImportStateError::ClockUnreadable
+        })?;
+
+        self.restore_frequency(state.frequency);
+        self.timedata.accumulated_steps = state.accumulated_steps;
+        self.in_startup = state.in_startup;
+
+        for (key, filter_state) in state.sources {
+            let source = self
+                .sources
+                .iter_mut()
+                .find(|(id, _)| source_key(**id).as_ref() == Some(&key));
+            match source {
+                Some((_, source)) => source.state = SourceState::import(filter_state, now),
+                None => info!(?key, "Ignoring imported state of unknown source"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Use only the pinned source, as long as it is synchronized itself
+fn select_pinned<Index: Copy + Eq>(
+    pinned: Index,
+    candidates: Vec<SourceSnapshot<Index>>,
+) -> (Vec<SourceSnapshot<Index>>, Vec<(Index, SelectionStatus)>) {
+    let mut selection = vec![];
+    let mut status = vec![];
+    for snapshot in candidates {
+        if snapshot.index != pinned {
+            status.push((snapshot.index, SelectionStatus::NotPinned));
+        } else if !snapshot.leap_indicator.is_synchronized() {
+            status.push((snapshot.index, SelectionStatus::Unsynchronized));
+        } else {
+            status.push((snapshot.index, SelectionStatus::Pinned));
+            selection.push(snapshot);
+        }
+    }
+    (selection, status)
 }
 
 impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> TimeSyncController<C, SourceId>
@@ -318,16 +990,46 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> TimeSyncController<C, Sour
         clock.status_update(NtpLeapIndicator::Unknown)?;
         clock.set_frequency(0.0)?;
 
+        let mut timedata = TimeSnapshot::default();
+        let mut local_precision = NtpDuration::ZERO;
+        if synchronization_config.measure_precision {
+            match measure_precision(&clock)? {
+                Some(precision) => {
+                    info!(
+                        precision = precision.to_seconds(),
+                        "Measured precision of the local clock"
+                    );
+                    timedata.precision = precision;
+                    local_precision = precision;
+                }
+                None => warn!("Could not measure the precision of the local clock"),
+            }
+        }
+
         Ok(KalmanClockController {
             sources: HashMap::new(),
+            selection: HashMap::new(),
+            pinned: None,
+            only_no_step_selected: false,
+            divergence: None,
+            convergence: None,
             clock,
             synchronization_config,
             source_defaults_config,
             algo_config,
             freq_offset: 0.0,
             desired_freq: 0.0,
-            timedata: TimeSnapshot::default(),
+            timedata,
             in_startup: true,
+            in_low_power: false,
+            last_measurement_time: None,
+            freq_only_diverged: false,
+            frequency_clamped: false,
+            last_clock_update: None,
+            pending_clock_update: false,
+            leap_indicator: None,
+            last_measured_source: None,
+            local_precision,
         })
     }
 
@@ -342,17 +1044,81 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> TimeSyncController<C, Sour
         self.algo_config = algo_config;
     }
 
-    fn add_source(&mut self, id: SourceId) {
-        self.sources.insert(id, (SourceState::new(), false));
+    fn add_source(&mut self, id: SourceId, options: SourceOptions) {
+        if options.no_select {
+            info!(source = ?id, "Source is monitored only, excluding it from selection");
+        }
+        if options.no_step {
+            info!(source = ?id, "Source may not step the clock, only slew it");
+        }
+        if options.ignore_root {
+            info!(source = ?id, "Ignoring the root delay and dispersion advertised by source");
+        }
+        self.sources.insert(id, SourceEntry::new(options));
+    }
+
+    fn reset_source(&mut self, id: SourceId) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            *source = SourceEntry::new(source.options);
+        }
     }
 
     fn remove_source(&mut self, id: SourceId) {
         self.sources.remove(&id);
+        self.selection.remove(&id);
+        if self
+            .divergence
+            .map_or(false, |(first, second, _)| first == id || second == id)
+        {
+            self.divergence = None;
+        }
+        if self.last_measured_source == Some(id) {
+            self.last_measured_source = None;
+        }
+        if self.pinned == Some(id) {
+            info!("Pinned source removed, returning to automatic selection");
+            self.pinned = None;
+        }
+    }
+
+    fn pin_source(&mut self, id: Option<SourceId>) {
+        match id {
+            Some(id) => info!(source = ?id, "Pinning source, bypassing source selection"),
+            None => info!("Unpinning source, returning to automatic selection"),
+        }
+        self.pinned = id;
+    }
+
+    fn set_no_select(&mut self, id: SourceId, no_select: bool) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            if no_select {
+                info!(source = ?id, "Source is monitored only, excluding it from selection");
+            }
+            source.options.no_select = no_select;
+        }
+    }
+
+    fn set_no_step(&mut self, id: SourceId, no_step: bool) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            if no_step {
+                info!(source = ?id, "Source may not step the clock, only slew it");
+            }
+            source.options.no_step = no_step;
+        }
+    }
+
+    fn set_ignore_root(&mut self, id: SourceId, ignore_root: bool) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            if ignore_root {
+                info!(source = ?id, "Ignoring the root delay and dispersion advertised by source");
+            }
+            source.options.ignore_root = ignore_root;
+        }
     }
 
     fn source_update(&mut self, id: SourceId, usable: bool) {
-        if let Some(state) = self.sources.get_mut(&id) {
-            state.1 = usable;
+        if let Some(source) = self.sources.get_mut(&id) {
+            source.usable = usable;
         }
     }
 
@@ -361,31 +1127,147 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> TimeSyncController<C, Sour
         id: SourceId,
         measurement: Measurement,
     ) -> StateUpdate<SourceId> {
+        self.detect_clock_jump(measurement.monotime, measurement.localtime);
+        let consensus_step_threshold = self.algo_config.consensus_step_threshold;
+        if let Some(source) = self.sources.get_mut(&id) {
+            let offset = measurement.offset.to_seconds();
+            source.large_offset = (offset.abs() > consensus_step_threshold).then_some(offset);
+            source.measurement_count = source.measurement_count.saturating_add(1);
+        }
+        self.last_measured_source = Some(id);
+        if let Some(update) = self.consensus_step() {
+            return update;
+        }
         let should_update_clock = self.update_source(id, measurement);
         self.update_desired_poll();
-        if should_update_clock {
-            self.update_clock(measurement.localtime)
-        } else {
+        if !should_update_clock {
             StateUpdate {
                 used_sources: None,
                 time_snapshot: Some(self.timedata),
                 next_update: None,
             }
+        } else if let Some(holdoff) = self.clock_update_holdoff(measurement.localtime) {
+            // Schedule the pending update, unless a slew is running. Its end
+            // is already scheduled, and will apply the pending update.
+            let schedule = !self.pending_clock_update && self.desired_freq == 0.0;
+            self.pending_clock_update = true;
+            StateUpdate {
+                used_sources: None,
+                time_snapshot: Some(self.timedata),
+                next_update: schedule.then_some(holdoff),
+            }
+        } else {
+            self.update_clock(measurement.localtime)
         }
     }
 
     fn time_update(&mut self) -> StateUpdate<SourceId> {
         // End slew
-        self.change_desired_frequency(0.0, 0.0);
-        StateUpdate::default()
+        if self.desired_freq != 0.0 {
+            self.change_desired_frequency(0.0, 0.0);
+        }
+
+        if self.pending_clock_update {
+            let now = self.clock.now().expect("Cannot read clock");
+            match self.clock_update_holdoff(now) {
+                Some(holdoff) => StateUpdate {
+                    time_snapshot: Some(self.timedata),
+                    next_update: Some(holdoff),
+                    ..StateUpdate::default()
+                },
+                None => self.update_clock(now),
+            }
+        } else {
+            StateUpdate {
+                time_snapshot: Some(self.timedata),
+                ..StateUpdate::default()
+            }
+        }
+    }
+
+    fn reset_accumulated_steps(&mut self) {
+        info!(
+            "Resetting accumulated steps (was {}ms)",
+            self.timedata.accumulated_steps.to_seconds() * 1e3
+        );
+        self.timedata.accumulated_steps = NtpDuration::ZERO;
+    }
+
+    fn frequency(&self) -> f64 {
+        // freq_offset includes a steer of -desired_freq, so undo that
+        (1.0 + self.freq_offset) / (1.0 - self.desired_freq) - 1.0
+    }
+
+    fn restore_frequency(&mut self, frequency: f64) {
+        info!("Restoring frequency of {}ppm", frequency * 1e6);
+        let change = (1.0 + frequency) * (1.0 - self.desired_freq) / (1.0 + self.freq_offset) - 1.0;
+        self.steer_frequency(change);
+    }
+
+    fn set_leap_indicator(&mut self, leap: Option<NtpLeapIndicator>) {
+        self.leap_indicator = leap;
+        // Arm the kernel right away, but only claim to be synchronized once
+        // the sources agree on the time
+        if let Some(leap) = leap {
+            let was_synchronized = self.timedata.leap_indicator.is_synchronized();
+            self.apply_leap_indicator(leap);
+            if !was_synchronized {
+                self.timedata.leap_indicator = NtpLeapIndicator::Unknown;
+            }
+        }
     }
 
     fn source_snapshot(&self, id: SourceId) -> Option<ObservableSourceTimedata> {
+        let source = self.sources.get(&id)?;
+        let snapshot = source.state.snapshot_with_config(id, &self.algo_config)?;
+        Some(ObservableSourceTimedata {
+            selection: self.selection.get(&id).copied().unwrap_or_default(),
+            falseticker_score: source.falseticker.score,
+            excluded_until: source.falseticker.excluded_until,
+            no_step: source.options.no_step,
+            ..SourceSnapshot {
+                weight: source.options.weight,
+                ..snapshot
+            }
+            .observe()
+        })
+    }
+
+    fn source_filter(&self, id: SourceId) -> Option<ObservableSourceFilter> {
         self.sources
             .get(&id)
-            .and_then(|v| v.0.snapshot(id))
-            .map(|v| v.observe())
+            .map(|source| source.state.filter_entries())
+    }
+
+    fn divergence(&self) -> Option<(SourceId, SourceId, NtpDuration)> {
+        self.divergence.map(|(first, second, divergence)| {
+            (first, second, NtpDuration::from_seconds(divergence))
+        })
+    }
+
+    fn convergence_estimate(&self, target: NtpDuration) -> Option<ConvergenceEstimate> {
+        Some(self.convergence?.estimate(target))
+    }
+}
+
+/// The pair of sources whose offsets differ most, and that difference
+fn max_divergence<Index: Copy>(
+    candidates: &[SourceSnapshot<Index>],
+) -> Option<(Index, Index, f64)> {
+    let mut divergence = None;
+    for (i, first) in candidates.iter().enumerate() {
+        for second in &candidates[i + 1..] {
+            let difference = (first.offset() - second.offset()).abs();
+            let is_max = match divergence {
+                Some((_, _, max)) => difference > max,
+                None => true,
+            };
+            if is_max {
+                divergence = Some((first.index, second.index, difference));
+            }
+        }
     }
+    divergence
 }
 
 #[cfg(test)]
@@ -400,6 +1282,7 @@ mod tests {
     #[derive(Debug, Clone)]
     struct TestClock {
         has_steered: RefCell<bool>,
+        frequency: RefCell<f64>,
         current_time: NtpTimestamp,
     }
 
@@ -410,8 +1293,9 @@ mod tests {
             Ok(self.current_time)
         }
 
-        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+        fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
             *self.has_steered.borrow_mut() = true;
+            *self.frequency.borrow_mut() = freq;
             Ok(self.current_time)
         }
 
@@ -438,19 +1322,54 @@ mod tests {
     }
 
     #[test]
-    fn test_startup_flag_unsets() {
-        let synchronization_config = SynchronizationConfig {
-            minimum_agreeing_sources: 1,
-            ..SynchronizationConfig::default()
+    fn frequency_steer_is_clamped() {
+        let algo_config = AlgorithmConfig {
+            maximum_frequency_steer: 100e-6,
+            ..Default::default()
         };
-        let algo_config = AlgorithmConfig::default();
-        let source_defaults_config = SourceDefaultsConfig::default();
-        let mut algo = KalmanClockController::new(
+        let mut algo = KalmanClockController::<_, u32>::new(
             TestClock {
                 has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
                 current_time: NtpTimestamp::from_fixed_int(0),
             },
-            synchronization_config,
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+
+        // a 1 second offset measured over 10 milliseconds
+        algo.steer_frequency(1.0 / 0.01);
+        assert_eq!(*algo.clock.frequency.borrow(), 100e-6);
+        assert!((algo.timedata.frequency_ppm - 100.0).abs() < 1e-9);
+        assert!(algo.frequency_clamped);
+
+        algo.steer_frequency(-1.0 / 0.01);
+        assert_eq!(*algo.clock.frequency.borrow(), -100e-6);
+        assert!(algo.frequency_clamped);
+
+        // changes within the limit are applied as is
+        algo.steer_frequency(150e-6);
+        assert!((*algo.clock.frequency.borrow() - 50e-6).abs() < 1e-7);
+        assert!(!algo.frequency_clamped);
+    }
+
+    #[test]
+    fn test_startup_flag_unsets() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
             source_defaults_config,
             algo_config,
         )
@@ -460,60 +1379,849 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        algo.add_source(0);
+        algo.add_source(0, SourceOptions::default());
         algo.source_update(0, true);
 
         assert!(algo.in_startup);
 
         let mut noise = 1e-9;
 
-        while !*algo.clock.has_steered.borrow() {
-            cur_instant = cur_instant + std::time::Duration::from_secs(1);
-            algo.clock.current_time += NtpDuration::from_seconds(1.0);
-            noise += 1e-9;
-            algo.source_measurement(
-                0,
-                Measurement {
-                    delay: NtpDuration::from_seconds(0.001 + noise),
-                    offset: NtpDuration::from_seconds(1700.0 + noise),
-                    transmit_timestamp: Default::default(),
-                    receive_timestamp: Default::default(),
-                    localtime: algo.clock.current_time,
-                    monotime: cur_instant,
+        while !*algo.clock.has_steered.borrow() {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            noise += 1e-9;
+            algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001 + noise),
+                    offset: NtpDuration::from_seconds(1700.0 + noise),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+        }
+
+        assert!(!algo.in_startup);
+        assert_eq!(algo.timedata.leap_indicator, NtpLeapIndicator::NoWarning);
+        assert_ne!(algo.timedata.root_delay, NtpDuration::ZERO);
+        assert_ne!(algo.timedata.root_dispersion, NtpDuration::ZERO);
+        // a single source has no selection jitter
+        assert_eq!(algo.timedata.system_jitter, algo.timedata.root_dispersion);
+    }
+
+    #[test]
+    fn pinned_source_bypasses_selection() {
+        // two sources can never form a majority with the default config
+        let synchronization_config = SynchronizationConfig::default();
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.add_source(1, SourceOptions::default());
+        algo.source_update(0, true);
+        algo.source_update(1, true);
+        algo.pin_source(Some(1));
+
+        let mut used_sources = None;
+        for _ in 0..20 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            for (id, offset) in [(0, 0.5), (1, 0.001)] {
+                let update = algo.source_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(offset),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+                if update.used_sources.is_some() {
+                    used_sources = update.used_sources;
+                }
+            }
+        }
+
+        assert_eq!(used_sources, Some(vec![1]));
+        assert_eq!(
+            algo.source_snapshot(1).unwrap().selection,
+            SelectionStatus::Pinned
+        );
+        assert_eq!(
+            algo.source_snapshot(0).unwrap().selection,
+            SelectionStatus::NotPinned
+        );
+
+        // unpinning returns to the regular selection
+        algo.pin_source(None);
+        algo.update_clock(algo.clock.current_time);
+        assert_eq!(
+            algo.source_snapshot(1).unwrap().selection,
+            SelectionStatus::NoMajority
+        );
+
+        // removing the pinned source also unpins it
+        algo.pin_source(Some(1));
+        algo.remove_source(1);
+        assert_eq!(algo.pinned, None);
+    }
+
+    #[test]
+    fn clock_updates_are_rate_limited() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig {
+            min_clock_update_interval: 16.0,
+            ..AlgorithmConfig::default()
+        };
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+
+        let mut measure = |algo: &mut KalmanClockController<TestClock, usize>| {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.0),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            )
+        };
+
+        // wait for the source to have enough samples to update the clock
+        while algo.last_clock_update.is_none() {
+            measure(&mut algo);
+        }
+        let first_update = algo.last_clock_update;
+
+        // measurements within the interval are coalesced, and the pending
+        // update is scheduled only once
+        let update = measure(&mut algo);
+        assert_eq!(update.next_update, Some(Duration::from_secs(15)));
+        assert!(algo.pending_clock_update);
+        let update = measure(&mut algo);
+        assert_eq!(update.next_update, None);
+        assert_eq!(algo.last_clock_update, first_update);
+
+        // the timer applies the pending update once the interval has passed
+        algo.clock.current_time += NtpDuration::from_seconds(8.0);
+        let update = algo.time_update();
+        assert!(update.next_update.is_some());
+        assert_eq!(algo.last_clock_update, first_update);
+
+        algo.clock.current_time += NtpDuration::from_seconds(6.0);
+        algo.time_update();
+        assert_eq!(algo.last_clock_update, Some(algo.clock.current_time));
+        assert!(!algo.pending_clock_update);
+
+        // without new measurements, the timer does not update the clock
+        algo.clock.current_time += NtpDuration::from_seconds(32.0);
+        algo.time_update();
+        assert_ne!(algo.last_clock_update, Some(algo.clock.current_time));
+    }
+
+    #[test]
+    fn no_select_source_is_not_used() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+        algo.set_no_select(0, true);
+
+        for _ in 0..20 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            let update = algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+            assert!(update.used_sources.is_none());
+        }
+
+        assert_eq!(
+            algo.source_snapshot(0).unwrap().selection,
+            SelectionStatus::NoSelect
+        );
+
+        // the measurements are still kept, so the source can be selected
+        // once the flag is cleared
+        algo.set_no_select(0, false);
+        algo.update_clock(algo.clock.current_time);
+        assert_eq!(
+            algo.source_snapshot(0).unwrap().selection,
+            SelectionStatus::Selected
+        );
+    }
+
+    #[test]
+    fn no_step_source_only_slews() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+        algo.set_no_step(0, true);
+
+        for _ in 0..20 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+        }
+
+        let snapshot = algo.source_snapshot(0).unwrap();
+        assert_eq!(snapshot.selection, SelectionStatus::Selected);
+        assert!(snapshot.no_step);
+
+        // an offset that would otherwise be stepped is slewed
+        algo.in_startup = false;
+        assert!(algo.steer_offset(5.0, 0.0).is_some());
+        algo.change_desired_frequency(0.0, 0.0);
+
+        // once a source that may step is selected, steps are possible again
+        algo.set_no_step(0, false);
+        algo.update_clock(algo.clock.current_time);
+        assert!(!algo.source_snapshot(0).unwrap().no_step);
+        assert!(algo.steer_offset(5.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn ignore_root_source_uses_measured_values() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+
+        for _ in 0..20 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::from_seconds(1.0),
+                    root_dispersion: NtpDuration::from_seconds(1.0),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+        }
+
+        // the advertised root values count towards our own
+        assert!(algo.timedata.root_delay >= NtpDuration::from_seconds(1.0));
+        assert!(algo.timedata.root_dispersion >= NtpDuration::from_seconds(1.0));
+
+        // unless they are ignored, in which case only our measurements count
+        algo.set_ignore_root(0, true);
+        algo.update_clock(algo.clock.current_time);
+        assert!(algo.timedata.root_delay < NtpDuration::from_seconds(0.1));
+        assert!(algo.timedata.root_dispersion < NtpDuration::from_seconds(0.1));
+
+        // the controller forgets about the source when it is removed
+        algo.remove_source(0);
+        assert!(algo.sources.is_empty());
+    }
+
+    #[test]
+    fn divergence_of_candidates() {
+        let snapshot = |index: usize, offset: f64| SourceSnapshot {
+            index,
+            state: Vector::new_vector([offset, 0.0]),
+            uncertainty: Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+            delay: 0.001,
+            source_uncertainty: NtpDuration::ZERO,
+            source_delay: NtpDuration::ZERO,
+            leap_indicator: NtpLeapIndicator::NoWarning,
+            last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            stratum: 1,
+            precision: -20,
+            leap_trusted: true,
+        };
+
+        assert!(max_divergence::<usize>(&[]).is_none());
+        assert!(max_divergence(&[snapshot(0, 0.001)]).is_none());
+
+        // the pair furthest apart is reported
+        let (first, second, divergence) =
+            max_divergence(&[snapshot(0, 0.001), snapshot(1, 0.003), snapshot(2, -0.002)]).unwrap();
+        assert_eq!((first, second), (1, 2));
+        assert!((divergence - 0.005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn coarse_precision_source_is_not_used() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            min_server_precision: Some(-10),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+
+        for _ in 0..20 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            let update = algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: -6,
+                },
+            );
+            assert!(update.used_sources.is_none());
+        }
+
+        let snapshot = algo.source_snapshot(0).unwrap();
+        assert_eq!(snapshot.selection, SelectionStatus::LowPrecision);
+        assert_eq!(snapshot.remote_precision, Some(-6));
+
+        // a precision at the minimum is good enough
+        algo.synchronization_config.min_server_precision = Some(-6);
+        algo.update_clock(algo.clock.current_time);
+        assert_eq!(
+            algo.source_snapshot(0).unwrap().selection,
+            SelectionStatus::Selected
+        );
+    }
+
+    #[test]
+    fn repeated_falseticker_is_excluded() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig {
+            falseticker_exclusion_threshold: Some(3.0),
+            falseticker_exclusion_duration: 100.0,
+            ..AlgorithmConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        for id in 0..3 {
+            algo.add_source(id, SourceOptions::default());
+            algo.source_update(id, true);
+        }
+
+        let mut measure = |algo: &mut KalmanClockController<TestClock, usize>| {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            for (id, offset) in [(0, 0.001), (1, 0.001), (2, 0.5)] {
+                algo.source_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(offset),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+            }
+        };
+
+        for _ in 0..10 {
+            measure(&mut algo);
+        }
+
+        let snapshot = algo.source_snapshot(2).unwrap();
+        assert_eq!(snapshot.selection, SelectionStatus::Excluded);
+        let excluded_until = snapshot.excluded_until.unwrap();
+        assert_eq!(
+            algo.source_snapshot(0).unwrap().selection,
+            SelectionStatus::Selected
+        );
+
+        // the source returns to selection after the exclusion, and is excluded
+        // for twice as long when it is still a falseticker
+        algo.clock.current_time = excluded_until;
+        algo.update_clock(algo.clock.current_time);
+        assert_eq!(
+            algo.source_snapshot(2).unwrap().selection,
+            SelectionStatus::Falseticker
+        );
+
+        for _ in 0..10 {
+            measure(&mut algo);
+        }
+
+        let snapshot = algo.source_snapshot(2).unwrap();
+        assert_eq!(snapshot.selection, SelectionStatus::Excluded);
+        let duration = snapshot.excluded_until.unwrap() - excluded_until;
+        assert!(duration.to_seconds() > 200.0 && duration.to_seconds() < 210.0);
+
+        // resetting the source forgets its history
+        algo.reset_source(2);
+        assert_eq!(algo.sources[&2].falseticker.exclusions, 0);
+        assert!(!algo.sources[&2].is_excluded());
+    }
+
+    #[test]
+    fn source_warms_up_before_selection() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            minimum_samples: 3,
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+
+        for sample in 1..=3 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            let update = algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: algo.clock.current_time,
+                    monotime: cur_instant,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+
+            // the sole source is used once it has enough samples
+            let selection = algo.source_snapshot(0).unwrap().selection;
+            if sample < 3 {
+                assert!(update.used_sources.is_none());
+                assert_eq!(selection, SelectionStatus::TooFewSamples);
+            } else {
+                assert_eq!(update.used_sources, Some(vec![0]));
+                assert_eq!(selection, SelectionStatus::Selected);
+            }
+        }
+    }
+
+    #[test]
+    fn slews_dont_accumulate() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            single_step_panic_threshold: StepThreshold {
+                forward: None,
+                backward: None,
+            },
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig {
+            step_threshold: 1800.0,
+            ..Default::default()
+        };
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        algo.in_startup = false;
+        algo.steer_offset(1000.0, 0.0);
+        assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
+    }
+
+    #[test]
+    fn freq_only_never_steps() {
+        let algo_config = AlgorithmConfig {
+            freq_only: true,
+            ..Default::default()
+        };
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+
+        // well beyond the panic threshold, but we only slew
+        algo.in_startup = false;
+        assert!(algo.steer_offset(5000.0, 0.0).is_some());
+        assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
+        assert!(algo.freq_only_diverged);
+
+        // an offset that is corrected quickly clears the alarm
+        algo.steer_offset(0.1, 0.0);
+        assert!(!algo.freq_only_diverged);
+    }
+
+    #[test]
+    fn trusted_rtc_is_slewed_at_startup() {
+        let synchronization_config = SynchronizationConfig {
+            rtc_trusted_offset: Some(NtpDuration::from_seconds(1.0)),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                // somewhere in 2023
+                current_time: NtpTimestamp::from_seconds_nanos_since_ntp_era(3_900_000_000, 0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        // small offsets are slewed, large ones still stepped
+        assert!(algo.steer_offset(0.5, 0.0).is_some());
+        algo.change_desired_frequency(0.0, 0.0);
+        assert!(algo.steer_offset(5.0, 0.0).is_none());
+
+        // a clock that was never set, here at the unix epoch, is not trusted
+        algo.clock.current_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(2_208_988_800, 0);
+        assert!(algo.steer_offset(0.5, 0.0).is_none());
+
+        // and after startup the usual rules apply
+        algo.clock.current_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(3_900_000_000, 0);
+        algo.in_startup = false;
+        assert!(algo.steer_offset(0.5, 0.0).is_none());
+    }
+
+    #[test]
+    fn step_needs_min_polls_of_source() {
+        let synchronization_config = SynchronizationConfig {
+            min_polls_before_step: 4,
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.add_source(0, SourceOptions::default());
+        algo.last_measured_source = Some(0);
+        algo.sources.get_mut(&0).unwrap().measurement_count = 1;
+
+        // the startup step is always allowed
+        assert!(algo.steer_offset(5.0, 0.0).is_none());
+        assert!(*algo.clock.has_steered.borrow());
+
+        // afterwards, a step needs enough measurements of the source
+        algo.in_startup = false;
+        *algo.clock.has_steered.borrow_mut() = false;
+        assert!(algo.steer_offset(5.0, 0.0).is_none());
+        assert!(!*algo.clock.has_steered.borrow());
+        assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
+
+        // small offsets are slewed as usual
+        assert!(algo.steer_offset(1e-3, 0.0).is_some());
+        algo.change_desired_frequency(0.0, 0.0);
+
+        algo.sources.get_mut(&0).unwrap().measurement_count = 4;
+        *algo.clock.has_steered.borrow_mut() = false;
+        assert!(algo.steer_offset(5.0, 0.0).is_none());
+        assert!(*algo.clock.has_steered.borrow());
+        assert_eq!(
+            algo.timedata.accumulated_steps,
+            NtpDuration::from_seconds(5.0)
+        );
+
+        // a removed source no longer counts
+        algo.remove_source(0);
+        assert_eq!(algo.last_measured_source, None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn jumps_add_absolutely() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            single_step_panic_threshold: StepThreshold {
+                forward: None,
+                backward: None,
+            },
+            accumulated_step_panic_threshold: Some(NtpDuration::from_seconds(1800.0)),
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        algo.in_startup = false;
+        algo.steer_offset(1000.0, 0.0);
+        algo.steer_offset(-1000.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn updated_step_threshold_is_used() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        // within the default threshold of 1000 seconds
+        algo.in_startup = false;
+        algo.steer_offset(-100.0, 0.0);
 
-                    stratum: 0,
-                    root_delay: NtpDuration::default(),
-                    root_dispersion: NtpDuration::default(),
-                    leap: NtpLeapIndicator::NoWarning,
-                    precision: 0,
+        algo.update_config(
+            SynchronizationConfig {
+                single_step_panic_threshold: StepThreshold {
+                    forward: Some(NtpDuration::from_seconds(1000.0)),
+                    backward: Some(NtpDuration::from_seconds(10.0)),
                 },
-            );
-        }
+                ..synchronization_config
+            },
+            source_defaults_config,
+            algo_config,
+        );
 
-        assert!(!algo.in_startup);
-        assert_eq!(algo.timedata.leap_indicator, NtpLeapIndicator::NoWarning);
-        assert_ne!(algo.timedata.root_delay, NtpDuration::ZERO);
-        assert_ne!(algo.timedata.root_dispersion, NtpDuration::ZERO);
+        // forward steps are still allowed, backward ones no longer
+        algo.steer_offset(100.0, 0.0);
+        algo.steer_offset(-100.0, 0.0);
     }
 
     #[test]
-    fn slews_dont_accumulate() {
+    fn reset_restores_step_budget() {
         let synchronization_config = SynchronizationConfig {
             minimum_agreeing_sources: 1,
             single_step_panic_threshold: StepThreshold {
                 forward: None,
                 backward: None,
             },
+            accumulated_step_panic_threshold: Some(NtpDuration::from_seconds(1800.0)),
             ..SynchronizationConfig::default()
         };
-        let algo_config = AlgorithmConfig {
-            step_threshold: 1800.0,
-            ..Default::default()
-        };
+        let algo_config = AlgorithmConfig::default();
         let source_defaults_config = SourceDefaultsConfig::default();
         let mut algo = KalmanClockController::<_, u32>::new(
             TestClock {
                 has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
                 current_time: NtpTimestamp::from_fixed_int(0),
             },
             synchronization_config,
@@ -524,19 +2232,19 @@ mod tests {
 
         algo.in_startup = false;
         algo.steer_offset(1000.0, 0.0);
+        assert_eq!(
+            algo.timedata.accumulated_steps,
+            NtpDuration::from_seconds(1000.0)
+        );
+        algo.reset_accumulated_steps();
         assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
+        algo.steer_offset(-1000.0, 0.0);
     }
 
     #[test]
-    #[should_panic]
-    fn jumps_add_absolutely() {
+    fn low_power_profile_hysteresis() {
         let synchronization_config = SynchronizationConfig {
-            minimum_agreeing_sources: 1,
-            single_step_panic_threshold: StepThreshold {
-                forward: None,
-                backward: None,
-            },
-            accumulated_step_panic_threshold: Some(NtpDuration::from_seconds(1800.0)),
+            power_profile: PowerProfile::LowPower,
             ..SynchronizationConfig::default()
         };
         let algo_config = AlgorithmConfig::default();
@@ -544,6 +2252,122 @@ mod tests {
         let mut algo = KalmanClockController::<_, u32>::new(
             TestClock {
                 has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+        let regular_poll = source_defaults_config.poll_interval_limits.max;
+        algo.update_desired_poll();
+        assert_eq!(algo.timedata.poll_interval, regular_poll);
+
+        // not yet synchronized tightly enough
+        algo.update_power_mode(0.0, 2e-3);
+        assert_eq!(algo.timedata.poll_interval, regular_poll);
+
+        algo.update_power_mode(0.0, 1e-4);
+        assert_eq!(
+            algo.timedata.poll_interval,
+            algo_config.low_power_poll_interval
+        );
+
+        // uncertainty grows with the long poll interval, and small offsets
+        // should not cause a wake-up
+        algo.update_power_mode(5e-3, 5e-3);
+        assert_eq!(
+            algo.timedata.poll_interval,
+            algo_config.low_power_poll_interval
+        );
+        algo.update_desired_poll();
+        assert_eq!(
+            algo.timedata.poll_interval,
+            algo_config.low_power_poll_interval
+        );
+
+        algo.update_power_mode(-20e-3, 5e-3);
+        assert_eq!(algo.timedata.poll_interval, regular_poll);
+
+        // regular polling until synchronized tightly again
+        algo.update_power_mode(0.0, 5e-3);
+        assert_eq!(algo.timedata.poll_interval, regular_poll);
+    }
+
+    #[test]
+    fn accuracy_profile_never_uses_low_power() {
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        algo.update_power_mode(0.0, 1e-6);
+        algo.update_desired_poll();
+        assert_eq!(
+            algo.timedata.poll_interval,
+            source_defaults_config.poll_interval_limits.max
+        );
+    }
+
+    #[test]
+    fn reports_applied_frequency() {
+        let synchronization_config = SynchronizationConfig::default();
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            source_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        assert_eq!(algo.timedata.frequency_ppm, 0.0);
+
+        algo.restore_frequency(20e-6);
+        assert_eq!(
+            algo.timedata.frequency_ppm,
+            *algo.clock.frequency.borrow() * 1e6
+        );
+        assert!((algo.timedata.frequency_ppm - 20.0).abs() < 1e-6);
+
+        algo.change_desired_frequency(-algo_config.slew_maximum_frequency_offset, 0.0);
+        assert_eq!(
+            algo.timedata.frequency_ppm,
+            *algo.clock.frequency.borrow() * 1e6
+        );
+
+        // ending the slew is reported to the system
+        let update = algo.time_update();
+        assert_eq!(
+            update.time_snapshot.unwrap().frequency_ppm,
+            *algo.clock.frequency.borrow() * 1e6
+        );
+    }
+
+    #[test]
+    fn restored_frequency_excludes_slew() {
+        let synchronization_config = SynchronizationConfig::default();
+        let algo_config = AlgorithmConfig::default();
+        let source_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
                 current_time: NtpTimestamp::from_fixed_int(0),
             },
             synchronization_config,
@@ -552,9 +2376,195 @@ mod tests {
         )
         .unwrap();
 
+        algo.restore_frequency(20e-6);
+        assert!((algo.frequency() - 20e-6).abs() < 1e-12);
+        assert!((algo.freq_offset - 20e-6).abs() < 1e-12);
+
+        // an ongoing slew should not change the reported frequency
+        algo.change_desired_frequency(-algo_config.slew_maximum_frequency_offset, 0.0);
+        assert!((algo.frequency() - 20e-6).abs() < 1e-12);
+        assert!((algo.freq_offset - 20e-6).abs() > 1e-6);
+
+        // restoring during a slew keeps the slew in place
+        algo.restore_frequency(-5e-6);
+        assert!((algo.frequency() + 5e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn consensus_on_large_offset_steps_immediately() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig {
+            consensus_step_quorum: Some(2),
+            ..AlgorithmConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        for id in 0..3 {
+            algo.add_source(id, SourceOptions::default());
+            algo.source_update(id, true);
+        }
+
+        let mut measure = |algo: &mut KalmanClockController<TestClock, usize>,
+                           offsets: [f64; 3]| {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            for (id, offset) in offsets.into_iter().enumerate() {
+                algo.source_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(offset),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+            }
+        };
+
+        for _ in 0..10 {
+            measure(&mut algo, [0.001, 0.001, 0.001]);
+        }
+        assert!(algo
+            .sources
+            .values()
+            .all(|source| source.state.has_samples(4)));
+
+        // a spike of a single source is not a consensus
+        algo.sources.get_mut(&2).unwrap().large_offset = Some(5.0);
+        assert!(algo.consensus_step().is_none());
+
+        // nor are large offsets that disagree
+        algo.sources.get_mut(&1).unwrap().large_offset = Some(-5.0);
+        assert!(algo.consensus_step().is_none());
+        for source in algo.sources.values_mut() {
+            source.large_offset = None;
+        }
+
+        // the step happens as soon as the quorum agrees, after which the
+        // filters start afresh
+        measure(&mut algo, [5.0, 5.01, 5.0]);
+        assert!(algo
+            .sources
+            .values()
+            .all(|source| !source.state.has_samples(2)));
+    }
+
+    #[test]
+    fn resume_reenters_startup() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
         algo.in_startup = false;
-        algo.steer_offset(1000.0, 0.0);
-        algo.steer_offset(-1000.0, 0.0);
+
+        let mut monotime = NtpInstant::now();
+        let mut localtime = NtpTimestamp::from_fixed_int(0);
+        algo.detect_clock_jump(monotime, localtime);
+
+        // regular progress of both clocks
+        monotime = monotime + std::time::Duration::from_secs(16);
+        localtime += NtpDuration::from_seconds(16.0);
+        algo.detect_clock_jump(monotime, localtime);
+        assert!(!algo.in_startup);
+
+        // an hour passes only for the system clock
+        monotime = monotime + std::time::Duration::from_secs(16);
+        localtime += NtpDuration::from_seconds(3616.0);
+        algo.detect_clock_jump(monotime, localtime);
+        assert!(algo.in_startup);
+
+        // unless disabled
+        algo.in_startup = false;
+        algo.synchronization_config.resume_grace = false;
+        monotime = monotime + std::time::Duration::from_secs(16);
+        localtime += NtpDuration::from_seconds(3616.0);
+        algo.detect_clock_jump(monotime, localtime);
+        assert!(!algo.in_startup);
+    }
+
+    #[test]
+    fn external_step_resets_filters() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.in_startup = false;
+        algo.add_source(0, SourceOptions::default());
+        algo.source_update(0, true);
+
+        let mut monotime = NtpInstant::now();
+        let mut localtime = NtpTimestamp::from_fixed_int(0);
+        let mut measure = |algo: &mut KalmanClockController<TestClock, u32>, jump: f64| {
+            monotime = monotime + std::time::Duration::from_secs(16);
+            localtime += NtpDuration::from_seconds(16.0 + jump);
+            algo.source_measurement(
+                0,
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.001),
+                    offset: NtpDuration::from_seconds(0.001),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime,
+                    monotime,
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+        };
+
+        for _ in 0..4 {
+            measure(&mut algo, 0.0);
+        }
+        assert!(algo.sources[&0].state.has_samples(4));
+
+        // small divergences between the clocks are tolerated
+        measure(&mut algo, 1.0);
+        assert!(algo.sources[&0].state.has_samples(5));
+
+        // a backward step by someone else restarts the filters
+        measure(&mut algo, -30.0);
+        assert!(!algo.sources[&0].state.has_samples(2));
+        assert!(!algo.in_startup);
     }
 
     #[test]
@@ -569,6 +2579,7 @@ mod tests {
         let mut algo = KalmanClockController::new(
             TestClock {
                 has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
                 current_time: NtpTimestamp::from_fixed_int(0),
             },
             synchronization_config,
@@ -581,7 +2592,7 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        algo.add_source(0);
+        algo.add_source(0, SourceOptions::default());
         algo.source_update(0, true);
 
         let mut noise = 1e-9;
@@ -626,6 +2637,7 @@ mod tests {
         let mut algo = KalmanClockController::new(
             TestClock {
                 has_steered: RefCell::new(false),
+                frequency: RefCell::new(0.0),
                 current_time: NtpTimestamp::from_fixed_int(0),
             },
             synchronization_config,
@@ -638,7 +2650,7 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        algo.add_source(0);
+        algo.add_source(0, SourceOptions::default());
         algo.source_update(0, true);
 
         let mut noise = 1e-9;
@@ -666,4 +2678,183 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn controller_state_round_trip() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let new_controller = || {
+            KalmanClockController::<_, usize>::new(
+                TestClock {
+                    has_steered: RefCell::new(false),
+                    frequency: RefCell::new(0.0),
+                    current_time: NtpTimestamp::from_fixed_int(0),
+                },
+                synchronization_config,
+                SourceDefaultsConfig::default(),
+                AlgorithmConfig::default(),
+            )
+            .unwrap()
+        };
+        let measurement = |localtime: NtpTimestamp, monotime: NtpInstant| Measurement {
+            delay: NtpDuration::from_seconds(0.001),
+            offset: NtpDuration::from_seconds(0.0),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime,
+            monotime,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        let mut old = new_controller();
+        old.restore_frequency(5e-6);
+        old.add_source(0, SourceOptions::default());
+        old.source_update(0, true);
+        old.add_source(1, SourceOptions::default());
+        old.source_update(1, true);
+        let mut cur_instant = NtpInstant::now();
+        for i in 0..12 {
+            cur_instant = cur_instant + Duration::from_secs(1);
+            old.clock.current_time += NtpDuration::from_seconds(1.0);
+            old.source_measurement(0, measurement(old.clock.current_time, cur_instant));
+            if i < 2 {
+                old.source_measurement(1, measurement(old.clock.current_time, cur_instant));
+            }
+        }
+        old.timedata.accumulated_steps = NtpDuration::from_seconds(0.25);
+
+        // source ids differ between instances, the state is keyed by address
+        let old_key = |id: usize| {
+            ["192.0.2.1:123", "192.0.2.2:123"]
+                .get(id)
+                .map(|a| a.to_string())
+        };
+        let new_key = |id: usize| old_key(id.checked_sub(10)?);
+
+        // only the source out of its initial phase is exported
+        let state = old.export_state(old_key);
+        assert_eq!(state.version, CONTROLLER_STATE_VERSION);
+        assert_eq!(state.sources.len(), 1);
+        assert_eq!(state.sources[0].0, "192.0.2.1:123");
+
+        let serialized = serde_json::to_string(&state).unwrap();
+        let deserialized: KalmanControllerState<String> =
+            serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, state);
+
+        let mut new = new_controller();
+        new.clock.current_time = old.clock.current_time;
+        new.add_source(10, SourceOptions::default());
+        new.source_update(10, true);
+        new.add_source(11, SourceOptions::default());
+        new.source_update(11, true);
+        new.import_state(deserialized, new_key).unwrap();
+
+        let imported = new.export_state(new_key);
+        assert!((imported.frequency - state.frequency).abs() < 1e-12);
+        assert_eq!(imported.accumulated_steps, state.accumulated_steps);
+        assert_eq!(imported.in_startup, state.in_startup);
+        assert_eq!(imported.sources, state.sources);
+        assert_eq!(
+            new.source_snapshot(10).unwrap().offset,
+            old.source_snapshot(0).unwrap().offset
+        );
+        assert!(new.source_filter(11).unwrap().initial);
+
+        // the imported source carries on without being mistaken for meddling
+        new.clock.current_time += NtpDuration::from_seconds(1.0);
+        new.source_measurement(
+            10,
+            measurement(
+                new.clock.current_time,
+                NtpInstant::now() + Duration::from_secs(1),
+            ),
+        );
+        assert!(!new.source_filter(10).unwrap().initial);
+
+        // state in a newer format is refused
+        let mut state = old.export_state(old_key);
+        state.version = CONTROLLER_STATE_VERSION + 1;
+        assert_eq!(
+            new.import_state(state, new_key),
+            Err(ImportStateError::UnsupportedVersion(
+                CONTROLLER_STATE_VERSION + 1
+            ))
+        );
+    }
+
+    /// Clock that only advances by a microsecond on every other read
+    #[derive(Debug, Clone, Default)]
+    struct CoarseClock {
+        reads: std::cell::Cell<u64>,
+    }
+
+    impl NtpClock for CoarseClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            let reads = self.reads.get();
+            self.reads.set(reads + 1);
+            Ok(NtpTimestamp::from_fixed_int(0)
+                + NtpDuration::from_seconds((reads / 2) as f64 * 1e-6))
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _maximum_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn precision_is_measured_at_startup() {
+        let algo = KalmanClockController::<_, u32>::new(
+            CoarseClock::default(),
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        assert!((algo.precision().to_seconds() - 1e-6).abs() < 1e-9);
+        assert_eq!(algo.local_precision, algo.precision());
+
+        // without measuring, the default is advertised but not accounted for
+        let algo = KalmanClockController::<_, u32>::new(
+            CoarseClock::default(),
+            SynchronizationConfig {
+                measure_precision: false,
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(algo.precision(), TimeSnapshot::default().precision);
+        assert_eq!(algo.local_precision, NtpDuration::ZERO);
+    }
 }
@@ -1,6 +1,6 @@
 use crate::config::SynchronizationConfig;
 
-use super::{config::AlgorithmConfig, SourceSnapshot};
+use super::{super::SelectionStatus, config::AlgorithmConfig, SourceSnapshot};
 
 enum BoundType {
     Start,
@@ -15,11 +15,14 @@ enum BoundType {
 // is also statistically more sound. Any difference (larger set of accepted sources)
 // can be compensated for if desired by setting tighter bounds on the weights
 // determining the confidence interval.
+//
+// Besides the selected candidates, this returns for every candidate the reason
+// it was or wasn't selected.
 pub(super) fn select<Index: Copy>(
     synchronization_config: &SynchronizationConfig,
     algo_config: &AlgorithmConfig,
     candidates: Vec<SourceSnapshot<Index>>,
-) -> Vec<SourceSnapshot<Index>> {
+) -> (Vec<SourceSnapshot<Index>>, Vec<(Index, SelectionStatus)>) {
     let mut bounds: Vec<(f64, BoundType)> = Vec::with_capacity(2 * candidates.len());
 
     for snapshot in candidates.iter() {
@@ -52,22 +55,37 @@ pub(super) fn select<Index: Copy>(
         }
     }
 
-    if max >= synchronization_config.minimum_agreeing_sources && max * 4 > bounds.len() {
-        candidates
-            .iter()
-            .filter(|snapshot| {
-                let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-                    + snapshot.delay * algo_config.range_delay_weight;
-                radius <= algo_config.maximum_source_uncertainty
-                    && snapshot.offset() - radius <= maxt
-                    && snapshot.offset() + radius >= maxt
-                    && snapshot.leap_indicator.is_synchronized()
-            })
-            .cloned()
-            .collect()
-    } else {
-        vec![]
-    }
+    let has_majority =
+        max >= synchronization_config.minimum_agreeing_sources && max * 4 > bounds.len();
+
+    let status: Vec<_> = candidates
+        .iter()
+        .map(|snapshot| {
+            let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
+                + snapshot.delay * algo_config.range_delay_weight;
+            let status = if radius > algo_config.maximum_source_uncertainty {
+                SelectionStatus::HighRootDistance
+            } else if !snapshot.leap_indicator.is_synchronized() {
+                SelectionStatus::Unsynchronized
+            } else if !has_majority {
+                SelectionStatus::NoMajority
+            } else if snapshot.offset() - radius <= maxt && snapshot.offset() + radius >= maxt {
+                SelectionStatus::Selected
+            } else {
+                SelectionStatus::Falseticker
+            };
+            (snapshot.index, status)
+        })
+        .collect();
+
+    let selected = candidates
+        .into_iter()
+        .zip(status.iter())
+        .filter(|(_, (_, status))| *status == SelectionStatus::Selected)
+        .map(|(snapshot, _)| snapshot)
+        .collect();
+
+    (selected, status)
 }
 
 #[cfg(test)]
@@ -94,6 +112,10 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            stratum: 1,
+            precision: -20,
+            leap_trusted: true,
         }
     }
 
@@ -119,7 +141,7 @@ mod tests {
             ..Default::default()
         };
 
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -128,7 +150,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -137,7 +159,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let (result, _) = select(&sysconfig, &algconfig, candidates);
         assert_eq!(result.len(), 4);
     }
 
@@ -160,7 +182,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 3);
 
         let algconfig = AlgorithmConfig {
@@ -169,7 +191,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 2);
 
         let algconfig = AlgorithmConfig {
@@ -178,7 +200,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 1);
 
         let algconfig = AlgorithmConfig {
@@ -187,7 +209,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let (result, _) = select(&sysconfig, &algconfig, candidates);
         assert_eq!(result.len(), 0);
     }
 
@@ -212,14 +234,14 @@ mod tests {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let (result, _) = select(&sysconfig, &algconfig, candidates.clone());
         assert_eq!(result.len(), 3);
 
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 4,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let (result, _) = select(&sysconfig, &algconfig, candidates);
         assert_eq!(result.len(), 0);
     }
 
@@ -242,7 +264,58 @@ mod tests {
             minimum_agreeing_sources: 1,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let (result, _) = select(&sysconfig, &algconfig, candidates);
+        assert_eq!(result.len(), 0);
+    }
+
+    #[test]
+    fn test_selection_status() {
+        let mut candidates = vec![
+            snapshot_for_range(0.0, 0.1, 0.1),
+            snapshot_for_range(0.0, 0.1, 0.1),
+            snapshot_for_range(0.0, 0.1, 0.1),
+            snapshot_for_range(1.0, 0.1, 0.1),
+            snapshot_for_range(0.0, 5.0, 0.1),
+            SourceSnapshot {
+                leap_indicator: NtpLeapIndicator::Unknown,
+                ..snapshot_for_range(0.0, 0.1, 0.1)
+            },
+        ];
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            candidate.index = index;
+        }
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 3,
+            ..Default::default()
+        };
+        let (result, status) = select(&sysconfig, &algconfig, candidates.clone());
+        assert_eq!(result.len(), 3);
+        assert_eq!(
+            status,
+            vec![
+                (0, SelectionStatus::Selected),
+                (1, SelectionStatus::Selected),
+                (2, SelectionStatus::Selected),
+                (3, SelectionStatus::Falseticker),
+                (4, SelectionStatus::HighRootDistance),
+                (5, SelectionStatus::Unsynchronized),
+            ]
+        );
+
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 4,
+            ..Default::default()
+        };
+        let (result, status) = select(&sysconfig, &algconfig, candidates);
         assert_eq!(result.len(), 0);
+        assert_eq!(status[0], (0, SelectionStatus::NoMajority));
+        assert_eq!(status[3], (3, SelectionStatus::NoMajority));
+        assert_eq!(status[4], (4, SelectionStatus::HighRootDistance));
     }
 }
@@ -0,0 +1,228 @@
+//! Deterministic simulation of the network between us and a source, to
+//! characterize how accurately the filter estimates the offset under
+//! realistic network conditions.
+//!
+//! The true offset of the source starts at an initial value and drifts at a
+//! constant rate. Every poll, the request and the response each take a fixed
+//! delay plus gaussian jitter, and either of them can get lost. All randomness
+//! comes from a seeded generator, so a simulation with the same seed always
+//! produces the same measurements.
+
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{config::AlgorithmConfig, source::SourceState};
+use crate::{
+    config::SourceDefaultsConfig,
+    packet::NtpLeapIndicator,
+    source::Measurement,
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp},
+};
+
+/// Conditions of the simulated network and source
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkConditions {
+    /// Delay of the request and of the response on an idle network (seconds)
+    pub delay: f64,
+    /// Standard deviation of the extra delay of the request and of the
+    /// response (seconds). Since packets can't arrive earlier than on an idle
+    /// network, the extra delay is the absolute value of a gaussian.
+    pub jitter: f64,
+    /// Probability that a request or its response gets lost (0-1)
+    pub loss: f64,
+    /// Offset of the source relative to the local clock at the start of the
+    /// simulation (seconds)
+    pub initial_offset: f64,
+    /// Rate at which the offset of the source changes (seconds per second)
+    pub drift: f64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            delay: 5e-3,
+            jitter: 1e-3,
+            loss: 0.0,
+            initial_offset: 0.0,
+            drift: 0.0,
+        }
+    }
+}
+
+/// Generates the measurements of polls through the simulated network
+pub struct NetworkSimulator {
+    conditions: NetworkConditions,
+    rng: StdRng,
+    localtime: NtpTimestamp,
+    monotime: NtpInstant,
+    elapsed: f64,
+}
+
+impl NetworkSimulator {
+    pub fn new(conditions: NetworkConditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+            localtime: NtpTimestamp::default(),
+            monotime: NtpInstant::now(),
+            elapsed: 0.0,
+        }
+    }
+
+    /// Seconds since the start of the simulation
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// The true offset of the source at the current time (seconds)
+    pub fn true_offset(&self) -> f64 {
+        self.conditions.initial_offset + self.conditions.drift * self.elapsed
+    }
+
+    /// Advance the time by the poll interval and poll the source. Returns
+    /// `None` if the request or the response got lost.
+    pub fn poll(&mut self, poll_interval: Duration) -> Option<Measurement> {
+        self.localtime += NtpDuration::from_system_duration(poll_interval);
+        self.monotime = self.monotime + poll_interval;
+        self.elapsed += poll_interval.as_secs_f64();
+
+        if self.rng.gen_bool(self.conditions.loss) || self.rng.gen_bool(self.conditions.loss) {
+            return None;
+        }
+
+        let request_delay = self.conditions.delay + self.jitter();
+        let response_delay = self.conditions.delay + self.jitter();
+        let offset = self.true_offset();
+
+        let send_timestamp = self.localtime;
+        let receive_timestamp = send_timestamp + NtpDuration::from_seconds(offset + request_delay);
+        let recv_timestamp = receive_timestamp + NtpDuration::from_seconds(response_delay - offset);
+
+        Some(Measurement {
+            delay: recv_timestamp - send_timestamp,
+            offset: NtpDuration::from_seconds(offset + (request_delay - response_delay) / 2.0),
+            transmit_timestamp: receive_timestamp,
+            receive_timestamp,
+            localtime: send_timestamp + (recv_timestamp - send_timestamp) / 2,
+            monotime: self.monotime,
+
+            stratum: 1,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        })
+    }
+
+    /// Absolute value of a gaussian with the configured standard deviation,
+    /// using the Box-Muller transform
+    fn jitter(&mut self) -> f64 {
+        let u1: f64 = 1.0 - self.rng.gen::<f64>();
+        let u2: f64 = self.rng.gen();
+        let gaussian = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        (gaussian * self.conditions.jitter).abs()
+    }
+}
+
+/// State of the simulation after a poll
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationStep {
+    /// Seconds since the start of the simulation
+    pub elapsed: f64,
+    /// The true offset of the source (seconds)
+    pub true_offset: f64,
+    /// Offset estimated by the filter, if it has one yet (seconds)
+    pub estimate: Option<f64>,
+}
+
+/// Run the source filter on `steps` polls through the simulated network at
+/// the minimum poll interval, tracing its estimate against the true offset
+pub fn simulate(conditions: NetworkConditions, seed: u64, steps: usize) -> Vec<SimulationStep> {
+    let source_defaults_config = SourceDefaultsConfig::default();
+    let algo_config = AlgorithmConfig::default();
+    let poll_interval = source_defaults_config
+        .poll_interval_limits
+        .min
+        .as_system_duration();
+
+    let mut simulator = NetworkSimulator::new(conditions, seed);
+    let mut state = SourceState::new();
+
+    (0..steps)
+        .map(|_| {
+            if let Some(measurement) = simulator.poll(poll_interval) {
+                state.update_self_using_measurement(
+                    &source_defaults_config,
+                    &algo_config,
+                    measurement,
+                );
+            }
+
+            SimulationStep {
+                elapsed: simulator.elapsed(),
+                true_offset: simulator.true_offset(),
+                estimate: state
+                    .snapshot_with_config(0, &algo_config)
+                    .map(|snapshot| snapshot.offset()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest error of the estimate after the first `warmup` steps
+    fn max_error(steps: &[SimulationStep], warmup: usize) -> f64 {
+        steps[warmup..]
+            .iter()
+            .map(|step| (step.estimate.unwrap() - step.true_offset).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        let conditions = NetworkConditions {
+            loss: 0.2,
+            ..Default::default()
+        };
+
+        let mut a = NetworkSimulator::new(conditions, 1);
+        let mut b = NetworkSimulator::new(conditions, 1);
+        for _ in 0..100 {
+            let a = a.poll(Duration::from_secs(16));
+            let b = b.poll(Duration::from_secs(16));
+            assert_eq!(a.map(|m| m.offset), b.map(|m| m.offset));
+            assert_eq!(a.map(|m| m.delay), b.map(|m| m.delay));
+        }
+    }
+
+    #[test]
+    fn test_converges_with_jitter() {
+        let conditions = NetworkConditions {
+            delay: 5e-3,
+            jitter: 1e-3,
+            initial_offset: 10e-3,
+            ..Default::default()
+        };
+
+        let steps = simulate(conditions, 0x5eed, 500);
+        assert!(max_error(&steps, 100) < 1e-3);
+    }
+
+    #[test]
+    fn test_converges_with_loss_and_drift() {
+        let conditions = NetworkConditions {
+            delay: 20e-3,
+            jitter: 2e-3,
+            loss: 0.1,
+            initial_offset: -50e-3,
+            drift: 1e-6,
+        };
+
+        let steps = simulate(conditions, 0x5eed, 1000);
+        assert!(max_error(&steps, 200) < 2e-3);
+    }
+}
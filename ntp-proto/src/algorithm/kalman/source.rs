@@ -72,12 +72,17 @@
 /// If they are often too small, v is quartered, and if they are often too
 /// large, v is quadrupled (note, this corresponds with doubling/halving
 /// the more intuitive standard deviation).
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info, trace};
 
 use crate::{
+    algorithm::ObservableSourceFilter,
     config::SourceDefaultsConfig,
+    packet::NtpLeapIndicator,
     source::Measurement,
-    time_types::{NtpDuration, NtpTimestamp, PollInterval, PollIntervalLimits},
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval, PollIntervalLimits},
 };
 
 use super::{
@@ -113,18 +118,77 @@ fn chi_1(chi: f64) -> f64 {
 
 impl AveragingBuffer {
     fn mean(&self) -> f64 {
-        self.data.iter().sum::<f64>() / (self.data.len() as f64)
+        mean(&self.data)
     }
 
     fn variance(&self) -> f64 {
-        let mean = self.mean();
-        self.data.iter().map(|v| sqr(v - mean)).sum::<f64>() / ((self.data.len() - 1) as f64)
+        variance(&self.data)
     }
 
     fn update(&mut self, rtt: f64) {
         self.data[self.next_idx] = rtt;
         self.next_idx = (self.next_idx + 1) % self.data.len();
     }
+
+    /// The first `filled` entries written, from oldest to newest
+    fn entries(&self, filled: usize) -> Vec<f64> {
+        if filled < self.data.len() {
+            self.data[..filled].to_vec()
+        } else {
+            let (newest, oldest) = self.data.split_at(self.next_idx);
+            oldest.iter().chain(newest).copied().collect()
+        }
+    }
+
+    /// Samples whose distance to the median is at most `mad_multiplier` times
+    /// the median absolute deviation (MAD), such that a single wild sample
+    /// does not pull the mean and variance.
+    fn inliers(&self, mad_multiplier: f64) -> Vec<f64> {
+        let median = median(self.data.to_vec());
+        let mad = median_absolute_deviation(&self.data, median);
+
+        // Without any spread we can't tell outliers apart
+        if mad == 0.0 {
+            return self.data.to_vec();
+        }
+
+        let inliers: Vec<f64> = self
+            .data
+            .iter()
+            .copied()
+            .filter(|v| (v - median).abs() <= mad_multiplier * mad)
+            .collect();
+
+        // The variance needs at least two samples
+        if inliers.len() < 2 {
+            self.data.to_vec()
+        } else {
+            inliers
+        }
+    }
+}
+
+fn median(mut data: Vec<f64>) -> f64 {
+    data.sort_by(|a, b| a.total_cmp(b));
+    let mid = data.len() / 2;
+    if data.len() % 2 == 0 {
+        (data[mid - 1] + data[mid]) / 2.
+    } else {
+        data[mid]
+    }
+}
+
+fn median_absolute_deviation(data: &[f64], median_value: f64) -> f64 {
+    median(data.iter().map(|v| (v - median_value).abs()).collect())
+}
+
+fn mean(data: &[f64]) -> f64 {
+    data.iter().sum::<f64>() / (data.len() as f64)
+}
+
+fn variance(data: &[f64]) -> f64 {
+    let mean = mean(data);
+    data.iter().map(|v| sqr(v - mean)).sum::<f64>() / ((data.len() - 1) as f64)
 }
 
 #[derive(Debug, Clone)]
@@ -175,6 +239,17 @@ struct SourceFilter {
 }
 
 impl SourceFilter {
+    /// Root dispersion of the source at the current filter time. This grows
+    /// with the time since the last measurement, so a source that stopped
+    /// responding gradually loses its influence.
+    fn aged_root_dispersion(&self, algo_config: &AlgorithmConfig) -> NtpDuration {
+        let age = (self.filter_time - self.last_measurement.localtime)
+            .to_seconds()
+            .max(0.0);
+        self.last_measurement.root_dispersion
+            + NtpDuration::from_seconds(algo_config.dispersion_growth_rate * age)
+    }
+
     /// Move the filter forward to reflect the situation at a new, later timestamp
     fn progress_filtertime(&mut self, time: NtpTimestamp) {
         debug_assert!(
@@ -253,6 +328,14 @@ impl SourceFilter {
         weight: f64,
         measurement_period: f64,
     ) {
+        // With equal limits the interval is fixed, so there is nothing to adapt
+        let limits = source_defaults_config.poll_interval_limits;
+        if limits.min == limits.max {
+            self.desired_poll_interval = limits.min;
+            self.poll_score = 0;
+            return;
+        }
+
         // We dont want to speed up when we already want more than we get, and vice versa.
         let reference_measurement_period = self.desired_poll_interval.as_duration().to_seconds();
         if weight < algo_config.poll_interval_low_weight
@@ -271,9 +354,11 @@ impl SourceFilter {
             self.desired_poll_interval = source_defaults_config.poll_interval_limits.min;
             self.poll_score = 0;
         } else if self.poll_score <= -algo_config.poll_interval_hysteresis {
-            self.desired_poll_interval = self
-                .desired_poll_interval
-                .inc(source_defaults_config.poll_interval_limits);
+            let mut limits = source_defaults_config.poll_interval_limits;
+            if let Some(max_stable) = algo_config.max_stable_poll_interval {
+                limits.max = limits.max.min(max_stable).max(limits.min);
+            }
+            self.desired_poll_interval = self.desired_poll_interval.inc(limits);
             self.poll_score = 0;
             info!(interval = ?self.desired_poll_interval, "Increased poll interval");
         } else if self.poll_score >= algo_config.poll_interval_hysteresis {
@@ -333,6 +418,7 @@ impl SourceFilter {
         self.last_measurement.root_delay = measurement.root_delay;
         self.last_measurement.root_dispersion = measurement.root_dispersion;
         self.last_measurement.stratum = measurement.stratum;
+        self.last_measurement.precision = measurement.precision;
         self.last_measurement.leap = measurement.leap;
 
         if measurement.localtime.is_before(self.filter_time) {
@@ -427,10 +513,13 @@ impl SourceState {
             SourceStateInner::Initial(filter) => {
                 filter.update(measurement);
                 if filter.samples == 8 {
+                    let offsets = filter
+                        .init_offset
+                        .inliers(algo_config.offset_outlier_mad_multiplier);
                     *self = SourceState(SourceStateInner::Stable(SourceFilter {
-                        state: Vector::new_vector([filter.init_offset.mean(), 0.]),
+                        state: Vector::new_vector([mean(&offsets), 0.]),
                         uncertainty: Matrix::new([
-                            [filter.init_offset.variance(), 0.],
+                            [variance(&offsets), 0.],
                             [0., sqr(algo_config.initial_frequency_uncertainty)],
                         ]),
                         clock_wander: sqr(algo_config.initial_wander),
@@ -479,7 +568,16 @@ impl SourceState {
         }
     }
 
+    #[cfg(test)]
     pub fn snapshot<Index: Copy>(&self, index: Index) -> Option<SourceSnapshot<Index>> {
+        self.snapshot_with_config(index, &AlgorithmConfig::default())
+    }
+
+    pub fn snapshot_with_config<Index: Copy>(
+        &self,
+        index: Index,
+        algo_config: &AlgorithmConfig,
+    ) -> Option<SourceSnapshot<Index>> {
         match &self.0 {
             SourceStateInner::Initial(InitialSourceFilter {
                 roundtriptime_stats,
@@ -518,6 +616,10 @@ impl SourceState {
                         [max_roundtrip, 0.0],
                         [0.0, INITIALIZATION_FREQ_UNCERTAINTY],
                     ]),
+                    weight: 1.0,
+                    stratum: last_measurement.stratum,
+                    precision: last_measurement.precision,
+                    leap_trusted: true,
                 })
             }
             SourceStateInner::Stable(filter) => Some(SourceSnapshot {
@@ -525,15 +627,57 @@ impl SourceState {
                 state: filter.state,
                 uncertainty: filter.uncertainty,
                 delay: filter.roundtriptime_stats.mean(),
-                source_uncertainty: filter.last_measurement.root_dispersion,
+                source_uncertainty: filter.aged_root_dispersion(algo_config),
                 source_delay: filter.last_measurement.root_delay,
                 leap_indicator: filter.last_measurement.leap,
                 last_update: filter.last_iter,
+                weight: 1.0,
+                stratum: filter.last_measurement.stratum,
+                precision: filter.last_measurement.precision,
+                leap_trusted: true,
             }),
             _ => None,
         }
     }
 
+    /// Estimated clock wander (s/s^2) and variance of a single measurement
+    /// (s^2) of the source, once out of the initial phase
+    pub fn noise_estimates(&self) -> Option<(f64, f64)> {
+        match &self.0 {
+            SourceStateInner::Initial(_) => None,
+            SourceStateInner::Stable(filter) => Some((
+                filter.clock_wander,
+                filter.roundtriptime_stats.variance() / 4.,
+            )),
+        }
+    }
+
+    /// Whether the source has received at least the given number of
+    /// measurements. Once out of the initial phase a source always has.
+    pub fn has_samples(&self, samples: u32) -> bool {
+        match &self.0 {
+            SourceStateInner::Initial(filter) => filter.samples >= samples as i32,
+            SourceStateInner::Stable(_) => true,
+        }
+    }
+
+    pub fn filter_entries(&self) -> ObservableSourceFilter {
+        match &self.0 {
+            SourceStateInner::Initial(filter) => ObservableSourceFilter {
+                initial: true,
+                delays: filter.roundtriptime_stats.entries(filter.samples as usize),
+                offsets: filter.init_offset.entries(filter.samples as usize),
+            },
+            SourceStateInner::Stable(filter) => ObservableSourceFilter {
+                initial: false,
+                delays: filter
+                    .roundtriptime_stats
+                    .entries(filter.roundtriptime_stats.data.len()),
+                offsets: vec![],
+            },
+        }
+    }
+
     pub fn get_filtertime(&self) -> Option<NtpTimestamp> {
         match &self.0 {
             SourceStateInner::Initial(_) => None,
@@ -568,6 +712,139 @@ impl SourceState {
             SourceStateInner::Stable(filter) => filter.process_frequency_steering(time, steer),
         }
     }
+
+    /// The filter state in serializable form. Sources still in the initial
+    /// phase have no filter state worth keeping, they quickly collect their
+    /// initial measurements again.
+    pub fn export(&self) -> Option<SourceFilterState> {
+        match &self.0 {
+            SourceStateInner::Initial(_) => None,
+            SourceStateInner::Stable(filter) => Some(SourceFilterState {
+                offset: filter.state.ventry(0),
+                frequency: filter.state.ventry(1),
+                uncertainty: [
+                    [
+                        filter.uncertainty.entry(0, 0),
+                        filter.uncertainty.entry(0, 1),
+                    ],
+                    [
+                        filter.uncertainty.entry(1, 0),
+                        filter.uncertainty.entry(1, 1),
+                    ],
+                ],
+                clock_wander: filter.clock_wander,
+                roundtriptimes: filter.roundtriptime_stats.data,
+                next_roundtriptime: filter.roundtriptime_stats.next_idx,
+                precision_score: filter.precision_score,
+                poll_score: filter.poll_score,
+                desired_poll_interval: filter.desired_poll_interval,
+                last_measurement: MeasurementState::from(filter.last_measurement),
+                prev_was_outlier: filter.prev_was_outlier,
+                last_iter: filter.last_iter,
+                filter_time: filter.filter_time,
+            }),
+        }
+    }
+
+    /// Continue from exported filter state. The monotonic time of the last
+    /// measurement doesn't carry over, so it is reconstructed from how long
+    /// ago the last measurement was according to the clock at `now`.
+    pub fn import(state: SourceFilterState, now: NtpTimestamp) -> Self {
+        let age = (now - state.last_measurement.localtime)
+            .to_seconds()
+            .max(0.0);
+        let now_mono = NtpInstant::now();
+        let monotime = now_mono
+            .checked_sub(Duration::from_secs_f64(age))
+            .unwrap_or(now_mono);
+
+        SourceState(SourceStateInner::Stable(SourceFilter {
+            state: Vector::new_vector([state.offset, state.frequency]),
+            uncertainty: Matrix::new(state.uncertainty),
+            clock_wander: state.clock_wander,
+            roundtriptime_stats: AveragingBuffer {
+                data: state.roundtriptimes,
+                next_idx: state.next_roundtriptime % state.roundtriptimes.len(),
+            },
+            precision_score: state.precision_score,
+            poll_score: state.poll_score,
+            desired_poll_interval: state.desired_poll_interval,
+            last_measurement: state.last_measurement.into_measurement(monotime),
+            prev_was_outlier: state.prev_was_outlier,
+            last_iter: state.last_iter,
+            filter_time: state.filter_time,
+        }))
+    }
+}
+
+/// Filter state of a source out of its initial phase, in a form that can be
+/// serialized to carry it over to another instance of the controller
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceFilterState {
+    offset: f64,
+    frequency: f64,
+    uncertainty: [[f64; 2]; 2],
+    clock_wander: f64,
+    roundtriptimes: [f64; 8],
+    next_roundtriptime: usize,
+    precision_score: i32,
+    poll_score: i32,
+    desired_poll_interval: PollInterval,
+    last_measurement: MeasurementState,
+    prev_was_outlier: bool,
+    last_iter: NtpTimestamp,
+    filter_time: NtpTimestamp,
+}
+
+/// A measurement without its monotonic time, which is meaningless outside
+/// of the process that took it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct MeasurementState {
+    delay: NtpDuration,
+    offset: NtpDuration,
+    transmit_timestamp: NtpTimestamp,
+    receive_timestamp: NtpTimestamp,
+    localtime: NtpTimestamp,
+    stratum: u8,
+    root_delay: NtpDuration,
+    root_dispersion: NtpDuration,
+    leap: NtpLeapIndicator,
+    precision: i8,
+}
+
+impl From<Measurement> for MeasurementState {
+    fn from(measurement: Measurement) -> Self {
+        MeasurementState {
+            delay: measurement.delay,
+            offset: measurement.offset,
+            transmit_timestamp: measurement.transmit_timestamp,
+            receive_timestamp: measurement.receive_timestamp,
+            localtime: measurement.localtime,
+            stratum: measurement.stratum,
+            root_delay: measurement.root_delay,
+            root_dispersion: measurement.root_dispersion,
+            leap: measurement.leap,
+            precision: measurement.precision,
+        }
+    }
+}
+
+impl MeasurementState {
+    fn into_measurement(self, monotime: NtpInstant) -> Measurement {
+        Measurement {
+            delay: self.delay,
+            offset: self.offset,
+            transmit_timestamp: self.transmit_timestamp,
+            receive_timestamp: self.receive_timestamp,
+            localtime: self.localtime,
+            monotime,
+            stratum: self.stratum,
+            root_delay: self.root_delay,
+            root_dispersion: self.root_dispersion,
+            leap: self.leap,
+            precision: self.precision,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1419,6 +1696,131 @@ mod tests {
         );
     }
 
+    /// Stable filter with a small uncertainty, whose last measurement was
+    /// at `base`
+    fn test_filter(base: NtpTimestamp) -> SourceFilter {
+        SourceFilter {
+            state: Vector::new_vector([0.0, 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-8,
+            roundtriptime_stats: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 0.875e-6, 0.875e-6, 0.875e-6, 0.875e-6],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: Measurement {
+                delay: NtpDuration::from_seconds(0.0),
+                offset: NtpDuration::from_seconds(0.0),
+                transmit_timestamp: Default::default(),
+                receive_timestamp: Default::default(),
+                localtime: base,
+                monotime: NtpInstant::now(),
+
+                stratum: 0,
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+        }
+    }
+
+    #[test]
+    fn test_max_stable_poll_interval() {
+        let config = SourceDefaultsConfig::default();
+        let min = PollIntervalLimits::default().min;
+        let cap = min.inc(PollIntervalLimits::default());
+        let algo_config = AlgorithmConfig {
+            poll_interval_hysteresis: 2,
+            max_stable_poll_interval: Some(cap),
+            ..Default::default()
+        };
+
+        let mut source = SourceFilter {
+            desired_poll_interval: min,
+            ..test_filter(NtpTimestamp::from_fixed_int(0))
+        };
+
+        // stable measurements grow the poll interval up to the cap
+        for _ in 0..2 {
+            let period = source.desired_poll_interval.as_duration().to_seconds() * 2.;
+            source.update_desired_poll(&config, &algo_config, 1.0, 0.0, period);
+        }
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, cap);
+
+        // but not beyond it, while the hysteresis keeps working
+        source.update_desired_poll(
+            &config,
+            &algo_config,
+            1.0,
+            0.0,
+            cap.as_duration().to_seconds(),
+        );
+        assert_eq!(source.poll_score, -1);
+        assert_eq!(source.desired_poll_interval, cap);
+        source.update_desired_poll(
+            &config,
+            &algo_config,
+            1.0,
+            0.0,
+            cap.as_duration().to_seconds(),
+        );
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, cap);
+
+        // decreasing is unaffected
+        for _ in 0..2 {
+            source.update_desired_poll(
+                &config,
+                &algo_config,
+                1.0,
+                1.0,
+                cap.as_duration().to_seconds(),
+            );
+        }
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, min);
+    }
+
+    #[test]
+    fn test_locked_poll_interval() {
+        let interval = PollIntervalLimits::default()
+            .min
+            .inc(PollIntervalLimits::default());
+        let config = SourceDefaultsConfig {
+            poll_interval_limits: PollIntervalLimits {
+                min: interval,
+                max: interval,
+            },
+            ..Default::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+
+        let mut source = SourceFilter {
+            desired_poll_interval: interval,
+            ..test_filter(NtpTimestamp::from_fixed_int(0))
+        };
+
+        // neither stable, unstable nor outlying measurements change the interval
+        let period = interval.as_duration().to_seconds();
+        for i in 0..100 {
+            let (p, weight) = match i % 3 {
+                0 => (1.0, 0.0),
+                1 => (1.0, 1.0),
+                _ => (0.0, 0.5),
+            };
+            source.update_desired_poll(&config, &algo_config, p, weight, period);
+            assert_eq!(source.desired_poll_interval, interval);
+            assert_eq!(source.poll_score, 0);
+        }
+    }
+
     #[test]
     fn test_wander_estimation() {
         let algo_config = AlgorithmConfig {
@@ -1494,4 +1896,129 @@ mod tests {
         assert_eq!(source.precision_score, 0);
         assert!((source.clock_wander - 1e-8).abs() < 1e-12);
     }
+
+    #[test]
+    fn test_offset_outlier_rejection() {
+        let mut buffer = AveragingBuffer::default();
+        for i in 0..10 {
+            buffer.update(10e-3 + if i % 2 == 0 { 1e-4 } else { -1e-4 });
+        }
+        buffer.update(1.0);
+
+        // the buffer keeps only the last 8 samples, including the wild one
+        assert!(buffer.data.contains(&1.0));
+        let inliers = buffer.inliers(AlgorithmConfig::default().offset_outlier_mad_multiplier);
+        assert_eq!(inliers.len(), buffer.data.len() - 1);
+        assert!(!inliers.contains(&1.0));
+        assert!((mean(&inliers) - 10e-3).abs() < 1e-4);
+
+        // without spread, nothing can be rejected
+        let buffer = AveragingBuffer {
+            data: [1.0; 8],
+            next_idx: 0,
+        };
+        assert_eq!(buffer.inliers(5.0).len(), 8);
+    }
+
+    #[test]
+    fn test_filter_entries() {
+        let mut source = SourceState::new();
+        assert_eq!(
+            source.filter_entries(),
+            ObservableSourceFilter {
+                initial: true,
+                delays: vec![],
+                offsets: vec![],
+            }
+        );
+
+        let mut buffer = AveragingBuffer::default();
+        for i in 0..3 {
+            buffer.update(i as f64);
+        }
+        assert_eq!(buffer.entries(3), [0.0, 1.0, 2.0]);
+
+        // once the buffer wrapped around, the oldest entry is the next to be replaced
+        for i in 3..11 {
+            buffer.update(i as f64);
+        }
+        assert_eq!(buffer.entries(8), [3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0]);
+
+        source.0 = SourceStateInner::Initial(InitialSourceFilter {
+            roundtriptime_stats: buffer,
+            init_offset: buffer,
+            last_measurement: None,
+            samples: 8,
+        });
+        let entries = source.filter_entries();
+        assert!(entries.initial);
+        assert_eq!(entries.delays.len(), 8);
+        assert_eq!(entries.offsets, entries.delays);
+    }
+
+    #[test]
+    fn test_root_dispersion_grows_between_measurements() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let filter = test_filter(base);
+        let mut source = SourceState(SourceStateInner::Stable(SourceFilter {
+            clock_wander: 0.0,
+            last_measurement: Measurement {
+                root_dispersion: NtpDuration::from_seconds(1e-3),
+                ..filter.last_measurement
+            },
+            ..filter
+        }));
+
+        let algo_config = AlgorithmConfig::default();
+        let snapshot = source.snapshot_with_config(0_usize, &algo_config).unwrap();
+        assert!((snapshot.source_uncertainty.to_seconds() - 1e-3).abs() < 1e-9);
+
+        source.progress_filtertime(base + NtpDuration::from_seconds(1000.0));
+        let snapshot = source.snapshot_with_config(0_usize, &algo_config).unwrap();
+        assert!((snapshot.source_uncertainty.to_seconds() - 16e-3).abs() < 1e-9);
+
+        let algo_config = AlgorithmConfig {
+            dispersion_growth_rate: 0.0,
+            ..Default::default()
+        };
+        let snapshot = source.snapshot_with_config(0_usize, &algo_config).unwrap();
+        assert!((snapshot.source_uncertainty.to_seconds() - 1e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_init_ignores_offset_outlier() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let mut source = SourceState::new();
+        // ten good samples with a wild one inside the initial window
+        for i in 0..11 {
+            let offset = match i {
+                3 => 1.0,
+                _ if i % 2 == 0 => 10e-3 + 1e-4,
+                _ => 10e-3 - 1e-4,
+            };
+            source.update_self_using_measurement(
+                &SourceDefaultsConfig::default(),
+                &AlgorithmConfig::default(),
+                Measurement {
+                    delay: NtpDuration::from_seconds(0.0),
+                    offset: NtpDuration::from_seconds(offset),
+                    transmit_timestamp: Default::default(),
+                    receive_timestamp: Default::default(),
+                    localtime: base + NtpDuration::from_seconds(1000.0 + i as f64),
+                    monotime: basei + std::time::Duration::from_secs(1000 + i),
+
+                    stratum: 0,
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+            );
+        }
+
+        let snapshot = source.snapshot(0_usize).unwrap();
+        assert!((snapshot.state.ventry(0) - 10e-3).abs() < 1e-4);
+        assert!(snapshot.uncertainty.entry(0, 0) < 1e-6);
+    }
 }
@@ -5,12 +5,13 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use crate::{
     clock::NtpClock,
     config::{SourceDefaultsConfig, SynchronizationConfig},
+    packet::NtpLeapIndicator,
     source::Measurement,
     system::TimeSnapshot,
-    time_types::{NtpDuration, NtpTimestamp},
+    time_types::{NtpDuration, NtpTimestamp, PollInterval},
 };
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ObservableSourceTimedata {
     pub offset: NtpDuration,
     pub uncertainty: NtpDuration,
@@ -18,8 +19,196 @@ pub struct ObservableSourceTimedata {
 
     pub remote_delay: NtpDuration,
     pub remote_uncertainty: NtpDuration,
+    /// Precision advertised by the source (log2 seconds)
+    #[serde(default)]
+    pub remote_precision: Option<i8>,
 
     pub last_update: NtpTimestamp,
+
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+
+    #[serde(default)]
+    pub selection: SelectionStatus,
+
+    /// How often the source was recently classified as a falseticker
+    #[serde(default)]
+    pub falseticker_score: f64,
+    /// Until when the source is excluded from selection for being a
+    /// falseticker too often
+    #[serde(default)]
+    pub excluded_until: Option<NtpTimestamp>,
+    /// Whether the source may only slew the clock, never step it
+    #[serde(default)]
+    pub no_step: bool,
+}
+
+/// Outcome of the last selection round for a source, explaining why it
+/// is or isn't used to determine the time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectionStatus {
+    /// Not enough measurements yet to take part in selection
+    #[default]
+    TooFewSamples,
+    /// Not reachable, or not acceptable as a time source
+    Unreachable,
+    /// Uncertainty (including root dispersion and delay) is too large
+    HighRootDistance,
+    /// The source itself is not synchronized
+    Unsynchronized,
+    /// Disagrees with the majority of the sources
+    Falseticker,
+    /// No sufficiently large group of sources agrees on the time
+    NoMajority,
+    /// Used to determine the time
+    Selected,
+    /// Pinned, and used on its own to determine the time
+    Pinned,
+    /// Not used, because another source is pinned
+    NotPinned,
+    /// Only monitored, never used to determine the time
+    NoSelect,
+    /// Advertised precision is coarser than the configured minimum
+    LowPrecision,
+    /// Temporarily excluded, because it was a falseticker too often
+    Excluded,
+    /// Stratum is outside of the range configured for the source
+    UnexpectedStratum,
+}
+
+impl std::fmt::Display for SelectionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SelectionStatus::TooFewSamples => "too few samples",
+            SelectionStatus::Unreachable => "unreachable",
+            SelectionStatus::HighRootDistance => "uncertainty too high",
+            SelectionStatus::Unsynchronized => "source unsynchronized",
+            SelectionStatus::Falseticker => "falseticker",
+            SelectionStatus::NoMajority => "no majority agreement",
+            SelectionStatus::Selected => "selected",
+            SelectionStatus::Pinned => "pinned",
+            SelectionStatus::NotPinned => "not used, another source is pinned",
+            SelectionStatus::NoSelect => "monitored only",
+            SelectionStatus::LowPrecision => "advertised precision too coarse",
+            SelectionStatus::Excluded => "excluded, repeatedly a falseticker",
+            SelectionStatus::UnexpectedStratum => "stratum outside the expected range",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Why the system source, whose stratum and reference id we advertise, was
+/// chosen among the selected sources
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemSourceChoice {
+    /// It has the lowest uncertainty
+    #[default]
+    LowestUncertainty,
+    /// It has the lowest stratum of the sources with about the lowest
+    /// uncertainty
+    LowestStratum,
+}
+
+impl std::fmt::Display for SystemSourceChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SystemSourceChoice::LowestUncertainty => "lowest uncertainty",
+            SystemSourceChoice::LowestStratum => "lowest stratum among equally good sources",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Raw contents of the measurement buffers of a source filter, for debugging.
+/// Values are in seconds, ordered from oldest to newest, and there are never
+/// more than the fixed number of entries the filter keeps.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ObservableSourceFilter {
+    /// Whether the filter is still collecting its initial measurements
+    pub initial: bool,
+    /// Round trip delays of the most recent measurements
+    pub delays: Vec<f64>,
+    /// Offsets collected during the initial phase (empty afterwards)
+    pub offsets: Vec<f64>,
+}
+
+/// Expected time until the clock is within a target accuracy of true time,
+/// extrapolated from the current uncertainty of the combined estimate of the
+/// sources, along with the assumptions the extrapolation is based on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ConvergenceEstimate {
+    /// Accuracy the estimate is for
+    pub target: NtpDuration,
+    /// Current bound on the error of the clock: the offset that is still to
+    /// be corrected plus the uncertainty of the estimate
+    pub current_error: NtpDuration,
+    /// Time until the error is expected to be within the target, zero when
+    /// it already is, and None when it is never expected to get there
+    pub time_to_target: Option<Duration>,
+    /// Uncertainty the estimate settles at, the best accuracy to expect
+    pub uncertainty_floor: NtpDuration,
+    /// Assumed interval at which the sources keep being polled
+    pub poll_interval: PollInterval,
+    /// Assumed noise (standard deviation) of the combination of a
+    /// measurement of each selected source
+    pub measurement_noise: NtpDuration,
+    /// Assumed clock wander relative to the selected sources (s/s^2)
+    pub wander: f64,
+    /// Assumed rate at which the remaining offset is slewed away (s/s)
+    pub slew_rate: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl Default for ObservableSourceTimedata {
+    fn default() -> Self {
+        Self {
+            offset: Default::default(),
+            uncertainty: Default::default(),
+            delay: Default::default(),
+            remote_delay: Default::default(),
+            remote_uncertainty: Default::default(),
+            remote_precision: None,
+            last_update: Default::default(),
+            weight: default_weight(),
+            selection: Default::default(),
+            falseticker_score: 0.0,
+            excluded_until: None,
+            no_step: false,
+        }
+    }
+}
+
+/// How the controller treats a source, as configured for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceOptions {
+    /// Influence of the source when combining the sources (positive, 1.0
+    /// being neutral)
+    pub weight: f64,
+    /// Trust the source to announce leap seconds under
+    /// [`LeapTrust::Trusted`](crate::LeapTrust)
+    pub trust_leap: bool,
+    /// Keep measuring the source, but never use it to determine the time
+    pub no_select: bool,
+    /// Never step the clock when only such sources are selected
+    pub no_step: bool,
+    /// Use our own measurements of the delay and uncertainty of the source
+    /// instead of the root delay and dispersion it advertises
+    pub ignore_root: bool,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            weight: default_weight(),
+            trust_leap: false,
+            no_select: false,
+            no_step: false,
+            ignore_root: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -62,10 +251,24 @@ pub trait TimeSyncController<C: NtpClock, SourceId: Hash + Eq + Copy + Debug>: S
         source_defaults_config: SourceDefaultsConfig,
         algorithm_config: Self::AlgorithmConfig,
     );
-    /// Notify the controller that there is a new source
-    fn add_source(&mut self, id: SourceId);
+    /// Notify the controller that there is a new source, to be treated
+    /// according to the given options.
+    fn add_source(&mut self, id: SourceId, options: SourceOptions);
     /// Notify the controller that a previous source has gone
     fn remove_source(&mut self, id: SourceId);
+    /// Synchronize to only the given source, bypassing the selection and
+    /// combination of sources. None returns to automatic selection.
+    fn pin_source(&mut self, id: Option<SourceId>);
+    /// Keep measuring a source, but never use it to determine the time.
+    fn set_no_select(&mut self, id: SourceId, no_select: bool);
+    /// Use a source as usual, but never step the clock when only such
+    /// sources are selected.
+    fn set_no_step(&mut self, id: SourceId, no_step: bool);
+    /// Don't trust the root delay and dispersion a source advertises, and
+    /// use our own measurements of its delay and uncertainty instead.
+    fn set_ignore_root(&mut self, id: SourceId, ignore_root: bool);
+    /// Discard all measurements of a source, so it starts afresh.
+    fn reset_source(&mut self, id: SourceId);
     /// Notify the controller that the status of a source (whether
     /// or not it is usable for synchronization) has changed.
     fn source_update(&mut self, id: SourceId, usable: bool);
@@ -79,11 +282,36 @@ pub trait TimeSyncController<C: NtpClock, SourceId: Hash + Eq + Copy + Debug>: S
     ) -> StateUpdate<SourceId>;
     /// Non-measurement driven update (queued via next_update)
     fn time_update(&mut self) -> StateUpdate<SourceId>;
+    /// Clear the running total of clock steps used for the accumulated
+    /// step panic threshold.
+    fn reset_accumulated_steps(&mut self);
+    /// Long-term frequency correction currently applied to the clock,
+    /// excluding any temporary adjustments used for slewing.
+    fn frequency(&self) -> f64;
+    /// Start from a previously determined frequency correction, for
+    /// example one persisted across a restart.
+    fn restore_frequency(&mut self, frequency: f64);
+    /// Announce the given leap indicator instead of those of the sources,
+    /// for example from a leap seconds file. None returns to the sources.
+    fn set_leap_indicator(&mut self, leap: Option<NtpLeapIndicator>);
     /// Get a snapshot of the timekeeping state of a source.
     fn source_snapshot(&self, id: SourceId) -> Option<ObservableSourceTimedata>;
+    /// Get the raw measurement buffers of the filter of a source.
+    fn source_filter(&self, id: SourceId) -> Option<ObservableSourceFilter>;
+    /// The two candidate sources whose offsets differed most in the last
+    /// selection round, and by how much.
+    fn divergence(&self) -> Option<(SourceId, SourceId, NtpDuration)>;
+    /// Expected time until the clock is within the target accuracy, as of
+    /// the last clock update, if there is a combined estimate to base it on.
+    fn convergence_estimate(&self, target: NtpDuration) -> Option<ConvergenceEstimate>;
 }
 
 mod kalman;
 
-pub use kalman::config::AlgorithmConfig;
-pub use kalman::KalmanClockController;
+pub use kalman::config::{AlgorithmConfig, SystemSourceTieBreak};
+#[cfg(feature = "__internal-test")]
+pub use kalman::simulation::{simulate, NetworkConditions, NetworkSimulator, SimulationStep};
+pub use kalman::{
+    ImportStateError, KalmanClockController, KalmanControllerState, SourceFilterState,
+    CONTROLLER_STATE_VERSION,
+};
@@ -37,3 +37,23 @@ pub trait NtpClock: Clone + Send + 'static {
     // the clocks synchronization status.
     fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error>;
 }
+
+/// Number of clock reads used to measure the precision of a clock
+const PRECISION_SAMPLES: usize = 1000;
+
+/// Measure the precision with which a clock can be read, as the smallest
+/// nonzero difference between consecutive reads. Returns None when all reads
+/// returned the same time, such as for a clock that is not advancing.
+pub fn measure_precision<C: NtpClock>(clock: &C) -> Result<Option<NtpDuration>, C::Error> {
+    let mut precision: Option<NtpDuration> = None;
+    let mut last = clock.now()?;
+    for _ in 0..PRECISION_SAMPLES {
+        let now = clock.now()?;
+        let delta = now - last;
+        if delta > NtpDuration::ZERO {
+            precision = Some(precision.map_or(delta, |precision| precision.min(delta)));
+        }
+        last = now;
+    }
+    Ok(precision)
+}
@@ -6,7 +6,8 @@ use serde::{
 };
 
 use crate::{
-    time_types::{NtpDuration, PollInterval, PollIntervalLimits},
+    identifiers::ReferenceId,
+    time_types::{EraPivot, NtpDuration, PollInterval, PollIntervalLimits},
     AlgorithmConfig,
 };
 
@@ -208,6 +209,42 @@ pub struct SourceDefaultsConfig {
     /// Initial poll interval of the system
     #[serde(default = "default_initial_poll_interval")]
     pub initial_poll_interval: PollInterval,
+
+    /// Discard the measurement history of a source when its stratum or
+    /// reference id changes, as its timebase may have shifted.
+    #[serde(default)]
+    pub reset_on_reference_change: bool,
+
+    /// Randomly delay each poll by 1 to 5 percent of the poll interval, to
+    /// make requests harder to predict.
+    #[serde(default = "default_randomize_poll")]
+    pub randomize_poll: bool,
+
+    /// Log the four timestamps of every completed exchange with a source,
+    /// together with the offset and delay computed from them.
+    #[serde(default)]
+    pub log_exchanges: bool,
+
+    /// Poll interval used while a source is unreachable, so that we notice
+    /// quickly when it comes back. Never below the minimum poll interval.
+    #[serde(default)]
+    pub recovery_poll: Option<PollInterval>,
+
+    /// Number of times to quickly repeat the first poll of a source while
+    /// it has not answered yet, so a lost first request doesn't delay the
+    /// first measurement by a full poll interval.
+    #[serde(default)]
+    pub initial_poll_retries: u8,
+
+    /// Seconds between the quick repetitions of the first poll
+    #[serde(default = "default_initial_poll_retry_interval")]
+    pub initial_poll_retry_interval: u64,
+
+    /// Number of recent requests whose responses are still accepted. When a
+    /// response arrives after the next poll was already sent, it is matched
+    /// against the earlier request instead of being rejected.
+    #[serde(default = "default_max_outstanding_requests")]
+    pub max_outstanding_requests: usize,
 }
 
 impl Default for SourceDefaultsConfig {
@@ -215,10 +252,29 @@ impl Default for SourceDefaultsConfig {
         Self {
             poll_interval_limits: Default::default(),
             initial_poll_interval: default_initial_poll_interval(),
+            reset_on_reference_change: false,
+            randomize_poll: default_randomize_poll(),
+            log_exchanges: false,
+            recovery_poll: None,
+            initial_poll_retries: 0,
+            initial_poll_retry_interval: default_initial_poll_retry_interval(),
+            max_outstanding_requests: default_max_outstanding_requests(),
         }
     }
 }
 
+fn default_initial_poll_retry_interval() -> u64 {
+    2
+}
+
+fn default_max_outstanding_requests() -> usize {
+    4
+}
+
+fn default_randomize_poll() -> bool {
+    true
+}
+
 fn default_initial_poll_interval() -> PollInterval {
     PollIntervalLimits::default().min
 }
@@ -237,6 +293,32 @@ pub struct SynchronizationConfig {
     #[serde(default = "default_minimum_agreeing_sources")]
     pub minimum_agreeing_sources: usize,
 
+    /// Number of measurements a source needs before it can be selected, so
+    /// a source that just came up can't dominate the selection with a single
+    /// measurement. Sources that completed their initial phase of 8
+    /// measurements always have enough.
+    #[serde(default = "default_minimum_samples")]
+    pub minimum_samples: u32,
+
+    /// Number of measurements the source whose measurement led to a clock
+    /// update needs before that update may step the clock. Until then, a
+    /// large offset is treated as a spike and not corrected. The step at
+    /// startup is not affected. Zero disables this safeguard.
+    #[serde(default)]
+    pub min_polls_before_step: u32,
+
+    /// Coarsest precision (log2 seconds) a source may advertise to be
+    /// selected. A source with a coarse clock is not worth synchronizing to
+    /// when better sources exist. None accepts any precision.
+    #[serde(default)]
+    pub min_server_precision: Option<i8>,
+
+    /// Whether to measure the precision of the local clock at startup. The
+    /// measured precision is advertised to clients and added to the
+    /// uncertainty of every source, and so to the root dispersion we serve.
+    #[serde(default = "default_measure_precision")]
+    pub measure_precision: bool,
+
     /// The maximum amount the system clock is allowed to change in a single go
     /// before we conclude something is seriously wrong. This is used to limit
     /// the changes to the clock to reasonable ammounts, and stop issues with
@@ -253,6 +335,12 @@ pub struct SynchronizationConfig {
     #[serde(default = "default_startup_step_panic_threshold")]
     pub startup_step_panic_threshold: StepThreshold,
 
+    /// Largest offset at startup for which we consider the real time clock
+    /// the system time was initialized from to be correct, and slew instead
+    /// of stepping. Without a bound we step any offset at startup.
+    #[serde(default)]
+    pub rtc_trusted_offset: Option<NtpDuration>,
+
     /// The maximum amount distributed amongst all steps except at startup the
     /// daemon is allowed to step the system clock.
     #[serde(
@@ -261,12 +349,101 @@ pub struct SynchronizationConfig {
     )]
     pub accumulated_step_panic_threshold: Option<NtpDuration>,
 
+    /// Whether to use the startup step threshold again once after the system
+    /// resumed from suspend, as the clock may have drifted far while
+    /// suspended.
+    #[serde(default = "default_resume_grace")]
+    pub resume_grace: bool,
+
     /// Stratum of the local clock, when not synchronized through ntp. This
     /// can be used in servers to indicate that there are external mechanisms
     /// synchronizing the clock
     #[serde(default = "default_local_stratum")]
     pub local_stratum: u8,
 
+    /// Reference id served to clients when the local stratum is 1, naming
+    /// the kind of reference clock that synchronizes the local clock.
+    #[serde(default)]
+    pub reference_id: ReferenceIdConfig,
+
+    /// Stratum served when no sources below this stratum are available. The
+    /// daemons of an isolated network then elect the one with the lowest
+    /// reference id as leader, and the others synchronize to it.
+    #[serde(default)]
+    pub orphan_stratum: Option<u8>,
+
+    /// How long no sources below the orphan stratum must be available, at
+    /// startup or after losing the last one, before this instance serves
+    /// time as orphan leader. Gives sources a chance to become reachable
+    /// before an unsynchronized clock is served to the isolated network.
+    #[serde(default = "default_orphan_wait")]
+    pub orphan_wait: NtpDuration,
+
+    /// Stratum at which the local clock is served when no sources are
+    /// available, as a last resort for isolated networks. Without a stratum
+    /// the local clock is never used as fallback.
+    #[serde(default)]
+    pub local_clock_fallback: Option<u8>,
+
+    /// Number of consecutive selections a better stratum must persist before
+    /// it is served, so the served stratum doesn't flap when selection
+    /// alternates between sources. A worse stratum is served immediately.
+    #[serde(default)]
+    pub stratum_hysteresis: u32,
+
+    /// How long to keep serving time after all sources were lost, in
+    /// seconds. Meanwhile the advertised root dispersion grows. Without a
+    /// duration we keep serving time indefinitely.
+    #[serde(default)]
+    pub holdover_duration: Option<NtpDuration>,
+
+    /// System jitter, in seconds, below which the clock counts as locked
+    /// once it stayed below it for the lock duration. Without a threshold
+    /// the clock is never reported as locked.
+    #[serde(default)]
+    pub lock_jitter_threshold: Option<NtpDuration>,
+
+    /// Difference in seconds between the offsets of two sources above which
+    /// an alarm is raised, as early warning of an attack or upstream
+    /// problem. Without a threshold no alarm is raised.
+    #[serde(default)]
+    pub divergence_alarm_threshold: Option<NtpDuration>,
+
+    /// How long, in seconds, the system jitter must stay below the lock
+    /// threshold before the clock counts as locked
+    #[serde(default = "default_lock_duration")]
+    pub lock_duration: NtpDuration,
+
+    /// Multiple of the lock threshold the system jitter must exceed for the
+    /// lock to be lost, so the lock doesn't flap around the threshold
+    #[serde(default = "default_lock_hysteresis")]
+    pub lock_hysteresis: f64,
+
+    /// Accuracy in seconds for which the status reports how long the clock
+    /// is expected to take to get within it
+    #[serde(default = "default_convergence_target")]
+    pub convergence_target: NtpDuration,
+
+    /// Constant added to every measured offset, compensating a known fixed
+    /// delay such as that of an antenna cable. A positive value moves the
+    /// local clock ahead of the measured time.
+    #[serde(default)]
+    pub clock_offset_correction: NtpDuration,
+
+    /// Trade-off between accuracy and power usage made when choosing the
+    /// poll interval
+    #[serde(default)]
+    pub power_profile: PowerProfile,
+
+    /// Sources whose leap second announcements are taken into account
+    #[serde(default)]
+    pub leap_trust: LeapTrust,
+
+    /// UNIX time around which timestamps are interpreted, deciding whether a
+    /// timestamp lies before or after the NTP era rollover in 2036
+    #[serde(default)]
+    pub era_pivot: EraPivot,
+
     #[serde(default)]
     pub algorithm: AlgorithmConfig,
 }
@@ -275,12 +452,33 @@ impl Default for SynchronizationConfig {
     fn default() -> Self {
         Self {
             minimum_agreeing_sources: default_minimum_agreeing_sources(),
+            minimum_samples: default_minimum_samples(),
+            min_polls_before_step: 0,
+            min_server_precision: None,
+            measure_precision: default_measure_precision(),
 
             single_step_panic_threshold: default_single_step_panic_threshold(),
             startup_step_panic_threshold: default_startup_step_panic_threshold(),
+            rtc_trusted_offset: None,
             accumulated_step_panic_threshold: None,
+            resume_grace: default_resume_grace(),
 
             local_stratum: default_local_stratum(),
+            reference_id: Default::default(),
+            orphan_stratum: None,
+            orphan_wait: default_orphan_wait(),
+            local_clock_fallback: None,
+            stratum_hysteresis: 0,
+            holdover_duration: None,
+            lock_jitter_threshold: None,
+            divergence_alarm_threshold: None,
+            lock_duration: default_lock_duration(),
+            lock_hysteresis: default_lock_hysteresis(),
+            convergence_target: default_convergence_target(),
+            clock_offset_correction: NtpDuration::ZERO,
+            power_profile: Default::default(),
+            leap_trust: Default::default(),
+            era_pivot: Default::default(),
             algorithm: Default::default(),
         }
     }
@@ -290,6 +488,34 @@ fn default_minimum_agreeing_sources() -> usize {
     3
 }
 
+fn default_minimum_samples() -> u32 {
+    1
+}
+
+fn default_orphan_wait() -> NtpDuration {
+    NtpDuration::from_seconds(300.0)
+}
+
+fn default_lock_duration() -> NtpDuration {
+    NtpDuration::from_seconds(300.0)
+}
+
+fn default_lock_hysteresis() -> f64 {
+    2.0
+}
+
+fn default_convergence_target() -> NtpDuration {
+    NtpDuration::from_seconds(1e-3)
+}
+
+fn default_resume_grace() -> bool {
+    true
+}
+
+fn default_measure_precision() -> bool {
+    true
+}
+
 fn default_single_step_panic_threshold() -> StepThreshold {
     let raw = NtpDuration::from_seconds(1000.);
     StepThreshold {
@@ -309,3 +535,124 @@ fn default_startup_step_panic_threshold() -> StepThreshold {
 fn default_local_stratum() -> u8 {
     16
 }
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerProfile {
+    /// Poll as often as needed for the best achievable accuracy
+    #[default]
+    Accuracy,
+    /// Once synchronized, poll only rarely and accept the reduced accuracy
+    LowPower,
+}
+
+/// Policy deciding which of the selected sources get a vote on upcoming
+/// leap seconds. Some servers never announce leap seconds, so a majority of
+/// them could otherwise outvote a source that does.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LeapTrust {
+    /// All selected sources
+    #[default]
+    All,
+    /// Only sources at or below the given stratum
+    MaxStratum(u8),
+    /// Only sources explicitly marked as trusted for leap seconds
+    Trusted,
+}
+
+/// A reference clock code, such as `GPS` or `PPS`, of at most four ASCII
+/// characters. As specified in RFC 5905, shorter codes are padded with zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceIdConfig {
+    id: [u8; 4],
+}
+
+impl ReferenceIdConfig {
+    pub fn to_reference_id(self) -> ReferenceId {
+        ReferenceId::from_bytes(self.id)
+    }
+}
+
+impl Default for ReferenceIdConfig {
+    fn default() -> Self {
+        Self {
+            id: ReferenceId::NONE.to_bytes(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReferenceIdConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = String::deserialize(deserializer)?;
+        if code.is_empty() || code.len() > 4 || !code.is_ascii() {
+            return Err(de::Error::invalid_value(
+                Unexpected::Str(&code),
+                &"a code of one to four ASCII characters",
+            ));
+        }
+
+        let mut id = [0; 4];
+        id[..code.len()].copy_from_slice(code.as_bytes());
+        Ok(Self { id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_test::{assert_de_tokens, assert_de_tokens_error, Token};
+
+    use super::*;
+
+    #[test]
+    fn test_leap_trust() {
+        assert_eq!(LeapTrust::default(), LeapTrust::All);
+
+        assert_de_tokens(
+            &LeapTrust::All,
+            &[Token::UnitVariant {
+                name: "LeapTrust",
+                variant: "all",
+            }],
+        );
+        assert_de_tokens(
+            &LeapTrust::Trusted,
+            &[Token::UnitVariant {
+                name: "LeapTrust",
+                variant: "trusted",
+            }],
+        );
+        assert_de_tokens(
+            &LeapTrust::MaxStratum(1),
+            &[
+                Token::NewtypeVariant {
+                    name: "LeapTrust",
+                    variant: "max-stratum",
+                },
+                Token::U8(1),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_reference_id_config() {
+        assert_eq!(
+            ReferenceIdConfig::default().to_reference_id(),
+            ReferenceId::NONE
+        );
+
+        assert_de_tokens(&ReferenceIdConfig { id: *b"GPS\0" }, &[Token::Str("GPS")]);
+        assert_de_tokens(&ReferenceIdConfig { id: *b"PPS1" }, &[Token::Str("PPS1")]);
+        assert_de_tokens_error::<ReferenceIdConfig>(
+            &[Token::Str("ATOMIC")],
+            "invalid value: string \"ATOMIC\", expected a code of one to four ASCII characters",
+        );
+        assert_de_tokens_error::<ReferenceIdConfig>(
+            &[Token::Str("")],
+            "invalid value: string \"\", expected a code of one to four ASCII characters",
+        );
+    }
+}
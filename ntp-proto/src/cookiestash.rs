@@ -55,7 +55,7 @@ impl CookieStash {
         (self.cookies.len() - self.valid) as u8
     }
 
-    #[cfg(test)]
+    /// Number of valid cookies in the stash
     pub fn len(&self) -> usize {
         self.valid
     }
@@ -0,0 +1,357 @@
+//! Parsing of the `leap-seconds.list` file published by NIST and the IERS,
+//! which lists all leap seconds together with the date until which the list
+//! is known to be complete.
+//!
+//! The file consists of lines with the time (in seconds since the NTP epoch)
+//! from which a new offset of TAI from UTC applies, and the offset itself.
+//! Special comment lines carry the time of the last update (`#$`), the
+//! expiry time (`#@`) and a SHA-1 hash of the data (`#h`).
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{NtpDuration, NtpLeapIndicator, NtpTimestamp};
+
+/// How long before a leap second it is announced. The kernel applies a leap
+/// second at the end of the day on which it is armed, so this may not exceed
+/// a day. (seconds)
+const LEAP_ANNOUNCE_WINDOW: f64 = 86400.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeapSecondsError {
+    /// A line could not be parsed, with its (1-based) line number
+    Malformed(usize),
+    /// The leap seconds are not in order, or change the offset by more than
+    /// one second at a time
+    Inconsistent,
+    MissingExpiry,
+    MissingHash,
+    InvalidHash,
+}
+
+impl Display for LeapSecondsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(line) => write!(f, "Malformed line {line}"),
+            Self::Inconsistent => f.write_str("Inconsistent leap seconds"),
+            Self::MissingExpiry => f.write_str("Missing expiry time"),
+            Self::MissingHash => f.write_str("Missing hash"),
+            Self::InvalidHash => f.write_str("Hash does not match the data"),
+        }
+    }
+}
+
+impl std::error::Error for LeapSecondsError {}
+
+/// A leap second that is yet to happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeapSecond {
+    /// Start of the day after the leap second
+    pub time: NtpTimestamp,
+    /// Whether a second is inserted or deleted
+    pub indicator: NtpLeapIndicator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeapSecondsFile {
+    last_update: NtpTimestamp,
+    expires: NtpTimestamp,
+    /// Times from which a new offset of TAI from UTC applies, with that
+    /// offset. The first entry is the initial offset, not a leap second.
+    offsets: Vec<(NtpTimestamp, i32)>,
+}
+
+fn parse_timestamp(value: &str) -> Option<NtpTimestamp> {
+    // times beyond 2036 continue in the next era
+    let seconds: u64 = value.parse().ok()?;
+    Some(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+        seconds as u32,
+        0,
+    ))
+}
+
+fn parse_hash(value: &str) -> Option<[u8; 20]> {
+    let mut hash = [0; 20];
+    let mut words = value.split_whitespace();
+    for chunk in hash.chunks_exact_mut(4) {
+        let word = u32::from_str_radix(words.next()?, 16).ok()?;
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    words.next().is_none().then_some(hash)
+}
+
+impl LeapSecondsFile {
+    /// Parse the contents of a leap seconds file, and verify its hash
+    pub fn parse(data: &str) -> Result<Self, LeapSecondsError> {
+        let mut hasher = Sha1::new();
+        let mut last_update = None;
+        let mut expires = None;
+        let mut hash = None;
+        let mut offsets = vec![];
+
+        for (index, line) in data.lines().enumerate() {
+            let malformed = LeapSecondsError::Malformed(index + 1);
+
+            if let Some(value) = line.strip_prefix("#$") {
+                let value = value.trim();
+                last_update = Some(parse_timestamp(value).ok_or(malformed)?);
+                hasher.update(value);
+            } else if let Some(value) = line.strip_prefix("#@") {
+                let value = value.trim();
+                expires = Some(parse_timestamp(value).ok_or(malformed)?);
+                hasher.update(value);
+            } else if let Some(value) = line.strip_prefix("#h") {
+                hash = Some(parse_hash(value).ok_or(malformed)?);
+            } else if !line.starts_with('#') && !line.trim().is_empty() {
+                let data = line.split('#').next().unwrap_or_default();
+                let mut fields = data.split_whitespace();
+                let (Some(time), Some(offset), None) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(malformed);
+                };
+                let offset_value: i32 = offset.parse().map_err(|_| malformed)?;
+                offsets.push((parse_timestamp(time).ok_or(malformed)?, offset_value));
+                hasher.update(time);
+                hasher.update(offset);
+            }
+        }
+
+        let consistent = offsets.windows(2).all(|pair| {
+            let ((previous_time, previous_offset), (time, offset)) = (pair[0], pair[1]);
+            time - previous_time > NtpDuration::ZERO && (offset - previous_offset).abs() == 1
+        });
+        if !consistent {
+            return Err(LeapSecondsError::Inconsistent);
+        }
+
+        let expires = expires.ok_or(LeapSecondsError::MissingExpiry)?;
+        let hash = hash.ok_or(LeapSecondsError::MissingHash)?;
+        if hasher.finalize()[..] != hash {
+            return Err(LeapSecondsError::InvalidHash);
+        }
+
+        Ok(LeapSecondsFile {
+            last_update: last_update.unwrap_or(expires),
+            expires,
+            offsets,
+        })
+    }
+
+    /// Time of the last update of the list
+    pub fn last_update(&self) -> NtpTimestamp {
+        self.last_update
+    }
+
+    /// Time until which the list is known to contain all leap seconds
+    pub fn expires(&self) -> NtpTimestamp {
+        self.expires
+    }
+
+    pub fn is_expired(&self, now: NtpTimestamp) -> bool {
+        now - self.expires >= NtpDuration::ZERO
+    }
+
+    /// The first leap second after `now`, if the list contains one
+    pub fn upcoming_leap(&self, now: NtpTimestamp) -> Option<LeapSecond> {
+        // Search from the end, so that long past entries, which may appear
+        // to be in the future once the NTP era wraps, are never considered.
+        let index = self
+            .offsets
+            .iter()
+            .rposition(|(time, _)| *time - now <= NtpDuration::ZERO)
+            .map_or(1, |index| index + 1);
+        let (time, offset) = *self.offsets.get(index)?;
+        let (_, previous_offset) = self.offsets[index - 1];
+
+        Some(LeapSecond {
+            time,
+            indicator: if offset > previous_offset {
+                NtpLeapIndicator::Leap61
+            } else {
+                NtpLeapIndicator::Leap59
+            },
+        })
+    }
+
+    /// Leap indicator to announce at `now`, warning of a leap second during
+    /// the last day before it.
+    pub fn leap_indicator(&self, now: NtpTimestamp) -> NtpLeapIndicator {
+        match self.upcoming_leap(now) {
+            Some(leap) if leap.time - now <= NtpDuration::from_seconds(LEAP_ANNOUNCE_WINDOW) => {
+                leap.indicator
+            }
+            _ => NtpLeapIndicator::NoWarning,
+        }
+    }
+
+    /// Time from `now` until the leap indicator changes or the list expires
+    pub fn next_change(&self, now: NtpTimestamp) -> Option<NtpDuration> {
+        let leap = self.upcoming_leap(now).map(|leap| leap.time);
+        [
+            Some(self.expires),
+            leap.map(|time| time - NtpDuration::from_seconds(LEAP_ANNOUNCE_WINDOW)),
+            leap,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|time| time - now)
+        .filter(|duration| *duration > NtpDuration::ZERO)
+        .min()
+    }
+}
+
+/// Append the hash line to the data of a leap seconds file
+#[cfg(test)]
+pub(crate) fn with_hash(data: &str) -> String {
+    let mut hasher = Sha1::new();
+    for line in data.lines() {
+        let line = line.trim_start_matches("#$").trim_start_matches("#@");
+        line.split_whitespace()
+            .for_each(|field| hasher.update(field));
+    }
+    let words: Vec<_> = hasher
+        .finalize()
+        .chunks_exact(4)
+        .map(|word| format!("{:x}", u32::from_be_bytes(word.try_into().unwrap())))
+        .collect();
+    format!("{data}#h {}\n", words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEAP_SECONDS_LIST: &str = "\
+#	Updated through IERS Bulletin C (https://hpiers.obspm.fr/iers/bul/bulc/bulletinc.dat)
+#
+#$	 3960835200
+#@	3991593600
+#
+2272060800	10	# 1 Jan 1972
+2287785600	11	# 1 Jul 1972
+2303683200	12	# 1 Jan 1973
+2335219200	13	# 1 Jan 1974
+2366755200	14	# 1 Jan 1975
+2398291200	15	# 1 Jan 1976
+2429913600	16	# 1 Jan 1977
+2461449600	17	# 1 Jan 1978
+2492985600	18	# 1 Jan 1979
+2524521600	19	# 1 Jan 1980
+2571782400	20	# 1 Jul 1981
+2603318400	21	# 1 Jul 1982
+2634854400	22	# 1 Jul 1983
+2698012800	23	# 1 Jul 1985
+2776982400	24	# 1 Jan 1988
+2840140800	25	# 1 Jan 1990
+2871676800	26	# 1 Jan 1991
+2918937600	27	# 1 Jul 1992
+2950473600	28	# 1 Jul 1993
+2982009600	29	# 1 Jul 1994
+3029443200	30	# 1 Jan 1996
+3076704000	31	# 1 Jul 1997
+3124137600	32	# 1 Jan 1999
+3345062400	33	# 1 Jan 2006
+3439756800	34	# 1 Jan 2009
+3550089600	35	# 1 Jul 2012
+3644697600	36	# 1 Jul 2015
+3692217600	37	# 1 Jan 2017
+#
+#h	49db2447 571e5e1b 2f002a53 9c8da8e4 39b8e49e
+";
+
+    fn timestamp(seconds: u32) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds, 0)
+    }
+
+    #[test]
+    fn parses_leap_seconds_list() {
+        let file = LeapSecondsFile::parse(LEAP_SECONDS_LIST).unwrap();
+        assert_eq!(file.last_update(), timestamp(3960835200));
+        assert_eq!(file.expires(), timestamp(3991593600));
+        assert_eq!(file.offsets.len(), 28);
+
+        assert!(!file.is_expired(timestamp(3991593599)));
+        assert!(file.is_expired(timestamp(3991593600)));
+    }
+
+    #[test]
+    fn rejects_modified_data() {
+        let modified = LEAP_SECONDS_LIST.replace("3692217600\t37", "3692217601\t37");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::InvalidHash)
+        );
+
+        let modified = LEAP_SECONDS_LIST.replace("3692217600\t37", "3692217600\t38");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::Inconsistent)
+        );
+
+        let modified = LEAP_SECONDS_LIST.replace("#h\t49db2447", "#h\t49db2448");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::InvalidHash)
+        );
+
+        let modified = LEAP_SECONDS_LIST.replace("#h", "#");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::MissingHash)
+        );
+
+        let modified = LEAP_SECONDS_LIST.replace("#@", "#");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::MissingExpiry)
+        );
+
+        let modified = LEAP_SECONDS_LIST.replace("3692217600\t37", "3692217600");
+        assert_eq!(
+            LeapSecondsFile::parse(&modified),
+            Err(LeapSecondsError::Malformed(33))
+        );
+    }
+
+    #[test]
+    fn announces_upcoming_leap() {
+        let file = LeapSecondsFile::parse(LEAP_SECONDS_LIST).unwrap();
+        let leap = timestamp(3692217600);
+        let day = NtpDuration::from_seconds(86400.0);
+
+        let now = leap - day - day;
+        assert_eq!(
+            file.upcoming_leap(now),
+            Some(LeapSecond {
+                time: leap,
+                indicator: NtpLeapIndicator::Leap61
+            })
+        );
+        assert_eq!(file.leap_indicator(now), NtpLeapIndicator::NoWarning);
+        assert_eq!(file.next_change(now), Some(day));
+
+        let now = leap - NtpDuration::from_seconds(1.0);
+        assert_eq!(file.leap_indicator(now), NtpLeapIndicator::Leap61);
+        assert_eq!(file.next_change(now), Some(NtpDuration::from_seconds(1.0)));
+
+        // no leap seconds after the last one in the list
+        assert_eq!(file.upcoming_leap(leap), None);
+        assert_eq!(file.leap_indicator(leap), NtpLeapIndicator::NoWarning);
+        assert_eq!(file.next_change(leap), Some(file.expires() - leap));
+        assert_eq!(file.next_change(file.expires()), None);
+    }
+
+    #[test]
+    fn announces_negative_leap() {
+        let data = with_hash("#@ 3991593600\n3644697600 36\n3692217600 35\n");
+        let file = LeapSecondsFile::parse(&data).unwrap();
+        let leap = timestamp(3692217600);
+        assert_eq!(
+            file.leap_indicator(leap - NtpDuration::from_seconds(60.0)),
+            NtpLeapIndicator::Leap59
+        );
+    }
+}
@@ -16,6 +16,8 @@ mod identifiers;
 mod io;
 mod ipfilter;
 mod keyset;
+mod leap_seconds;
+mod nmea;
 mod nts_record;
 mod packet;
 mod server;
@@ -37,16 +39,24 @@ pub(crate) mod exitcode {
 }
 
 mod exports {
+    #[cfg(feature = "__internal-test")]
+    pub use super::algorithm::{simulate, NetworkConditions, NetworkSimulator, SimulationStep};
     pub use super::algorithm::{
-        AlgorithmConfig, KalmanClockController, ObservableSourceTimedata, StateUpdate,
-        TimeSyncController,
+        AlgorithmConfig, ConvergenceEstimate, ImportStateError, KalmanClockController,
+        KalmanControllerState, ObservableSourceFilter, ObservableSourceTimedata, SelectionStatus,
+        SourceFilterState, SourceOptions, StateUpdate, SystemSourceChoice, SystemSourceTieBreak,
+        TimeSyncController, CONTROLLER_STATE_VERSION,
+    };
+    pub use super::clock::{measure_precision, NtpClock};
+    pub use super::config::{
+        LeapTrust, PowerProfile, SourceDefaultsConfig, StepThreshold, SynchronizationConfig,
     };
-    pub use super::clock::NtpClock;
-    pub use super::config::{SourceDefaultsConfig, StepThreshold, SynchronizationConfig};
     pub use super::identifiers::ReferenceId;
     #[cfg(feature = "__internal-fuzz")]
     pub use super::ipfilter::fuzz::fuzz_ipfilter;
     pub use super::keyset::{DecodedServerCookie, KeySet, KeySetProvider};
+    pub use super::leap_seconds::{LeapSecond, LeapSecondsError, LeapSecondsFile};
+    pub use super::nmea::NmeaSource;
 
     #[cfg(feature = "__internal-fuzz")]
     pub use super::keyset::test_cookie;
@@ -58,7 +68,7 @@ mod exports {
     };
     pub use super::server::{
         FilterAction, FilterList, IpSubnet, Server, ServerAction, ServerConfig, ServerReason,
-        ServerResponse, ServerStatHandler, SubnetParseError,
+        ServerResponse, ServerStatHandler, SubnetParseError, UnsynchronizedAction,
     };
     #[cfg(feature = "__internal-fuzz")]
     pub use super::source::fuzz_measurement_from_packet;
@@ -66,13 +76,15 @@ mod exports {
     pub use super::source::{source_snapshot, Measurement};
     pub use super::source::{
         AcceptSynchronizationError, NtpSource, NtpSourceAction, NtpSourceActionIterator,
-        NtpSourceSnapshot, NtpSourceUpdate, ProtocolVersion, Reach, SourceNtsData,
+        NtpSourceDebugDump, NtpSourceSnapshot, NtpSourceUpdate, PacketCounters, ProtocolVersion,
+        Reach, ReferenceChange, SourceNtsData,
     };
-    pub use super::system::{System, SystemSnapshot, TimeSnapshot};
+    pub use super::system::{DivergenceAlarm, HoldoverState, System, SystemSnapshot, TimeSnapshot};
     #[cfg(feature = "__internal-fuzz")]
     pub use super::time_types::fuzz_duration_from_seconds;
     pub use super::time_types::{
-        FrequencyTolerance, NtpDuration, NtpInstant, NtpTimestamp, PollInterval, PollIntervalLimits,
+        EraPivot, FrequencyTolerance, NtpDuration, NtpInstant, NtpTimestamp, PollInterval,
+        PollIntervalLimits,
     };
 
     #[cfg(feature = "__internal-fuzz")]
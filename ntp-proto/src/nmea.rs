@@ -0,0 +1,637 @@
+//! Use of a GPS receiver as source, through the time in the NMEA 0183
+//! sentences it sends over a serial line.
+//!
+//! The time in these sentences is only accurate to within a few hundred
+//! milliseconds of the start of the second, and the sentences arrive some
+//! device specific time after it. This makes for a coarse reference clock,
+//! that at least lets a machine without network keep the right time.
+
+use std::{
+    fmt::{Display, Formatter},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use tracing::debug;
+
+use crate::{
+    identifiers::ReferenceId,
+    packet::NtpLeapIndicator,
+    source::{
+        Measurement, NtpSourceSnapshot, NtpSourceUpdate, PacketCounters, ProtocolVersion, Reach,
+    },
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
+};
+
+/// Sentences are at most 82 characters including the line ending, anything
+/// longer is garbage
+const MAX_SENTENCE_LENGTH: usize = 82;
+
+/// Seconds from the NTP epoch (1900) to the unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// Identifies a GPS receiver, both as source and as the reference we serve
+/// when synchronized to it
+const GPS_REFERENCE_ID: ReferenceId = ReferenceId::from_int(u32::from_be_bytes(*b"GPS\0"));
+
+/// Precision of the time in the sentences (log2 seconds, about 8ms)
+const NMEA_PRECISION: i8 = -7;
+
+/// Smallest delay in seconds reported for a measurement, such that no
+/// sentence is considered exact
+const NMEA_MIN_DELAY: f64 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmeaError {
+    /// The line does not start with `$` or lacks the `*` before the checksum
+    MissingDelimiter,
+    InvalidChecksum,
+    TooLong,
+    Malformed,
+}
+
+impl Display for NmeaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingDelimiter => f.write_str("Missing sentence delimiter"),
+            Self::InvalidChecksum => f.write_str("Invalid checksum"),
+            Self::TooLong => f.write_str("Sentence too long"),
+            Self::Malformed => f.write_str("Malformed sentence"),
+        }
+    }
+}
+
+impl std::error::Error for NmeaError {}
+
+/// What a sentence tells about the time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmeaData {
+    /// Time from a sentence that does not report the status of the receiver
+    /// (`ZDA`)
+    Time(NtpTimestamp),
+    /// Time from a receiver that reports having a fix (`RMC`)
+    FixedTime(NtpTimestamp),
+    /// The receiver reports it has no fix, so its time may be off (`RMC`)
+    NoFix,
+}
+
+/// A GPS receiver read over a serial line, as a source of the time.
+///
+/// The sentences of a second arrive a device specific time after the start
+/// of that second, which the configured offset compensates. There is no
+/// round trip to measure a delay from, so the change in that latency since
+/// the previous second is reported as delay instead, which lets the filter
+/// estimate the jitter of the device.
+///
+/// The source reports stratum 0, as a reference clock, so that we serve
+/// stratum 1 when synchronized to it. Stratum 0 otherwise only occurs in
+/// kiss-o'-death packets, which never produce measurements or snapshots, so
+/// selection can treat it as any other stratum.
+///
+/// The sentences announce no leap seconds. The leap state is derived from
+/// the status of the fix instead: once the receiver reports it lost its fix,
+/// its time is unsynchronized until it reports a fix again. Receivers that
+/// don't report their status (only sending `ZDA`) are assumed to have a fix.
+#[derive(Debug)]
+pub struct NmeaSource {
+    parser: NmeaParser,
+    offset: NtpDuration,
+    reach: Reach,
+    /// Whether the receiver has a fix, according to its last status report
+    fix: Option<bool>,
+    /// Number of invalid sentences received
+    invalid_sentences: u64,
+    /// Time of the last second used, and how much later its sentence arrived
+    /// according to the local clock
+    last: Option<(NtpTimestamp, NtpDuration)>,
+}
+
+impl NmeaSource {
+    /// A receiver whose sentences arrive `offset` after the start of the
+    /// second they describe
+    pub fn new(offset: NtpDuration) -> Self {
+        Self {
+            parser: NmeaParser::new(),
+            offset,
+            reach: Reach::default(),
+            fix: None,
+            invalid_sentences: 0,
+            last: None,
+        }
+    }
+
+    /// Process data read from the device, of which the read completed at
+    /// `localtime`. Only the newest time in the data is used, and only once,
+    /// as older sentences or later sentences of the same second were waiting
+    /// longer. Returns an update when the data completed a new second.
+    pub fn handle_data(
+        &mut self,
+        data: &[u8],
+        localtime: NtpTimestamp,
+        monotime: NtpInstant,
+    ) -> Option<NtpSourceUpdate> {
+        let mut newest: Option<NtpTimestamp> = None;
+        for result in self.parser.feed(data) {
+            let time = match result {
+                Ok(NmeaData::Time(time)) => time,
+                Ok(NmeaData::FixedTime(time)) => {
+                    self.fix = Some(true);
+                    time
+                }
+                Ok(NmeaData::NoFix) => {
+                    self.fix = Some(false);
+                    continue;
+                }
+                Err(error) => {
+                    debug!(%error, "Ignoring invalid NMEA sentence");
+                    self.invalid_sentences += 1;
+                    continue;
+                }
+            };
+            if newest.map_or(true, |newest| newest.is_before(time)) {
+                newest = Some(time);
+            }
+        }
+
+        let time = newest?;
+        if self.last.map_or(false, |(last, _)| !last.is_before(time)) {
+            return None;
+        }
+
+        let latency = localtime - time;
+        let min_delay = NtpDuration::from_seconds(NMEA_MIN_DELAY);
+        let delay = match self.last {
+            Some((_, last_latency)) => latency.abs_diff(last_latency).max(min_delay),
+            None => min_delay,
+        };
+        self.last = Some((time, latency));
+        self.reach.poll();
+        self.reach.received_packet();
+
+        let remote = time + self.offset;
+        let measurement = Measurement {
+            delay,
+            offset: remote - localtime,
+            transmit_timestamp: remote,
+            receive_timestamp: remote,
+            localtime,
+            monotime,
+
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: self.leap_indicator(),
+            precision: NMEA_PRECISION,
+        };
+
+        Some(NtpSourceUpdate {
+            snapshot: self.snapshot(),
+            measurement: Some(measurement),
+            reset: false,
+        })
+    }
+
+    fn leap_indicator(&self) -> NtpLeapIndicator {
+        match self.fix {
+            Some(false) => NtpLeapIndicator::Unknown,
+            _ => NtpLeapIndicator::NoWarning,
+        }
+    }
+
+    /// No time was received for a while. The partial sentence is dropped, as
+    /// its rest can no longer be trusted to belong to the current second, and
+    /// the receiver becomes unreachable when this persists.
+    pub fn handle_timeout(&mut self) -> NtpSourceUpdate {
+        self.parser.reset();
+        self.reach.poll();
+
+        NtpSourceUpdate {
+            snapshot: self.snapshot(),
+            measurement: None,
+            reset: false,
+        }
+    }
+
+    pub fn snapshot(&self) -> NtpSourceSnapshot {
+        NtpSourceSnapshot {
+            source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            source_id: GPS_REFERENCE_ID,
+            poll_interval: PollInterval::from_byte(0),
+            remote_min_poll_interval: PollInterval::from_byte(0),
+            reach: self.reach,
+            reachability_pct_1h: None,
+            reachability_pct_24h: None,
+            stratum: 0,
+            reference_id: GPS_REFERENCE_ID,
+            last_reference_change: None,
+            protocol_version: ProtocolVersion::default(),
+            bogus_packets: self.invalid_sentences,
+            duplicate_packets: 0,
+            packets: PacketCounters::default(),
+            incompatible: false,
+            send_timestamped: false,
+            #[cfg(feature = "ntpv5")]
+            bloom_filter: None,
+        }
+    }
+}
+
+/// Collects the data read from a serial device into sentences, and extracts
+/// the time from `RMC` and `ZDA` sentences of any talker.
+#[derive(Debug, Default)]
+pub struct NmeaParser {
+    partial: Vec<u8>,
+}
+
+impl NmeaParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process data read from the device. Returns what every complete
+    /// sentence tells about the time. Incomplete sentences are kept until the
+    /// rest of the line is fed.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Result<NmeaData, NmeaError>> {
+        let mut result = vec![];
+
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                let line = std::mem::take(&mut self.partial);
+                if line.is_empty() {
+                    continue;
+                }
+                match std::str::from_utf8(&line) {
+                    Ok(line) => match parse_sentence(line) {
+                        Ok(Some(data)) => result.push(Ok(data)),
+                        Ok(None) => {}
+                        Err(e) => result.push(Err(e)),
+                    },
+                    Err(_) => result.push(Err(NmeaError::Malformed)),
+                }
+            } else if self.partial.len() < MAX_SENTENCE_LENGTH {
+                self.partial.push(byte);
+            } else {
+                // drop the line, and resynchronize on the next sentence start
+                self.partial.clear();
+                result.push(Err(NmeaError::TooLong));
+                if byte == b'$' {
+                    self.partial.push(byte);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Forget any incomplete sentence, for example after the read from the
+    /// device timed out. The rest of that sentence can no longer be trusted to
+    /// belong to the current second.
+    pub fn reset(&mut self) {
+        self.partial.clear();
+    }
+}
+
+/// Parse a single sentence, without line ending. Returns `None` for valid
+/// sentences that tell nothing about the time.
+pub fn parse_sentence(line: &str) -> Result<Option<NmeaData>, NmeaError> {
+    let body = line.strip_prefix('$').ok_or(NmeaError::MissingDelimiter)?;
+    let (body, checksum) = body.rsplit_once('*').ok_or(NmeaError::MissingDelimiter)?;
+
+    let checksum = u8::from_str_radix(checksum, 16).map_err(|_| NmeaError::Malformed)?;
+    if body.bytes().fold(0, |acc, b| acc ^ b) != checksum {
+        return Err(NmeaError::InvalidChecksum);
+    }
+    // the fields are sliced by byte position below
+    if !body.is_ascii() {
+        return Err(NmeaError::Malformed);
+    }
+
+    let mut fields = body.split(',');
+    let kind = fields.next().ok_or(NmeaError::Malformed)?;
+    let fields: Vec<&str> = fields.collect();
+
+    // the first two characters identify the talker (GP, GN, GL, ...)
+    match kind.get(2..) {
+        Some("RMC") => parse_rmc(&fields),
+        Some("ZDA") => parse_zda(&fields),
+        _ => Ok(None),
+    }
+}
+
+/// `$--RMC,hhmmss.ss,A,llll.ll,a,yyyyy.yy,a,x.x,x.x,ddmmyy,...`
+fn parse_rmc(fields: &[&str]) -> Result<Option<NmeaData>, NmeaError> {
+    if fields.len() < 9 {
+        return Err(NmeaError::Malformed);
+    }
+
+    // the receiver has no fix, and the time may be off
+    if fields[1] != "A" {
+        return Ok(Some(NmeaData::NoFix));
+    }
+    if fields[0].is_empty() || fields[8].is_empty() {
+        return Ok(None);
+    }
+
+    let date = fields[8];
+    if date.len() != 6 {
+        return Err(NmeaError::Malformed);
+    }
+    let day = parse_number(&date[0..2])?;
+    let month = parse_number(&date[2..4])?;
+    // two digit years are interpreted within 1980-2079, following the GPS epoch
+    let year = match parse_number(&date[4..6])? {
+        year @ 0..=79 => 2000 + year,
+        year => 1900 + year,
+    };
+
+    to_timestamp(year, month, day, fields[0]).map(|time| Some(NmeaData::FixedTime(time)))
+}
+
+/// `$--ZDA,hhmmss.ss,dd,mm,yyyy,xx,yy`
+fn parse_zda(fields: &[&str]) -> Result<Option<NmeaData>, NmeaError> {
+    if fields.len() < 4 {
+        return Err(NmeaError::Malformed);
+    }
+
+    if fields[..4].iter().any(|field| field.is_empty()) {
+        return Ok(None);
+    }
+
+    let day = parse_number(fields[1])?;
+    let month = parse_number(fields[2])?;
+    let year = parse_number(fields[3])?;
+
+    to_timestamp(year, month, day, fields[0]).map(|time| Some(NmeaData::Time(time)))
+}
+
+fn parse_number(field: &str) -> Result<u32, NmeaError> {
+    if field.is_empty() || !field.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(NmeaError::Malformed);
+    }
+    field.parse().map_err(|_| NmeaError::Malformed)
+}
+
+fn to_timestamp(year: u32, month: u32, day: u32, time: &str) -> Result<NtpTimestamp, NmeaError> {
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    if time.len() != 6 {
+        return Err(NmeaError::Malformed);
+    }
+    let hours = parse_number(&time[0..2])?;
+    let minutes = parse_number(&time[2..4])?;
+    // 60 is allowed for leap seconds
+    let seconds = parse_number(&time[4..6])?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 {
+        return Err(NmeaError::Malformed);
+    }
+    if seconds > 60 {
+        return Err(NmeaError::Malformed);
+    }
+
+    let nanos = if fraction.is_empty() {
+        0
+    } else if fraction.len() <= 9 {
+        parse_number(fraction)? * 10u32.pow(9 - fraction.len() as u32)
+    } else {
+        return Err(NmeaError::Malformed);
+    };
+
+    let unix_seconds = days_since_unix_epoch(year as i64, month as i64, day as i64) * 86400
+        + (hours * 3600 + minutes * 60 + seconds) as i64;
+
+    // the ntp era wraps, so truncating is intended
+    Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+        (unix_seconds + NTP_UNIX_OFFSET) as u32,
+        nanos,
+    ))
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_since_unix_epoch(year: i64, month: i64, day: i64) -> i64 {
+    // count years from march, so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(seconds: u32, nanos: u32) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds + NTP_UNIX_OFFSET as u32, nanos)
+    }
+
+    #[test]
+    fn test_days_since_unix_epoch() {
+        assert_eq!(days_since_unix_epoch(1970, 1, 1), 0);
+        assert_eq!(days_since_unix_epoch(2000, 3, 1), 11017);
+        assert_eq!(days_since_unix_epoch(2024, 2, 29), 19782);
+    }
+
+    #[test]
+    fn test_parse_rmc() {
+        assert_eq!(
+            parse_sentence("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A"),
+            Ok(Some(NmeaData::FixedTime(unix(764426119, 0))))
+        );
+
+        // no fix
+        assert_eq!(
+            parse_sentence("$GPRMC,123519,V,,,,,,,230394,,*33"),
+            Ok(Some(NmeaData::NoFix))
+        );
+    }
+
+    #[test]
+    fn test_parse_zda() {
+        assert_eq!(
+            parse_sentence("$GNZDA,201530.50,04,07,2002,00,00*7B"),
+            Ok(Some(NmeaData::Time(unix(1025813730, 500_000_000))))
+        );
+
+        // other sentences are ignored
+        assert_eq!(
+            parse_sentence("$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_invalid_sentences() {
+        assert_eq!(
+            parse_sentence("$GNZDA,201530.50,04,07,2002,00,00*7C"),
+            Err(NmeaError::InvalidChecksum)
+        );
+        assert_eq!(
+            parse_sentence("GNZDA,201530.50,04,07,2002,00,00*7B"),
+            Err(NmeaError::MissingDelimiter)
+        );
+        assert_eq!(
+            parse_sentence("$GNZDA,201530.50,04,07,2002,00,00"),
+            Err(NmeaError::MissingDelimiter)
+        );
+        assert_eq!(
+            parse_sentence("$GNZDA,201530.50,04,13,2002,00,00*7E"),
+            Err(NmeaError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_feed_partial_lines() {
+        let mut parser = NmeaParser::new();
+
+        assert_eq!(parser.feed(b"$GNZDA,201530.50,04,"), vec![]);
+        assert_eq!(
+            parser.feed(b"07,2002,00,00*7B\r\n$GPGSA,A,3"),
+            vec![Ok(NmeaData::Time(unix(1025813730, 500_000_000)))]
+        );
+
+        // the rest of a sentence from before a timeout is not used
+        parser.reset();
+        assert_eq!(
+            parser.feed(b",04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39\r\n"),
+            vec![Err(NmeaError::MissingDelimiter)]
+        );
+
+        // garbage without line endings does not grow the buffer forever
+        let garbage = [b'x'; 200];
+        assert_eq!(
+            parser.feed(&garbage),
+            vec![Err(NmeaError::TooLong), Err(NmeaError::TooLong)]
+        );
+        parser.reset();
+        assert_eq!(
+            parser.feed(b"$GNZDA,201530.50,04,07,2002,00,00*7B\n"),
+            vec![Ok(NmeaData::Time(unix(1025813730, 500_000_000)))]
+        );
+    }
+
+    #[test]
+    fn test_source_measurements() {
+        let mut source = NmeaSource::new(NtpDuration::from_seconds(0.1));
+        let second = unix(1025813730, 0);
+        let monotime = NtpInstant::now();
+
+        let localtime = second + NtpDuration::from_seconds(0.3);
+        let update = source
+            .handle_data(
+                b"$GNZDA,201530.00,04,07,2002,00,00*7E\r\n",
+                localtime,
+                monotime,
+            )
+            .unwrap();
+        let measurement = update.measurement.unwrap();
+        assert!((measurement.offset.to_seconds() + 0.2).abs() < 1e-6);
+        assert_eq!(measurement.delay, NtpDuration::from_seconds(NMEA_MIN_DELAY));
+        assert_eq!(measurement.localtime, localtime);
+        assert!(update.snapshot.reach.is_reachable());
+        assert_eq!(update.snapshot.source_id, GPS_REFERENCE_ID);
+
+        // later sentences of the same second are not used
+        assert!(source
+            .handle_data(
+                b"$GNZDA,201530.00,04,07,2002,00,00*7E\r\n",
+                localtime + NtpDuration::from_seconds(0.05),
+                monotime,
+            )
+            .is_none());
+
+        // the change in latency is reported as delay
+        let localtime = second + NtpDuration::from_seconds(1.32);
+        let update = source
+            .handle_data(
+                b"$GNZDA,201531.00,04,07,2002,00,00*7F\r\n",
+                localtime,
+                monotime,
+            )
+            .unwrap();
+        let measurement = update.measurement.unwrap();
+        assert!((measurement.offset.to_seconds() + 0.22).abs() < 1e-6);
+        assert!((measurement.delay.to_seconds() - 0.02).abs() < 1e-6);
+
+        // invalid sentences are counted, and yield no measurement
+        let update = source.handle_data(
+            b"$GNZDA,201532.00,04,07,2002,00,00*7F\r\n",
+            localtime + NtpDuration::from_seconds(1.0),
+            monotime,
+        );
+        assert!(update.is_none());
+        assert_eq!(source.snapshot().bogus_packets, 1);
+    }
+
+    #[test]
+    fn test_source_fix_status() {
+        let mut source = NmeaSource::new(NtpDuration::ZERO);
+        let localtime = unix(1025813730, 0);
+        let monotime = NtpInstant::now();
+
+        // without status reports, the receiver is assumed to have a fix
+        let update = source
+            .handle_data(
+                b"$GNZDA,201530.00,04,07,2002,00,00*7E\r\n",
+                localtime,
+                monotime,
+            )
+            .unwrap();
+        assert_eq!(
+            update.measurement.unwrap().leap,
+            NtpLeapIndicator::NoWarning
+        );
+
+        // after losing its fix, its time is unsynchronized
+        let update = source
+            .handle_data(
+                b"$GNRMC,201531.00,V,,,,,,,040702,,*04\r\n$GNZDA,201531.00,04,07,2002,00,00*7F\r\n",
+                localtime + NtpDuration::from_seconds(1.0),
+                monotime,
+            )
+            .unwrap();
+        assert_eq!(update.measurement.unwrap().leap, NtpLeapIndicator::Unknown);
+
+        // until it has a fix again
+        let update = source
+            .handle_data(
+                b"$GNRMC,201532.00,A,4807.038,N,01131.000,E,022.4,084.4,040702,003.1,W*5E\r\n",
+                localtime + NtpDuration::from_seconds(2.0),
+                monotime,
+            )
+            .unwrap();
+        assert_eq!(
+            update.measurement.unwrap().leap,
+            NtpLeapIndicator::NoWarning
+        );
+    }
+
+    #[test]
+    fn test_source_timeout() {
+        let mut source = NmeaSource::new(NtpDuration::ZERO);
+        let localtime = unix(1025813730, 0);
+        assert!(source
+            .handle_data(
+                b"$GNZDA,201530.00,04,07,2002,00,00*7E\r\n$GNZDA,2015",
+                localtime,
+                NtpInstant::now(),
+            )
+            .is_some());
+
+        // the partial sentence is dropped
+        let update = source.handle_timeout();
+        assert!(update.measurement.is_none());
+        assert!(update.snapshot.reach.is_reachable());
+        assert!(source
+            .handle_data(
+                b"31.00,04,07,2002,00,00*7F\r\n",
+                localtime,
+                NtpInstant::now()
+            )
+            .is_none());
+
+        for _ in 0..7 {
+            source.handle_timeout();
+        }
+        assert!(!source.snapshot().reach.is_reachable());
+    }
+}
@@ -249,7 +249,11 @@ impl NtpHeaderV3V4 {
             // Timestamp must be last to make it as accurate as possible.
             transmit_timestamp: clock.now().expect("Failed to read time"),
             leap: system.time_snapshot.leap_indicator,
-            reference_timestamp: Default::default(),
+            reference_timestamp: match system.stratum {
+                // at stratum 1 an external reference clock keeps updating the clock
+                1 => recv_timestamp,
+                _ => system.reference_timestamp,
+            },
         }
     }
 
@@ -273,6 +277,13 @@ impl NtpHeaderV3V4 {
         }
     }
 
+    fn restrict_response(packet_from_client: Self) -> Self {
+        Self {
+            reference_id: ReferenceId::KISS_RSTR,
+            ..Self::deny_response(packet_from_client)
+        }
+    }
+
     fn nts_nak_response(packet_from_client: Self) -> Self {
         Self {
             mode: NtpAssociationMode::Server,
@@ -582,6 +593,18 @@ impl<'a> NtpPacket<'a> {
         )
     }
 
+    pub fn poll_message_v3(poll_interval: PollInterval) -> (Self, RequestIdentifier) {
+        let (header, id) = NtpHeaderV3V4::poll_message(poll_interval);
+        (
+            NtpPacket {
+                header: NtpHeader::V3(header),
+                efdata: Default::default(),
+                mac: None,
+            },
+            id,
+        )
+    }
+
     #[cfg(feature = "ntpv5")]
     pub fn poll_message_upgrade_request(poll_interval: PollInterval) -> (Self, RequestIdentifier) {
         let (mut header, id) = NtpHeaderV3V4::poll_message(poll_interval);
@@ -1005,6 +1028,31 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    /// Like [`NtpPacket::deny_response`], but with the `RSTR` kiss code
+    pub fn restrict_response(packet_from_client: Self) -> Self {
+        let header = packet_from_client.header;
+        let mut response = Self::deny_response(packet_from_client);
+        response.header = Self::restrict_header(header);
+        response
+    }
+
+    /// Like [`NtpPacket::nts_deny_response`], but with the `RSTR` kiss code
+    pub fn nts_restrict_response(packet_from_client: Self) -> Self {
+        let header = packet_from_client.header;
+        let mut response = Self::nts_deny_response(packet_from_client);
+        response.header = Self::restrict_header(header);
+        response
+    }
+
+    fn restrict_header(header: NtpHeader) -> NtpHeader {
+        match header {
+            NtpHeader::V3(header) => NtpHeader::V3(NtpHeaderV3V4::restrict_response(header)),
+            NtpHeader::V4(header) => NtpHeader::V4(NtpHeaderV3V4::restrict_response(header)),
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(header) => NtpHeader::V5(v5::NtpHeaderV5::restrict_response(header)),
+        }
+    }
+
     pub fn nts_nak_response(packet_from_client: Self) -> Self {
         match packet_from_client.header {
             NtpHeader::V3(_) => unreachable!("NTS shouldn't work with NTPv3"),
@@ -1152,6 +1200,16 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    pub fn reference_timestamp(&self) -> NtpTimestamp {
+        match self.header {
+            NtpHeader::V3(header) => header.reference_timestamp,
+            NtpHeader::V4(header) => header.reference_timestamp,
+            #[cfg(feature = "ntpv5")]
+            // NTPv5 does not have a reference timestamp
+            NtpHeader::V5(_header) => NtpTimestamp::default(),
+        }
+    }
+
     pub fn reference_id(&self) -> ReferenceId {
         match self.header {
             NtpHeader::V3(header) => header.reference_id,
@@ -1232,6 +1290,15 @@ impl<'a> NtpPacket<'a> {
                 return false;
             }
         }
+        self.matches_origin(identifier)
+    }
+
+    /// Whether the origin timestamp (or client cookie for NTPv5) of this packet
+    /// echoes the transmit timestamp of the request it is identified by. A
+    /// response for which this does not hold is bogus: it is a duplicate,
+    /// a reply to an older request, or was forged by someone who did not see
+    /// our request.
+    pub fn matches_origin(&self, identifier: RequestIdentifier) -> bool {
         match self.header {
             NtpHeader::V3(header) => {
                 header.origin_timestamp == identifier.expected_origin_timestamp
@@ -1337,6 +1404,15 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    pub fn set_poll(&mut self, poll: PollInterval) {
+        match &mut self.header {
+            NtpHeader::V3(ref mut header) => header.poll = poll,
+            NtpHeader::V4(ref mut header) => header.poll = poll,
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(ref mut header) => header.poll = poll,
+        }
+    }
+
     pub fn set_precision(&mut self, precision: i8) {
         match &mut self.header {
             NtpHeader::V3(ref mut header) => header.precision = precision,
@@ -1810,6 +1886,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reference_timestamp() {
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let clock = TestClock {
+            now: NtpTimestamp::from_fixed_int(3),
+        };
+
+        let response = NtpPacket::timestamp_response(
+            &SystemSnapshot {
+                stratum: 2,
+                reference_timestamp: NtpTimestamp::from_fixed_int(1),
+                ..Default::default()
+            },
+            packet.clone(),
+            NtpTimestamp::from_fixed_int(2),
+            &clock,
+        );
+        assert_eq!(
+            response.reference_timestamp(),
+            NtpTimestamp::from_fixed_int(1)
+        );
+
+        let response = NtpPacket::timestamp_response(
+            &SystemSnapshot {
+                stratum: 1,
+                ..Default::default()
+            },
+            packet,
+            NtpTimestamp::from_fixed_int(2),
+            &clock,
+        );
+        assert_eq!(
+            response.reference_timestamp(),
+            NtpTimestamp::from_fixed_int(2)
+        );
+    }
+
     #[test]
     fn test_timestamp_response() {
         let decoded = DecodedServerCookie {
@@ -1870,6 +1983,8 @@ mod tests {
             NtpTimestamp::from_fixed_int(1)
         );
         assert_eq!(response.leap(), NtpLeapIndicator::Leap59);
+        // never synchronized
+        assert_eq!(response.reference_timestamp(), NtpTimestamp::default());
 
         let (mut packet, _) =
             NtpPacket::nts_poll_message(&cookie, 0, PollIntervalLimits::default().min);
@@ -246,6 +246,10 @@ impl NtpHeaderV5 {
         Self::kiss_response(packet_from_client, *b"DENY")
     }
 
+    pub(crate) fn restrict_response(packet_from_client: Self) -> Self {
+        Self::kiss_response(packet_from_client, *b"RSTR")
+    }
+
     pub(crate) fn nts_nak_response(packet_from_client: Self) -> Self {
         Self::kiss_response(packet_from_client, *b"NTSN")
     }
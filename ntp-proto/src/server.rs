@@ -10,8 +10,8 @@ use std::{
 use serde::{de, Deserialize, Deserializer};
 
 use crate::{
-    ipfilter::IpFilter, KeySet, NoCipher, NtpClock, NtpPacket, NtpTimestamp, PacketParsingError,
-    SystemSnapshot,
+    ipfilter::IpFilter, HoldoverState, KeySet, NoCipher, NtpClock, NtpDuration, NtpLeapIndicator,
+    NtpPacket, NtpTimestamp, PacketParsingError, ReferenceId, SystemSnapshot, TimeSnapshot,
 };
 
 pub enum ServerAction<'a> {
@@ -31,6 +31,8 @@ pub enum ServerReason {
     InternalError,
     /// Configuration was used to decide response
     Policy,
+    /// The server itself is not synchronized
+    Unsynchronized,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -39,6 +41,8 @@ pub enum ServerResponse {
     NTSNak,
     /// Sent a deny response to client
     Deny,
+    /// Sent a restrict (`RSTR`) response to client
+    Restrict,
     /// Only for a concious choice to not respond, error conditions are separate
     Ignore,
     /// Accepted packet and provided time to requestor
@@ -55,6 +59,8 @@ pub trait ServerStatHandler {
 pub enum FilterAction {
     Ignore,
     Deny,
+    /// Respond with the `RSTR` kiss code
+    Restrict,
 }
 
 impl From<FilterAction> for ServerResponse {
@@ -62,10 +68,21 @@ impl From<FilterAction> for ServerResponse {
         match value {
             FilterAction::Ignore => ServerResponse::Ignore,
             FilterAction::Deny => ServerResponse::Deny,
+            FilterAction::Restrict => ServerResponse::Restrict,
         }
     }
 }
 
+/// What to do with requests while we are not synchronized ourselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnsynchronizedAction {
+    /// Drop requests without responding
+    Ignore,
+    /// Respond with stratum 16 and an unknown leap indicator
+    Unsynchronized,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
 pub struct FilterList {
     pub filter: Vec<IpSubnet>,
@@ -78,6 +95,16 @@ pub struct ServerConfig {
     pub allowlist: FilterList,
     pub rate_limiting_cache_size: usize,
     pub rate_limiting_cutoff: Duration,
+    /// Warn about clients whose requests indicate a synchronization loop
+    pub detect_loops: bool,
+    /// How to handle requests while we are not synchronized, serve time as
+    /// usual when unset
+    pub serve_only_when_synced: Option<UnsynchronizedAction>,
+    /// Lowest root delay we advertise, whatever our sources report
+    pub min_root_delay: NtpDuration,
+    /// Lowest root dispersion we advertise, reflecting the uncertainty of
+    /// our own clock and reference
+    pub min_root_dispersion: NtpDuration,
 }
 
 pub struct Server<C> {
@@ -88,6 +115,8 @@ pub struct Server<C> {
     client_cache: TimestampedCache<IpAddr>,
     system: SystemSnapshot,
     keyset: Arc<KeySet>,
+    /// Addresses of this machine, to recognize clients synchronizing to us
+    local_ips: Arc<[IpAddr]>,
 }
 
 // Quick estimation of ntp packet message version without doing full parsing
@@ -114,6 +143,7 @@ impl<C> Server<C> {
             client_cache,
             system,
             keyset,
+            local_ips: Arc::from([]),
         }
     }
 
@@ -141,6 +171,33 @@ impl<C> Server<C> {
         self.keyset = keyset;
     }
 
+    /// Provide the server with the current addresses of this machine
+    pub fn update_ip_list(&mut self, ip_list: Arc<[IpAddr]>) {
+        self.local_ips = ip_list;
+    }
+
+    /// Whether a request comes from a client that synchronizes to us, as its
+    /// reference id names one of our addresses, while not being further from
+    /// a real reference than we are. A client that follows us has a higher
+    /// stratum, so this indicates a loop or a misbehaving client.
+    fn request_loops_back(&self, packet: &NtpPacket) -> bool {
+        let reference_id = packet.reference_id();
+        packet.stratum() > 1
+            && packet.stratum() <= self.system.stratum
+            && self
+                .local_ips
+                .iter()
+                .any(|ip| ReferenceId::from_ip(*ip) == reference_id)
+    }
+
+    /// Whether we have sources to synchronize to. Both during holdover and
+    /// before the first synchronization the time we serve is not backed by
+    /// any source.
+    fn is_synchronized(&self) -> bool {
+        self.system.time_snapshot.leap_indicator.is_synchronized()
+            && self.system.holdover == HoldoverState::Inactive
+    }
+
     fn intended_action(&mut self, client_ip: IpAddr) -> (ServerResponse, ServerReason) {
         if self.denyfilter.is_in(&client_ip) {
             // First apply denylist
@@ -155,6 +212,18 @@ impl<C> Server<C> {
         ) {
             // Then ratelimit
             (ServerResponse::Ignore, ServerReason::RateLimit)
+        } else if let (Some(action), false) =
+            (self.config.serve_only_when_synced, self.is_synchronized())
+        {
+            // Then our own synchronization status
+            match action {
+                UnsynchronizedAction::Ignore => {
+                    (ServerResponse::Ignore, ServerReason::Unsynchronized)
+                }
+                UnsynchronizedAction::Unsynchronized => {
+                    (ServerResponse::ProvideTime, ServerReason::Unsynchronized)
+                }
+            }
         } else {
             // Then accept
             (ServerResponse::ProvideTime, ServerReason::Policy)
@@ -189,7 +258,7 @@ impl<C: NtpClock> Server<C> {
             Ok(packet) => packet,
             Err(PacketParsingError::DecryptError(packet)) => {
                 // Don't care about decryption errors when denying anyway
-                if action != ServerResponse::Deny {
+                if !matches!(action, ServerResponse::Deny | ServerResponse::Restrict) {
                     action = ServerResponse::NTSNak;
                     reason = ServerReason::InvalidCrypto;
                 }
@@ -206,6 +275,50 @@ impl<C: NtpClock> Server<C> {
             }
         };
 
+        if self.config.detect_loops
+            && action == ServerResponse::ProvideTime
+            && self.request_loops_back(&packet)
+        {
+            // If we in turn synchronize to this client, neither clock is
+            // steered by a real reference.
+            tracing::warn!(
+                ?client_ip,
+                "Client synchronizes to us at our own stratum, possible synchronization loop"
+            );
+        }
+
+        // Never claim to be more accurate than configured
+        let mut advertised = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                root_delay: self
+                    .system
+                    .time_snapshot
+                    .root_delay
+                    .max(self.config.min_root_delay),
+                root_dispersion: self
+                    .system
+                    .time_snapshot
+                    .root_dispersion
+                    .max(self.config.min_root_dispersion),
+                ..self.system.time_snapshot
+            },
+            ..self.system
+        };
+
+        // Tell clients not to trust the time we serve
+        if reason == ServerReason::Unsynchronized {
+            advertised = SystemSnapshot {
+                stratum: 16,
+                reference_id: ReferenceId::NONE,
+                time_snapshot: TimeSnapshot {
+                    leap_indicator: NtpLeapIndicator::Unknown,
+                    ..advertised.time_snapshot
+                },
+                ..advertised
+            };
+        }
+        let system = &advertised;
+
         // Generate the appropriate response
         let version = packet.version();
         let nts = cookie.is_some() || action == ServerResponse::NTSNak;
@@ -225,10 +338,21 @@ impl<C: NtpClock> Server<C> {
                     NtpPacket::deny_response(packet).serialize(&mut cursor, &NoCipher, None)
                 }
             }
+            ServerResponse::Restrict => {
+                if let Some(cookie) = cookie {
+                    NtpPacket::nts_restrict_response(packet).serialize(
+                        &mut cursor,
+                        cookie.s2c.as_ref(),
+                        None,
+                    )
+                } else {
+                    NtpPacket::restrict_response(packet).serialize(&mut cursor, &NoCipher, None)
+                }
+            }
             ServerResponse::ProvideTime => {
                 if let Some(cookie) = cookie {
                     NtpPacket::nts_timestamp_response(
-                        &self.system,
+                        system,
                         packet,
                         recv_timestamp,
                         &self.clock,
@@ -241,7 +365,7 @@ impl<C: NtpClock> Server<C> {
                         Some(message.len()),
                     )
                 } else {
-                    NtpPacket::timestamp_response(&self.system, packet, recv_timestamp, &self.clock)
+                    NtpPacket::timestamp_response(system, packet, recv_timestamp, &self.clock)
                         .serialize(&mut cursor, &NoCipher, Some(message.len()))
                 }
             }
@@ -402,7 +526,7 @@ mod tests {
 
     use crate::{
         nts_record::AeadAlgorithm, packet::AesSivCmac256, Cipher, DecodedServerCookie,
-        KeySetProvider, NtpDuration, NtpLeapIndicator, PollIntervalLimits,
+        KeySetProvider, NtpDuration, PollIntervalLimits,
     };
 
     use super::*;
@@ -494,7 +618,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -563,7 +691,11 @@ mod tests {
                 action: FilterAction::Deny,
             },
             rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -586,6 +718,44 @@ mod tests {
         let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
         assert!(packet.valid_server_response(id, false));
         assert!(packet.is_kiss_deny());
+
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["127.0.0.0/24".parse().unwrap()],
+                action: FilterAction::Restrict,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
+            rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
+        };
+        server.update_config(config);
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "128.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::Policy, ServerResponse::Restrict))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert!(packet.is_kiss_rstr());
     }
 
     #[test]
@@ -600,7 +770,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -675,7 +849,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -706,7 +884,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 32,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -805,7 +987,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
 
         server.update_config(config);
@@ -867,6 +1053,229 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_server_unsynchronized() {
+        let mut config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: Some(UnsynchronizedAction::Ignore),
+            rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+
+        let mut synchronized = SystemSnapshot {
+            stratum: 2,
+            ..SystemSnapshot::default()
+        };
+        synchronized.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+
+        let mut server = Server::new(
+            config.clone(),
+            clock,
+            synchronized,
+            KeySetProvider::new(1).get(),
+        );
+
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencryped(&packet);
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::Policy, ServerResponse::ProvideTime))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert_eq!(packet.stratum(), 2);
+        assert_eq!(packet.leap(), NtpLeapIndicator::NoWarning);
+
+        // in holdover we are no longer synchronized
+        server.update_system(SystemSnapshot {
+            holdover: HoldoverState::Active,
+            ..synchronized
+        });
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::Unsynchronized,
+                ServerResponse::Ignore
+            ))
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+
+        config.serve_only_when_synced = Some(UnsynchronizedAction::Unsynchronized);
+        server.update_config(config);
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::Unsynchronized,
+                ServerResponse::ProvideTime
+            ))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert_eq!(packet.stratum(), 16);
+        assert_eq!(packet.leap(), NtpLeapIndicator::Unknown);
+        assert_eq!(
+            packet.transmit_timestamp(),
+            NtpTimestamp::from_fixed_int(200)
+        );
+    }
+
+    #[test]
+    fn test_server_root_floor() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: false,
+            serve_only_when_synced: None,
+            rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::from_seconds(0.001),
+            min_root_dispersion: NtpDuration::from_seconds(0.002),
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+
+        let mut system = SystemSnapshot {
+            stratum: 1,
+            ..SystemSnapshot::default()
+        };
+        system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        system.time_snapshot.root_delay = NtpDuration::ZERO;
+        system.time_snapshot.root_dispersion = NtpDuration::from_seconds(0.01);
+
+        let mut server = Server::new(config, clock, system, KeySetProvider::new(1).get());
+
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencryped(&packet);
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+
+        // the floor applies to the root delay, a larger dispersion is kept
+        assert!((packet.root_delay().to_seconds() - 0.001).abs() < 1e-4);
+        assert!((packet.root_dispersion().to_seconds() - 0.01).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_server_detects_loops() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            detect_loops: true,
+            serve_only_when_synced: None,
+            rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let system = SystemSnapshot {
+            stratum: 3,
+            reference_id: ReferenceId::from_ip("192.0.2.2".parse().unwrap()),
+            ..SystemSnapshot::default()
+        };
+
+        let mut server = Server::new(config, clock, system, KeySetProvider::new(1).get());
+        server.update_ip_list(Arc::from(["192.0.2.1".parse().unwrap()]));
+
+        let ours = ReferenceId::from_ip("192.0.2.1".parse().unwrap());
+        let other = ReferenceId::from_ip("192.0.2.3".parse().unwrap());
+        for (stratum, reference_id, looping) in [
+            // a client synchronizing to us at our own stratum or below
+            (3, ours, true),
+            (2, ours, true),
+            // a client that follows us
+            (4, ours, false),
+            // a client synchronizing to someone else
+            (3, other, false),
+            // an unsynchronized client, whose reference id is not an address
+            (0, ours, false),
+        ] {
+            let (mut packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+            packet.set_stratum(stratum);
+            packet.set_reference_id(reference_id);
+            assert_eq!(server.request_loops_back(&packet), looping);
+        }
+    }
+
     #[test]
     fn test_server_corrupted() {
         let config = ServerConfig {
@@ -879,7 +1288,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -941,7 +1354,11 @@ mod tests {
                 action: FilterAction::Deny,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -969,7 +1386,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -997,7 +1418,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -1025,7 +1450,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         server.update_config(config);
 
@@ -1044,6 +1473,57 @@ mod tests {
         assert!(matches!(response, ServerAction::Ignore));
     }
 
+    #[test]
+    fn test_server_truncated_extension_field() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
+            rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+
+        let mut server = Server::new(
+            config,
+            clock,
+            SystemSnapshot::default(),
+            KeySetProvider::new(1).get(),
+        );
+
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let mut serialized = serialize_packet_unencryped(&packet);
+        // unique identifier extension field claiming 128 bytes, but only 64 are present
+        serialized.extend_from_slice(&[0x01, 0x04, 0x00, 0x80]);
+        serialized.extend_from_slice(&[0; 60]);
+
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::ParseError, ServerResponse::Ignore))
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+    }
+
     #[test]
     fn test_server_nts() {
         let config = ServerConfig {
@@ -1056,7 +1536,11 @@ mod tests {
                 action: FilterAction::Ignore,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1146,7 +1630,11 @@ mod tests {
                 action: FilterAction::Deny,
             },
             rate_limiting_cutoff: Duration::from_millis(100),
+            detect_loops: false,
+            serve_only_when_synced: None,
             rate_limiting_cache_size: 0,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
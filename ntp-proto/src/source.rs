@@ -11,11 +11,12 @@ use crate::{
     system::SystemSnapshot,
     time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
 };
-use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::VecDeque,
     io::Cursor,
     net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
     time::Duration,
 };
 use tracing::{debug, info, instrument, trace, warn};
@@ -23,6 +24,35 @@ use tracing::{debug, info, instrument, trace, warn};
 const MAX_STRATUM: u8 = 16;
 const POLL_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
 const STARTUP_TRIES_THRESHOLD: usize = 3;
+// Number of consecutive replies that could not be parsed, or were of the wrong
+// version, after which we fall back from NTPv4 to NTPv3, and after that give
+// up on the source. This must stay below STARTUP_TRIES_THRESHOLD, or an
+// unreachable source is reset before the fallback happens.
+const VERSION_FALLBACK_THRESHOLD: u8 = 2;
+/// Send delays above this are assumed to be caused by something else, such
+/// as a clock step between the two timestamps (in seconds)
+const MAX_SEND_DELAY: f64 = 1e-3;
+/// Weight of the newest sample in the send delay estimate, as 1/n
+const SEND_DELAY_AVERAGING: i64 = 8;
+/// Consecutive answered polls needed before a recovering source is polled at
+/// its normal poll interval again
+const RECOVERY_ANSWERED_POLLS: u32 = 2;
+/// Poll outcomes are aggregated in buckets of this length in the
+/// reachability history
+const REACH_BUCKET_LENGTH: Duration = Duration::from_secs(300);
+/// Length of the reachability history, the longest window over which
+/// reachability is reported
+const REACH_HISTORY_LENGTH: Duration = Duration::from_secs(24 * 3600);
+
+/// A request sent to the server that has not been answered yet
+#[derive(Debug, Clone, Copy)]
+struct OutstandingRequest {
+    identifier: RequestIdentifier,
+    // Responses arriving after this are no longer accepted
+    expires: NtpInstant,
+    // When the request was actually sent, known once it is on the wire
+    send_time: Option<NtpTimestamp>,
+}
 
 pub struct SourceNtsData {
     pub(crate) cookies: CookieStash,
@@ -61,19 +91,55 @@ pub struct NtpSource {
     // The poll interval desired by the remove server.
     // Must be increased when the server sends the RATE kiss code.
     remote_min_poll_interval: PollInterval,
-
-    // Identifier of the last request sent to the server. This is correlated
-    // with any received response from the server to guard against replay
-    // attacks and packet reordering.
-    current_request_identifier: Option<(RequestIdentifier, NtpInstant)>,
+    // The poll interval advertised by the server in the poll field of its
+    // last reply that did not just echo our own poll interval.
+    remote_advertised_poll_interval: Option<PollInterval>,
+
+    // The most recent requests sent to the server, oldest first. Received
+    // responses are correlated with these to guard against replay attacks
+    // and packet reordering. More than one is kept so that a response
+    // arriving after the next poll was sent can still be used.
+    outstanding_requests: VecDeque<OutstandingRequest>,
+    // Identifier of the last request we processed a response to, so further
+    // responses to it can be recognized as duplicates.
+    answered_request_identifier: Option<RequestIdentifier>,
+    // Number of additional responses received to an already answered request
+    duplicate_packets: u64,
+    packets: PacketCounters,
+    // Number of responses received whose origin timestamp did not match
+    // the transmit timestamp of our outstanding request.
+    bogus_packets: u64,
+    // Number of consecutive replies that could not be parsed or were of the
+    // wrong version
+    parse_failures: u8,
+    // Neither NTPv4 nor NTPv3 replies of the source could be parsed, so we
+    // stopped polling it
+    incompatible: bool,
+    // Estimated time between taking the send timestamp in userspace and the
+    // request actually leaving, learned from kernel send timestamps.
+    send_delay: Option<NtpDuration>,
+    // Whether the last request got a send timestamp from the kernel or
+    // network card.
+    send_timestamped: bool,
 
     stratum: u8,
     reference_id: ReferenceId,
+    // Most recent change of the upstream reference of the source
+    last_reference_change: Option<ReferenceChange>,
 
     source_addr: SocketAddr,
     source_id: ReferenceId,
     reach: Reach,
+    // Longer, time based history of the poll outcomes than `reach`
+    reach_history: ReachHistory,
     tries: usize,
+    // Whether we poll at the recovery poll interval because the source
+    // became unreachable
+    recovering: bool,
+    // Quick repetitions of the first poll sent so far, and whether the
+    // pending timer is for one
+    initial_retries: u8,
+    initial_retry_pending: bool,
 
     source_defaults_config: SourceDefaultsConfig,
 
@@ -168,7 +234,7 @@ impl Reach {
 
     /// A packet received some number of poll intervals ago is decreasingly relevant for
     /// determining that a source is still reachable. We discount the packets received so far.
-    fn poll(&mut self) {
+    pub(crate) fn poll(&mut self) {
         self.0 <<= 1;
     }
 
@@ -176,6 +242,151 @@ impl Reach {
     pub fn unanswered_polls(&self) -> u32 {
         self.0.trailing_zeros()
     }
+
+    /// Number of consecutive polls, up to the most recent one, that were answered
+    fn answered_polls(&self) -> u32 {
+        self.0.trailing_ones()
+    }
+}
+
+/// Outcomes of the polls over the last day, for reporting reachability over
+/// longer windows than the 8 polls of [`Reach`]. Every poll counts for the
+/// time until the next poll, so the reachability is the fraction of time that
+/// the source answered, independent of the poll interval.
+#[derive(Debug, Default, Clone)]
+struct ReachHistory {
+    /// Completed poll intervals, aggregated in buckets, the oldest first
+    buckets: VecDeque<ReachBucket>,
+    /// Time of the most recent poll, and whether it was answered yet
+    last_poll: Option<(NtpInstant, bool)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReachBucket {
+    start: NtpInstant,
+    /// Seconds covered by the poll intervals in this bucket
+    polled: f64,
+    /// Seconds covered by the answered poll intervals in this bucket
+    answered: f64,
+}
+
+impl ReachHistory {
+    /// A new poll completes the interval of the previous one
+    fn poll(&mut self, now: NtpInstant) {
+        if let Some((since, answered)) = self.last_poll.replace((now, false)) {
+            self.record(since, now.abs_diff(since).to_seconds(), answered);
+        }
+
+        let length = NtpDuration::from_system_duration(REACH_HISTORY_LENGTH);
+        while let Some(bucket) = self.buckets.front() {
+            if now.abs_diff(bucket.start) <= length {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn received_packet(&mut self) {
+        if let Some((_, answered)) = &mut self.last_poll {
+            *answered = true;
+        }
+    }
+
+    fn record(&mut self, poll: NtpInstant, seconds: f64, answered: bool) {
+        let bucket_length = NtpDuration::from_system_duration(REACH_BUCKET_LENGTH);
+        let bucket = match self.buckets.back_mut() {
+            Some(bucket) if poll.abs_diff(bucket.start) < bucket_length => bucket,
+            _ => {
+                self.buckets.push_back(ReachBucket {
+                    start: poll,
+                    polled: 0.0,
+                    answered: 0.0,
+                });
+                self.buckets.back_mut().expect("bucket was just pushed")
+            }
+        };
+
+        bucket.polled += seconds;
+        if answered {
+            bucket.answered += seconds;
+        }
+    }
+
+    /// Percentage of the time in the window before `now` that the polls were
+    /// answered, or `None` when no poll interval completed in the window
+    fn percentage(&self, now: NtpInstant, window: Duration) -> Option<f64> {
+        let window = NtpDuration::from_system_duration(window);
+        let (polled, answered) = self
+            .buckets
+            .iter()
+            .filter(|bucket| now.abs_diff(bucket.start) <= window)
+            .fold((0.0, 0.0), |(polled, answered), bucket| {
+                (polled + bucket.polled, answered + bucket.answered)
+            });
+
+        (polled > 0.0).then(|| 100.0 * answered / polled)
+    }
+}
+
+/// A change in the stratum and/or reference id reported by a source, such as
+/// when the server fails over to a different reference clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceChange {
+    pub previous_stratum: u8,
+    pub previous_reference_id: ReferenceId,
+    pub stratum: u8,
+    pub reference_id: ReferenceId,
+}
+
+/// Counts of the packets exchanged with a source. Every received packet is
+/// either processed or counted in exactly one of the other categories.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketCounters {
+    /// Requests sent to the source
+    pub sent: u64,
+    /// Packets received from the source, whether usable or not
+    pub received: u64,
+    /// Packets that could not be parsed, or had an unexpected version
+    pub rejected_malformed: u64,
+    /// Packets that are not a response to our outstanding request, such as
+    /// late, duplicate or spoofed responses
+    pub rejected_unexpected: u64,
+    /// Responses with unusable contents, such as an invalid stratum or mode
+    pub rejected_invalid: u64,
+    /// Kiss-o'-Death packets received
+    pub kiss_of_death: u64,
+}
+
+impl PacketCounters {
+    /// Packets counted since `earlier`. A counter that went backwards was
+    /// restarted, as happens when a source is reset, so then all of its
+    /// current count is new.
+    pub fn since(&self, earlier: &PacketCounters) -> PacketCounters {
+        let delta = |now: u64, before: u64| if now >= before { now - before } else { now };
+        PacketCounters {
+            sent: delta(self.sent, earlier.sent),
+            received: delta(self.received, earlier.received),
+            rejected_malformed: delta(self.rejected_malformed, earlier.rejected_malformed),
+            rejected_unexpected: delta(self.rejected_unexpected, earlier.rejected_unexpected),
+            rejected_invalid: delta(self.rejected_invalid, earlier.rejected_invalid),
+            kiss_of_death: delta(self.kiss_of_death, earlier.kiss_of_death),
+        }
+    }
+}
+
+impl std::ops::AddAssign for PacketCounters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.sent = self.sent.saturating_add(rhs.sent);
+        self.received = self.received.saturating_add(rhs.received);
+        self.rejected_malformed = self
+            .rejected_malformed
+            .saturating_add(rhs.rejected_malformed);
+        self.rejected_unexpected = self
+            .rejected_unexpected
+            .saturating_add(rhs.rejected_unexpected);
+        self.rejected_invalid = self.rejected_invalid.saturating_add(rhs.rejected_invalid);
+        self.kiss_of_death = self.kiss_of_death.saturating_add(rhs.kiss_of_death);
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -185,13 +396,38 @@ pub struct NtpSourceSnapshot {
     pub source_id: ReferenceId,
 
     pub poll_interval: PollInterval,
+    /// The minimum poll interval imposed by the server, either through the
+    /// RATE kiss code or through the poll field of its replies
+    pub remote_min_poll_interval: PollInterval,
     pub reach: Reach,
+    /// Percentage of the time over the last hour that the source answered
+    /// its polls
+    pub reachability_pct_1h: Option<f64>,
+    /// Percentage of the time over the last day that the source answered
+    /// its polls
+    pub reachability_pct_24h: Option<f64>,
 
     pub stratum: u8,
     pub reference_id: ReferenceId,
+    pub last_reference_change: Option<ReferenceChange>,
 
     pub protocol_version: ProtocolVersion,
 
+    pub bogus_packets: u64,
+
+    /// Number of duplicate responses received to an already answered request
+    pub duplicate_packets: u64,
+
+    pub packets: PacketCounters,
+
+    /// Replies could not be parsed with NTPv4 nor with NTPv3, so the source
+    /// is no longer polled
+    pub incompatible: bool,
+
+    /// The last request was timestamped by the kernel or network card when
+    /// it was sent, rather than in userspace
+    pub send_timestamped: bool,
+
     #[cfg(feature = "ntpv5")]
     pub bloom_filter: Option<BloomFilter>,
 }
@@ -201,6 +437,7 @@ impl NtpSourceSnapshot {
         &self,
         local_stratum: u8,
         local_ips: &[IpAddr],
+        expected_stratum: Option<&RangeInclusive<u8>>,
         #[cfg_attr(not(feature = "ntpv5"), allow(unused_variables))] system: &SystemSnapshot,
     ) -> Result<(), AcceptSynchronizationError> {
         use AcceptSynchronizationError::*;
@@ -214,6 +451,16 @@ impl NtpSourceSnapshot {
             return Err(Stratum);
         }
 
+        if let Some(expected) = expected_stratum.filter(|range| !range.contains(&self.stratum)) {
+            info!(
+                source_stratum = self.stratum,
+                min_stratum = expected.start(),
+                max_stratum = expected.end(),
+                "Source rejected because its stratum is outside the range expected for it",
+            );
+            return Err(UnexpectedStratum);
+        }
+
         // Detect whether the remote uses us as their main time reference.
         // if so, we shouldn't sync to them as that would create a loop.
         // Note, this can only ever be an issue if the source is not using
@@ -247,20 +494,76 @@ impl NtpSourceSnapshot {
     }
 
     pub fn from_source(source: &NtpSource) -> Self {
+        let now = NtpInstant::now();
         Self {
             source_addr: source.source_addr,
             source_id: source.source_id,
             stratum: source.stratum,
             reference_id: source.reference_id,
+            last_reference_change: source.last_reference_change,
             reach: source.reach,
+            reachability_pct_1h: source
+                .reach_history
+                .percentage(now, Duration::from_secs(3600)),
+            reachability_pct_24h: source.reach_history.percentage(now, REACH_HISTORY_LENGTH),
             poll_interval: source.last_poll_interval,
+            remote_min_poll_interval: source.remote_poll_floor(),
             protocol_version: source.protocol_version,
+            bogus_packets: source.bogus_packets,
+            duplicate_packets: source.duplicate_packets,
+            packets: source.packets,
+            incompatible: source.incompatible,
+            send_timestamped: source.send_timestamped,
             #[cfg(feature = "ntpv5")]
             bloom_filter: source.bloom_filter.full_filter().copied(),
         }
     }
 }
 
+/// The full internal state of a source, to attach to bug reports. This is
+/// verbose and not meant to be stable. Secrets, such as the NTS keys and
+/// cookies, are never included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NtpSourceDebugDump {
+    pub source_addr: SocketAddr,
+    pub source_id: ReferenceId,
+    pub protocol_version: String,
+
+    pub last_poll_interval: PollInterval,
+    pub remote_min_poll_interval: PollInterval,
+    pub remote_advertised_poll_interval: Option<PollInterval>,
+
+    pub reach: Reach,
+    pub reachability_pct_1h: Option<f64>,
+    pub reachability_pct_24h: Option<f64>,
+    /// Polls since the source was last reset or answered during startup
+    pub tries: usize,
+    pub recovering: bool,
+    pub initial_retries: u8,
+    /// Whether a request is outstanding that hasn't been answered yet
+    pub awaiting_response: bool,
+
+    pub stratum: u8,
+    pub reference_id: ReferenceId,
+    pub last_reference_change: Option<ReferenceChange>,
+
+    pub packets: PacketCounters,
+    pub duplicate_packets: u64,
+    pub bogus_packets: u64,
+    pub parse_failures: u8,
+    pub incompatible: bool,
+
+    pub send_delay: Option<NtpDuration>,
+    pub send_timestamped: bool,
+
+    pub nts: bool,
+    /// Number of NTS cookies available, the cookies themselves are secret
+    pub nts_cookies: usize,
+
+    /// The configuration of the source, as debug output
+    pub config: String,
+}
+
 #[cfg(feature = "__internal-test")]
 pub fn source_snapshot() -> NtpSourceSnapshot {
     use std::net::Ipv4Addr;
@@ -273,10 +576,19 @@ pub fn source_snapshot() -> NtpSourceSnapshot {
         source_id: ReferenceId::from_int(0),
         stratum: 0,
         reference_id: ReferenceId::from_int(0),
+        last_reference_change: None,
 
         reach,
+        reachability_pct_1h: None,
+        reachability_pct_24h: None,
         poll_interval: crate::time_types::PollIntervalLimits::default().min,
+        remote_min_poll_interval: crate::time_types::PollIntervalLimits::default().min,
         protocol_version: Default::default(),
+        bogus_packets: 0,
+        duplicate_packets: 0,
+        packets: PacketCounters::default(),
+        incompatible: false,
+        send_timestamped: false,
         #[cfg(feature = "ntpv5")]
         bloom_filter: None,
     }
@@ -289,10 +601,14 @@ pub enum AcceptSynchronizationError {
     Loop,
     Distance,
     Stratum,
+    /// The stratum is outside of the range expected for the source
+    UnexpectedStratum,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ProtocolVersion {
+    /// Fallback for old servers that mishandle NTPv4 requests
+    V3,
     V4,
     #[cfg(feature = "ntpv5")]
     V4UpgradingToV5 {
@@ -305,6 +621,7 @@ pub enum ProtocolVersion {
 impl ProtocolVersion {
     pub fn expected_incoming_version(&self) -> u8 {
         match self {
+            ProtocolVersion::V3 => 3,
             ProtocolVersion::V4 => 4,
             #[cfg(feature = "ntpv5")]
             ProtocolVersion::V4UpgradingToV5 { .. } => 4,
@@ -330,6 +647,15 @@ impl Default for ProtocolVersion {
 pub struct NtpSourceUpdate {
     pub(crate) snapshot: NtpSourceSnapshot,
     pub(crate) measurement: Option<Measurement>,
+    // Discard earlier measurements of the source before using this one
+    pub(crate) reset: bool,
+}
+
+impl NtpSourceUpdate {
+    /// Whether the update carries a new measurement of the source
+    pub fn has_measurement(&self) -> bool {
+        self.measurement.is_some()
+    }
 }
 
 #[cfg(feature = "__internal-test")]
@@ -338,6 +664,7 @@ impl NtpSourceUpdate {
         NtpSourceUpdate {
             snapshot,
             measurement: None,
+            reset: false,
         }
     }
 
@@ -345,6 +672,7 @@ impl NtpSourceUpdate {
         NtpSourceUpdate {
             snapshot,
             measurement: Some(measurement),
+            reset: false,
         }
     }
 }
@@ -358,6 +686,9 @@ pub enum NtpSourceAction {
     UpdateSystem(NtpSourceUpdate),
     /// Call [`NtpSource::handle_timer`] after given duration
     SetTimer(Duration),
+    /// Call [`NtpSource::handle_timer`] after the given poll interval. The caller is expected to
+    /// add some random jitter to it, to make it harder to predict poll requests.
+    SetPollTimer(Duration),
     /// A complete reset of the connection is necessary, including a potential new NTSKE client session and/or DNS lookup.
     Reset,
     /// We must stop talking to this particular server.
@@ -414,15 +745,29 @@ impl NtpSource {
 
                 last_poll_interval: source_defaults_config.poll_interval_limits.min,
                 remote_min_poll_interval: source_defaults_config.poll_interval_limits.min,
-
-                current_request_identifier: None,
+                remote_advertised_poll_interval: None,
+
+                outstanding_requests: VecDeque::new(),
+                answered_request_identifier: None,
+                duplicate_packets: 0,
+                packets: PacketCounters::default(),
+                bogus_packets: 0,
+                parse_failures: 0,
+                incompatible: false,
+                send_delay: None,
+                send_timestamped: false,
                 source_id: ReferenceId::from_ip(source_addr.ip()),
                 source_addr,
                 reach: Default::default(),
+                reach_history: Default::default(),
                 tries: 0,
+                recovering: false,
+                initial_retries: 0,
+                initial_retry_pending: false,
 
                 stratum: 16,
                 reference_id: ReferenceId::NONE,
+                last_reference_change: None,
 
                 source_defaults_config,
 
@@ -454,21 +799,107 @@ impl NtpSource {
         )
     }
 
+    /// Capture the full internal state of the source, for bug reports
+    pub fn debug_dump(&self) -> NtpSourceDebugDump {
+        let now = NtpInstant::now();
+        NtpSourceDebugDump {
+            source_addr: self.source_addr,
+            source_id: self.source_id,
+            protocol_version: format!("{:?}", self.protocol_version),
+            last_poll_interval: self.last_poll_interval,
+            remote_min_poll_interval: self.remote_min_poll_interval,
+            remote_advertised_poll_interval: self.remote_advertised_poll_interval,
+            reach: self.reach,
+            reachability_pct_1h: self
+                .reach_history
+                .percentage(now, Duration::from_secs(3600)),
+            reachability_pct_24h: self.reach_history.percentage(now, REACH_HISTORY_LENGTH),
+            tries: self.tries,
+            recovering: self.recovering,
+            initial_retries: self.initial_retries,
+            awaiting_response: self
+                .outstanding_requests
+                .iter()
+                .any(|request| request.expires >= now),
+            stratum: self.stratum,
+            reference_id: self.reference_id,
+            last_reference_change: self.last_reference_change,
+            packets: self.packets,
+            duplicate_packets: self.duplicate_packets,
+            bogus_packets: self.bogus_packets,
+            parse_failures: self.parse_failures,
+            incompatible: self.incompatible,
+            send_delay: self.send_delay,
+            send_timestamped: self.send_timestamped,
+            nts: self.nts.is_some(),
+            nts_cookies: self.nts.as_ref().map_or(0, |nts| nts.cookies.len()),
+            config: format!("{:?}", self.source_defaults_config),
+        }
+    }
+
+    /// The minimum poll interval the server asks of us. This never exceeds
+    /// our own maximum poll interval, as the advertised poll interval is
+    /// capped to it when received.
+    fn remote_poll_floor(&self) -> PollInterval {
+        match self.remote_advertised_poll_interval {
+            Some(advertised) => self.remote_min_poll_interval.max(advertised),
+            None => self.remote_min_poll_interval,
+        }
+    }
+
     pub fn current_poll_interval(&self, system: SystemSnapshot) -> PollInterval {
-        system
+        let poll_interval = system
             .time_snapshot
             .poll_interval
-            .max(self.remote_min_poll_interval)
+            .max(self.remote_poll_floor());
+
+        match self.source_defaults_config.recovery_poll {
+            Some(recovery_poll) if self.recovering => recovery_poll
+                .max(self.source_defaults_config.poll_interval_limits.min)
+                .max(self.remote_poll_floor())
+                .min(poll_interval),
+            _ => poll_interval,
+        }
+    }
+
+    /// Start polling at the recovery poll interval once the source is
+    /// unreachable, and only go back to the normal poll interval after a
+    /// few consecutive answers, so a source that answers sporadically
+    /// doesn't flap between the two.
+    fn update_recovering(&mut self) {
+        if !self.reach.is_reachable() {
+            self.recovering = true;
+        } else if self.reach.answered_polls() >= RECOVERY_ANSWERED_POLLS {
+            self.recovering = false;
+        }
     }
 
     #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
     pub fn handle_timer(&mut self, system: SystemSnapshot) -> NtpSourceActionIterator {
+        if self.incompatible {
+            // Don't poll anymore, but keep the timer going so the source
+            // stays visible as incompatible instead of being reset and retried
+            return actions!(NtpSourceAction::SetTimer(
+                self.source_defaults_config
+                    .poll_interval_limits
+                    .max
+                    .as_system_duration()
+            ));
+        }
+
         if !self.reach.is_reachable() && self.tries >= STARTUP_TRIES_THRESHOLD {
             return actions!(NtpSourceAction::Reset);
         }
 
+        self.update_recovering();
         self.reach.poll();
-        self.tries = self.tries.saturating_add(1);
+        self.reach_history.poll(NtpInstant::now());
+        // Quick retries don't count towards giving up on the source, or a
+        // dead source would be reset and retried rapidly forever
+        if !self.initial_retry_pending {
+            self.tries = self.tries.saturating_add(1);
+        }
+        self.packets.sent = self.packets.sent.saturating_add(1);
 
         let poll_interval = self.current_poll_interval(system);
         let (mut packet, identifier) = match &mut self.nts {
@@ -485,7 +916,8 @@ impl NtpSource {
                     .gap()
                     .min(((self.buffer.len() - 300) / cookie.len()).min(u8::MAX as usize) as u8);
                 match self.protocol_version {
-                    ProtocolVersion::V4 => {
+                    // NTS sources are never downgraded to NTPv3
+                    ProtocolVersion::V3 | ProtocolVersion::V4 => {
                         NtpPacket::nts_poll_message(&cookie, new_cookies, poll_interval)
                     }
                     #[cfg(feature = "ntpv5")]
@@ -495,6 +927,7 @@ impl NtpSource {
                 }
             }
             None => match self.protocol_version {
+                ProtocolVersion::V3 => NtpPacket::poll_message_v3(poll_interval),
                 ProtocolVersion::V4 => NtpPacket::poll_message(poll_interval),
                 #[cfg(feature = "ntpv5")]
                 ProtocolVersion::V4UpgradingToV5 { .. } => {
@@ -504,7 +937,18 @@ impl NtpSource {
                 ProtocolVersion::V5 => NtpPacket::poll_message_v5(poll_interval),
             },
         };
-        self.current_request_identifier = Some((identifier, NtpInstant::now() + POLL_WINDOW));
+        let now = NtpInstant::now();
+        self.outstanding_requests
+            .retain(|request| request.expires >= now);
+        self.outstanding_requests.push_back(OutstandingRequest {
+            identifier,
+            expires: now + POLL_WINDOW,
+            send_time: None,
+        });
+        let max_outstanding = self.source_defaults_config.max_outstanding_requests.max(1);
+        while self.outstanding_requests.len() > max_outstanding {
+            self.outstanding_requests.pop_front();
+        }
 
         #[cfg(feature = "ntpv5")]
         if let NtpHeader::V5(header) = packet.header() {
@@ -529,52 +973,173 @@ impl NtpSource {
         let used = cursor.position();
         let result = &cursor.into_inner()[..used as usize];
 
+        // Until the source answers for the first time, poll again soon in
+        // case the request got lost, up to the configured number of times
+        self.initial_retry_pending = self.packets.received == 0
+            && self.initial_retries < self.source_defaults_config.initial_poll_retries;
+        let timer = if self.initial_retry_pending {
+            self.initial_retries += 1;
+            NtpSourceAction::SetTimer(
+                Duration::from_secs(self.source_defaults_config.initial_poll_retry_interval)
+                    .min(poll_interval.as_system_duration()),
+            )
+        } else {
+            NtpSourceAction::SetPollTimer(poll_interval.as_system_duration())
+        };
+
         actions!(
             NtpSourceAction::Send(result.into()),
             NtpSourceAction::UpdateSystem(NtpSourceUpdate {
                 snapshot,
-                measurement: None
+                measurement: None,
+                reset: false,
             }),
-            // randomize the poll interval a little to make it harder to predict poll requests
-            NtpSourceAction::SetTimer(
-                poll_interval
-                    .as_system_duration()
-                    .mul_f64(thread_rng().gen_range(1.01..=1.05))
-            )
+            timer
         )
     }
 
+    /// Determine the send timestamp of the request just sent, from the
+    /// timestamp taken in userspace right before sending it and the kernel
+    /// send timestamp, if one is available. Without a kernel timestamp the
+    /// delay between the two, as estimated from earlier kernel timestamps, is
+    /// added to the userspace timestamp. The result is recorded as the send
+    /// time of the request, to be used when its response arrives.
+    pub fn send_timestamp(
+        &mut self,
+        before_send: NtpTimestamp,
+        kernel: Option<NtpTimestamp>,
+    ) -> NtpTimestamp {
+        let send_time = self.estimate_send_timestamp(before_send, kernel);
+        if let Some(request) = self.outstanding_requests.back_mut() {
+            request.send_time.get_or_insert(send_time);
+        }
+        send_time
+    }
+
+    /// Send timestamp of the most recent request that made it onto the wire
+    pub fn last_send_timestamp(&self) -> Option<NtpTimestamp> {
+        self.outstanding_requests
+            .iter()
+            .rev()
+            .find_map(|request| request.send_time)
+    }
+
+    fn estimate_send_timestamp(
+        &mut self,
+        before_send: NtpTimestamp,
+        kernel: Option<NtpTimestamp>,
+    ) -> NtpTimestamp {
+        self.send_timestamped = kernel.is_some();
+
+        let Some(kernel) = kernel else {
+            return before_send + self.send_delay.unwrap_or(NtpDuration::ZERO);
+        };
+
+        let delay = kernel - before_send;
+        if delay >= NtpDuration::ZERO && delay.to_seconds() <= MAX_SEND_DELAY {
+            self.send_delay = Some(match self.send_delay {
+                Some(estimate) => estimate + (delay - estimate) / SEND_DELAY_AVERAGING,
+                None => delay,
+            });
+        }
+
+        kernel
+    }
+
     #[instrument(skip(self, system), fields(source = debug(self.source_id)))]
     pub fn handle_incoming(
         &mut self,
         system: SystemSnapshot,
         message: &[u8],
         local_clock_time: NtpInstant,
-        send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
     ) -> NtpSourceActionIterator {
+        self.packets.received = self.packets.received.saturating_add(1);
+
         let message =
             match NtpPacket::deserialize(message, &self.nts.as_ref().map(|nts| nts.s2c.as_ref())) {
                 Ok((packet, _)) => packet,
                 Err(e) => {
                     warn!("received invalid packet: {}", e);
-                    return actions!();
+                    self.packets.rejected_malformed =
+                        self.packets.rejected_malformed.saturating_add(1);
+                    return self.handle_parse_failure();
                 }
             };
 
         if message.version() != self.protocol_version.expected_incoming_version() {
+            debug!(
+                version = message.version(),
+                "Received packet with unexpected version"
+            );
+            self.packets.rejected_malformed = self.packets.rejected_malformed.saturating_add(1);
+            return self.handle_parse_failure();
+        }
+
+        // A second response to a request we already processed, for example
+        // because the network duplicated it, would count the same exchange
+        // twice.
+        if matches!(self.answered_request_identifier, Some(answered) if message.matches_origin(answered))
+        {
+            self.duplicate_packets = self.duplicate_packets.saturating_add(1);
+            self.packets.rejected_unexpected = self.packets.rejected_unexpected.saturating_add(1);
+            debug!(
+                duplicate_packets = self.duplicate_packets,
+                "Received duplicate response from source"
+            );
             return actions!();
         }
 
-        let request_identifier = match self.current_request_identifier {
-            Some((next_expected_origin, validity)) if validity >= NtpInstant::now() => {
-                next_expected_origin
-            }
-            _ => {
-                debug!("Received old/unexpected packet from source");
-                return actions!();
-            }
+        let now = NtpInstant::now();
+        self.outstanding_requests
+            .retain(|request| request.expires >= now);
+        if self.outstanding_requests.is_empty() {
+            debug!("Received old/unexpected packet from source");
+            self.packets.rejected_unexpected = self.packets.rejected_unexpected.saturating_add(1);
+            return actions!();
+        }
+
+        // The transmit timestamp we put on the wire is random, and the real
+        // send time is tracked locally. So the origin timestamp of a genuine
+        // response must match the random value from our outstanding request.
+        //
+        // Servers fill the origin field by copying the transmit field of the
+        // request verbatim, whether or not they otherwise follow the spec, so
+        // the random value is the only thing any server can echo. In
+        // particular, a response carrying our real send time as its origin
+        // can't be an echo of our request, as that time never left this host.
+        // It is rejected like any other mismatch: accepting it would let an
+        // off-path attacker that guesses our clock inject responses, which
+        // is exactly what the random value protects against.
+        //
+        // On high-latency links the response to a request can arrive after
+        // the next request was sent, so any of the outstanding requests may
+        // be the one answered.
+        let Some(request_index) = self
+            .outstanding_requests
+            .iter()
+            .position(|request| message.matches_origin(request.identifier))
+        else {
+            self.bogus_packets = self.bogus_packets.saturating_add(1);
+            self.packets.rejected_unexpected = self.packets.rejected_unexpected.saturating_add(1);
+            debug!(
+                bogus_packets = self.bogus_packets,
+                "Received bogus packet from source, origin timestamp does not match our request"
+            );
+            return actions!();
+        };
+        let request = self.outstanding_requests[request_index];
+        let request_identifier = request.identifier;
+
+        let Some(send_time) = request.send_time else {
+            // We never learned that this request made it onto the wire
+            debug!("Received response to a request that was not sent");
+            self.packets.rejected_unexpected = self.packets.rejected_unexpected.saturating_add(1);
+            return actions!();
         };
+        if request_index + 1 < self.outstanding_requests.len() {
+            debug!("Received response to an earlier request after sending the next one");
+        }
 
         #[cfg(feature = "ntpv5")]
         if message.valid_server_response(request_identifier, self.nts.is_some()) {
@@ -601,8 +1166,37 @@ impl NtpSource {
             // packet that is not a response will leave us vulnerable
             // to denial of service attacks.
             debug!("Received old/unexpected packet from source");
+            self.packets.rejected_unexpected = self.packets.rejected_unexpected.saturating_add(1);
+            actions!()
+        } else if message.is_kiss() {
+            self.packets.kiss_of_death = self.packets.kiss_of_death.saturating_add(1);
+            self.handle_kiss(&message)
+        } else if message.stratum() > MAX_STRATUM {
+            // A servers stratum should be between 1 and MAX_STRATUM (16) inclusive.
+            warn!(
+                "Received message from server with excessive stratum {}",
+                message.stratum()
+            );
+            self.packets.rejected_invalid = self.packets.rejected_invalid.saturating_add(1);
+            actions!()
+        } else if message.mode() != NtpAssociationMode::Server {
+            // we currently only support a client <-> server association
+            warn!("Received packet with invalid mode");
+            self.packets.rejected_invalid = self.packets.rejected_invalid.saturating_add(1);
             actions!()
-        } else if message.is_kiss_rate() {
+        } else {
+            // We received a response to this request, and don't want to accept
+            // future ones to it. Responses to even earlier requests would give
+            // measurements older than this one, so those are dropped as well.
+            self.answered_request_identifier = Some(request_identifier);
+            self.outstanding_requests.drain(..=request_index);
+
+            self.process_message(system, message, local_clock_time, send_time, recv_time)
+        }
+    }
+
+    fn handle_kiss(&mut self, message: &NtpPacket) -> NtpSourceActionIterator {
+        if message.is_kiss_rate() {
             // KISS packets may not have correct timestamps at all, handle them anyway
             self.remote_min_poll_interval = Ord::max(
                 self.remote_min_poll_interval
@@ -620,23 +1214,44 @@ impl NtpSource {
             // as these can be easily faked, we dont immediately give up on receiving
             // a response.
             actions!()
-        } else if message.is_kiss() {
+        } else {
             warn!("Unrecognized KISS Message from source");
             // Ignore unrecognized control messages
             actions!()
-        } else if message.stratum() > MAX_STRATUM {
-            // A servers stratum should be between 1 and MAX_STRATUM (16) inclusive.
-            warn!(
-                "Received message from server with excessive stratum {}",
-                message.stratum()
-            );
-            actions!()
-        } else if message.mode() != NtpAssociationMode::Server {
-            // we currently only support a client <-> server association
-            warn!("Received packet with invalid mode");
-            actions!()
-        } else {
-            self.process_message(system, message, local_clock_time, send_time, recv_time)
+        }
+    }
+
+    /// Keep track of consecutive replies we could not use, falling back to
+    /// NTPv3 when NTPv4 keeps failing, and giving up when NTPv3 fails too.
+    fn handle_parse_failure(&mut self) -> NtpSourceActionIterator {
+        self.parse_failures = self.parse_failures.saturating_add(1);
+        if self.parse_failures < VERSION_FALLBACK_THRESHOLD || self.nts.is_some() {
+            return actions!();
+        }
+
+        match self.protocol_version {
+            ProtocolVersion::V4 => {
+                warn!("Replies of source keep failing to parse, falling back to NTPv3");
+                self.protocol_version = ProtocolVersion::V3;
+                self.parse_failures = 0;
+                // give NTPv3 the full number of tries before resetting
+                self.tries = 0;
+                actions!()
+            }
+            ProtocolVersion::V3 => {
+                warn!(
+                    "Replies of source can't be parsed with NTPv4 nor NTPv3, no longer polling it"
+                );
+                self.incompatible = true;
+                self.outstanding_requests.clear();
+                actions!(NtpSourceAction::UpdateSystem(NtpSourceUpdate {
+                    snapshot: NtpSourceSnapshot::from_source(self),
+                    measurement: None,
+                    reset: false,
+                }))
+            }
+            #[cfg(feature = "ntpv5")]
+            ProtocolVersion::V4UpgradingToV5 { .. } | ProtocolVersion::V5 => actions!(),
         }
     }
 
@@ -650,29 +1265,57 @@ impl NtpSource {
         recv_time: NtpTimestamp,
     ) -> NtpSourceActionIterator {
         trace!("Packet accepted for processing");
+        self.parse_failures = 0;
+        // Only a source we recently heard from has a reference that can change
+        let had_reference = self.reach.is_reachable();
+
         // For reachability, mark that we have had a response
         self.reach.received_packet();
-
-        // we received this packet, and don't want to accept future ones with this next_expected_origin
-        self.current_request_identifier = None;
+        self.reach_history.received_packet();
 
         // Update stratum and reference id
+        let reference_changed = had_reference
+            && (self.stratum != message.stratum() || self.reference_id != message.reference_id());
+        if reference_changed {
+            let change = ReferenceChange {
+                previous_stratum: self.stratum,
+                previous_reference_id: self.reference_id,
+                stratum: message.stratum(),
+                reference_id: message.reference_id(),
+            };
+            info!(
+                previous_stratum = change.previous_stratum,
+                previous_reference_id = ?change.previous_reference_id,
+                stratum = change.stratum,
+                reference_id = ?change.reference_id,
+                "Source changed its reference"
+            );
+            self.last_reference_change = Some(change);
+        }
         self.stratum = message.stratum();
         self.reference_id = message.reference_id();
 
+        // Handle the poll interval advertised by the server. Servers usually
+        // echo the poll interval of the request, which tells us nothing about
+        // the rate they accept, so only a differing value is taken as the new
+        // minimum. It is capped at our own maximum so a server can't make us
+        // poll arbitrarily slowly.
+        let advertised_poll = message
+            .poll()
+            .min(self.source_defaults_config.poll_interval_limits.max);
+        if advertised_poll != self.last_poll_interval
+            && Some(advertised_poll) != self.remote_advertised_poll_interval
+        {
+            debug!(
+                ?advertised_poll,
+                ?self.last_poll_interval,
+                "Adapting to poll interval advertised by server"
+            );
+            self.remote_advertised_poll_interval = Some(advertised_poll);
+        }
+
         #[cfg(feature = "ntpv5")]
         if let NtpHeader::V5(header) = message.header() {
-            // Handle new requested poll interval
-            let requested_poll = message.poll();
-            if requested_poll > self.remote_min_poll_interval {
-                debug!(
-                    ?requested_poll,
-                    ?self.remote_min_poll_interval,
-                    "Adapting to longer poll interval requested by server"
-                );
-                self.remote_min_poll_interval = requested_poll;
-            }
-
             // Update our bloom filter (we need separate branches due to types
             let bloom_responses = if self.nts.is_some() {
                 message
@@ -711,6 +1354,18 @@ impl NtpSource {
             system.time_snapshot.precision,
         );
 
+        if self.source_defaults_config.log_exchanges {
+            info!(
+                t1 = ?send_time,
+                t2 = ?measurement.receive_timestamp,
+                t3 = ?measurement.transmit_timestamp,
+                t4 = ?recv_time,
+                offset = measurement.offset.to_seconds(),
+                delay = measurement.delay.to_seconds(),
+                "Completed exchange with source"
+            );
+        }
+
         // Process new cookies
         if let Some(nts) = self.nts.as_mut() {
             for cookie in message.new_cookies() {
@@ -718,10 +1373,40 @@ impl NtpSource {
             }
         }
 
-        actions!(NtpSourceAction::UpdateSystem(NtpSourceUpdate {
+        let update = NtpSourceAction::UpdateSystem(NtpSourceUpdate {
             snapshot: NtpSourceSnapshot::from_source(self),
             measurement: Some(measurement),
-        }))
+            reset: reference_changed && self.source_defaults_config.reset_on_reference_change,
+        });
+
+        // The source answered, so continue at the normal poll interval
+        // instead of with the pending quick retry
+        if self.initial_retry_pending {
+            self.initial_retry_pending = false;
+            actions!(
+                update,
+                NtpSourceAction::SetPollTimer(self.last_poll_interval.as_system_duration())
+            )
+        } else {
+            actions!(update)
+        }
+    }
+
+    /// Handle the timer like [`Self::handle_timer`], and record a send
+    /// timestamp for the request sent, as the daemon does once it is on the
+    /// wire
+    #[cfg(test)]
+    pub(crate) fn handle_timer_and_send(
+        &mut self,
+        system: SystemSnapshot,
+    ) -> NtpSourceActionIterator {
+        let actions = self.handle_timer(system);
+        if let Some(request) = self.outstanding_requests.back_mut() {
+            request
+                .send_time
+                .get_or_insert(NtpTimestamp::from_fixed_int(0));
+        }
+        actions
     }
 
     #[cfg(test)]
@@ -733,16 +1418,30 @@ impl NtpSource {
 
             last_poll_interval: PollInterval::default(),
             remote_min_poll_interval: PollInterval::default(),
-
-            current_request_identifier: None,
+            remote_advertised_poll_interval: None,
+
+            outstanding_requests: VecDeque::new(),
+            answered_request_identifier: None,
+            duplicate_packets: 0,
+            packets: PacketCounters::default(),
+            bogus_packets: 0,
+            parse_failures: 0,
+            incompatible: false,
+            send_delay: None,
+            send_timestamped: false,
 
             source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
             source_id: ReferenceId::from_int(0),
             reach: Reach::default(),
+            reach_history: ReachHistory::default(),
             tries: 0,
+            recovering: false,
+            initial_retries: 0,
+            initial_retry_pending: false,
 
             stratum: 0,
             reference_id: ReferenceId::from_int(0),
+            last_reference_change: None,
 
             source_defaults_config: SourceDefaultsConfig::default(),
 
@@ -907,6 +1606,50 @@ mod test {
         assert!(reach.is_reachable());
     }
 
+    #[test]
+    fn reachability_history() {
+        let base = NtpInstant::now();
+        let at = |seconds: u64| base + Duration::from_secs(seconds);
+        let hour = Duration::from_secs(3600);
+
+        let mut history = ReachHistory::default();
+        assert_eq!(history.percentage(base, hour), None);
+
+        // 30 answered polls, 64 seconds apart
+        for i in 0..30 {
+            history.poll(at(i * 64));
+            history.received_packet();
+        }
+
+        // a single unanswered poll, after which the poll interval went up
+        history.poll(at(1920));
+        history.poll(at(2944));
+
+        // time based, not the 30 out of 31 polls that were answered
+        let percentage = history.percentage(at(2944), hour).unwrap();
+        assert!((percentage - 100.0 * 1920.0 / 2944.0).abs() < 1e-6);
+        assert_eq!(
+            history.percentage(at(2944), REACH_HISTORY_LENGTH),
+            Some(percentage)
+        );
+
+        // polls that started over an hour ago only count for the longer window
+        history.poll(at(2944 + 7200));
+        assert_eq!(history.percentage(at(2944 + 7200), hour), None);
+        let percentage = history
+            .percentage(at(2944 + 7200), REACH_HISTORY_LENGTH)
+            .unwrap();
+        assert!((percentage - 100.0 * 1920.0 / 10144.0).abs() < 1e-6);
+
+        // and are forgotten after a day
+        history.poll(at(2944 + 7200 + 24 * 3600));
+        assert_eq!(history.buckets.len(), 1);
+        assert_eq!(
+            history.percentage(at(2944 + 7200 + 24 * 3600), REACH_HISTORY_LENGTH),
+            Some(0.0)
+        );
+    }
+
     #[test]
     fn test_accept_synchronization() {
         use AcceptSynchronizationError::*;
@@ -921,10 +1664,17 @@ mod test {
             system.server_id = ServerId::new(&mut thread_rng());
         }
 
+        let mut expected_stratum = None;
+
         macro_rules! accept {
             () => {{
                 let snapshot = NtpSourceSnapshot::from_source(&source);
-                snapshot.accept_synchronization(16, &["127.0.0.1".parse().unwrap()], &system)
+                snapshot.accept_synchronization(
+                    16,
+                    &["127.0.0.1".parse().unwrap()],
+                    expected_stratum.as_ref(),
+                    &system,
+                )
             }};
         }
 
@@ -938,6 +1688,14 @@ mod test {
 
         assert_eq!(accept!(), Ok(()));
 
+        // a stratum 1 server that degraded to stratum 2
+        expected_stratum = Some(1..=1);
+        source.stratum = 2;
+        assert_eq!(accept!(), Err(UnexpectedStratum));
+        source.stratum = 1;
+        assert_eq!(accept!(), Ok(()));
+        expected_stratum = None;
+
         source.stratum = 42;
         assert_eq!(accept!(), Err(Stratum));
     }
@@ -962,13 +1720,361 @@ mod test {
         assert!(source.current_poll_interval(system) >= system.time_snapshot.poll_interval);
     }
 
+    #[test]
+    fn test_advertised_poll_interval() {
+        let mut source = NtpSource::test_ntp_source();
+        let system = SystemSnapshot::default();
+        let limits = source.source_defaults_config.poll_interval_limits;
+
+        let respond = |source: &mut NtpSource, poll: PollInterval| {
+            let mut outgoingbuf = None;
+            for action in source.handle_timer_and_send(system) {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            let outgoingbuf = outgoingbuf.unwrap();
+            let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_poll(poll);
+            packet.set_origin_timestamp(outgoing.transmit_timestamp());
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+            source
+                .handle_incoming(
+                    system,
+                    &packet.serialize_without_encryption_vec(None).unwrap(),
+                    NtpInstant::now(),
+                    NtpTimestamp::from_fixed_int(400),
+                )
+                .for_each(drop);
+        };
+
+        // a server echoing our poll interval does not constrain us
+        let initial = source.current_poll_interval(system);
+        respond(&mut source, initial);
+        assert_eq!(source.current_poll_interval(system), initial);
+
+        // a longer advertised poll interval becomes a floor
+        let advertised = initial.inc(limits).inc(limits);
+        respond(&mut source, advertised);
+        assert_eq!(source.current_poll_interval(system), advertised);
+        assert_eq!(
+            NtpSourceSnapshot::from_source(&source).remote_min_poll_interval,
+            advertised
+        );
+
+        // which stays in place while the server echoes it
+        respond(&mut source, advertised);
+        assert_eq!(source.current_poll_interval(system), advertised);
+
+        // but an absurd value can't push us past our maximum
+        respond(&mut source, PollInterval::from_byte(100));
+        assert_eq!(source.current_poll_interval(system), limits.max);
+
+        // and the server may lower it again
+        respond(&mut source, initial);
+        assert_eq!(source.current_poll_interval(system), initial);
+    }
+
+    #[test]
+    fn test_poll_field_matches_interval() {
+        let limits = PollIntervalLimits::default();
+
+        for protocol_version in [ProtocolVersion::V3, ProtocolVersion::V4] {
+            let mut source = NtpSource::test_ntp_source();
+            source.protocol_version = protocol_version;
+
+            // the poll field follows our poll interval as it changes
+            let mut poll_interval = limits.min;
+            loop {
+                let mut system = SystemSnapshot::default();
+                system.time_snapshot.poll_interval = poll_interval;
+                // keep the source from being reset for not answering
+                source.reach.received_packet();
+
+                let mut outgoingbuf = None;
+                for action in source.handle_timer_and_send(system) {
+                    if let NtpSourceAction::Send(buf) = action {
+                        outgoingbuf = Some(buf);
+                    }
+                }
+                let outgoingbuf = outgoingbuf.unwrap();
+                let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+                assert_eq!(outgoing.poll(), poll_interval);
+                assert_eq!(outgoing.poll(), source.current_poll_interval(system));
+
+                if poll_interval == limits.max {
+                    break;
+                }
+                poll_interval = poll_interval.inc(limits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recovery_poll_interval() {
+        let mut source = NtpSource::test_ntp_source();
+        let limits = source.source_defaults_config.poll_interval_limits;
+        // below the minimum, so the minimum is used instead
+        source.source_defaults_config.recovery_poll = Some(PollInterval::from_byte(0));
+        let mut system = SystemSnapshot::default();
+        system.time_snapshot.poll_interval = limits.max;
+
+        let poll = |source: &mut NtpSource| {
+            let mut outgoingbuf = None;
+            for action in source.handle_timer_and_send(system) {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            NtpPacket::deserialize(&outgoingbuf.unwrap(), &NoCipher)
+                .unwrap()
+                .0
+                .transmit_timestamp()
+        };
+        let respond = |source: &mut NtpSource, request: NtpTimestamp| {
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_poll(limits.min);
+            packet.set_origin_timestamp(request);
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+            source
+                .handle_incoming(
+                    system,
+                    &packet.serialize_without_encryption_vec(None).unwrap(),
+                    NtpInstant::now(),
+                    NtpTimestamp::from_fixed_int(400),
+                )
+                .for_each(drop);
+        };
+
+        // an unreachable source is polled at the recovery poll interval
+        assert_eq!(source.current_poll_interval(system), limits.max);
+        poll(&mut source);
+        assert_eq!(source.current_poll_interval(system), limits.min);
+
+        // until it answered a few polls in a row
+        for _ in 0..RECOVERY_ANSWERED_POLLS {
+            let request = poll(&mut source);
+            assert_eq!(source.current_poll_interval(system), limits.min);
+            respond(&mut source, request);
+        }
+        poll(&mut source);
+        assert_eq!(source.current_poll_interval(system), limits.max);
+
+        // a few unanswered polls don't make it recover again
+        for _ in 0..4 {
+            poll(&mut source);
+            assert_eq!(source.current_poll_interval(system), limits.max);
+        }
+
+        // without a recovery poll interval, nothing changes
+        let mut source = NtpSource::test_ntp_source();
+        poll(&mut source);
+        assert_eq!(source.current_poll_interval(system), limits.max);
+    }
+
+    #[test]
+    fn test_duplicate_response() {
+        let mut source = NtpSource::test_ntp_source();
+        let system = SystemSnapshot::default();
+
+        let mut outgoingbuf = None;
+        for action in source.handle_timer_and_send(system) {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+        let response = packet.serialize_without_encryption_vec(None).unwrap();
+
+        let actions = source.handle_incoming(
+            system,
+            &response,
+            NtpInstant::now(),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions
+            .into_iter()
+            .any(|action| matches!(action, NtpSourceAction::UpdateSystem(_))));
+
+        let mut actions = source.handle_incoming(
+            system,
+            &response,
+            NtpInstant::now(),
+            NtpTimestamp::from_fixed_int(500),
+        );
+        assert!(actions.next().is_none());
+
+        let snapshot = NtpSourceSnapshot::from_source(&source);
+        assert_eq!(snapshot.duplicate_packets, 1);
+        assert_eq!(snapshot.bogus_packets, 0);
+    }
+
+    #[test]
+    fn test_late_response() {
+        let system = SystemSnapshot::default();
+
+        let respond = |actions: NtpSourceActionIterator| {
+            let mut outgoingbuf = None;
+            for action in actions {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            let outgoingbuf = outgoingbuf.unwrap();
+            let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_origin_timestamp(outgoing.transmit_timestamp());
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+            packet.serialize_without_encryption_vec(None).unwrap()
+        };
+        let poll = |source: &mut NtpSource| respond(source.handle_timer_and_send(system));
+        let receive = |source: &mut NtpSource, response: &[u8]| {
+            source
+                .handle_incoming(
+                    system,
+                    response,
+                    NtpInstant::now(),
+                    NtpTimestamp::from_fixed_int(400),
+                )
+                .any(|action| matches!(action, NtpSourceAction::UpdateSystem(_)))
+        };
+
+        // the response to the first poll arrives after the second was sent
+        let mut source = NtpSource::test_ntp_source();
+        let first = poll(&mut source);
+        let second = poll(&mut source);
+        assert!(receive(&mut source, &first));
+        assert!(source.debug_dump().awaiting_response);
+        assert!(receive(&mut source, &second));
+        assert!(!source.debug_dump().awaiting_response);
+        assert_eq!(source.bogus_packets, 0);
+
+        // a response to an earlier request after a later one was answered
+        // would be older than the last measurement
+        let mut source = NtpSource::test_ntp_source();
+        let first = poll(&mut source);
+        let second = poll(&mut source);
+        assert!(receive(&mut source, &second));
+        assert!(!receive(&mut source, &first));
+
+        // only the last request is answered when just one is kept
+        let mut source = NtpSource::test_ntp_source();
+        source.source_defaults_config.max_outstanding_requests = 1;
+        let first = poll(&mut source);
+        let second = poll(&mut source);
+        assert!(!receive(&mut source, &first));
+        assert_eq!(source.bogus_packets, 1);
+        assert!(receive(&mut source, &second));
+
+        // without a send timestamp, a request did not make it onto the wire
+        let mut source = NtpSource::test_ntp_source();
+        let first = poll(&mut source);
+        let second = respond(source.handle_timer(system));
+        assert!(!receive(&mut source, &second));
+        assert_eq!(source.bogus_packets, 0);
+        assert!(receive(&mut source, &first));
+    }
+
+    #[test]
+    fn test_packet_counters() {
+        let mut source = NtpSource::test_ntp_source();
+        let system = SystemSnapshot::default();
+
+        let poll = |source: &mut NtpSource| {
+            let mut outgoingbuf = None;
+            for action in source.handle_timer_and_send(system) {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            let outgoingbuf = outgoingbuf.unwrap();
+            let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_origin_timestamp(outgoing.transmit_timestamp());
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+            packet
+        };
+        let receive = |source: &mut NtpSource, packet: &[u8]| {
+            source
+                .handle_incoming(
+                    system,
+                    packet,
+                    NtpInstant::now(),
+                    NtpTimestamp::from_fixed_int(400),
+                )
+                .for_each(drop);
+        };
+
+        // garbage, then the response and a duplicate of it
+        let response = poll(&mut source)
+            .serialize_without_encryption_vec(None)
+            .unwrap();
+        receive(&mut source, &[0; 3]);
+        receive(&mut source, &response);
+        receive(&mut source, &response);
+
+        // a kiss-o'-death
+        let mut packet = poll(&mut source);
+        packet.set_stratum(0);
+        packet.set_reference_id(ReferenceId::KISS_RATE);
+        receive(
+            &mut source,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+        );
+
+        // a response in the wrong mode
+        let mut packet = poll(&mut source);
+        packet.set_mode(NtpAssociationMode::Client);
+        receive(
+            &mut source,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+        );
+
+        assert_eq!(
+            NtpSourceSnapshot::from_source(&source).packets,
+            PacketCounters {
+                sent: 3,
+                received: 5,
+                rejected_malformed: 1,
+                rejected_unexpected: 1,
+                rejected_invalid: 1,
+                kiss_of_death: 1,
+            }
+        );
+    }
+
     #[test]
     fn test_handle_incoming() {
         let base = NtpInstant::now();
         let mut source = NtpSource::test_ntp_source();
 
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -993,7 +2099,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(400),
         );
         for action in actions {
@@ -1002,6 +2107,7 @@ mod test {
                 NtpSourceAction::Reset
                     | NtpSourceAction::Demobilize
                     | NtpSourceAction::SetTimer(_)
+                    | NtpSourceAction::SetPollTimer(_)
                     | NtpSourceAction::Send(_)
             ));
         }
@@ -1009,48 +2115,373 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
     }
 
+    #[test]
+    fn test_handle_incoming_bogus_origin() {
+        let base = NtpInstant::now();
+        let mut source = NtpSource::test_ntp_source();
+
+        let system = SystemSnapshot::default();
+        let actions = source.handle_timer_and_send(system);
+        let mut outgoingbuf = None;
+        for action in actions {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp() + NtpDuration::from_seconds(1.0));
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+
+        let mut actions = source.handle_incoming(
+            system,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.next().is_none());
+        assert_eq!(source.bogus_packets, 1);
+        assert_eq!(NtpSourceSnapshot::from_source(&source).bogus_packets, 1);
+
+        // the genuine response must still be accepted afterwards
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        let mut actions = source.handle_incoming(
+            system,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.any(|action| matches!(action, NtpSourceAction::UpdateSystem(_))));
+        assert_eq!(source.bogus_packets, 1);
+    }
+
+    #[test]
+    fn test_handle_incoming_origin_is_wire_transmit() {
+        let base = NtpInstant::now();
+        let mut source = NtpSource::test_ntp_source();
+        let send_time = NtpTimestamp::from_fixed_int(0);
+
+        let system = SystemSnapshot::default();
+        let actions = source.handle_timer_and_send(system);
+        let mut outgoingbuf = None;
+        for action in actions {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+        assert_ne!(outgoing.transmit_timestamp(), send_time);
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+
+        // our real send time was never on the wire, so can't be echoed
+        packet.set_origin_timestamp(send_time);
+        let mut actions = source.handle_incoming(
+            system,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.next().is_none());
+        assert_eq!(source.bogus_packets, 1);
+
+        // the random transmit timestamp copied verbatim is accepted
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        let mut actions = source.handle_incoming(
+            system,
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.any(|action| matches!(action, NtpSourceAction::UpdateSystem(_))));
+        assert_eq!(source.bogus_packets, 1);
+    }
+
+    #[test]
+    fn test_version_fallback() {
+        let mut source = NtpSource::test_ntp_source();
+        source.protocol_version = ProtocolVersion::V4;
+        let system = SystemSnapshot::default();
+
+        let poll = |source: &mut NtpSource| {
+            let mut outgoingbuf = None;
+            for action in source.handle_timer_and_send(system) {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            outgoingbuf
+        };
+        let garbage = |source: &mut NtpSource| {
+            source
+                .handle_incoming(
+                    system,
+                    &[0xff; 12],
+                    NtpInstant::now(),
+                    NtpTimestamp::from_fixed_int(400),
+                )
+                .collect::<Vec<_>>()
+        };
+
+        // a single failure is not enough to give up on NTPv4
+        poll(&mut source).unwrap();
+        garbage(&mut source);
+        assert_eq!(source.protocol_version, ProtocolVersion::V4);
+        garbage(&mut source);
+        assert_eq!(source.protocol_version, ProtocolVersion::V3);
+
+        let request = poll(&mut source).unwrap();
+        let request = NtpPacket::deserialize(&request, &NoCipher).unwrap().0;
+        assert_eq!(request.version(), 3);
+
+        garbage(&mut source);
+        assert!(!source.incompatible);
+        let actions = garbage(&mut source);
+        assert!(source.incompatible);
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            NtpSourceAction::UpdateSystem(update) if update.snapshot.incompatible
+        )));
+
+        // an incompatible source is neither polled nor reset
+        for _ in 0..STARTUP_TRIES_THRESHOLD + 1 {
+            let actions: Vec<_> = source.handle_timer_and_send(system).collect();
+            assert!(matches!(actions[..], [NtpSourceAction::SetTimer(_)]));
+        }
+    }
+
+    #[test]
+    fn test_send_timestamp_correction() {
+        let mut source = NtpSource::test_ntp_source();
+        let before_send = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let after = |micros| before_send + NtpDuration::from_seconds(micros * 1e-6);
+
+        // without any kernel timestamps there is nothing to correct with
+        assert_eq!(source.send_timestamp(before_send, None), before_send);
+        assert!(!NtpSourceSnapshot::from_source(&source).send_timestamped);
+
+        // kernel timestamps are used as is, and teach us the send delay
+        assert_eq!(
+            source.send_timestamp(before_send, Some(after(40.0))),
+            after(40.0)
+        );
+        assert!(NtpSourceSnapshot::from_source(&source).send_timestamped);
+        source.send_timestamp(before_send, Some(after(120.0)));
+        let corrected = source.send_timestamp(before_send, None);
+        assert!(((corrected - before_send).to_seconds() - 50e-6).abs() < 1e-8);
+        assert!(!NtpSourceSnapshot::from_source(&source).send_timestamped);
+
+        // implausible delays do not disturb the estimate
+        source.send_timestamp(before_send, Some(after(-500.0)));
+        source.send_timestamp(before_send, Some(after(50_000.0)));
+        let corrected = source.send_timestamp(before_send, None);
+        assert!(((corrected - before_send).to_seconds() - 50e-6).abs() < 1e-8);
+    }
+
+    fn poll_and_respond(
+        source: &mut NtpSource,
+        stratum: u8,
+        reference_id: ReferenceId,
+    ) -> Option<NtpSourceUpdate> {
+        let system = SystemSnapshot::default();
+        let mut outgoingbuf = None;
+        for action in source.handle_timer_and_send(system) {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(stratum);
+        packet.set_reference_id(reference_id);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+
+        source
+            .handle_incoming(
+                system,
+                &packet.serialize_without_encryption_vec(None).unwrap(),
+                NtpInstant::now(),
+                NtpTimestamp::from_fixed_int(400),
+            )
+            .find_map(|action| match action {
+                NtpSourceAction::UpdateSystem(update) => Some(update),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn test_reference_change() {
+        let first = ReferenceId::from_int(1);
+        let second = ReferenceId::from_int(2);
+
+        let mut source = NtpSource::test_ntp_source();
+
+        // the first response establishes the reference, it is not a change
+        let update = poll_and_respond(&mut source, 1, first).unwrap();
+        assert_eq!(update.snapshot.last_reference_change, None);
+        assert!(!update.reset);
+
+        let update = poll_and_respond(&mut source, 1, first).unwrap();
+        assert_eq!(update.snapshot.last_reference_change, None);
+
+        let update = poll_and_respond(&mut source, 2, second).unwrap();
+        assert_eq!(
+            update.snapshot.last_reference_change,
+            Some(ReferenceChange {
+                previous_stratum: 1,
+                previous_reference_id: first,
+                stratum: 2,
+                reference_id: second,
+            })
+        );
+        assert_eq!(update.snapshot.reference_id, second);
+        // by default continuity is preferred
+        assert!(!update.reset);
+
+        // the change remains visible after later responses
+        let update = poll_and_respond(&mut source, 2, second).unwrap();
+        assert!(update.snapshot.last_reference_change.is_some());
+        assert!(!update.reset);
+
+        source.source_defaults_config.reset_on_reference_change = true;
+        let update = poll_and_respond(&mut source, 2, first).unwrap();
+        assert!(update.reset);
+        let update = poll_and_respond(&mut source, 2, first).unwrap();
+        assert!(!update.reset);
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut source = NtpSource::test_ntp_source();
+        let dump = source.debug_dump();
+        assert!(!dump.awaiting_response);
+        assert_eq!(dump.packets.received, 0);
+
+        let _ = source.handle_timer(SystemSnapshot::default());
+        let dump = source.debug_dump();
+        assert!(dump.awaiting_response);
+        assert_eq!(dump.tries, 1);
+
+        assert!(poll_and_respond(&mut source, 1, ReferenceId::from_int(7)).is_some());
+        let dump = source.debug_dump();
+        assert!(!dump.awaiting_response);
+        assert_eq!(dump.packets.received, 1);
+        assert_eq!(dump.stratum, 1);
+        assert_eq!(dump.reference_id, ReferenceId::from_int(7));
+        assert!(!dump.nts);
+        assert_eq!(dump.nts_cookies, 0);
+    }
+
     #[test]
     fn test_startup_unreachable() {
         let mut source = NtpSource::test_ntp_source();
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let mut actions = source.handle_timer(system);
+        let mut actions = source.handle_timer_and_send(system);
         assert!(matches!(actions.next(), Some(NtpSourceAction::Reset)));
     }
 
+    fn timer_of(mut actions: impl Iterator<Item = NtpSourceAction>) -> Option<Duration> {
+        actions.find_map(|action| match action {
+            NtpSourceAction::SetTimer(timer) | NtpSourceAction::SetPollTimer(timer) => Some(timer),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_initial_poll_retries() {
+        let system = SystemSnapshot::default();
+        let quick = Duration::from_secs(2);
+
+        // an unresponsive source gets the configured number of quick retries
+        let mut source = NtpSource::test_ntp_source();
+        source.source_defaults_config.initial_poll_retries = 2;
+        source.source_defaults_config.initial_poll_retry_interval = 2;
+
+        assert_eq!(timer_of(source.handle_timer_and_send(system)), Some(quick));
+        assert_eq!(timer_of(source.handle_timer_and_send(system)), Some(quick));
+        let poll_interval = timer_of(source.handle_timer_and_send(system)).unwrap();
+        assert!(poll_interval > quick);
+        assert_eq!(
+            poll_interval,
+            source.last_poll_interval.as_system_duration()
+        );
+        assert_eq!(
+            timer_of(source.handle_timer_and_send(system)),
+            Some(poll_interval)
+        );
+        // the quick retries don't count towards resetting the source
+        assert_eq!(source.tries, 2);
+
+        // once the source answers it is polled at the normal interval
+        let mut source = NtpSource::test_ntp_source();
+        source.source_defaults_config.initial_poll_retries = 3;
+        source.source_defaults_config.initial_poll_retry_interval = 2;
+        assert!(poll_and_respond(&mut source, 1, ReferenceId::from_int(0)).is_some());
+        assert!(!source.initial_retry_pending);
+        assert_eq!(
+            timer_of(source.handle_timer_and_send(system)),
+            Some(poll_interval)
+        );
+
+        // only the regular poll interval is meant to be jittered
+        let mut source = NtpSource::test_ntp_source();
+        source.source_defaults_config.initial_poll_retries = 1;
+        source.source_defaults_config.initial_poll_retry_interval = 2;
+        assert!(source
+            .handle_timer_and_send(system)
+            .any(|action| matches!(action, NtpSourceAction::SetTimer(timer) if timer == quick)));
+        assert!(source
+            .handle_timer_and_send(system)
+            .any(|action| matches!(action, NtpSourceAction::SetPollTimer(_))));
+    }
+
     #[test]
     fn test_running_unreachable() {
         let base = NtpInstant::now();
         let mut source = NtpSource::test_ntp_source();
 
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1074,7 +2505,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(400),
         );
         for action in actions {
@@ -1083,67 +2513,68 @@ mod test {
                 NtpSourceAction::Reset
                     | NtpSourceAction::Demobilize
                     | NtpSourceAction::SetTimer(_)
+                    | NtpSourceAction::SetPollTimer(_)
                     | NtpSourceAction::Send(_)
             ));
         }
 
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         for action in actions {
             assert!(!matches!(
                 action,
                 NtpSourceAction::Reset | NtpSourceAction::Demobilize
             ));
         }
-        let mut actions = source.handle_timer(system);
+        let mut actions = source.handle_timer_and_send(system);
         assert!(matches!(actions.next(), Some(NtpSourceAction::Reset)));
     }
 
@@ -1153,7 +2584,7 @@ mod test {
         let mut source = NtpSource::test_ntp_source();
 
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1177,7 +2608,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
@@ -1187,7 +2617,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
@@ -1206,14 +2635,13 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
 
         let mut packet = NtpPacket::test();
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1233,7 +2661,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(matches!(actions.next(), Some(NtpSourceAction::Demobilize)));
@@ -1246,14 +2673,13 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
 
         let mut packet = NtpPacket::test();
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1273,7 +2699,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(matches!(actions.next(), Some(NtpSourceAction::Demobilize)));
@@ -1287,7 +2712,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
@@ -1296,7 +2720,7 @@ mod test {
         let old_remote_interval = source.remote_min_poll_interval;
         let mut packet = NtpPacket::test();
         let system = SystemSnapshot::default();
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1316,7 +2740,6 @@ mod test {
             system,
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
@@ -1336,7 +2759,7 @@ mod test {
         ));
 
         for _ in 0..8 {
-            let actions = source.handle_timer(system);
+            let actions = source.handle_timer_and_send(system);
             let mut outgoingbuf = None;
             for action in actions {
                 assert!(!matches!(
@@ -1368,7 +2791,6 @@ mod test {
                 &response,
                 NtpInstant::now(),
                 NtpTimestamp::default(),
-                NtpTimestamp::default(),
             );
             for action in actions {
                 assert!(!matches!(
@@ -1378,7 +2800,7 @@ mod test {
             }
         }
 
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1407,7 +2829,7 @@ mod test {
             ProtocolVersion::V4UpgradingToV5 { .. }
         ));
 
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1436,7 +2858,6 @@ mod test {
             &response,
             NtpInstant::now(),
             NtpTimestamp::default(),
-            NtpTimestamp::default(),
         );
         for action in actions {
             assert!(!matches!(
@@ -1448,7 +2869,7 @@ mod test {
         // We should have received a upgrade response and updated to NTPv5
         assert!(matches!(source.protocol_version, ProtocolVersion::V5));
 
-        let actions = source.handle_timer(system);
+        let actions = source.handle_timer_and_send(system);
         let mut outgoingbuf = None;
         for action in actions {
             assert!(!matches!(
@@ -1484,7 +2905,7 @@ mod test {
         let mut tries = 0;
 
         while client.bloom_filter.full_filter().is_none() && tries < 100 {
-            let actions = client.handle_timer(system);
+            let actions = client.handle_timer_and_send(system);
             let mut outgoingbuf = None;
             for action in actions {
                 assert!(!matches!(
@@ -1507,7 +2928,6 @@ mod test {
                 &resp_bytes,
                 NtpInstant::now(),
                 NtpTimestamp::default(),
-                NtpTimestamp::default(),
             );
             for action in actions {
                 assert!(!matches!(
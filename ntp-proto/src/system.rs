@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::IpAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt::Debug, hash::Hash};
@@ -11,16 +12,24 @@ use crate::source::NtpSourceUpdate;
 #[cfg(feature = "ntpv5")]
 use crate::source::ProtocolVersion;
 use crate::{
-    algorithm::{KalmanClockController, ObservableSourceTimedata, StateUpdate, TimeSyncController},
+    algorithm::{
+        ConvergenceEstimate, KalmanClockController, ObservableSourceFilter,
+        ObservableSourceTimedata, SelectionStatus, SourceOptions, StateUpdate, SystemSourceChoice,
+        TimeSyncController,
+    },
     clock::NtpClock,
-    config::{SourceDefaultsConfig, SynchronizationConfig},
+    config::{SourceDefaultsConfig, StepThreshold, SynchronizationConfig},
     identifiers::ReferenceId,
+    leap_seconds::{LeapSecond, LeapSecondsFile},
     packet::NtpLeapIndicator,
-    source::NtpSourceSnapshot,
-    time_types::{NtpDuration, PollInterval},
+    source::{NtpSourceSnapshot, PacketCounters},
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
 };
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+/// How often the advertised root dispersion is updated while in holdover
+const HOLDOVER_UPDATE_INTERVAL: Duration = Duration::from_secs(16);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct TimeSnapshot {
     /// Desired poll interval
     pub poll_interval: PollInterval,
@@ -34,6 +43,23 @@ pub struct TimeSnapshot {
     pub leap_indicator: NtpLeapIndicator,
     /// Total amount that the clock has stepped
     pub accumulated_steps: NtpDuration,
+    /// Frequency correction currently applied to the clock, in PPM
+    #[serde(default)]
+    pub frequency_ppm: f64,
+    /// Offset of the clock from the combined sources at the last update,
+    /// before it was corrected
+    #[serde(default)]
+    pub system_offset: NtpDuration,
+    /// Uncertainty of the combined offset, including the spread of the
+    /// offsets of the selected sources
+    #[serde(default)]
+    pub system_jitter: NtpDuration,
+    /// Why the system source was chosen among the selected sources
+    #[serde(default)]
+    pub system_source_choice: SystemSourceChoice,
+    /// Whether an offset correction by adjusting the frequency is in progress
+    #[serde(default)]
+    pub slewing: bool,
 }
 
 impl Default for TimeSnapshot {
@@ -45,6 +71,11 @@ impl Default for TimeSnapshot {
             root_dispersion: NtpDuration::ZERO,
             leap_indicator: NtpLeapIndicator::Unknown,
             accumulated_steps: NtpDuration::ZERO,
+            frequency_ppm: 0.0,
+            system_offset: NtpDuration::ZERO,
+            system_jitter: NtpDuration::ZERO,
+            system_source_choice: SystemSourceChoice::default(),
+            slewing: false,
         }
     }
 }
@@ -53,10 +84,46 @@ impl Default for TimeSnapshot {
 pub struct SystemSnapshot {
     /// Log of the precision of the local clock
     pub stratum: u8,
+    /// Stratum we actually have, before the stratum hysteresis is applied
+    /// to the served `stratum`
+    #[serde(default = "default_raw_stratum")]
+    pub raw_stratum: u8,
     /// Reference ID of current primary time source
     pub reference_id: ReferenceId,
     /// Crossing this amount of stepping will cause a Panic
     pub accumulated_steps_threshold: Option<NtpDuration>,
+    /// Amount of stepping left before the accumulated steps threshold is crossed
+    pub accumulated_steps_headroom: Option<NtpDuration>,
+    /// Constant correction added to all measured offsets
+    #[serde(default)]
+    pub clock_offset_correction: NtpDuration,
+    /// Whether the clock is running on its own after losing all sources
+    #[serde(default)]
+    pub holdover: HoldoverState,
+    /// Whether the local clock is served as fallback for lack of sources
+    #[serde(default)]
+    pub local_clock_fallback: bool,
+    /// Whether the system jitter stayed below the lock threshold for long
+    /// enough to consider the clock stable
+    #[serde(default)]
+    pub locked: bool,
+    /// Time of the last update of the local clock, zero until the first one
+    #[serde(default)]
+    pub reference_timestamp: NtpTimestamp,
+    /// Next leap second according to the leap seconds file
+    #[serde(default)]
+    pub upcoming_leap: Option<LeapSecond>,
+    /// Raised while the offsets of two sources differ by more than the
+    /// divergence alarm threshold
+    #[serde(default)]
+    pub divergence_alarm: Option<DivergenceAlarm>,
+    /// Expected time until the clock is within the convergence target, as
+    /// of the last clock update
+    #[serde(default)]
+    pub convergence: Option<ConvergenceEstimate>,
+    /// Packets exchanged with all sources, including those since removed
+    #[serde(default)]
+    pub packets: PacketCounters,
     /// Timekeeping data
     #[serde(flatten)]
     pub time_snapshot: TimeSnapshot,
@@ -75,12 +142,20 @@ impl SystemSnapshot {
     pub fn update_timedata(&mut self, timedata: TimeSnapshot, config: &SynchronizationConfig) {
         self.time_snapshot = timedata;
         self.accumulated_steps_threshold = config.accumulated_step_panic_threshold;
+        self.accumulated_steps_headroom = self
+            .accumulated_steps_threshold
+            .map(|threshold| (threshold - timedata.accumulated_steps).max(NtpDuration::ZERO));
+        self.clock_offset_correction = config.clock_offset_correction;
     }
 
     pub fn update_used_sources(&mut self, used_sources: impl Iterator<Item = NtpSourceSnapshot>) {
         let mut used_sources = used_sources.peekable();
         if let Some(system_source_snapshot) = used_sources.peek() {
-            self.stratum = system_source_snapshot.stratum.saturating_add(1);
+            self.raw_stratum = system_source_snapshot.stratum.saturating_add(1);
+            self.stratum = self.raw_stratum;
+            // Serve the identity of the source itself (derived from its
+            // address as per RFC 5905), not the reference id it reports,
+            // so that downstream clients can detect loops through us.
             self.reference_id = system_source_snapshot.source_id;
         }
 
@@ -99,12 +174,27 @@ impl SystemSnapshot {
     }
 }
 
+fn default_raw_stratum() -> u8 {
+    16
+}
+
 impl Default for SystemSnapshot {
     fn default() -> Self {
         Self {
             stratum: 16,
+            raw_stratum: default_raw_stratum(),
             reference_id: ReferenceId::NONE,
             accumulated_steps_threshold: None,
+            accumulated_steps_headroom: None,
+            clock_offset_correction: NtpDuration::ZERO,
+            holdover: HoldoverState::Inactive,
+            local_clock_fallback: false,
+            locked: false,
+            reference_timestamp: NtpTimestamp::default(),
+            upcoming_leap: None,
+            divergence_alarm: None,
+            convergence: None,
+            packets: PacketCounters::default(),
             time_snapshot: TimeSnapshot::default(),
             #[cfg(feature = "ntpv5")]
             bloom_filter: BloomFilter::new(),
@@ -114,6 +204,43 @@ impl Default for SystemSnapshot {
     }
 }
 
+/// Two sources whose offsets differ by more than the divergence alarm
+/// threshold, the pair differing most of all
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DivergenceAlarm {
+    pub sources: [SocketAddr; 2],
+    pub divergence: NtpDuration,
+}
+
+/// State of the clock after all sources were lost
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HoldoverState {
+    /// Synchronized to sources, or never synchronized at all
+    #[default]
+    Inactive,
+    /// Keeping time on the local clock alone, with a growing root dispersion
+    Active,
+    /// Held over for longer than the configured holdover duration, no longer
+    /// serving time as synchronized
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Holdover {
+    since: NtpInstant,
+    /// Root dispersion when the last source was lost
+    root_dispersion: NtpDuration,
+}
+
+/// Role of this instance while no sources below the orphan stratum are available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrphanRole<SourceId> {
+    /// Serve the orphan stratum without synchronizing to any source
+    Leader,
+    /// Synchronize to the orphan leader
+    Following(SourceId),
+}
+
 pub struct System<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> {
     synchronization_config: SynchronizationConfig,
     source_defaults_config: SourceDefaultsConfig,
@@ -121,9 +248,30 @@ pub struct System<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> {
     ip_list: Arc<[IpAddr]>,
 
     sources: HashMap<SourceId, Option<NtpSourceSnapshot>>,
+    pinned: Option<SourceId>,
+    /// Strata outside of which sources are distrusted
+    stratum_ranges: HashMap<SourceId, RangeInclusive<u8>>,
+    /// Sources whose stratum is outside of their expected range
+    unexpected_stratum: HashSet<SourceId>,
+    orphan: Option<OrphanRole<SourceId>>,
+    /// Since when no sources below the orphan stratum are available, starting
+    /// at startup. Orphan leadership is only taken after the orphan wait.
+    external_lost_since: Option<NtpInstant>,
+    /// Better stratum not served yet, and the number of consecutive
+    /// selections it persisted for
+    stratum_candidate: Option<(u8, u32)>,
+    synchronized: bool,
+    holdover: Option<Holdover>,
+    /// Since when the system jitter is below the lock threshold, while not
+    /// locked yet
+    lock_candidate_since: Option<NtpInstant>,
+    leap_seconds: Option<LeapSecondsFile>,
+    /// Leap indicator from the leap seconds file, while it is valid
+    leap_indicator: Option<NtpLeapIndicator>,
 
     clock: C,
     controller: Option<KalmanClockController<C, SourceId>>,
+    initial_frequency: Option<f64>,
 }
 
 impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
@@ -136,12 +284,16 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
         // Setup system snapshot
         let mut system = SystemSnapshot {
             stratum: synchronization_config.local_stratum,
+            raw_stratum: synchronization_config.local_stratum,
+            clock_offset_correction: synchronization_config.clock_offset_correction,
             ..Default::default()
         };
 
         if synchronization_config.local_stratum == 1 {
-            // We are a stratum 1 server so mark our selves synchronized.
+            // We are a stratum 1 server so mark our selves synchronized,
+            // and identify the reference clock we are synchronized to.
             system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+            system.reference_id = synchronization_config.reference_id.to_reference_id();
         }
 
         System {
@@ -150,8 +302,20 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
             system,
             ip_list,
             sources: Default::default(),
+            pinned: None,
+            stratum_ranges: Default::default(),
+            unexpected_stratum: Default::default(),
+            orphan: None,
+            external_lost_since: Some(NtpInstant::now()),
+            stratum_candidate: None,
+            synchronized: false,
+            holdover: None,
+            lock_candidate_since: None,
+            leap_seconds: None,
+            leap_indicator: None,
             clock,
             controller: None,
+            initial_frequency: None,
         }
     }
 
@@ -162,44 +326,317 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
     fn clock_controller(&mut self) -> Result<&mut KalmanClockController<C, SourceId>, C::Error> {
         let controller = match self.controller.take() {
             Some(controller) => controller,
-            None => KalmanClockController::new(
-                self.clock.clone(),
-                self.synchronization_config,
-                self.source_defaults_config,
-                self.synchronization_config.algorithm,
-            )?,
+            None => {
+                let mut controller = KalmanClockController::new(
+                    self.clock.clone(),
+                    self.synchronization_config,
+                    self.source_defaults_config,
+                    self.synchronization_config.algorithm,
+                )?;
+                if let Some(frequency) = self.initial_frequency.take() {
+                    controller.restore_frequency(frequency);
+                }
+                if self.leap_indicator.is_some() {
+                    controller.set_leap_indicator(self.leap_indicator);
+                }
+                self.system.time_snapshot.precision = controller.precision();
+                controller
+            }
         };
         Ok(self.controller.insert(controller))
     }
 
-    pub fn handle_source_create(&mut self, id: SourceId) -> Result<(), C::Error> {
-        self.clock_controller()?.add_source(id);
+    pub fn handle_source_create(
+        &mut self,
+        id: SourceId,
+        options: SourceOptions,
+    ) -> Result<(), C::Error> {
+        self.clock_controller()?.add_source(id, options);
         self.sources.insert(id, None);
         Ok(())
     }
 
+    /// Change the largest step of the clock allowed after startup. The
+    /// controller uses the new threshold from its next decision on. Returns
+    /// the previous threshold.
+    pub fn update_single_step_panic_threshold(
+        &mut self,
+        threshold: StepThreshold,
+    ) -> StepThreshold {
+        let previous = std::mem::replace(
+            &mut self.synchronization_config.single_step_panic_threshold,
+            threshold,
+        );
+        if let Some(controller) = self.controller.as_mut() {
+            controller.update_config(
+                self.synchronization_config,
+                self.source_defaults_config,
+                self.synchronization_config.algorithm,
+            );
+        }
+        previous
+    }
+
+    /// The source the system is pinned to, if any
+    pub fn pinned_source(&self) -> Option<SourceId> {
+        self.pinned
+    }
+
+    /// Synchronize to only the given source, bypassing the selection and
+    /// combination of sources. Passing None returns to automatic selection.
+    pub fn pin_source(&mut self, id: Option<SourceId>) -> Result<(), C::Error> {
+        self.pinned = id;
+        if !matches!(self.orphan, Some(OrphanRole::Following(_))) {
+            self.clock_controller()?.pin_source(id);
+        }
+        Ok(())
+    }
+
+    /// Only synchronize to a source while its stratum is within the given
+    /// range, for example to distrust a stratum 1 server that lost its own
+    /// reference clock.
+    pub fn set_source_stratum_range(&mut self, id: SourceId, range: RangeInclusive<u8>) {
+        self.stratum_ranges.insert(id, range);
+    }
+
+    /// Warn when the stratum of a source leaves its expected range, and
+    /// note when it returns
+    fn update_unexpected_stratum(&mut self, id: SourceId, snapshot: &NtpSourceSnapshot) {
+        let Some(range) = self.stratum_ranges.get(&id) else {
+            return;
+        };
+
+        if range.contains(&snapshot.stratum) {
+            if self.unexpected_stratum.remove(&id) {
+                tracing::info!(
+                    source = ?snapshot.source_addr,
+                    stratum = snapshot.stratum,
+                    "Stratum of source is back within its expected range"
+                );
+            }
+        } else if self.unexpected_stratum.insert(id) {
+            tracing::warn!(
+                source = ?snapshot.source_addr,
+                stratum = snapshot.stratum,
+                min_stratum = range.start(),
+                max_stratum = range.end(),
+                "Stratum of source is outside its expected range, no longer synchronizing to it"
+            );
+        }
+    }
+
     pub fn handle_source_remove(&mut self, id: SourceId) -> Result<(), C::Error> {
         self.clock_controller()?.remove_source(id);
         self.sources.remove(&id);
+        self.stratum_ranges.remove(&id);
+        self.unexpected_stratum.remove(&id);
+        if self.pinned == Some(id) {
+            self.pinned = None;
+        }
+        self.update_orphan_mode(NtpInstant::now())?;
+        self.update_local_clock_fallback();
+        self.update_holdover(NtpInstant::now());
+        self.update_lock(NtpInstant::now());
         Ok(())
     }
 
-    pub fn handle_source_update(
-        &mut self,
-        id: SourceId,
-        update: NtpSourceUpdate,
-    ) -> Result<Option<Duration>, C::Error> {
-        let usable = update
-            .snapshot
+    fn accepts(&self, id: SourceId, snapshot: &NtpSourceSnapshot) -> bool {
+        snapshot
             .accept_synchronization(
                 self.synchronization_config.local_stratum,
                 self.ip_list.as_ref(),
+                self.stratum_ranges.get(&id),
                 &self.system,
             )
-            .is_ok();
+            .is_ok()
+    }
+
+    /// Sources with a snapshot that are acceptable to synchronize to
+    fn accepted_sources(&self) -> impl Iterator<Item = (SourceId, NtpSourceSnapshot)> + '_ {
+        self.sources
+            .iter()
+            .filter_map(|(id, snapshot)| snapshot.map(|snapshot| (*id, snapshot)))
+            .filter(|(id, snapshot)| self.accepts(*id, snapshot))
+    }
+
+    /// Sources at or above the orphan stratum are only used when they are
+    /// the orphan leader we follow.
+    fn usable(&self, id: SourceId, snapshot: &NtpSourceSnapshot) -> bool {
+        match self.synchronization_config.orphan_stratum {
+            Some(orphan_stratum) if snapshot.stratum >= orphan_stratum => {
+                self.orphan == Some(OrphanRole::Following(id)) && self.accepts(id, snapshot)
+            }
+            _ => self.accepts(id, snapshot),
+        }
+    }
+
+    /// Reference id this instance identifies itself with as orphan leader,
+    /// derived from its lowest (non-loopback) address.
+    fn orphan_reference_id(&self) -> ReferenceId {
+        self.ip_list
+            .iter()
+            .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+            .map(|ip| ReferenceId::from_ip(*ip))
+            .min()
+            .unwrap_or(ReferenceId::NONE)
+    }
+
+    fn apply_orphan_leader(&mut self, orphan_stratum: u8) {
+        self.system.raw_stratum = orphan_stratum;
+        self.system.stratum = self.system.raw_stratum;
+        self.system.reference_id = self.orphan_reference_id();
+        self.system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+    }
+
+    /// Whether the orphan wait passed without sources below the orphan stratum
+    fn orphan_wait_passed(&self, now: NtpInstant) -> bool {
+        self.external_lost_since.map_or(false, |since| {
+            now.abs_diff(since) >= self.synchronization_config.orphan_wait
+        })
+    }
+
+    /// Enter, leave or change roles in orphan mode, depending on whether any
+    /// sources below the orphan stratum are available.
+    fn update_orphan_mode(&mut self, now: NtpInstant) -> Result<(), C::Error> {
+        let Some(orphan_stratum) = self.synchronization_config.orphan_stratum else {
+            return Ok(());
+        };
+
+        let external = self
+            .accepted_sources()
+            .any(|(_, snapshot)| snapshot.stratum < orphan_stratum);
+
+        if external {
+            self.external_lost_since = None;
+        } else if self.external_lost_since.is_none() {
+            self.external_lost_since = Some(now);
+        }
+
+        let role = if external {
+            None
+        } else {
+            // Other leaders serve the orphan stratum, the lowest reference id wins
+            let own_id = self.orphan_reference_id();
+            let leader = self
+                .accepted_sources()
+                .filter(|(_, snapshot)| snapshot.stratum == orphan_stratum)
+                .min_by_key(|(_, snapshot)| snapshot.reference_id)
+                .filter(|(_, snapshot)| snapshot.reference_id < own_id);
+
+            match leader {
+                Some((id, _)) => Some(OrphanRole::Following(id)),
+                // Don't serve our unsynchronized clock before sources had
+                // a chance to become available
+                None if self.orphan_wait_passed(now) => Some(OrphanRole::Leader),
+                None => None,
+            }
+        };
+
+        if role == self.orphan {
+            return Ok(());
+        }
+
+        match role {
+            None => tracing::info!("Sources available again, leaving orphan mode"),
+            Some(OrphanRole::Leader) => tracing::info!(
+                stratum = orphan_stratum,
+                "No sources available, serving time as orphan leader"
+            ),
+            Some(OrphanRole::Following(id)) => {
+                tracing::info!(leader = ?id, "No sources available, following orphan leader")
+            }
+        }
+        let was_leader = self.orphan == Some(OrphanRole::Leader);
+        self.orphan = role;
+
+        let pin = match role {
+            Some(OrphanRole::Following(id)) => Some(id),
+            _ => self.pinned,
+        };
+        self.clock_controller()?.pin_source(pin);
+
+        let usable: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|(id, snapshot)| {
+                snapshot.map(|snapshot| (*id, self.usable(*id, &snapshot)))
+            })
+            .collect();
+        for (id, usable) in usable {
+            self.clock_controller()?.source_update(id, usable);
+        }
+
+        match role {
+            Some(OrphanRole::Leader) => self.apply_orphan_leader(orphan_stratum),
+            _ if was_leader => {
+                // no longer serving as leader, until synchronized again
+                self.system.raw_stratum = self.synchronization_config.local_stratum;
+                self.system.stratum = self.system.raw_stratum;
+                self.system.reference_id = ReferenceId::NONE;
+                self.system.time_snapshot.leap_indicator = NtpLeapIndicator::Unknown;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn apply_local_clock_fallback(&mut self, fallback_stratum: u8) {
+        self.system.raw_stratum = fallback_stratum;
+        self.system.stratum = self.system.raw_stratum;
+        self.system.reference_id = ReferenceId::LOCL;
+        self.system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+    }
+
+    /// Serve the local clock at the fallback stratum while no sources are
+    /// available. Orphan mode takes precedence when it is configured.
+    fn update_local_clock_fallback(&mut self) {
+        let Some(fallback_stratum) = self.synchronization_config.local_clock_fallback else {
+            return;
+        };
+
+        let active = self.orphan.is_none() && self.accepted_sources().next().is_none();
+
+        if active == self.system.local_clock_fallback {
+            return;
+        }
+        self.system.local_clock_fallback = active;
+
+        if active {
+            tracing::warn!(
+                stratum = fallback_stratum,
+                "No sources available, serving the local clock as fallback"
+            );
+            self.apply_local_clock_fallback(fallback_stratum);
+        } else {
+            tracing::info!("Sources available again, no longer serving the local clock");
+            // until synchronized to the sources again
+            self.system.raw_stratum = self.synchronization_config.local_stratum;
+            self.system.stratum = self.system.raw_stratum;
+            self.system.reference_id = ReferenceId::NONE;
+            self.system.time_snapshot.leap_indicator = NtpLeapIndicator::Unknown;
+        }
+    }
+
+    pub fn handle_source_update(
+        &mut self,
+        id: SourceId,
+        update: NtpSourceUpdate,
+    ) -> Result<Option<Duration>, C::Error> {
+        let previous = self.sources.get_mut(&id).unwrap().replace(update.snapshot);
+        let previous_packets = previous.map(|s| s.packets).unwrap_or_default();
+        self.system.packets += update.snapshot.packets.since(&previous_packets);
+        self.update_unexpected_stratum(id, &update.snapshot);
+        self.update_orphan_mode(NtpInstant::now())?;
+        self.update_local_clock_fallback();
+        self.update_holdover(NtpInstant::now());
+        let usable = self.usable(id, &update.snapshot);
+        if update.reset {
+            self.clock_controller()?.reset_source(id);
+        }
         self.clock_controller()?.source_update(id, usable);
-        *self.sources.get_mut(&id).unwrap() = Some(update.snapshot);
-        if let Some(measurement) = update.measurement {
+        if let Some(mut measurement) = update.measurement {
+            measurement.offset += self.synchronization_config.clock_offset_correction;
             let update = self.clock_controller()?.source_measurement(id, measurement);
             Ok(self.handle_algorithm_state_update(update))
         } else {
@@ -209,20 +646,296 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
 
     fn handle_algorithm_state_update(&mut self, update: StateUpdate<SourceId>) -> Option<Duration> {
         if let Some(ref used_sources) = update.used_sources {
+            self.synchronized |= !used_sources.is_empty();
+            let served_stratum = self.system.stratum;
             self.system
                 .update_used_sources(used_sources.iter().map(|v| {
                     self.sources.get(v).and_then(|snapshot| *snapshot).expect(
                     "Critical error: Source used for synchronization that is not known to system",
                 )
                 }));
+            if !used_sources.is_empty() {
+                self.smooth_stratum(served_stratum);
+            }
+            self.update_divergence_alarm();
         }
         if let Some(time_snapshot) = update.time_snapshot {
             self.system
                 .update_timedata(time_snapshot, &self.synchronization_config);
+            self.system.convergence = self.controller.as_ref().and_then(|controller| {
+                controller.convergence_estimate(self.synchronization_config.convergence_target)
+            });
+            match self.clock.now() {
+                Ok(now) => self.system.reference_timestamp = now,
+                Err(e) => tracing::warn!(error = ?e, "Could not read the time of the clock update"),
+            }
+        }
+        if let (Some(OrphanRole::Leader), Some(orphan_stratum)) =
+            (self.orphan, self.synchronization_config.orphan_stratum)
+        {
+            self.apply_orphan_leader(orphan_stratum);
         }
+        if let Some(fallback_stratum) = self
+            .synchronization_config
+            .local_clock_fallback
+            .filter(|_| self.system.local_clock_fallback)
+        {
+            self.apply_local_clock_fallback(fallback_stratum);
+        }
+        self.update_leap_seconds();
+        // the algorithm does not know about holdover, keep advertising it
+        self.update_holdover(NtpInstant::now());
+        self.update_lock(NtpInstant::now());
         update.next_update
     }
 
+    /// Keep serving the previous stratum until a better stratum persisted for
+    /// the configured number of selections. A worse stratum is served right
+    /// away, so we never claim to be closer to a reference than we are.
+    fn smooth_stratum(&mut self, served_stratum: u8) {
+        let raw_stratum = self.system.raw_stratum;
+        let required = self.synchronization_config.stratum_hysteresis;
+        // Getting synchronized isn't flapping, so it isn't delayed either
+        if required == 0 || raw_stratum >= served_stratum || served_stratum >= 16 {
+            self.stratum_candidate = None;
+            return;
+        }
+
+        let selections = match self.stratum_candidate {
+            Some((candidate, selections)) if candidate == raw_stratum => selections + 1,
+            _ => 1,
+        };
+        if selections >= required {
+            self.stratum_candidate = None;
+        } else {
+            self.stratum_candidate = Some((raw_stratum, selections));
+            self.system.stratum = served_stratum;
+        }
+    }
+
+    /// Raise the divergence alarm while the sources that differ most in the
+    /// last selection round differ by more than the threshold, and clear it
+    /// once they agree again
+    fn update_divergence_alarm(&mut self) {
+        let alarm = self
+            .synchronization_config
+            .divergence_alarm_threshold
+            .zip(self.controller.as_ref())
+            .and_then(|(threshold, controller)| {
+                let (first, second, divergence) = controller.divergence()?;
+                if divergence <= threshold {
+                    return None;
+                }
+                let first = self.sources.get(&first).copied().flatten()?;
+                let second = self.sources.get(&second).copied().flatten()?;
+                Some(DivergenceAlarm {
+                    sources: [first.source_addr, second.source_addr],
+                    divergence,
+                })
+            });
+
+        match (self.system.divergence_alarm, alarm) {
+            (None, Some(alarm)) => tracing::warn!(
+                sources = ?alarm.sources,
+                divergence = alarm.divergence.to_seconds(),
+                "Offsets of sources diverge beyond the alarm threshold"
+            ),
+            (Some(_), None) => tracing::info!("Offsets of sources agree again, alarm cleared"),
+            _ => {}
+        }
+        self.system.divergence_alarm = alarm;
+    }
+
+    /// Announce leap seconds from the given leap seconds file, instead of
+    /// relying on the sources, for as long as the file has not expired.
+    pub fn set_leap_seconds(&mut self, leap_seconds: LeapSecondsFile) {
+        self.leap_seconds = Some(leap_seconds);
+        self.update_leap_seconds();
+    }
+
+    fn update_leap_seconds(&mut self) {
+        let Some(leap_seconds) = &self.leap_seconds else {
+            return;
+        };
+        let now = match self.clock.now() {
+            Ok(now) => now,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Could not read the time to check for leap seconds");
+                return;
+            }
+        };
+
+        let leap = if leap_seconds.is_expired(now) {
+            if self.leap_indicator.is_some() {
+                tracing::warn!(
+                    "Leap seconds file expired, using the leap indicators of sources instead"
+                );
+            }
+            None
+        } else {
+            Some(leap_seconds.leap_indicator(now))
+        };
+        self.system.upcoming_leap = leap_seconds.upcoming_leap(now);
+
+        if leap != self.leap_indicator {
+            self.leap_indicator = leap;
+            if let Some(controller) = self.controller.as_mut() {
+                controller.set_leap_indicator(leap);
+            }
+        }
+        if let Some(leap) = leap {
+            if self.system.time_snapshot.leap_indicator.is_synchronized() {
+                self.system.time_snapshot.leap_indicator = leap;
+            }
+        }
+    }
+
+    /// Time until the leap indicator from the leap seconds file changes, or
+    /// the file expires, to be handled by
+    /// [`System::handle_leap_seconds_timer`].
+    pub fn leap_seconds_timer(&self) -> Option<Duration> {
+        let now = self.clock.now().ok()?;
+        let (seconds, nanos) = self
+            .leap_seconds
+            .as_ref()?
+            .next_change(now)?
+            .as_seconds_nanos();
+        Some(Duration::new(seconds as u64, nanos))
+    }
+
+    pub fn handle_leap_seconds_timer(&mut self) {
+        self.update_leap_seconds();
+    }
+
+    /// Time until the orphan wait has passed, while it is running. Call
+    /// [`System::handle_orphan_wait_timer`] once it has.
+    pub fn orphan_wait_timer(&self) -> Option<Duration> {
+        self.synchronization_config.orphan_stratum?;
+        if self.orphan == Some(OrphanRole::Leader) {
+            return None;
+        }
+        let waited = NtpInstant::now().abs_diff(self.external_lost_since?);
+        let remaining = self.synchronization_config.orphan_wait - waited;
+        (remaining > NtpDuration::ZERO).then(|| Duration::from_secs_f64(remaining.to_seconds()))
+    }
+
+    pub fn handle_orphan_wait_timer(&mut self) -> Result<(), C::Error> {
+        self.update_orphan_mode(NtpInstant::now())?;
+        self.update_holdover(NtpInstant::now());
+        Ok(())
+    }
+
+    /// Enter, update or leave holdover, depending on whether any sources are
+    /// available. While in holdover the root dispersion grows at the
+    /// dispersion growth rate, until the holdover duration runs out and we
+    /// stop serving time as synchronized.
+    fn update_holdover(&mut self, now: NtpInstant) {
+        // orphan mode or the local clock takes over when it is configured
+        let available = !self.synchronized
+            || self.orphan.is_some()
+            || self.system.local_clock_fallback
+            || self.accepted_sources().next().is_some();
+
+        if available {
+            if self.holdover.take().is_some() {
+                // the algorithm slews back to the sources from here
+                tracing::info!("Sources available again, leaving holdover");
+                self.system.holdover = HoldoverState::Inactive;
+            }
+            return;
+        }
+
+        let root_dispersion = self.system.time_snapshot.root_dispersion;
+        let holdover = *self.holdover.get_or_insert_with(|| {
+            tracing::warn!("No sources available, entering holdover");
+            Holdover {
+                since: now,
+                root_dispersion,
+            }
+        });
+        let elapsed = now.abs_diff(holdover.since);
+
+        match self.synchronization_config.holdover_duration {
+            Some(duration) if elapsed >= duration => {
+                if self.system.holdover != HoldoverState::Expired {
+                    tracing::warn!("Holdover expired, no longer serving time as synchronized");
+                }
+                self.system.holdover = HoldoverState::Expired;
+                self.system.raw_stratum = 16;
+                self.system.stratum = self.system.raw_stratum;
+                self.system.reference_id = ReferenceId::NONE;
+                self.system.time_snapshot.leap_indicator = NtpLeapIndicator::Unknown;
+            }
+            _ => {
+                self.system.holdover = HoldoverState::Active;
+                self.system.time_snapshot.root_dispersion = holdover.root_dispersion
+                    + NtpDuration::from_seconds(
+                        self.synchronization_config.algorithm.dispersion_growth_rate
+                            * elapsed.to_seconds(),
+                    );
+            }
+        }
+    }
+
+    /// Time until the advertised root dispersion should be updated through
+    /// [`System::handle_holdover_timer`], if we are in holdover.
+    pub fn holdover_timer(&self) -> Option<Duration> {
+        if self.system.holdover != HoldoverState::Active {
+            return None;
+        }
+        let holdover = self.holdover?;
+        let elapsed = holdover.since.elapsed();
+
+        let mut next = HOLDOVER_UPDATE_INTERVAL
+            - Duration::from_nanos(
+                (elapsed.as_nanos() % HOLDOVER_UPDATE_INTERVAL.as_nanos()) as u64,
+            );
+        if let Some(duration) = self.synchronization_config.holdover_duration {
+            let remaining = Duration::from_secs_f64(duration.to_seconds()).saturating_sub(elapsed);
+            next = next.min(remaining);
+        }
+        Some(next)
+    }
+
+    pub fn handle_holdover_timer(&mut self) {
+        self.update_holdover(NtpInstant::now());
+        self.update_lock(NtpInstant::now());
+    }
+
+    /// Consider the clock locked once the system jitter stayed below the lock
+    /// threshold for the lock duration while synchronized. The lock is lost
+    /// when we are no longer synchronized, or the jitter rises above the
+    /// threshold multiplied by the lock hysteresis.
+    fn update_lock(&mut self, now: NtpInstant) {
+        let Some(threshold) = self.synchronization_config.lock_jitter_threshold else {
+            self.system.locked = false;
+            self.lock_candidate_since = None;
+            return;
+        };
+
+        let synchronized = self.system.time_snapshot.leap_indicator.is_synchronized()
+            && self.system.holdover == HoldoverState::Inactive;
+        let jitter = self.system.time_snapshot.system_jitter.to_seconds();
+
+        if self.system.locked {
+            if !synchronized
+                || jitter > threshold.to_seconds() * self.synchronization_config.lock_hysteresis
+            {
+                tracing::info!(jitter, "System jitter too high, clock no longer locked");
+                self.system.locked = false;
+            }
+        } else if !synchronized || jitter >= threshold.to_seconds() {
+            self.lock_candidate_since = None;
+        } else {
+            let since = *self.lock_candidate_since.get_or_insert(now);
+            if now.abs_diff(since) >= self.synchronization_config.lock_duration {
+                tracing::info!(jitter, "Clock locked");
+                self.system.locked = true;
+                self.lock_candidate_since = None;
+            }
+        }
+    }
+
     pub fn handle_timer(&mut self) -> Option<Duration> {
         tracing::debug!("Timer expired");
         // note: local needed for borrow checker
@@ -244,11 +957,59 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
                 .copied()
                 .flatten()
                 .and_then(|v| controller.source_snapshot(id).map(|s| (v, s)))
+                .map(|(v, mut s)| {
+                    if self.unexpected_stratum.contains(&id) {
+                        s.selection = SelectionStatus::UnexpectedStratum;
+                    }
+                    (v, s)
+                })
         } else {
             None
         }
     }
 
+    /// Latest snapshot of a source, also when it has no measurements yet
+    pub fn source_snapshot(&self, id: SourceId) -> Option<NtpSourceSnapshot> {
+        self.sources.get(&id).copied().flatten()
+    }
+
+    /// Raw measurement buffers of the filter of a source, for debugging
+    pub fn observe_source_filter(&self, id: SourceId) -> Option<ObservableSourceFilter> {
+        self.controller
+            .as_ref()
+            .and_then(|controller| controller.source_filter(id))
+    }
+
+    /// Clear the running total of clock steps, restoring the full
+    /// accumulated step budget.
+    pub fn reset_accumulated_steps(&mut self) {
+        if let Some(controller) = self.controller.as_mut() {
+            controller.reset_accumulated_steps();
+        }
+        let mut time_snapshot = self.system.time_snapshot;
+        time_snapshot.accumulated_steps = NtpDuration::ZERO;
+        self.system
+            .update_timedata(time_snapshot, &self.synchronization_config);
+    }
+
+    /// Frequency correction currently applied to the clock, if we are
+    /// controlling it.
+    pub fn frequency(&self) -> Option<f64> {
+        self.controller
+            .as_ref()
+            .map(|controller| controller.frequency())
+    }
+
+    /// Start the clock from the given frequency correction instead of zero.
+    /// When the clock is not yet being controlled, this is applied once
+    /// control starts.
+    pub fn restore_frequency(&mut self, frequency: f64) {
+        match self.controller.as_mut() {
+            Some(controller) => controller.restore_frequency(frequency),
+            None => self.initial_frequency = Some(frequency),
+        }
+    }
+
     pub fn update_ip_list(&mut self, ip_list: Arc<[IpAddr]>) {
         self.ip_list = ip_list;
     }
@@ -258,10 +1019,610 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> System<C, SourceId> {
 mod tests {
     use std::net::{Ipv4Addr, SocketAddr};
 
-    use crate::time_types::PollIntervalLimits;
+    use crate::{
+        source::{Measurement, Reach},
+        time_types::PollIntervalLimits,
+        NtpTimestamp,
+    };
 
     use super::*;
 
+    #[derive(Debug, Clone)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn reachable_snapshot(address: Ipv4Addr, stratum: u8) -> NtpSourceSnapshot {
+        let mut reach = Reach::default();
+        reach.received_packet();
+        NtpSourceSnapshot {
+            source_addr: SocketAddr::new(IpAddr::V4(address), 123),
+            source_id: ReferenceId::from_ip(IpAddr::V4(address)),
+            poll_interval: PollIntervalLimits::default().min,
+            reach,
+            reachability_pct_1h: None,
+            reachability_pct_24h: None,
+            stratum,
+            // orphan leaders identify themselves by their own address
+            reference_id: ReferenceId::from_ip(IpAddr::V4(address)),
+            last_reference_change: None,
+            protocol_version: Default::default(),
+            bogus_packets: 0,
+            duplicate_packets: 0,
+            packets: Default::default(),
+            incompatible: false,
+            remote_min_poll_interval: PollIntervalLimits::default().min,
+            send_timestamped: false,
+            #[cfg(feature = "ntpv5")]
+            bloom_filter: None,
+        }
+    }
+
+    fn snapshot_update(snapshot: NtpSourceSnapshot) -> NtpSourceUpdate {
+        NtpSourceUpdate {
+            snapshot,
+            measurement: None,
+            reset: false,
+        }
+    }
+
+    #[test]
+    fn test_orphan_mode() {
+        let own_ip = Ipv4Addr::new(10, 0, 0, 5);
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig {
+                orphan_stratum: Some(10),
+                orphan_wait: NtpDuration::ZERO,
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::from([IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V4(own_ip)]),
+        );
+
+        // an external source keeps us out of orphan mode
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                1,
+                snapshot_update(reachable_snapshot([192, 0, 2, 1].into(), 2)),
+            )
+            .unwrap();
+        assert_eq!(system.orphan, None);
+
+        // a leader with a higher reference id than ours, so we lead
+        system
+            .handle_source_create(2, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                2,
+                snapshot_update(reachable_snapshot([10, 0, 0, 9].into(), 10)),
+            )
+            .unwrap();
+        system.handle_source_remove(1).unwrap();
+        assert_eq!(system.orphan, Some(OrphanRole::Leader));
+        let snapshot = system.system_snapshot();
+        assert_eq!(snapshot.stratum, 10);
+        assert_eq!(
+            snapshot.reference_id,
+            ReferenceId::from_ip(IpAddr::V4(own_ip))
+        );
+        assert_eq!(
+            snapshot.time_snapshot.leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+
+        // a leader with a lower reference id wins
+        system
+            .handle_source_create(3, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                3,
+                snapshot_update(reachable_snapshot([10, 0, 0, 1].into(), 10)),
+            )
+            .unwrap();
+        assert_eq!(system.orphan, Some(OrphanRole::Following(3)));
+        assert_eq!(system.system_snapshot().stratum, 16);
+
+        // sources below the orphan stratum end orphan mode
+        system
+            .handle_source_create(4, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                4,
+                snapshot_update(reachable_snapshot([192, 0, 2, 4].into(), 3)),
+            )
+            .unwrap();
+        assert_eq!(system.orphan, None);
+    }
+
+    #[test]
+    fn test_orphan_wait() {
+        let own_ip = Ipv4Addr::new(10, 0, 0, 5);
+        let wait = Duration::from_secs(300);
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig {
+                orphan_stratum: Some(10),
+                orphan_wait: NtpDuration::from_seconds(300.0),
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::from([IpAddr::V4(own_ip)]),
+        );
+
+        // no leadership is claimed right after startup
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        let start = NtpInstant::now();
+        assert_eq!(system.orphan, None);
+        assert!(system.orphan_wait_timer().is_some());
+        assert!(!system
+            .system_snapshot()
+            .time_snapshot
+            .leap_indicator
+            .is_synchronized());
+
+        system.update_orphan_mode(start + wait).unwrap();
+        assert_eq!(system.orphan, Some(OrphanRole::Leader));
+        assert_eq!(system.orphan_wait_timer(), None);
+
+        // an external source ends orphan mode
+        system
+            .handle_source_update(
+                1,
+                snapshot_update(reachable_snapshot([192, 0, 2, 1].into(), 2)),
+            )
+            .unwrap();
+        assert_eq!(system.orphan, None);
+
+        // and after losing it, we wait again before leading
+        system.handle_source_remove(1).unwrap();
+        assert_eq!(system.orphan, None);
+        assert!(system.orphan_wait_timer().is_some());
+        system.update_orphan_mode(NtpInstant::now() + wait).unwrap();
+        assert_eq!(system.orphan, Some(OrphanRole::Leader));
+    }
+
+    #[test]
+    fn test_clock_offset_correction() {
+        let offset = NtpDuration::from_seconds(0.1);
+        let delay = NtpDuration::from_seconds(0.02);
+
+        // a positive correction moves the local clock ahead, so the measured
+        // offset of the source grows
+        for (correction, expected) in [(0.25, 0.35), (-0.25, -0.15), (0.0, 0.1)] {
+            let mut system = System::new(
+                TestClock {},
+                SynchronizationConfig {
+                    clock_offset_correction: NtpDuration::from_seconds(correction),
+                    ..Default::default()
+                },
+                SourceDefaultsConfig::default(),
+                Arc::new([]),
+            );
+            system
+                .handle_source_create(1, SourceOptions::default())
+                .unwrap();
+            system
+                .handle_source_update(
+                    1,
+                    NtpSourceUpdate {
+                        snapshot: reachable_snapshot([192, 0, 2, 1].into(), 2),
+                        measurement: Some(Measurement {
+                            delay,
+                            offset,
+                            transmit_timestamp: NtpTimestamp::default(),
+                            receive_timestamp: NtpTimestamp::default(),
+                            localtime: NtpTimestamp::default(),
+                            monotime: NtpInstant::now(),
+                            stratum: 2,
+                            root_delay: NtpDuration::ZERO,
+                            root_dispersion: NtpDuration::ZERO,
+                            leap: NtpLeapIndicator::NoWarning,
+                            precision: 0,
+                        }),
+                        reset: false,
+                    },
+                )
+                .unwrap();
+
+            let filter = system.observe_source_filter(1).unwrap();
+            assert_eq!(filter.offsets.len(), 1);
+            assert!((filter.offsets[0] - expected).abs() < 1e-9);
+            assert_eq!(filter.delays, [delay.to_seconds()]);
+        }
+    }
+
+    #[test]
+    fn test_source_stratum_range() {
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        system.set_source_stratum_range(1, 1..=1);
+
+        let address = [192, 0, 2, 1].into();
+        system
+            .handle_source_update(1, snapshot_update(reachable_snapshot(address, 1)))
+            .unwrap();
+        assert_eq!(system.accepted_sources().count(), 1);
+
+        // the stratum 1 server lost its reference clock
+        system
+            .handle_source_update(1, snapshot_update(reachable_snapshot(address, 2)))
+            .unwrap();
+        assert_eq!(system.accepted_sources().count(), 0);
+        assert!(system.unexpected_stratum.contains(&1));
+
+        system
+            .handle_source_update(1, snapshot_update(reachable_snapshot(address, 1)))
+            .unwrap();
+        assert_eq!(system.accepted_sources().count(), 1);
+        assert!(system.unexpected_stratum.is_empty());
+
+        system.handle_source_remove(1).unwrap();
+        assert!(system.stratum_ranges.is_empty());
+    }
+
+    #[test]
+    fn test_local_clock_fallback() {
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig {
+                local_clock_fallback: Some(10),
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                1,
+                snapshot_update(reachable_snapshot([192, 0, 2, 1].into(), 2)),
+            )
+            .unwrap();
+        assert!(!system.system_snapshot().local_clock_fallback);
+
+        // without sources the local clock is served
+        system.handle_source_remove(1).unwrap();
+        let snapshot = system.system_snapshot();
+        assert!(snapshot.local_clock_fallback);
+        assert_eq!(snapshot.stratum, 10);
+        assert_eq!(snapshot.reference_id, ReferenceId::LOCL);
+        assert_eq!(
+            snapshot.time_snapshot.leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+
+        // until a source is available again
+        system
+            .handle_source_create(2, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                2,
+                snapshot_update(reachable_snapshot([192, 0, 2, 2].into(), 2)),
+            )
+            .unwrap();
+        let snapshot = system.system_snapshot();
+        assert!(!snapshot.local_clock_fallback);
+        assert_eq!(snapshot.stratum, 16);
+        assert_eq!(snapshot.reference_id, ReferenceId::NONE);
+    }
+
+    #[test]
+    fn test_leap_seconds_file() {
+        let mut system = System::<_, usize>::new(
+            TestClock {},
+            SynchronizationConfig {
+                local_stratum: 1,
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+
+        // the test clock is at the NTP epoch, the leap second an hour later
+        let data = crate::leap_seconds::with_hash("#@ 100000\n0 36\n3600 37\n");
+        system.set_leap_seconds(LeapSecondsFile::parse(&data).unwrap());
+        let snapshot = system.system_snapshot();
+        assert_eq!(
+            snapshot.time_snapshot.leap_indicator,
+            NtpLeapIndicator::Leap61
+        );
+        assert_eq!(
+            snapshot.upcoming_leap,
+            Some(LeapSecond {
+                time: NtpTimestamp::from_seconds_nanos_since_ntp_era(3600, 0),
+                indicator: NtpLeapIndicator::Leap61,
+            })
+        );
+        assert_eq!(system.leap_seconds_timer(), Some(Duration::from_secs(3600)));
+
+        assert_eq!(system.leap_indicator, Some(NtpLeapIndicator::Leap61));
+
+        // an expired file is ignored
+        let data = crate::leap_seconds::with_hash("#@ 0\n0 36\n3600 37\n");
+        system.set_leap_seconds(LeapSecondsFile::parse(&data).unwrap());
+        assert_eq!(system.leap_indicator, None);
+        assert_eq!(system.leap_seconds_timer(), Some(Duration::from_secs(3600)));
+    }
+
+    fn select_source(system: &mut System<TestClock, i32>, id: i32) -> SystemSnapshot {
+        system.handle_algorithm_state_update(StateUpdate {
+            time_snapshot: None,
+            used_sources: Some(vec![id]),
+            next_update: None,
+        });
+        system.system_snapshot()
+    }
+
+    #[test]
+    fn test_stratum_hysteresis() {
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig {
+                stratum_hysteresis: 3,
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        for (id, stratum) in [(1, 3), (2, 1)] {
+            let snapshot = reachable_snapshot([192, 0, 2, id as u8].into(), stratum);
+            system
+                .handle_source_create(id, SourceOptions::default())
+                .unwrap();
+            system
+                .handle_source_update(id, snapshot_update(snapshot))
+                .unwrap();
+        }
+
+        // getting synchronized is not delayed
+        assert_eq!(select_source(&mut system, 1).stratum, 4);
+
+        // a better stratum must persist first
+        let snapshot = select_source(&mut system, 2);
+        assert_eq!((snapshot.stratum, snapshot.raw_stratum), (4, 2));
+        assert_eq!(select_source(&mut system, 2).stratum, 4);
+        assert_eq!(select_source(&mut system, 2).stratum, 2);
+
+        // a worse stratum is served immediately
+        assert_eq!(select_source(&mut system, 1).stratum, 4);
+
+        // alternating sources don't make the served stratum flap
+        for _ in 0..3 {
+            assert_eq!(select_source(&mut system, 2).stratum, 4);
+            assert_eq!(select_source(&mut system, 1).stratum, 4);
+        }
+    }
+
+    #[test]
+    fn test_holdover() {
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig {
+                holdover_duration: Some(NtpDuration::from_seconds(1000.0)),
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+
+        let snapshot = reachable_snapshot([192, 0, 2, 1].into(), 2);
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(1, snapshot_update(snapshot))
+            .unwrap();
+        assert_eq!(system.system_snapshot().holdover, HoldoverState::Inactive);
+
+        // never synchronized, so there is nothing to hold over
+        system.handle_source_remove(1).unwrap();
+        assert_eq!(system.system_snapshot().holdover, HoldoverState::Inactive);
+
+        system.synchronized = true;
+        system.system.stratum = 3;
+        system.system.time_snapshot.root_dispersion = NtpDuration::from_seconds(0.01);
+        system
+            .handle_source_create(2, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_update(
+                2,
+                snapshot_update(NtpSourceSnapshot {
+                    reach: Reach::default(),
+                    ..snapshot
+                }),
+            )
+            .unwrap();
+        assert_eq!(system.system_snapshot().holdover, HoldoverState::Active);
+        assert!(system.holdover_timer().is_some());
+
+        // the root dispersion grows while the clock runs on its own
+        let since = system.holdover.unwrap().since;
+        system.update_holdover(since + Duration::from_secs(100));
+        let snapshot_during = system.system_snapshot();
+        assert_eq!(snapshot_during.stratum, 3);
+        assert!(
+            (snapshot_during.time_snapshot.root_dispersion.to_seconds() - (0.01 + 100.0 * 15e-6))
+                .abs()
+                < 1e-7
+        );
+
+        // after the holdover duration we stop serving time
+        system.update_holdover(since + Duration::from_secs(1000));
+        let snapshot_expired = system.system_snapshot();
+        assert_eq!(snapshot_expired.holdover, HoldoverState::Expired);
+        assert_eq!(snapshot_expired.stratum, 16);
+        assert_eq!(
+            snapshot_expired.time_snapshot.leap_indicator,
+            NtpLeapIndicator::Unknown
+        );
+        assert_eq!(system.holdover_timer(), None);
+
+        // a source coming back ends holdover
+        system
+            .handle_source_update(2, snapshot_update(snapshot))
+            .unwrap();
+        assert_eq!(system.system_snapshot().holdover, HoldoverState::Inactive);
+        assert!(system.holdover.is_none());
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut system = System::<_, usize>::new(
+            TestClock {},
+            SynchronizationConfig {
+                lock_jitter_threshold: Some(NtpDuration::from_seconds(0.001)),
+                lock_duration: NtpDuration::from_seconds(100.0),
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        let start = NtpInstant::now();
+
+        // not synchronized yet
+        system.update_lock(start);
+        assert!(system.lock_candidate_since.is_none());
+
+        system.system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0005);
+        system.update_lock(start);
+        system.update_lock(start + Duration::from_secs(50));
+        assert!(!system.system_snapshot().locked);
+        system.update_lock(start + Duration::from_secs(100));
+        assert!(system.system_snapshot().locked);
+
+        // jitter within the hysteresis band keeps the lock
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0015);
+        system.update_lock(start + Duration::from_secs(150));
+        assert!(system.system_snapshot().locked);
+
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0025);
+        system.update_lock(start + Duration::from_secs(200));
+        assert!(!system.system_snapshot().locked);
+
+        // the jitter must stay low for the full duration again
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0005);
+        system.update_lock(start + Duration::from_secs(250));
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0015);
+        system.update_lock(start + Duration::from_secs(300));
+        system.system.time_snapshot.system_jitter = NtpDuration::from_seconds(0.0005);
+        system.update_lock(start + Duration::from_secs(350));
+        assert!(!system.system_snapshot().locked);
+        system.update_lock(start + Duration::from_secs(450));
+        assert!(system.system_snapshot().locked);
+
+        // losing synchronization loses the lock
+        system.system.holdover = HoldoverState::Active;
+        system.update_lock(start + Duration::from_secs(500));
+        assert!(!system.system_snapshot().locked);
+    }
+
+    #[test]
+    fn test_packet_counters_aggregate() {
+        let mut system = System::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        let counters = |sent, received| PacketCounters {
+            sent,
+            received,
+            ..Default::default()
+        };
+        let snapshot = reachable_snapshot([192, 0, 2, 1].into(), 2);
+
+        system
+            .handle_source_create(1, SourceOptions::default())
+            .unwrap();
+        system
+            .handle_source_create(2, SourceOptions::default())
+            .unwrap();
+        for (id, packets) in [
+            (1, counters(2, 1)),
+            (2, counters(1, 1)),
+            (1, counters(3, 2)),
+        ] {
+            system
+                .handle_source_update(
+                    id,
+                    snapshot_update(NtpSourceSnapshot {
+                        packets,
+                        ..snapshot
+                    }),
+                )
+                .unwrap();
+        }
+        assert_eq!(system.system_snapshot().packets, counters(4, 3));
+
+        // a reset source starts counting from zero again
+        system
+            .handle_source_update(
+                1,
+                snapshot_update(NtpSourceSnapshot {
+                    packets: counters(1, 0),
+                    ..snapshot
+                }),
+            )
+            .unwrap();
+        assert_eq!(system.system_snapshot().packets, counters(5, 3));
+
+        // and the counts of removed sources are kept
+        system.handle_source_remove(2).unwrap();
+        assert_eq!(system.system_snapshot().packets, counters(5, 3));
+    }
+
     #[test]
     fn test_empty_source_update() {
         let mut system = SystemSnapshot::default();
@@ -284,9 +1645,18 @@ mod tests {
                     source_id: ReferenceId::KISS_DENY,
                     poll_interval: PollIntervalLimits::default().max,
                     reach: Default::default(),
+                    reachability_pct_1h: None,
+                    reachability_pct_24h: None,
                     stratum: 2,
                     reference_id: ReferenceId::NONE,
+                    last_reference_change: None,
                     protocol_version: Default::default(),
+                    bogus_packets: 0,
+                    duplicate_packets: 0,
+                    packets: Default::default(),
+                    incompatible: false,
+                    remote_min_poll_interval: PollIntervalLimits::default().min,
+                    send_timestamped: false,
                     #[cfg(feature = "ntpv5")]
                     bloom_filter: None,
                 },
@@ -295,9 +1665,18 @@ mod tests {
                     source_id: ReferenceId::KISS_RATE,
                     poll_interval: PollIntervalLimits::default().max,
                     reach: Default::default(),
+                    reachability_pct_1h: None,
+                    reachability_pct_24h: None,
                     stratum: 3,
                     reference_id: ReferenceId::NONE,
+                    last_reference_change: None,
                     protocol_version: Default::default(),
+                    bogus_packets: 0,
+                    duplicate_packets: 0,
+                    packets: Default::default(),
+                    incompatible: false,
+                    remote_min_poll_interval: PollIntervalLimits::default().min,
+                    send_timestamped: false,
                     #[cfg(feature = "ntpv5")]
                     bloom_filter: None,
                 },
@@ -334,5 +1713,39 @@ mod tests {
             system.accumulated_steps_threshold,
             Some(new_accumulated_threshold),
         );
+        assert_eq!(
+            system.accumulated_steps_headroom,
+            Some(new_accumulated_threshold),
+        );
+
+        let snapshot = TimeSnapshot {
+            accumulated_steps: NtpDuration::from_seconds(1.0),
+            ..Default::default()
+        };
+        system.update_timedata(
+            snapshot,
+            &SynchronizationConfig {
+                accumulated_step_panic_threshold: Some(new_accumulated_threshold),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            system.accumulated_steps_headroom,
+            Some(NtpDuration::from_seconds(1.0)),
+        );
+
+        system.update_timedata(snapshot, &SynchronizationConfig::default());
+        assert_eq!(system.accumulated_steps_headroom, None);
+        assert_eq!(system.clock_offset_correction, NtpDuration::ZERO);
+
+        let correction = NtpDuration::from_seconds(-0.25e-6);
+        system.update_timedata(
+            snapshot,
+            &SynchronizationConfig {
+                clock_offset_correction: correction,
+                ..Default::default()
+            },
+        );
+        assert_eq!(system.clock_offset_correction, correction);
     }
 }
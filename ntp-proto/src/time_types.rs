@@ -3,6 +3,7 @@ use rand::{
     Rng,
 };
 use serde::{de::Unexpected, Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 use std::time::{Duration, Instant};
 
@@ -43,6 +44,13 @@ impl NtpInstant {
     pub fn elapsed(&self) -> std::time::Duration {
         self.instant.elapsed()
     }
+
+    /// The instant the given duration before this one, if representable
+    pub fn checked_sub(self, rhs: Duration) -> Option<Self> {
+        self.instant
+            .checked_sub(rhs)
+            .map(|instant| Self { instant })
+    }
 }
 
 impl Add<Duration> for NtpInstant {
@@ -98,6 +106,50 @@ impl NtpTimestamp {
         NtpTimestamp::from_bits(timestamp.to_be_bytes())
     }
 
+    /// Create an NTP timestamp from a UNIX time. Only the position within the
+    /// NTP era is kept, so times before 1900 and from 2036 on wrap around.
+    pub const fn from_unix_seconds_nanos(seconds: i64, nanos: u32) -> Self {
+        // the ntp era wraps, so truncating is intended
+        Self::from_seconds_nanos_since_ntp_era(seconds.wrapping_add(NTP_UNIX_OFFSET) as u32, nanos)
+    }
+
+    /// Seconds since the start of the NTP prime epoch (1900-01-01), choosing
+    /// the era that puts the timestamp within 68 years of the pivot.
+    fn seconds_since_prime_epoch(self, pivot: EraPivot) -> i64 {
+        let pivot = pivot.unix_seconds + NTP_UNIX_OFFSET;
+        let seconds = (self.timestamp >> 32) as i64;
+        let mut offset = (seconds - pivot).rem_euclid(1 << 32);
+        if offset >= 1 << 31 {
+            offset -= 1 << 32;
+        }
+        pivot + offset
+    }
+
+    /// NTP era of the timestamp, as resolved with the given pivot. Era 0
+    /// starts in 1900, era 1 in 2036.
+    pub fn era(self, pivot: EraPivot) -> i32 {
+        self.seconds_since_prime_epoch(pivot).div_euclid(1 << 32) as i32
+    }
+
+    /// The UNIX time (seconds and nanoseconds) of the timestamp, choosing the
+    /// era that puts it within 68 years of the pivot.
+    pub fn to_unix_seconds_nanos(self, pivot: EraPivot) -> (i64, u32) {
+        let nanos = ((self.timestamp & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+        (
+            self.seconds_since_prime_epoch(pivot) - NTP_UNIX_OFFSET,
+            nanos as u32,
+        )
+    }
+
+    /// Order two timestamps after resolving both of their eras with the given
+    /// pivot. Unlike [`NtpTimestamp::is_before`], this also orders timestamps
+    /// that are more than 68 years apart.
+    pub fn cmp_with_pivot(self, other: NtpTimestamp, pivot: EraPivot) -> Ordering {
+        self.seconds_since_prime_epoch(pivot)
+            .cmp(&other.seconds_since_prime_epoch(pivot))
+            .then((self.timestamp as u32).cmp(&(other.timestamp as u32)))
+    }
+
     pub fn is_before(self, other: NtpTimestamp) -> bool {
         // Around an era change, self can be near the maximum value
         // for NtpTimestamp and other near the minimum, and that must
@@ -112,6 +164,39 @@ impl NtpTimestamp {
     }
 }
 
+/// Seconds between the NTP prime epoch (1900) and the UNIX epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// Point in time used to decide which NTP era a timestamp belongs to, as the
+/// timestamps in packets don't include it. A timestamp is taken to be the
+/// moment closest to the pivot, so timestamps are interpreted correctly as
+/// long as they lie within 68 years of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct EraPivot {
+    unix_seconds: i64,
+}
+
+impl EraPivot {
+    /// 2024-01-01, around when this version was released. No timestamp we
+    /// handle lies before then, so this keeps times correct until 2092.
+    pub const RELEASE: EraPivot = EraPivot::from_unix_seconds(1_704_067_200);
+
+    pub const fn from_unix_seconds(unix_seconds: i64) -> Self {
+        EraPivot { unix_seconds }
+    }
+
+    pub const fn unix_seconds(self) -> i64 {
+        self.unix_seconds
+    }
+}
+
+impl Default for EraPivot {
+    fn default() -> Self {
+        Self::RELEASE
+    }
+}
+
 // In order to provide increased entropy on origin timestamps,
 // we should generate these randomly. This helps avoid
 // attacks from attackers guessing our current time.
@@ -190,7 +275,7 @@ impl SubAssign<NtpDuration> for NtpTimestamp {
 /// A negative duration interval is interpreted to mean that the first
 /// timestamp used to define the interval represents a point in time after
 /// the second timestamp.
-#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default)]
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Default)]
 pub struct NtpDuration {
     duration: i64,
 }
@@ -686,6 +771,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_timestamp_unix_conversion() {
+        let pivot = EraPivot::default();
+        for (seconds, nanos) in [(0, 0), (1_704_067_200, 500_000_000), (-1, 999_999_999)] {
+            let timestamp = NtpTimestamp::from_unix_seconds_nanos(seconds, nanos);
+            let (s, n) = timestamp.to_unix_seconds_nanos(pivot);
+            assert_eq!(s, seconds);
+            assert!(n.abs_diff(nanos) <= 1);
+        }
+
+        assert_eq!(
+            NtpTimestamp::from_unix_seconds_nanos(0, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(2_208_988_800, 0)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_era_pivot() {
+        // the last second of era 0 and the first of era 1 (2036-02-07)
+        let last = NtpTimestamp::from_seconds_nanos_since_ntp_era(u32::MAX, 0);
+        let first = NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 0);
+
+        let pivot = EraPivot::default();
+        assert_eq!(last.era(pivot), 0);
+        assert_eq!(first.era(pivot), 1);
+        assert_eq!(last.to_unix_seconds_nanos(pivot), (2_085_978_495, 0));
+        assert_eq!(first.to_unix_seconds_nanos(pivot), (2_085_978_496, 0));
+        assert_eq!(last.cmp_with_pivot(first, pivot), Ordering::Less);
+
+        // with a pivot in 1950, the same timestamps are around 1900
+        let pivot = EraPivot::from_unix_seconds(-631_152_000);
+        assert_eq!(first.era(pivot), 0);
+        assert_eq!(last.era(pivot), -1);
+        assert_eq!(first.to_unix_seconds_nanos(pivot), (-2_208_988_800, 0));
+        assert_eq!(last.to_unix_seconds_nanos(pivot), (-2_208_988_801, 0));
+
+        // timestamps up to 68 years after the pivot are still in its era
+        let pivot = EraPivot::from_unix_seconds(1_000_000_000);
+        let late = NtpTimestamp::from_unix_seconds_nanos(1_000_000_000 + i32::MAX as i64, 0);
+        assert_eq!(late.era(pivot), 1);
+        let early = NtpTimestamp::from_unix_seconds_nanos(1_000_000_000 - (1 << 31), 0);
+        assert_eq!(early.era(pivot), 0);
+        assert_eq!(early.cmp_with_pivot(late, pivot), Ordering::Less);
+
+        // within a second, the fraction decides
+        let a = NtpTimestamp::from_seconds_nanos_since_ntp_era(5, 100);
+        let b = NtpTimestamp::from_seconds_nanos_since_ntp_era(5, 200);
+        assert_eq!(a.cmp_with_pivot(b, pivot), Ordering::Less);
+        assert_eq!(a.cmp_with_pivot(a, pivot), Ordering::Equal);
+    }
+
     #[test]
     fn test_timestamp_duration_math() {
         let mut a = NtpTimestamp::from_fixed_int(5);
@@ -65,11 +65,11 @@ fn main() {
 
     println!(
         "cargo:rustc-env=NTPD_RS_GIT_REV={}",
-        git_rev.unwrap_or("-".to_owned())
+        git_rev.unwrap_or("unknown".to_owned())
     );
     println!(
         "cargo:rustc-env=NTPD_RS_GIT_DATE={}",
-        git_date.unwrap_or("-".to_owned())
+        git_date.unwrap_or("unknown".to_owned())
     );
     println!("cargo:rustc-rerun-if-changed=.git/HEAD");
 }
@@ -1,11 +1,14 @@
 use std::{path::PathBuf, process::ExitCode};
 
-use crate::daemon::{config::CliArg, tracing::LogLevel, Config, ObservableState};
+use crate::daemon::{config::CliArg, tracing::LogLevel, Config, ObservableState, ObserveRequest};
+use ntp_proto::{HoldoverState, NtpDuration};
 use tracing_subscriber::util::SubscriberInitExt;
 
 const USAGE_MSG: &str = "\
 usage: ntp-ctl validate [-c PATH]
        ntp-ctl status [-f FORMAT] [-c PATH]
+       ntp-ctl health [-c PATH]
+       ntp-ctl version [-c PATH]
        ntp-ctl -h | ntp-ctl -v";
 
 const DESCRIPTOR: &str = "ntp-ctl - ntp-daemon monitoring";
@@ -34,6 +37,8 @@ pub enum NtpCtlAction {
     Version,
     Validate,
     Status,
+    Health,
+    DaemonVersion,
 }
 
 #[derive(Debug, Default)]
@@ -44,6 +49,8 @@ pub(crate) struct NtpCtlOptions {
     version: bool,
     validate: bool,
     status: bool,
+    health: bool,
+    daemon_version: bool,
     action: NtpCtlAction,
 }
 
@@ -104,6 +111,12 @@ impl NtpCtlOptions {
                             "status" => {
                                 options.status = true;
                             }
+                            "health" => {
+                                options.health = true;
+                            }
+                            "version" => {
+                                options.daemon_version = true;
+                            }
                             unknown => {
                                 eprintln!("Warning: Unknown command {unknown}");
                             }
@@ -129,6 +142,10 @@ impl NtpCtlOptions {
             self.action = NtpCtlAction::Validate;
         } else if self.status {
             self.action = NtpCtlAction::Status;
+        } else if self.health {
+            self.action = NtpCtlAction::Health;
+        } else if self.daemon_version {
+            self.action = NtpCtlAction::DaemonVersion;
         } else {
             self.action = NtpCtlAction::Help;
         }
@@ -173,25 +190,61 @@ pub async fn main() -> std::io::Result<ExitCode> {
         }
         NtpCtlAction::Validate => validate(options.config).await,
         NtpCtlAction::Status => {
-            let config = Config::from_args(options.config, vec![], vec![]).await;
-
-            if let Err(ref e) = config {
-                println!("Warning: Unable to load configuration file: {e}");
-            }
-
-            let config = config.unwrap_or_default();
-
-            let observation = config
-                .observability
-                .observation_path
-                .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe"));
+            let observation = observation_path(options.config).await;
 
             match options.format {
                 Format::Plain => print_state(Format::Plain, observation).await,
                 Format::Prometheus => print_state(Format::Prometheus, observation).await,
             }
         }
+        NtpCtlAction::Health => {
+            let observation = observation_path(options.config).await;
+
+            let health = crate::daemon::observer::health(&observation).await;
+            println!("{health}");
+            Ok(ExitCode::from(health.exit_code()))
+        }
+        NtpCtlAction::DaemonVersion => {
+            let observation = observation_path(options.config).await;
+
+            match crate::daemon::observer::version(&observation).await {
+                Ok(program) => {
+                    println!("ntp-daemon {}", program.version);
+                    println!(
+                        "Commit: {} ({})",
+                        program.build_commit, program.build_commit_date
+                    );
+                    if program.features.is_empty() {
+                        println!("Features: none");
+                    } else {
+                        println!("Features: {}", program.features.join(", "));
+                    }
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Could not request version from {}: {e}",
+                        observation.display()
+                    );
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+    }
+}
+
+async fn observation_path(config: Option<PathBuf>) -> PathBuf {
+    let config = Config::from_args(config, vec![], vec![]).await;
+
+    if let Err(ref e) = config {
+        println!("Warning: Unable to load configuration file: {e}");
     }
+
+    config
+        .unwrap_or_default()
+        .observability
+        .observation_path
+        .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe"))
 }
 
 async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode, std::io::Error> {
@@ -203,7 +256,16 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
         }
     };
 
-    let mut msg = Vec::with_capacity(16 * 1024);
+    let request = ObserveRequest {
+        compress: true,
+        ..Default::default()
+    };
+    if let Err(e) = crate::daemon::sockets::write_json(&mut stream, &request).await {
+        eprintln!("Failed to send request to observation socket: {e}");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let mut msg = Vec::new();
     let mut output =
         match crate::daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
             Ok(output) => output,
@@ -220,6 +282,9 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
             output.sources.sort_by_key(|p| match p {
                 crate::daemon::ObservableSourceState::Nothing => None,
                 crate::daemon::ObservableSourceState::Observable(s) => Some((s.name.clone(), s.id)),
+                crate::daemon::ObservableSourceState::Incompatible { name, id, .. } => {
+                    Some((name.clone(), *id))
+                }
             });
             output.servers.sort_by_key(|s| s.address);
 
@@ -229,6 +294,11 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                 output.system.time_snapshot.root_dispersion.to_seconds(),
                 output.system.time_snapshot.root_delay.to_seconds()
             );
+            println!(
+                "Offset: {:+.6}s, Jitter: {:.6}s",
+                output.system.time_snapshot.system_offset.to_seconds(),
+                output.system.time_snapshot.system_jitter.to_seconds()
+            );
             println!(
                 "Desired poll interval: {:.0}s",
                 output
@@ -238,12 +308,87 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                     .as_duration()
                     .to_seconds()
             );
+            println!(
+                "Frequency correction: {:.3}ppm",
+                output.system.time_snapshot.frequency_ppm
+            );
+            println!(
+                "Clock precision: {:.9}s",
+                output.system.time_snapshot.precision.to_seconds()
+            );
+            if output.system.clock_offset_correction != NtpDuration::ZERO {
+                println!(
+                    "Clock offset correction: {:+.9}s",
+                    output.system.clock_offset_correction.to_seconds()
+                );
+            }
             println!("Stratum: {}", output.system.stratum);
+            if output.system.raw_stratum < output.system.stratum {
+                println!(
+                    "Stratum of the system source: {} (held back by the stratum hysteresis)",
+                    output.system.raw_stratum
+                );
+            }
+            if output.system.stratum < 16 {
+                println!(
+                    "System source chosen by: {}",
+                    output.system.time_snapshot.system_source_choice
+                );
+            }
+            match output.system.holdover {
+                HoldoverState::Inactive => {}
+                HoldoverState::Active => println!("Holdover: active"),
+                HoldoverState::Expired => println!("Holdover: expired"),
+            }
+            if output.system.local_clock_fallback {
+                println!("Local clock fallback: active");
+            }
+            if output.system.locked {
+                println!("Locked: jitter stable below the lock threshold");
+            }
+            if let Some(alarm) = output.system.divergence_alarm {
+                println!(
+                    "Divergence alarm: offsets of {} and {} differ by {:.6}s",
+                    alarm.sources[0],
+                    alarm.sources[1],
+                    alarm.divergence.to_seconds()
+                );
+            }
+            if let Some(convergence) = output.system.convergence {
+                let target = convergence.target.to_seconds();
+                match convergence.time_to_target {
+                    Some(time) if time.is_zero() => {
+                        println!("Convergence: error within {target:.6}s")
+                    }
+                    Some(time) => println!(
+                        "Convergence: error within {target:.6}s expected in {:.0}s",
+                        time.as_secs_f64()
+                    ),
+                    None => println!(
+                        "Convergence: error not expected to get within {target:.6}s, settles at {:.6}s",
+                        convergence.uncertainty_floor.to_seconds()
+                    ),
+                }
+                println!(
+                    "    current error {:.6}s, assuming poll interval {:.0}s, measurement noise {:.6}s, wander {:.3e}s/s^2, slew rate {:.0}ppm",
+                    convergence.current_error.to_seconds(),
+                    convergence.poll_interval.as_duration().to_seconds(),
+                    convergence.measurement_noise.to_seconds(),
+                    convergence.wander,
+                    convergence.slew_rate * 1e6
+                );
+            }
             println!();
             println!("Sources:");
             for source in &output.sources {
                 match source {
                     crate::daemon::ObservableSourceState::Nothing => {}
+                    crate::daemon::ObservableSourceState::Incompatible { name, address, id } => {
+                        println!(
+                            "{}/{} ({}): incompatible, replies could not be parsed",
+                            name, address, id
+                        );
+                    }
                     crate::daemon::ObservableSourceState::Observable(
                         crate::daemon::ObservedSourceState {
                             timedata,
@@ -252,13 +397,15 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                             name: address,
                             address: ip,
                             id,
+                            ..
                         },
                     ) => {
                         println!(
                             concat!(
                                 "{}/{} ({}): {:+.6}±{:.6}(±{:.6})s\n",
                                 "    poll interval: {:.0}s, missing polls: {}\n",
-                                "    root dispersion: {:.6}s, root delay:{:.6}s"
+                                "    root dispersion: {:.6}s, root delay:{:.6}s\n",
+                                "    selection: {}"
                             ),
                             address,
                             ip,
@@ -270,7 +417,18 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                             unanswered_polls,
                             timedata.remote_uncertainty.to_seconds(),
                             timedata.remote_delay.to_seconds(),
+                            timedata.selection,
                         );
+                        if let Some(precision) = timedata.remote_precision {
+                            println!(
+                                "    advertised precision: 2^{}s ({:.6}s)",
+                                precision,
+                                2f64.powi(precision.into())
+                            );
+                        }
+                        if timedata.no_step {
+                            println!("    may only slew the clock, not step it");
+                        }
                     }
                 }
             }
@@ -295,12 +453,23 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                     server.stats.response_send_errors.get()
                 );
                 println!(
-                    "    denied {}, nts nak {}, rate limited {}, ignored {}",
+                    "    denied {}, nts nak {}, rate limited {}, ignored {}, shed {}, capped {}",
                     server.stats.denied_packets.get(),
                     server.stats.nts_nak_packets.get(),
                     server.stats.rate_limited_packets.get(),
-                    server.stats.ignored_packets.get()
+                    server.stats.ignored_packets.get(),
+                    server.stats.shed_packets.get(),
+                    server.stats.rate_capped_packets.get()
                 );
+                let latency = server.response_latency;
+                if let (Some(p50), Some(p95), Some(p99)) = (latency.p50, latency.p95, latency.p99) {
+                    println!(
+                        "    response latency p50 {:.3}ms, p95 {:.3}ms, p99 {:.3}ms",
+                        p50 * 1e3,
+                        p95 * 1e3,
+                        p99 * 1e3
+                    );
+                }
             }
         }
         Format::Prometheus => {
@@ -325,7 +494,7 @@ mod tests {
 
     use crate::daemon::{
         config::ObservabilityConfig,
-        sockets::{create_unix_socket_with_permissions, write_json},
+        sockets::{create_unix_socket_with_permissions, read_json, write_json, ObserverError},
     };
 
     use super::*;
@@ -333,7 +502,7 @@ mod tests {
     async fn write_socket_helper(
         command: Format,
         socket_name: &str,
-    ) -> std::io::Result<Result<ExitCode, std::io::Error>> {
+    ) -> Result<Result<ExitCode, std::io::Error>, ObserverError> {
         let config: ObservabilityConfig = Default::default();
 
         // be careful with copying: tests run concurrently and should use a unique socket name!
@@ -355,9 +524,14 @@ mod tests {
             system: Default::default(),
             sources: vec![],
             servers: vec![],
+            source_filter: None,
+            source_history: None,
+            source_debug: None,
         };
 
         let (mut stream, _addr) = sources_listener.accept().await?;
+        let request: ObserveRequest = read_json(&mut stream, &mut Vec::new()).await?;
+        assert!(request.compress);
         write_json(&mut stream, &value).await?;
 
         let result = handle.await.unwrap();
@@ -366,7 +540,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_control_socket_source() -> std::io::Result<()> {
+    async fn test_control_socket_source() -> Result<(), ObserverError> {
         // be careful with copying: tests run concurrently and should use a unique socket name!
         let result = write_socket_helper(Format::Plain, "ntp-test-stream-6").await?;
 
@@ -379,7 +553,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_control_socket_prometheus() -> std::io::Result<()> {
+    async fn test_control_socket_prometheus() -> Result<(), ObserverError> {
         // be careful with copying: tests run concurrently and should use a unique socket name!
         let result = write_socket_helper(Format::Prometheus, "ntp-test-stream-8").await?;
 
@@ -392,7 +566,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_control_socket_source_invalid_input() -> std::io::Result<()> {
+    async fn test_control_socket_source_invalid_input() -> Result<(), ObserverError> {
         let config: ObservabilityConfig = Default::default();
 
         // be careful with copying: tests run concurrently and should use a unique socket name!
@@ -412,6 +586,7 @@ mod tests {
         let value = 42u32;
 
         let (mut stream, _addr) = sources_listener.accept().await?;
+        let _: ObserveRequest = read_json(&mut stream, &mut Vec::new()).await?;
         write_json(&mut stream, &value).await?;
 
         let result = handle.await.unwrap();
@@ -436,6 +611,24 @@ mod tests {
         assert_eq!(options.config.unwrap().as_path(), config);
     }
 
+    #[test]
+    fn cli_health() {
+        let arguments = &[BINARY, "health"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Health);
+    }
+
+    #[test]
+    fn cli_daemon_version() {
+        let arguments = &[BINARY, "version"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::DaemonVersion);
+
+        let arguments = &[BINARY, "-v", "version"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Version);
+    }
+
     #[test]
     fn cli_format() {
         let arguments = &[BINARY, "-f", "plain"];
@@ -3,26 +3,30 @@ mod server;
 pub mod subnet;
 
 use clock_steering::unix::UnixClock;
-use ntp_proto::{SourceDefaultsConfig, SynchronizationConfig};
+use ntp_proto::{NtpDuration, SourceDefaultsConfig, SynchronizationConfig};
 pub use ntp_source::*;
-use serde::{Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer};
 pub use server::*;
 use std::{
     fmt::Display,
     io::ErrorKind,
     net::SocketAddr,
+    num::NonZeroUsize,
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 use timestamped_socket::interface::InterfaceName;
 use tokio::{fs::read_to_string, io};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-use super::{clock::NtpClockWrapper, tracing::LogLevel};
+use super::{clock::NtpClockWrapper, state_file::ClockKey, tracing::LogLevel};
 
 const USAGE_MSG: &str = "\
 usage: ntp-daemon [-c PATH] [-l LOG_LEVEL]
+       ntp-daemon -q [--once-quorum=COUNT] [--once-timeout=SECONDS] [-c PATH] [-l LOG_LEVEL]
+       ntp-daemon --check-config [-c PATH]
        ntp-daemon -h
        ntp-daemon -v";
 
@@ -31,6 +35,10 @@ const DESCRIPTOR: &str = "ntp-daemon - synchronize system time";
 const HELP_MSG: &str = "Options:
   -c, --config=PATH             change the config .toml file
   -l, --log-level=LOG_LEVEL     change the log level
+      --check-config            validate the config .toml file and exit
+  -q, --once                    set the clock once from the sources and exit
+      --once-quorum=COUNT       sources that must agree before setting the clock once
+      --once-timeout=SECONDS    give up setting the clock once after this long
   -h, --help                    display this help text
   -v, --version                 display version information";
 
@@ -46,6 +54,12 @@ pub(crate) struct NtpDaemonOptions {
     pub log_level: Option<LogLevel>,
     help: bool,
     version: bool,
+    check_config: bool,
+    once: bool,
+    /// Number of agreeing sources needed to set the clock in one-shot mode
+    pub once_quorum: Option<usize>,
+    /// How long one-shot mode waits for the clock to be set
+    pub once_timeout: Option<Duration>,
     pub action: NtpDaemonAction,
 }
 
@@ -139,11 +153,14 @@ pub enum NtpDaemonAction {
     #[default]
     Help,
     Version,
+    CheckConfig,
     Run,
+    Once,
 }
 
 impl NtpDaemonOptions {
-    const TAKES_ARGUMENT: &'static [&'static str] = &["--config", "--log-level"];
+    const TAKES_ARGUMENT: &'static [&'static str] =
+        &["--config", "--log-level", "--once-quorum", "--once-timeout"];
     const TAKES_ARGUMENT_SHORT: &'static [char] = &['c', 'l'];
 
     /// parse an iterator over command line arguments
@@ -170,6 +187,12 @@ impl NtpDaemonOptions {
                     "-v" | "--version" => {
                         options.version = true;
                     }
+                    "--check-config" => {
+                        options.check_config = true;
+                    }
+                    "-q" | "--once" => {
+                        options.once = true;
+                    }
                     option => {
                         Err(format!("invalid option provided: {option}"))?;
                     }
@@ -182,6 +205,14 @@ impl NtpDaemonOptions {
                         Ok(level) => options.log_level = Some(level),
                         Err(_) => return Err("invalid log level".into()),
                     },
+                    "--once-quorum" => match value.parse() {
+                        Ok(quorum) if quorum > 0 => options.once_quorum = Some(quorum),
+                        _ => return Err("invalid quorum".into()),
+                    },
+                    "--once-timeout" => match value.parse() {
+                        Ok(seconds) => options.once_timeout = Some(Duration::from_secs(seconds)),
+                        Err(_) => return Err("invalid timeout".into()),
+                    },
                     option => {
                         Err(format!("invalid option provided: {option}"))?;
                     }
@@ -202,30 +233,33 @@ impl NtpDaemonOptions {
             self.action = NtpDaemonAction::Help;
         } else if self.version {
             self.action = NtpDaemonAction::Version;
+        } else if self.check_config {
+            self.action = NtpDaemonAction::CheckConfig;
+        } else if self.once {
+            self.action = NtpDaemonAction::Once;
         } else {
             self.action = NtpDaemonAction::Run;
         }
     }
 }
 
-fn deserialize_ntp_clock<'de, D>(deserializer: D) -> Result<NtpClockWrapper, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let data: Option<PathBuf> = Deserialize::deserialize(deserializer)?;
-
-    if let Some(path) = data {
+fn open_ntp_clock(path: Option<PathBuf>) -> Result<(NtpClockWrapper, ClockKey), String> {
+    if let Some(path) = path {
         tracing::info!("using custom clock {path:?}");
         #[cfg(target_os = "linux")]
-        return Ok(NtpClockWrapper::new(
-            UnixClock::open(path).map_err(|e| serde::de::Error::custom(e.to_string()))?,
+        return Ok((
+            NtpClockWrapper::new(UnixClock::open(&path).map_err(|e| e.to_string())?),
+            ClockKey::from_path(&path),
         ));
 
         #[cfg(not(target_os = "linux"))]
         panic!("Custom clock paths not supported on this platform");
     } else {
         tracing::debug!("using REALTIME clock");
-        Ok(NtpClockWrapper::new(UnixClock::CLOCK_REALTIME))
+        Ok((
+            NtpClockWrapper::new(UnixClock::CLOCK_REALTIME),
+            ClockKey::realtime(),
+        ))
     }
 }
 
@@ -289,16 +323,136 @@ impl TimestampMode {
     }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone, Default)]
-#[serde(rename_all = "kebab-case", deny_unknown_fields)]
-pub struct ClockConfig {
-    #[serde(deserialize_with = "deserialize_ntp_clock", default)]
-    pub clock: NtpClockWrapper,
-    #[serde(deserialize_with = "deserialize_interface", default)]
+/// The clock to steer and how to timestamp packets. Applications embedding
+/// the daemon can substitute their own clock.
+#[derive(Debug, Clone, Default)]
+pub struct ClockConfig<C = NtpClockWrapper> {
+    pub clock: C,
+    /// Identifies the clock in the clock state file
+    pub clock_key: ClockKey,
     pub interface: Option<InterfaceName>,
     pub timestamp_mode: TimestampMode,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ClockConfigData {
+    #[serde(default)]
+    clock: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_interface", default)]
+    interface: Option<InterfaceName>,
+    timestamp_mode: TimestampMode,
+}
+
+impl TryFrom<ClockConfigData> for ClockConfig {
+    type Error = String;
+
+    fn try_from(data: ClockConfigData) -> Result<Self, Self::Error> {
+        let (clock, clock_key) = open_ntp_clock(data.clock)?;
+        Ok(ClockConfig {
+            clock,
+            clock_key,
+            interface: data.interface,
+            timestamp_mode: data.timestamp_mode,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ClockConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ClockConfigData::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ClockStateConfig {
+    /// File in which the frequency of each clock is stored across restarts
+    #[serde(default)]
+    pub storage_path: Option<PathBuf>,
+    /// How often to store the clock state (seconds between stores)
+    #[serde(default = "default_clock_state_store_interval")]
+    pub store_interval: u64,
+}
+
+impl Default for ClockStateConfig {
+    fn default() -> Self {
+        Self {
+            storage_path: None,
+            store_interval: default_clock_state_store_interval(),
+        }
+    }
+}
+
+fn default_clock_state_store_interval() -> u64 {
+    // 1 hour in seconds
+    3600
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LeapSecondsConfig {
+    /// Leap seconds file in the NIST/IERS `leap-seconds.list` format, used
+    /// to announce leap seconds instead of relying on the sources
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DnsConfig {
+    /// Maximum number of DNS lookups in progress at the same time (unlimited when unset)
+    #[serde(default)]
+    pub max_concurrent_lookups: Option<NonZeroUsize>,
+    /// Time after which a single DNS lookup is abandoned (seconds)
+    #[serde(default = "default_dns_lookup_timeout")]
+    pub lookup_timeout: u64,
+    /// Time for which the result of a lookup is reused for further lookups
+    /// of the same name (seconds, 0 disables caching)
+    #[serde(default)]
+    pub cache_ttl: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_lookups: None,
+            lookup_timeout: default_dns_lookup_timeout(),
+            cache_ttl: 0,
+        }
+    }
+}
+
+fn default_dns_lookup_timeout() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// Add the NTP servers received through DHCP as sources
+    #[serde(default)]
+    pub use_dhcp_ntp_servers: bool,
+    /// File in which the DHCP client stores the NTP servers it received
+    #[serde(default = "default_dhcp_servers_file")]
+    pub servers_file: PathBuf,
+}
+
+impl Default for DhcpConfig {
+    fn default() -> Self {
+        Self {
+            use_dhcp_ntp_servers: false,
+            servers_file: default_dhcp_servers_file(),
+        }
+    }
+}
+
+fn default_dhcp_servers_file() -> PathBuf {
+    PathBuf::from("/run/ntpd-rs/dhcp-servers")
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ObservabilityConfig {
@@ -308,6 +462,22 @@ pub struct ObservabilityConfig {
     pub observation_path: Option<PathBuf>,
     #[serde(default = "default_observation_permissions")]
     pub observation_permissions: u32,
+    /// Also serve the observation protocol over tcp on this address, for
+    /// monitoring from other hosts
+    #[serde(default)]
+    pub observation_listen: Option<SocketAddr>,
+    /// File containing the token clients must present when observing over
+    /// tcp
+    #[serde(default)]
+    pub observation_token_path: Option<PathBuf>,
+    /// Number of measurements of each source kept for the source history
+    /// observation, 0 disables the history
+    #[serde(default = "default_source_history_length")]
+    pub source_history_length: usize,
+    #[serde(default)]
+    pub control_path: Option<PathBuf>,
+    #[serde(default = "default_control_permissions")]
+    pub control_permissions: u32,
     #[serde(default = "default_metrics_exporter_listen")]
     pub metrics_exporter_listen: SocketAddr,
 }
@@ -318,6 +488,11 @@ impl Default for ObservabilityConfig {
             log_level: Default::default(),
             observation_path: Default::default(),
             observation_permissions: default_observation_permissions(),
+            observation_listen: None,
+            observation_token_path: None,
+            source_history_length: default_source_history_length(),
+            control_path: Default::default(),
+            control_permissions: default_control_permissions(),
             metrics_exporter_listen: default_metrics_exporter_listen(),
         }
     }
@@ -327,6 +502,14 @@ const fn default_observation_permissions() -> u32 {
     0o666
 }
 
+const fn default_source_history_length() -> usize {
+    256
+}
+
+const fn default_control_permissions() -> u32 {
+    0o600
+}
+
 fn default_metrics_exporter_listen() -> SocketAddr {
     "127.0.0.1:9975".parse().unwrap()
 }
@@ -349,6 +532,14 @@ pub struct Config {
     #[serde(default)]
     pub keyset: KeysetConfig,
     #[serde(default)]
+    pub clock_state: ClockStateConfig,
+    #[serde(default)]
+    pub leap_seconds: LeapSecondsConfig,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    #[serde(default)]
+    pub dhcp: DhcpConfig,
+    #[serde(default)]
     #[cfg(feature = "hardware-timestamping")]
     pub clock: ClockConfig,
 }
@@ -422,6 +613,7 @@ impl Config {
             match source {
                 NtpSourceConfig::Standard(_) => count += 1,
                 NtpSourceConfig::Nts(_) => count += 1,
+                NtpSourceConfig::Nmea(_) => count += 1,
                 NtpSourceConfig::Pool(config) => count += config.count,
                 #[cfg(feature = "unstable_nts-pool")]
                 NtpSourceConfig::NtsPool(config) => count += config.count,
@@ -430,8 +622,176 @@ impl Config {
         count
     }
 
-    /// Check that the config is reasonable. This function may panic if the
-    /// configuration is egregious, although it doesn't do so currently.
+    /// Run semantic checks on the configuration, beyond what is already
+    /// enforced while parsing. This does not touch the network or the clock.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+
+        if !self.sources.is_empty()
+            && self.count_sources() < self.synchronization.minimum_agreeing_sources
+        {
+            issues.push(ConfigIssue::error("Fewer sources configured than are required to agree on the current time. Daemon will not change system time."));
+        }
+
+        let pinned = self
+            .sources
+            .iter()
+            .filter(|source| source.is_pinned())
+            .count();
+        if pinned > 1 {
+            issues.push(ConfigIssue::error(format!(
+                "Multiple sources ({pinned}) are pinned, at most one source can be pinned."
+            )));
+        }
+
+        for source in &self.sources {
+            issues.extend(source.validate());
+        }
+
+        let limits = self.source_defaults.poll_interval_limits;
+        if limits.min > limits.max {
+            issues.push(ConfigIssue::error(format!(
+                "Minimum poll interval ({}) is larger than maximum poll interval ({}).",
+                limits.min.as_log(),
+                limits.max.as_log()
+            )));
+        } else {
+            let initial = self.source_defaults.initial_poll_interval;
+            if initial < limits.min || initial > limits.max {
+                issues.push(ConfigIssue::warning(format!(
+                    "Initial poll interval ({}) is outside of the poll interval limits ({} to {}).",
+                    initial.as_log(),
+                    limits.min.as_log(),
+                    limits.max.as_log()
+                )));
+            }
+        }
+
+        if !(1..=16).contains(&self.synchronization.local_stratum) {
+            issues.push(ConfigIssue::error(format!(
+                "Local stratum ({}) must be between 1 and 16.",
+                self.synchronization.local_stratum
+            )));
+        }
+
+        if let Some(orphan_stratum) = self.synchronization.orphan_stratum {
+            if orphan_stratum == 0 || orphan_stratum >= self.synchronization.local_stratum {
+                issues.push(ConfigIssue::error(format!(
+                    "Orphan stratum ({}) must be at least 1 and lower than the local stratum ({}).",
+                    orphan_stratum, self.synchronization.local_stratum
+                )));
+            }
+        }
+
+        if let Some(holdover_duration) = self.synchronization.holdover_duration {
+            if holdover_duration <= NtpDuration::ZERO {
+                issues.push(ConfigIssue::error(format!(
+                    "Holdover duration ({}s) must be positive.",
+                    holdover_duration.to_seconds()
+                )));
+            }
+        }
+
+        let maximum_frequency_steer = self.synchronization.algorithm.maximum_frequency_steer;
+        if maximum_frequency_steer.is_nan() || maximum_frequency_steer <= 0.0 {
+            issues.push(ConfigIssue::error(format!(
+                "Maximum frequency steer ({maximum_frequency_steer}) must be positive."
+            )));
+        } else if maximum_frequency_steer > 500e-6 {
+            issues.push(ConfigIssue::warning(format!(
+                "Maximum frequency steer ({maximum_frequency_steer}) is above the 500ppm most kernels accept."
+            )));
+        }
+
+        let dispersion_growth_rate = self.synchronization.algorithm.dispersion_growth_rate;
+        if dispersion_growth_rate.is_nan() || dispersion_growth_rate < 0.0 {
+            issues.push(ConfigIssue::error(format!(
+                "Dispersion growth rate ({dispersion_growth_rate}) must not be negative."
+            )));
+        }
+
+        let min_clock_update_interval = self.synchronization.algorithm.min_clock_update_interval;
+        if !min_clock_update_interval.is_finite() || min_clock_update_interval < 0.0 {
+            issues.push(ConfigIssue::error(format!(
+                "Minimum clock update interval ({min_clock_update_interval}s) must be a finite, non-negative number."
+            )));
+        }
+
+        if let Some(listen) = self.observability.observation_listen {
+            if self.observability.observation_token_path.is_none() {
+                issues.push(ConfigIssue::error(format!(
+                    "Observation over tcp on {listen} requires an observation token path."
+                )));
+            }
+        }
+
+        if self.clock_state.store_interval == 0 {
+            issues.push(ConfigIssue::error(
+                "Clock state store interval must be at least 1 second.",
+            ));
+        }
+
+        if self.dns.lookup_timeout == 0 {
+            issues.push(ConfigIssue::error(
+                "DNS lookup timeout must be at least 1 second.",
+            ));
+        }
+
+        for (i, server) in self.servers.iter().enumerate() {
+            if server.track_interfaces && !server.listen.ip().is_unspecified() {
+                issues.push(ConfigIssue::error(format!(
+                    "Server on {} tracks interfaces, which requires an unspecified listen address such as 0.0.0.0 or [::].",
+                    server.listen
+                )));
+            }
+
+            if let Some(peer) = server.connect {
+                if server.track_interfaces {
+                    issues.push(ConfigIssue::error(format!(
+                        "Server on {} is connected to {peer}, which can't be combined with tracking interfaces.",
+                        server.listen
+                    )));
+                }
+                if peer.is_ipv4() != server.listen.is_ipv4() || peer.ip().is_unspecified() {
+                    issues.push(ConfigIssue::error(format!(
+                        "Server on {} can't connect to {peer}, the peer must be a specific address of the same family.",
+                        server.listen
+                    )));
+                }
+            }
+
+            if server.min_root_delay < NtpDuration::ZERO
+                || server.min_root_dispersion < NtpDuration::ZERO
+            {
+                issues.push(ConfigIssue::error(format!(
+                    "Minimum root delay and dispersion of server on {} can't be negative.",
+                    server.listen
+                )));
+            }
+
+            if server.max_request_rate == Some(0) {
+                issues.push(ConfigIssue::error(format!(
+                    "Maximum request rate of server on {} must be at least 1.",
+                    server.listen
+                )));
+            }
+
+            if self.servers[..i]
+                .iter()
+                .any(|other| other.listen == server.listen)
+            {
+                issues.push(ConfigIssue::error(format!(
+                    "Multiple servers configured to listen on {}.",
+                    server.listen
+                )));
+            }
+        }
+
+        issues
+    }
+
+    /// Check that the config is reasonable, logging any issues found. Returns
+    /// false if any of the issues is an error.
     pub fn check(&self) -> bool {
         let mut ok = true;
 
@@ -443,17 +803,64 @@ impl Config {
             info!("No sources configured. Daemon will not change system time.");
         }
 
-        if !self.sources.is_empty()
-            && self.count_sources() < self.synchronization.minimum_agreeing_sources
-        {
-            warn!("Fewer sources configured than are required to agree on the current time. Daemon will not change system time.");
-            ok = false;
+        for issue in self.validate() {
+            match issue.severity {
+                ConfigIssueSeverity::Warning => warn!("{}", issue.message),
+                ConfigIssueSeverity::Error => {
+                    error!("{}", issue.message);
+                    ok = false;
+                }
+            }
         }
 
         ok
     }
 }
 
+/// Parse the configuration file and run all semantic checks on it, without
+/// binding any sockets or spawning any tasks.
+pub async fn check_config(file: Option<impl AsRef<Path>>) -> Result<Vec<ConfigIssue>, ConfigError> {
+    Ok(Config::from_first_file(file).await?.validate())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigIssueSeverity {
+    Warning,
+    Error,
+}
+
+/// A semantic problem with an otherwise well-formed configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: ConfigIssueSeverity,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn warning(message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: ConfigIssueSeverity::Warning,
+            message: message.into(),
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: ConfigIssueSeverity::Error,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            ConfigIssueSeverity::Warning => write!(f, "Warning: {}", self.message),
+            ConfigIssueSeverity::Error => write!(f, "Error: {}", self.message),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     Io(io::Error),
@@ -497,6 +904,15 @@ mod tests {
             config.sources,
             vec![NtpSourceConfig::Standard(StandardSource {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
             })]
         );
         assert!(config.observability.log_level.is_none());
@@ -510,6 +926,15 @@ mod tests {
             config.sources,
             vec![NtpSourceConfig::Standard(StandardSource {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
             })]
         );
 
@@ -521,6 +946,15 @@ mod tests {
             config.sources,
             vec![NtpSourceConfig::Standard(StandardSource {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
             })]
         );
         assert_eq!(
@@ -540,6 +974,15 @@ mod tests {
             config.sources,
             vec![NtpSourceConfig::Standard(StandardSource {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
             })]
         );
         assert!(config
@@ -553,6 +996,19 @@ mod tests {
             .backward
             .is_none());
 
+        let config: Config =
+            toml::from_str("[synchronization]\nleap-trust = { max-stratum = 1 }").unwrap();
+        assert_eq!(
+            config.synchronization.leap_trust,
+            ntp_proto::LeapTrust::MaxStratum(1)
+        );
+
+        let config: Config = toml::from_str("[synchronization]\nleap-trust = \"trusted\"").unwrap();
+        assert_eq!(
+            config.synchronization.leap_trust,
+            ntp_proto::LeapTrust::Trusted
+        );
+
         let config: Config = toml::from_str(
             r#"
             [[source]]
@@ -561,10 +1017,15 @@ mod tests {
             [source-defaults]
             poll-interval-limits = { min = 5, max = 9 }
             initial-poll-interval = 5
+            randomize-poll = false
+            log-exchanges = true
+            recovery-poll = 6
             [observability]
             log-level = "info"
             observation-path = "/foo/bar/observe"
             observation-permissions = 0o567
+            observation-listen = "0.0.0.0:9976"
+            observation-token-path = "/foo/bar/token"
             "#,
         )
         .unwrap();
@@ -575,11 +1036,28 @@ mod tests {
             Some(PathBuf::from("/foo/bar/observe"))
         );
         assert_eq!(config.observability.observation_permissions, 0o567);
+        assert_eq!(
+            config.observability.observation_listen,
+            Some("0.0.0.0:9976".parse().unwrap())
+        );
+        assert_eq!(
+            config.observability.observation_token_path,
+            Some(PathBuf::from("/foo/bar/token"))
+        );
 
         assert_eq!(
             config.sources,
             vec![NtpSourceConfig::Standard(StandardSource {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
             })]
         );
 
@@ -588,6 +1066,12 @@ mod tests {
         assert_eq!(poll_interval_limits.max.as_log(), 9);
 
         assert_eq!(config.source_defaults.initial_poll_interval.as_log(), 5);
+        assert!(!config.source_defaults.randomize_poll);
+        assert!(config.source_defaults.log_exchanges);
+        assert_eq!(
+            config.source_defaults.recovery_poll.map(|p| p.as_log()),
+            Some(6)
+        );
     }
 
     #[test]
@@ -632,6 +1116,126 @@ mod tests {
         assert_eq!(parsed_empty.log_level.unwrap(), LogLevel::Debug);
     }
 
+    #[test]
+    fn cli_check_config() {
+        let arguments = &["/usr/bin/ntp-daemon", "--check-config", "-c", "other.toml"];
+        let parsed = NtpDaemonOptions::try_parse_from(arguments).unwrap();
+
+        assert_eq!(parsed.config, Some("other.toml".into()));
+        assert_eq!(parsed.action, NtpDaemonAction::CheckConfig);
+    }
+
+    #[test]
+    fn cli_once() {
+        let arguments = &[
+            "/usr/bin/ntp-daemon",
+            "-q",
+            "--once-quorum",
+            "2",
+            "--once-timeout=30",
+        ];
+        let parsed = NtpDaemonOptions::try_parse_from(arguments).unwrap();
+
+        assert_eq!(parsed.action, NtpDaemonAction::Once);
+        assert_eq!(parsed.once_quorum, Some(2));
+        assert_eq!(parsed.once_timeout, Some(Duration::from_secs(30)));
+
+        let arguments = &["/usr/bin/ntp-daemon", "--once", "--once-quorum=0"];
+        assert!(NtpDaemonOptions::try_parse_from(arguments).is_err());
+    }
+
+    #[test]
+    fn validate_reports_issues() {
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "pool"
+            address = "pool.example.com"
+            count = 0
+            [[source]]
+            mode = "server"
+            address = ":123"
+            pin = true
+            [[source]]
+            mode = "server"
+            address = "example.com"
+            pin = true
+            no-select = true
+            [[server]]
+            listen = "[::]:123"
+            [[server]]
+            listen = "[::]:123"
+            [[server]]
+            listen = "127.0.0.1:123"
+            track-interfaces = true
+            [source-defaults]
+            poll-interval-limits = { min = 8, max = 6 }
+            [synchronization]
+            minimum-agreeing-sources = 1
+            local-stratum = 0
+            orphan-stratum = 10
+            holdover-duration = 0
+            [synchronization.algorithm]
+            min-clock-update-interval = -1
+            [observability]
+            observation-listen = "0.0.0.0:9976"
+            [dns]
+            lookup-timeout = 0
+            "#,
+        )
+        .unwrap();
+
+        let issues = config.validate();
+        let count = |severity| issues.iter().filter(|i| i.severity == severity).count();
+
+        // empty address, multiple pins, pinned no-select source, poll limits,
+        // local stratum, orphan stratum, holdover duration, clock update
+        // interval, tracking interfaces on a specific address, duplicate
+        // listen address, observation over tcp without a token and a DNS
+        // lookup timeout of 0
+        assert_eq!(count(ConfigIssueSeverity::Error), 12);
+        // pool with a count of 0
+        assert_eq!(count(ConfigIssueSeverity::Warning), 1);
+        assert!(!config.check());
+    }
+
+    #[test]
+    fn validate_initial_poll_interval() {
+        let config: Config = toml::from_str(
+            r#"
+            [source-defaults]
+            poll-interval-limits = { min = 5, max = 9 }
+            initial-poll-interval = 4
+            "#,
+        )
+        .unwrap();
+
+        let issues = config.validate();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, ConfigIssueSeverity::Warning);
+        assert!(config.check());
+    }
+
+    #[test]
+    fn validate_default_config_is_clean() {
+        assert!(Config::default().validate().is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "pool"
+            address = "pool.example.com"
+            count = 4
+            [[server]]
+            listen = "0.0.0.0:123"
+            [[server]]
+            listen = "[::]:123"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_empty());
+    }
+
     #[test]
     fn toml_sources_invalid() {
         let config: Result<Config, _> = toml::from_str(
@@ -737,6 +1341,27 @@ mod tests {
         assert!(error.to_string().contains("expected a positive number"));
     }
 
+    #[test]
+    fn offset_outlier_mad_multiplier_positive() {
+        for value in ["0.0", "-1.0", "nan"] {
+            let config: Result<SynchronizationConfig, _> = toml::from_str(&format!(
+                "[algorithm]\noffset-outlier-mad-multiplier = {value}"
+            ));
+
+            let error = config.unwrap_err();
+            assert!(error.to_string().contains("positive, finite number"));
+        }
+
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            [algorithm]
+            offset-outlier-mad-multiplier = 3.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.algorithm.offset_outlier_mad_multiplier, 3.0);
+    }
+
     #[test]
     fn deny_unknown_fields() {
         let config: Result<SynchronizationConfig, _> = toml::from_str(
@@ -764,5 +1389,52 @@ mod tests {
         assert_eq!(config.interface, Some(expected));
 
         assert_eq!(config.timestamp_mode, TimestampMode::Software);
+        assert_eq!(config.clock_key, ClockKey::realtime());
+    }
+
+    #[test]
+    fn leap_seconds_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [leap-seconds]
+            file = "/usr/share/zoneinfo/leap-seconds.list"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.leap_seconds.file,
+            Some(PathBuf::from("/usr/share/zoneinfo/leap-seconds.list"))
+        );
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.leap_seconds.file, None);
+    }
+
+    #[test]
+    fn clock_state_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [clock-state]
+            storage-path = "/var/lib/ntpd-rs/clock-state.json"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.clock_state.storage_path,
+            Some(PathBuf::from("/var/lib/ntpd-rs/clock-state.json"))
+        );
+        assert_eq!(config.clock_state.store_interval, 3600);
+        assert!(config.validate().is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [clock-state]
+            store-interval = 0
+            "#,
+        )
+        .unwrap();
+        assert!(!config.check());
     }
 }
@@ -1,20 +1,112 @@
 use std::{
     fmt,
     net::{IpAddr, SocketAddr},
-    ops::Deref,
+    ops::{Deref, RangeInclusive},
     path::PathBuf,
     sync::{Arc, Mutex},
 };
 
+use ntp_proto::NtpDuration;
 use rustls::pki_types::CertificateDer;
 use serde::{de, Deserialize, Deserializer};
 
 use super::super::keyexchange::certificates_from_file;
+use super::ConfigIssue;
+
+/// Relative trust in a source, scaling its influence when the measurements
+/// of all sources are combined. A source with weight 2.0 counts twice as much
+/// as a source of the same quality with the default weight of 1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceWeight(f64);
+
+// Weights are always finite, so equality is reflexive
+impl Eq for SourceWeight {}
+
+impl SourceWeight {
+    pub fn new(weight: f64) -> Option<SourceWeight> {
+        (weight.is_finite() && weight > 0.0).then_some(SourceWeight(weight))
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl Default for SourceWeight {
+    fn default() -> Self {
+        SourceWeight(1.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceWeight {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let weight = f64::deserialize(deserializer)?;
+        SourceWeight::new(weight)
+            .ok_or_else(|| de::Error::custom("weight should be a positive, finite number"))
+    }
+}
 
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct StandardSource {
     pub address: NtpAddress,
+    #[serde(default)]
+    pub weight: SourceWeight,
+    #[serde(default, rename = "trust-leap")]
+    pub trust_leap: bool,
+    #[serde(default)]
+    pub pin: bool,
+    #[serde(default, rename = "no-select")]
+    pub no_select: bool,
+    /// Only slew the clock based on this source, stepping requires another
+    /// source to agree
+    #[serde(default, rename = "no-step")]
+    pub no_step: bool,
+    /// Use our own measurements instead of the root delay and dispersion
+    /// advertised by the source
+    #[serde(default, rename = "ignore-root")]
+    pub ignore_root: bool,
+    /// Lowest stratum the source is expected to have, it is not
+    /// synchronized to at a lower stratum
+    #[serde(default, rename = "min-stratum")]
+    pub min_stratum: Option<u8>,
+    /// Highest stratum the source is expected to have, it is not
+    /// synchronized to at a higher stratum
+    #[serde(default, rename = "max-stratum")]
+    pub max_stratum: Option<u8>,
+    #[serde(default, rename = "on-route-loss")]
+    pub on_route_loss: RouteLossAction,
+}
+
+/// Range of strata a source with the given bounds is expected to have, if
+/// any bound is configured
+fn stratum_range(min: Option<u8>, max: Option<u8>) -> Option<RangeInclusive<u8>> {
+    if min.is_none() && max.is_none() {
+        return None;
+    }
+    Some(min.unwrap_or(0)..=max.unwrap_or(u8::MAX))
+}
+
+impl StandardSource {
+    pub fn stratum_range(&self) -> Option<RangeInclusive<u8>> {
+        stratum_range(self.min_stratum, self.max_stratum)
+    }
+}
+
+/// What a source does when sending fails because there is no route to its
+/// server.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum RouteLossAction {
+    /// Remove the source and spawn it again, resolving its address anew
+    #[default]
+    Restart,
+    /// Keep the source, but stop polling and probe with backoff until the
+    /// route returns
+    Probe,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
@@ -27,6 +119,36 @@ pub struct NtsSourceConfig {
         rename = "certificate-authority"
     )]
     pub certificate_authorities: Arc<[CertificateDer<'static>]>,
+    #[serde(default)]
+    pub weight: SourceWeight,
+    #[serde(default, rename = "trust-leap")]
+    pub trust_leap: bool,
+    #[serde(default)]
+    pub pin: bool,
+    #[serde(default, rename = "no-select")]
+    pub no_select: bool,
+    /// Only slew the clock based on this source, stepping requires another
+    /// source to agree
+    #[serde(default, rename = "no-step")]
+    pub no_step: bool,
+    /// Use our own measurements instead of the root delay and dispersion
+    /// advertised by the source
+    #[serde(default, rename = "ignore-root")]
+    pub ignore_root: bool,
+    /// Lowest stratum the source is expected to have, it is not
+    /// synchronized to at a lower stratum
+    #[serde(default, rename = "min-stratum")]
+    pub min_stratum: Option<u8>,
+    /// Highest stratum the source is expected to have, it is not
+    /// synchronized to at a higher stratum
+    #[serde(default, rename = "max-stratum")]
+    pub max_stratum: Option<u8>,
+}
+
+impl NtsSourceConfig {
+    pub fn stratum_range(&self) -> Option<RangeInclusive<u8>> {
+        stratum_range(self.min_stratum, self.max_stratum)
+    }
 }
 
 fn deserialize_certificate_authorities<'de, D>(
@@ -59,6 +181,18 @@ pub struct PoolSourceConfig {
     pub count: usize,
     #[serde(default)]
     pub ignore: Vec<IpAddr>,
+    #[serde(default)]
+    pub weight: SourceWeight,
+    #[serde(default, rename = "trust-leap")]
+    pub trust_leap: bool,
+    /// Replace sources with a fresh address from the pool after at most this
+    /// many seconds
+    #[serde(default, rename = "max-association-lifetime")]
+    pub max_association_lifetime: Option<u64>,
+    /// Keep at most this many unused addresses of the pool around to replace
+    /// sources with
+    #[serde(default, rename = "max-backups")]
+    pub max_backups: Option<usize>,
 }
 
 fn max_sources_default() -> usize {
@@ -79,6 +213,25 @@ pub struct NtsPoolSourceConfig {
     pub certificate_authorities: Arc<[CertificateDer<'static>]>,
     #[serde(default = "max_sources_default")]
     pub count: usize,
+    #[serde(default)]
+    pub weight: SourceWeight,
+    #[serde(default, rename = "trust-leap")]
+    pub trust_leap: bool,
+}
+
+/// A GPS receiver sending NMEA sentences over a serial line. The line must
+/// already be set up (baud rate, raw mode), for example with `stty`.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NmeaSourceConfig {
+    /// Device the receiver is connected to, such as `/dev/ttyUSB0`
+    pub path: PathBuf,
+    /// How long after the start of a second the receiver's sentences for
+    /// that second arrive
+    #[serde(default)]
+    pub offset: NtpDuration,
+    #[serde(default)]
+    pub weight: SourceWeight,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
@@ -93,6 +246,99 @@ pub enum NtpSourceConfig {
     #[cfg(feature = "unstable_nts-pool")]
     #[serde(rename = "nts-pool")]
     NtsPool(NtsPoolSourceConfig),
+    #[serde(rename = "nmea")]
+    Nmea(NmeaSourceConfig),
+}
+
+impl NtpSourceConfig {
+    /// Whether the daemon should synchronize to only this source
+    pub fn is_pinned(&self) -> bool {
+        match self {
+            NtpSourceConfig::Standard(config) => config.pin,
+            NtpSourceConfig::Nts(config) => config.pin,
+            _ => false,
+        }
+    }
+
+    /// Run the semantic checks that apply to this source on its own. Checks
+    /// that involve other sources, such as pinning more than one source, are
+    /// left to the caller.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+
+        let address = match self {
+            NtpSourceConfig::Standard(config) => {
+                if config.pin && config.no_select {
+                    issues.push(ConfigIssue::error(format!(
+                        "Source {} is pinned, so it cannot also be excluded with no-select.",
+                        config.address.0
+                    )));
+                }
+                if config
+                    .stratum_range()
+                    .map_or(false, |range| range.is_empty())
+                {
+                    issues.push(ConfigIssue::error(format!(
+                        "Source {} has a minimum stratum above its maximum stratum.",
+                        config.address.0
+                    )));
+                }
+                &config.address.0
+            }
+            NtpSourceConfig::Nts(config) => {
+                if config.pin && config.no_select {
+                    issues.push(ConfigIssue::error(format!(
+                        "Source {} is pinned, so it cannot also be excluded with no-select.",
+                        config.address.0
+                    )));
+                }
+                if config
+                    .stratum_range()
+                    .map_or(false, |range| range.is_empty())
+                {
+                    issues.push(ConfigIssue::error(format!(
+                        "Source {} has a minimum stratum above its maximum stratum.",
+                        config.address.0
+                    )));
+                }
+                &config.address.0
+            }
+            NtpSourceConfig::Pool(config) => {
+                if config.count == 0 {
+                    issues.push(ConfigIssue::warning(format!(
+                        "Pool {} has a count of 0 and will not provide any sources.",
+                        config.addr.0
+                    )));
+                }
+                if config.max_association_lifetime == Some(0) {
+                    issues.push(ConfigIssue::error(format!(
+                        "Pool {} has a maximum association lifetime of 0 seconds.",
+                        config.addr.0
+                    )));
+                }
+                &config.addr.0
+            }
+            #[cfg(feature = "unstable_nts-pool")]
+            NtpSourceConfig::NtsPool(config) => {
+                if config.count == 0 {
+                    issues.push(ConfigIssue::warning(format!(
+                        "NTS pool {} has a count of 0 and will not provide any sources.",
+                        config.addr.0
+                    )));
+                }
+                &config.addr.0
+            }
+            NtpSourceConfig::Nmea(_) => return issues,
+        };
+
+        if address.server_name.is_empty() {
+            issues.push(ConfigIssue::error(
+                "Source configured with an empty address.",
+            ));
+        }
+
+        issues
+    }
 }
 
 /// A normalized address has a host and a port part. However, the host may be
@@ -189,12 +435,24 @@ impl Deref for NtpAddress {
     }
 }
 
+/// Convert IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) to their IPv4 form,
+/// such that both forms of an address compare equal.
+pub(crate) fn canonical_socket_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(v4.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
 impl NormalizedAddress {
     const NTP_DEFAULT_PORT: u16 = 123;
     const NTS_KE_DEFAULT_PORT: u16 = 4460;
 
     /// Specifically, this adds the `:123` port if no port is specified
-    pub(crate) fn from_string_ntp(address: String) -> std::io::Result<Self> {
+    pub fn from_string_ntp(address: String) -> std::io::Result<Self> {
         let (server_name, port) = Self::from_string_help(address, Self::NTP_DEFAULT_PORT)?;
 
         Ok(Self {
@@ -224,6 +482,11 @@ impl NormalizedAddress {
             // IPv6, try to parse it as such
             match address.parse::<SocketAddr>() {
                 Ok(socket_addr) => {
+                    if let SocketAddr::V4(v4) = canonical_socket_addr(socket_addr) {
+                        // IPv4-mapped, use the plain IPv4 form
+                        return Ok((v4.ip().to_string(), v4.port()));
+                    }
+
                     // strip off the port
                     let (server_name, _) = address.rsplit_once(':').unwrap();
 
@@ -232,10 +495,13 @@ impl NormalizedAddress {
                 Err(e) => {
                     // Could be because of no port, add one and see
                     let address_with_port = format!("[{address}]:{default_port}");
-                    if address_with_port.parse::<SocketAddr>().is_ok() {
-                        Ok((format!("[{address}]"), default_port))
-                    } else {
-                        Err(std::io::Error::new(std::io::ErrorKind::Other, e))
+                    match address_with_port
+                        .parse::<SocketAddr>()
+                        .map(canonical_socket_addr)
+                    {
+                        Ok(SocketAddr::V4(v4)) => Ok((v4.ip().to_string(), default_port)),
+                        Ok(SocketAddr::V6(_)) => Ok((format!("[{address}]"), default_port)),
+                        Err(_) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
                     }
                 }
             }
@@ -280,7 +546,9 @@ impl NormalizedAddress {
 
     #[cfg(not(test))]
     pub async fn lookup_host(&self) -> std::io::Result<impl Iterator<Item = SocketAddr> + '_> {
-        tokio::net::lookup_host((self.server_name.as_str(), self.port)).await
+        let addresses = tokio::net::lookup_host((self.server_name.as_str(), self.port)).await?;
+
+        Ok(addresses.map(canonical_socket_addr))
     }
 
     #[cfg(test)]
@@ -295,7 +563,7 @@ impl NormalizedAddress {
 
         let addresses = addresses.to_vec();
 
-        Ok(addresses.into_iter())
+        Ok(addresses.into_iter().map(canonical_socket_addr))
     }
 }
 
@@ -311,6 +579,15 @@ impl TryFrom<&str> for StandardSource {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         Ok(Self {
             address: NormalizedAddress::from_string_ntp(value.to_string())?.into(),
+            weight: Default::default(),
+            trust_leap: false,
+            pin: false,
+            no_select: false,
+            no_step: false,
+            ignore_root: false,
+            min_stratum: None,
+            max_stratum: None,
+            on_route_loss: Default::default(),
         })
     }
 }
@@ -334,6 +611,7 @@ mod tests {
             NtpSourceConfig::Pool(c) => c.addr.to_string(),
             #[cfg(feature = "unstable_nts-pool")]
             NtpSourceConfig::NtsPool(c) => c.addr.to_string(),
+            NtpSourceConfig::Nmea(c) => c.path.display().to_string(),
         }
     }
 
@@ -434,6 +712,22 @@ mod tests {
                 assert_eq!(config.address.to_string(), "example.com:4460");
             }
         }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "nmea"
+            path = "/dev/ttyUSB0"
+            offset = 0.25
+            "#,
+        )
+        .unwrap();
+        assert_eq!(source_addr(&test.source), "/dev/ttyUSB0");
+        let NtpSourceConfig::Nmea(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(config.offset, NtpDuration::from_seconds(0.25));
+        assert_eq!(config.weight, SourceWeight::default());
     }
 
     #[test]
@@ -463,6 +757,146 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deserialize_source_weight() {
+        #[derive(Deserialize, Debug)]
+        struct TestConfig {
+            source: NtpSourceConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(config.weight.get(), 1.0);
+        assert!(!config.trust_leap);
+        assert!(!config.pin);
+        assert!(!config.no_select);
+        assert!(!config.no_step);
+        assert!(!config.ignore_root);
+        assert_eq!(config.stratum_range(), None);
+        assert_eq!(config.on_route_loss, RouteLossAction::Restart);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            pin = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert!(config.pin);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            no-select = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert!(config.no_select);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            no-step = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert!(config.no_step);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            ignore-root = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert!(config.ignore_root);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            max-stratum = 1
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(config.stratum_range(), Some(0..=1));
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "server"
+            address = "example.com"
+            on-route-loss = "probe"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(config.on_route_loss, RouteLossAction::Probe);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "pool"
+            address = "example.com"
+            weight = 2.5
+            trust-leap = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(config) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(config.weight.get(), 2.5);
+        assert!(config.trust_leap);
+
+        for weight in ["0.0", "-1.0", "nan", "inf"] {
+            let test: Result<TestConfig, _> = toml::from_str(&format!(
+                r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                weight = {weight}
+                "#,
+            ));
+            assert!(test.is_err(), "weight {weight} should be rejected");
+        }
+    }
+
     #[test]
     fn test_source_from_string() {
         let source = NtpSourceConfig::try_from("example.com").unwrap();
@@ -487,5 +921,9 @@ mod tests {
         assert_eq!(addr.to_string(), "127.0.0.1:123");
         let addr = NormalizedAddress::from_string_ntp("1234567890.example.com".into()).unwrap();
         assert_eq!(addr.to_string(), "1234567890.example.com:123");
+        let addr = NormalizedAddress::from_string_ntp("[::ffff:127.0.0.1]:456".into()).unwrap();
+        assert_eq!(addr.to_string(), "127.0.0.1:456");
+        let addr = NormalizedAddress::from_string_ntp("::ffff:127.0.0.1".into()).unwrap();
+        assert_eq!(addr.to_string(), "127.0.0.1:123");
     }
 }
@@ -5,7 +5,7 @@ use std::{
     time::Duration,
 };
 
-use ntp_proto::FilterList;
+use ntp_proto::{FilterList, NtpDuration, UnsynchronizedAction};
 use serde::{Deserialize, Deserializer};
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
@@ -57,6 +57,37 @@ pub struct ServerConfig {
         deserialize_with = "deserialize_rate_limiting_cutoff"
     )]
     pub rate_limiting_cutoff: Duration,
+    #[serde(default)]
+    pub detect_loops: bool,
+    #[serde(
+        default,
+        rename = "response-jitter-us",
+        deserialize_with = "deserialize_response_jitter"
+    )]
+    pub response_jitter: Duration,
+    #[serde(default)]
+    pub track_interfaces: bool,
+    #[serde(
+        default,
+        rename = "max-request-lag-ms",
+        deserialize_with = "deserialize_max_request_lag"
+    )]
+    pub max_request_lag: Option<Duration>,
+    /// Most requests processed per second, excess requests are dropped
+    #[serde(default)]
+    pub max_request_rate: Option<u32>,
+    #[serde(default)]
+    pub serve_only_when_synced: Option<UnsynchronizedAction>,
+    /// Only serve this peer, through a socket connected to it, instead of
+    /// serving any client
+    #[serde(default)]
+    pub connect: Option<SocketAddr>,
+    /// Lowest root delay advertised to clients (seconds)
+    #[serde(default)]
+    pub min_root_delay: NtpDuration,
+    /// Lowest root dispersion advertised to clients (seconds)
+    #[serde(default)]
+    pub min_root_dispersion: NtpDuration,
 }
 
 fn default_denylist() -> FilterList {
@@ -73,12 +104,34 @@ fn default_allowlist() -> FilterList {
     }
 }
 
+/// Upper bound on the response jitter, larger delays cost clients too much
+/// accuracy.
+const MAX_RESPONSE_JITTER_MICROS: u64 = 1000;
+
 fn deserialize_rate_limiting_cutoff<'de, D: Deserializer<'de>>(
     deserializer: D,
 ) -> Result<Duration, D::Error> {
     Ok(Duration::from_millis(u64::deserialize(deserializer)?))
 }
 
+fn deserialize_response_jitter<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let micros = u64::deserialize(deserializer)?;
+    if micros > MAX_RESPONSE_JITTER_MICROS {
+        return Err(serde::de::Error::custom(format!(
+            "response jitter must be at most {MAX_RESPONSE_JITTER_MICROS}us"
+        )));
+    }
+    Ok(Duration::from_micros(micros))
+}
+
+fn deserialize_max_request_lag<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Some(Duration::from_millis(u64::deserialize(deserializer)?)))
+}
+
 impl TryFrom<&str> for ServerConfig {
     type Error = AddrParseError;
 
@@ -89,6 +142,15 @@ impl TryFrom<&str> for ServerConfig {
             allowlist: default_allowlist(),
             rate_limiting_cache_size: Default::default(),
             rate_limiting_cutoff: Default::default(),
+            detect_loops: false,
+            response_jitter: Duration::ZERO,
+            track_interfaces: false,
+            max_request_lag: None,
+            max_request_rate: None,
+            serve_only_when_synced: None,
+            connect: None,
+            min_root_delay: NtpDuration::ZERO,
+            min_root_dispersion: NtpDuration::ZERO,
         })
     }
 }
@@ -100,6 +162,10 @@ impl From<ServerConfig> for ntp_proto::ServerConfig {
             allowlist: value.allowlist,
             rate_limiting_cache_size: value.rate_limiting_cache_size,
             rate_limiting_cutoff: value.rate_limiting_cutoff,
+            detect_loops: value.detect_loops,
+            serve_only_when_synced: value.serve_only_when_synced,
+            min_root_delay: value.min_root_delay,
+            min_root_dispersion: value.min_root_dispersion,
         }
     }
 }
@@ -148,6 +214,14 @@ mod tests {
             ntp_proto::FilterAction::Ignore
         );
         assert_eq!(test.server.denylist.action, ntp_proto::FilterAction::Deny);
+        assert_eq!(test.server.response_jitter, Duration::ZERO);
+        assert!(!test.server.track_interfaces);
+        assert_eq!(test.server.max_request_lag, None);
+        assert_eq!(test.server.max_request_rate, None);
+        assert_eq!(test.server.serve_only_when_synced, None);
+        assert_eq!(test.server.connect, None);
+        assert_eq!(test.server.min_root_delay, NtpDuration::ZERO);
+        assert_eq!(test.server.min_root_dispersion, NtpDuration::ZERO);
 
         let test: TestConfig = toml::from_str(
             r#"
@@ -155,6 +229,11 @@ mod tests {
             listen = "127.0.0.1:123"
             rate-limiting-cutoff-ms = 1000
             rate-limiting-cache-size = 32
+            response-jitter-us = 250
+            max-request-lag-ms = 50
+            max-request-rate = 10000
+            serve-only-when-synced = "unsynchronized"
+            min-root-dispersion = 0.0005
             "#,
         )
         .unwrap();
@@ -164,6 +243,26 @@ mod tests {
             test.server.rate_limiting_cutoff,
             Duration::from_millis(1000)
         );
+        assert_eq!(test.server.response_jitter, Duration::from_micros(250));
+
+        let too_much_jitter: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            response-jitter-us = 5000
+            "#,
+        );
+        assert!(too_much_jitter.is_err());
+        assert_eq!(test.server.max_request_lag, Some(Duration::from_millis(50)));
+        assert_eq!(test.server.max_request_rate, Some(10000));
+        assert_eq!(
+            test.server.serve_only_when_synced,
+            Some(UnsynchronizedAction::Unsynchronized)
+        );
+        assert_eq!(
+            test.server.min_root_dispersion,
+            NtpDuration::from_seconds(0.0005)
+        );
 
         let test: TestConfig = toml::from_str(
             r#"
@@ -0,0 +1,347 @@
+use super::config::NtpSourceConfig;
+use super::sockets::{create_unix_socket_with_permissions, ObserverError};
+use super::spawn::SourceId;
+use super::system::SystemCommand;
+use ntp_proto::StepThreshold;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use serde::{Deserialize, Serialize};
+
+/// How long the control socket waits for a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum number of control connections handled at the same time
+const MAX_CONNECTIONS: usize = 16;
+
+/// How long an add request waits for the source to be created. This includes
+/// resolving its address (and for NTS, the key exchange).
+const ADD_SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request sent by a client after connecting to the control socket. The
+/// current sources and their ids are available through the observation socket.
+#[derive(Debug, Deserialize)]
+pub enum ControlRequest {
+    /// Add sources, using the same format as a `[[source]]` in the configuration
+    AddSource(NtpSourceConfig),
+    /// Remove a running source. Sources from a pool are not replaced by the
+    /// same address again.
+    RemoveSource(SourceId),
+    /// Replace the single step panic threshold, using the same format as
+    /// `single-step-panic-threshold` in the configuration. The backward
+    /// threshold must be bounded.
+    SetStepPanicThreshold(StepThreshold),
+    /// Clear the running total of clock steps counted against the
+    /// accumulated step panic threshold
+    ResetAccumulatedSteps,
+    /// Synchronize to only this source, bypassing the selection and
+    /// combination of sources, until the daemon restarts
+    PinSource(SourceId),
+    /// Return to automatic selection of sources
+    UnpinSource,
+    /// Start measuring the response latency percentiles of the servers anew
+    ResetServerLatency,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlResponse {
+    /// The (first) source for the configuration is running
+    SourceAdded(SourceId),
+    SourceRemoved,
+    UnknownSource,
+    StepPanicThresholdSet,
+    AccumulatedStepsReset,
+    SourcePinned,
+    SourceUnpinned,
+    ServerLatencyReset,
+    Failed(String),
+}
+
+pub async fn spawn(
+    config: &super::config::ObservabilityConfig,
+    command_sender: mpsc::Sender<SystemCommand>,
+) -> JoinHandle<Result<(), ObserverError>> {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let result = control(config, command_sender).await;
+        if let Err(ref e) = result {
+            warn!("Abnormal termination of the control socket: {e}");
+            warn!("Sources can not be changed at runtime");
+        }
+        result
+    })
+}
+
+async fn control(
+    config: super::config::ObservabilityConfig,
+    command_sender: mpsc::Sender<SystemCommand>,
+) -> Result<(), ObserverError> {
+    let path = match config.control_path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let permissions: std::fs::Permissions = PermissionsExt::from_mode(config.control_permissions);
+
+    let listener = create_unix_socket_with_permissions(&path, permissions)?;
+
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        // wait for a free slot before accepting, so clients can't pile up tasks
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let (stream, _addr) = listener.accept().await?;
+
+        // adding a source can take a while, don't block other clients meanwhile
+        let command_sender = command_sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, command_sender).await {
+                warn!("Could not handle control request: {e}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: UnixStream,
+    command_sender: mpsc::Sender<SystemCommand>,
+) -> Result<(), ObserverError> {
+    let mut msg = Vec::new();
+    let request = match tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        super::sockets::read_json::<ControlRequest>(&mut stream, &mut msg),
+    )
+    .await
+    {
+        Ok(request) => request?,
+        Err(_) => {
+            warn!("Timed out waiting for control request");
+            return Ok(());
+        }
+    };
+
+    let response = handle_request(request, &command_sender).await;
+    super::sockets::write_json(&mut stream, &response).await
+}
+
+async fn handle_request(
+    request: ControlRequest,
+    command_sender: &mpsc::Sender<SystemCommand>,
+) -> ControlResponse {
+    match request {
+        ControlRequest::AddSource(config) => {
+            let (reply, response) = oneshot::channel();
+            if command_sender
+                .send(SystemCommand::AddSource { config, reply })
+                .await
+                .is_err()
+            {
+                return ControlResponse::Failed("the daemon is shutting down".into());
+            }
+
+            match tokio::time::timeout(ADD_SOURCE_TIMEOUT, response).await {
+                Ok(Ok(id)) => ControlResponse::SourceAdded(id),
+                Ok(Err(_)) => ControlResponse::Failed("could not add source".into()),
+                Err(_) => ControlResponse::Failed(
+                    "timed out waiting for the source, it will be added once it is reachable"
+                        .into(),
+                ),
+            }
+        }
+        ControlRequest::RemoveSource(id) => {
+            let (reply, response) = oneshot::channel();
+            if command_sender
+                .send(SystemCommand::RemoveSource { id, reply })
+                .await
+                .is_err()
+            {
+                return ControlResponse::Failed("the daemon is shutting down".into());
+            }
+
+            match response.await {
+                Ok(true) => ControlResponse::SourceRemoved,
+                Ok(false) => ControlResponse::UnknownSource,
+                Err(_) => ControlResponse::Failed("could not remove source".into()),
+            }
+        }
+        ControlRequest::SetStepPanicThreshold(threshold) => {
+            // an unlimited backward threshold is easily set by accident, as
+            // it is what leaving out `backward` means
+            if threshold.backward.is_none() {
+                return ControlResponse::Failed("the backward threshold must be bounded".into());
+            }
+
+            let (reply, response) = oneshot::channel();
+            if command_sender
+                .send(SystemCommand::SetStepPanicThreshold { threshold, reply })
+                .await
+                .is_err()
+            {
+                return ControlResponse::Failed("the daemon is shutting down".into());
+            }
+
+            match response.await {
+                Ok(()) => ControlResponse::StepPanicThresholdSet,
+                Err(_) => ControlResponse::Failed("could not set step panic threshold".into()),
+            }
+        }
+        ControlRequest::ResetAccumulatedSteps => {
+            let (reply, response) = oneshot::channel();
+            if command_sender
+                .send(SystemCommand::ResetAccumulatedSteps { reply })
+                .await
+                .is_err()
+            {
+                return ControlResponse::Failed("the daemon is shutting down".into());
+            }
+
+            match response.await {
+                Ok(()) => ControlResponse::AccumulatedStepsReset,
+                Err(_) => ControlResponse::Failed("could not reset accumulated steps".into()),
+            }
+        }
+        ControlRequest::PinSource(id) => pin_source(command_sender, Some(id)).await,
+        ControlRequest::UnpinSource => pin_source(command_sender, None).await,
+        ControlRequest::ResetServerLatency => {
+            let (reply, response) = oneshot::channel();
+            if command_sender
+                .send(SystemCommand::ResetServerLatency { reply })
+                .await
+                .is_err()
+            {
+                return ControlResponse::Failed("the daemon is shutting down".into());
+            }
+
+            match response.await {
+                Ok(()) => ControlResponse::ServerLatencyReset,
+                Err(_) => ControlResponse::Failed("could not reset server latency".into()),
+            }
+        }
+    }
+}
+
+async fn pin_source(
+    command_sender: &mpsc::Sender<SystemCommand>,
+    id: Option<SourceId>,
+) -> ControlResponse {
+    let (reply, response) = oneshot::channel();
+    if command_sender
+        .send(SystemCommand::PinSource { id, reply })
+        .await
+        .is_err()
+    {
+        return ControlResponse::Failed("the daemon is shutting down".into());
+    }
+
+    match (response.await, id) {
+        (Ok(true), Some(_)) => ControlResponse::SourcePinned,
+        (Ok(true), None) => ControlResponse::SourceUnpinned,
+        (Ok(false), _) => ControlResponse::UnknownSource,
+        (Err(_), _) => ControlResponse::Failed("could not change the pinned source".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sockets::{read_json, write_json};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_control() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-15");
+        let config = super::super::config::ObservabilityConfig {
+            control_path: Some(path.clone()),
+            control_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (command_sender, mut command_receiver) = mpsc::channel(1);
+        let handle = spawn(&config, command_sender).await;
+
+        let source_id = SourceId::new();
+        let system = tokio::spawn(async move {
+            while let Some(command) = command_receiver.recv().await {
+                match command {
+                    SystemCommand::AddSource { config, reply } => {
+                        assert!(matches!(config, NtpSourceConfig::Standard(_)));
+                        reply.send(source_id).unwrap();
+                    }
+                    SystemCommand::RemoveSource { id, reply } => {
+                        reply.send(id == source_id).unwrap();
+                    }
+                    SystemCommand::SetStepPanicThreshold { threshold, reply } => {
+                        assert_eq!(
+                            threshold.backward,
+                            Some(ntp_proto::NtpDuration::from_seconds(10.0))
+                        );
+                        reply.send(()).unwrap();
+                    }
+                    SystemCommand::ResetAccumulatedSteps { reply } => {
+                        reply.send(()).unwrap();
+                    }
+                    SystemCommand::PinSource { id, reply } => {
+                        reply.send(id.map_or(true, |id| id == source_id)).unwrap();
+                    }
+                    SystemCommand::ResetServerLatency { reply } => {
+                        reply.send(()).unwrap();
+                    }
+                    SystemCommand::ObserveSourceFilter { .. }
+                    | SystemCommand::ObserveSourceHistory { .. }
+                    | SystemCommand::ObserveSourceDebug { .. } => unreachable!(),
+                }
+            }
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let requests = [
+            serde_json::json!({ "AddSource": { "mode": "server", "address": "127.0.0.1:123" } }),
+            serde_json::json!({ "RemoveSource": source_id }),
+            serde_json::json!({ "RemoveSource": SourceId::new() }),
+            serde_json::json!({ "SetStepPanicThreshold": { "forward": "inf", "backward": 10 } }),
+            serde_json::json!({ "SetStepPanicThreshold": { "forward": 10 } }),
+            serde_json::json!("ResetAccumulatedSteps"),
+            serde_json::json!({ "PinSource": source_id }),
+            serde_json::json!({ "PinSource": SourceId::new() }),
+            serde_json::json!("UnpinSource"),
+            serde_json::json!("ResetServerLatency"),
+        ];
+        let mut responses = vec![];
+        for request in requests {
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            write_json(&mut stream, &request).await.unwrap();
+            let mut msg = Vec::new();
+            let response: ControlResponse = read_json(&mut stream, &mut msg).await.unwrap();
+            responses.push(response);
+        }
+
+        assert_eq!(
+            responses,
+            [
+                ControlResponse::SourceAdded(source_id),
+                ControlResponse::SourceRemoved,
+                ControlResponse::UnknownSource,
+                ControlResponse::StepPanicThresholdSet,
+                ControlResponse::Failed("the backward threshold must be bounded".into()),
+                ControlResponse::AccumulatedStepsReset,
+                ControlResponse::SourcePinned,
+                ControlResponse::UnknownSource,
+                ControlResponse::SourceUnpinned,
+                ControlResponse::ServerLatencyReset,
+            ]
+        );
+
+        handle.abort();
+        system.abort();
+    }
+}
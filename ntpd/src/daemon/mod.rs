@@ -1,23 +1,27 @@
 mod clock;
 pub mod config;
+pub mod control;
 pub mod keyexchange;
 mod local_ip_provider;
+mod nmea_source;
 mod ntp_source;
 pub mod nts_key_provider;
 pub mod observer;
 mod server;
 pub mod sockets;
 pub mod spawn;
+pub mod state_file;
 mod system;
 pub mod tracing;
 mod util;
 
-use std::{error::Error, path::PathBuf};
+use std::{error::Error, path::PathBuf, time::Duration};
 
 use ::tracing::info;
 pub use config::Config;
-pub use observer::{ObservableSourceState, ObservableState, ObservedSourceState};
-pub use system::spawn;
+use ntp_proto::KeySetProvider;
+pub use observer::{ObservableSourceState, ObservableState, ObserveRequest, ObservedSourceState};
+pub use system::{spawn, spawn_with_resolved, DaemonChannels, SystemStateConfig};
 use tracing_subscriber::util::SubscriberInitExt;
 
 use config::NtpDaemonOptions;
@@ -26,6 +30,9 @@ use self::tracing::LogLevel;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long one-shot mode waits for the clock to be set by default
+const DEFAULT_ONCE_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub async fn main() -> Result<(), Box<dyn Error>> {
     let options = NtpDaemonOptions::try_parse_from(std::env::args())?;
 
@@ -36,7 +43,9 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         config::NtpDaemonAction::Version => {
             eprintln!("ntp-daemon {VERSION}");
         }
+        config::NtpDaemonAction::CheckConfig => check_config(options).await,
         config::NtpDaemonAction::Run => run(options).await?,
+        config::NtpDaemonAction::Once => run_once(options).await?,
     }
 
     Ok(())
@@ -78,6 +87,25 @@ pub(crate) async fn initialize_logging_parse_config(
     config
 }
 
+async fn check_config(options: NtpDaemonOptions) {
+    // log messages emitted while parsing (such as about permissions) are useful here too
+    self::tracing::tracing_init(options.log_level.unwrap_or_default()).init();
+
+    let config = match Config::from_args(options.config, vec![], vec![]).await {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: Could not load configuration: {e}");
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+
+    if !config.check() {
+        std::process::exit(exitcode::CONFIG);
+    }
+
+    eprintln!("Config looks good");
+}
+
 async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
     let config = initialize_logging_parse_config(options.log_level, options.config).await;
 
@@ -91,24 +119,16 @@ async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
     config.check();
 
     // we always generate the keyset (even if NTS is not used)
-    let keyset = nts_key_provider::spawn(config.keyset).await;
+    let keyset = nts_key_provider::spawn(config.keyset.clone()).await;
 
     #[cfg(feature = "hardware-timestamping")]
-    let clock_config = config.clock;
+    let clock_config = config.clock.clone();
 
     #[cfg(not(feature = "hardware-timestamping"))]
     let clock_config = config::ClockConfig::default();
 
     ::tracing::debug!("Configuration loaded, spawning daemon jobs");
-    let (main_loop_handle, channels) = spawn(
-        config.synchronization,
-        config.source_defaults,
-        clock_config,
-        &config.sources,
-        &config.servers,
-        keyset.clone(),
-    )
-    .await?;
+    let (main_loop_handle, channels) = spawn(&config, clock_config, keyset.clone()).await?;
 
     for nts_ke_config in config.nts_ke {
         let _join_handle = keyexchange::spawn(nts_ke_config, keyset.clone());
@@ -119,12 +139,95 @@ async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
         channels.source_snapshots_receiver,
         channels.server_data_receiver,
         channels.system_snapshot_receiver,
+        channels.command_sender.clone(),
     )
     .await;
 
+    control::spawn(&config.observability, channels.command_sender).await;
+
     Ok(main_loop_handle.await??)
 }
 
+/// Set the clock once from the configured sources and return, like `ntpd -q`.
+/// Only the sources and the clock steering run in this mode: no servers,
+/// key exchange, observation or control sockets are started.
+async fn run_once(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
+    let mut config = initialize_logging_parse_config(options.log_level, options.config).await;
+
+    if let Some(quorum) = options.once_quorum {
+        config.synchronization.minimum_agreeing_sources = quorum;
+    }
+    let timeout = options.once_timeout.unwrap_or(DEFAULT_ONCE_TIMEOUT);
+
+    config.check();
+
+    // nothing is served in one-shot mode, so the keyset is never rotated
+    let (_keyset_sender, keyset) =
+        tokio::sync::watch::channel(KeySetProvider::new(config.keyset.stale_key_count).get());
+
+    #[cfg(feature = "hardware-timestamping")]
+    let clock_config = config.clock.clone();
+
+    #[cfg(not(feature = "hardware-timestamping"))]
+    let clock_config = config::ClockConfig::default();
+
+    // nothing is served or observed in one-shot mode
+    config.servers.clear();
+    config.observability.source_history_length = 0;
+
+    let (mut main_loop_handle, channels) = spawn(&config, clock_config, keyset).await?;
+
+    let mut system_snapshots = channels.system_snapshot_receiver;
+    let clock_set = async {
+        loop {
+            // The clock is set once the sources agreed on the time, and any
+            // slew correcting the offset has ended
+            let time_snapshot = system_snapshots.borrow_and_update().time_snapshot;
+            if time_snapshot.leap_indicator.is_synchronized() && !time_snapshot.slewing {
+                return true;
+            }
+            if system_snapshots.changed().await.is_err() {
+                return false;
+            }
+        }
+    };
+
+    let mut stopped = false;
+    let result: Result<(), Box<dyn Error>> = tokio::select! {
+        clock_set = tokio::time::timeout(timeout, clock_set) => match clock_set {
+            Ok(true) => {
+                info!("Clock set, exiting");
+                Ok(())
+            }
+            Ok(false) => Err("the daemon stopped before the clock was set".into()),
+            Err(_) => Err(format!(
+                "the clock was not set within {}s",
+                timeout.as_secs()
+            )
+            .into()),
+        },
+        result = &mut main_loop_handle => {
+            stopped = true;
+            result??;
+            Err("the daemon stopped before the clock was set".into())
+        }
+    };
+
+    // let the system task shut down its sources before exiting
+    if !stopped {
+        channels.cancel.cancel();
+        match main_loop_handle.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => ::tracing::warn!("Error while shutting down: {e}"),
+            Err(e) => ::tracing::warn!("Error while shutting down: {e}"),
+        }
+    }
+    if let Err(ref e) = result {
+        ::tracing::error!("Could not set the clock once: {e}");
+    }
+    result
+}
+
 pub(crate) mod exitcode {
     /// An internal software error has been detected.  This
     /// should be limited to non-operating system related
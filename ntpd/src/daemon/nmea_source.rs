@@ -0,0 +1,261 @@
+use std::{path::PathBuf, time::Duration};
+
+use ntp_proto::{NmeaSource, NtpClock, NtpDuration, NtpInstant, NtpSourceUpdate};
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::{error, info, instrument, warn, Instrument, Span};
+
+use super::{
+    exitcode, ntp_source::MsgForSystem, observer::SourceDebugDump, spawn::SourceId,
+    util::CancellationToken,
+};
+
+/// Receivers send their sentences every second, so a receiver that sent no
+/// time for this long is missing a second
+const NMEA_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Wait before opening the device again after it could not be read
+const NMEA_REOPEN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reads a GPS receiver from a serial device, and reports the time of its
+/// NMEA sentences to the system
+pub(crate) struct NmeaSourceTask<C: 'static + NtpClock + Send> {
+    index: SourceId,
+    path: PathBuf,
+    clock: C,
+    device: Option<File>,
+    source: NmeaSource,
+    msg_for_system_sender: mpsc::Sender<MsgForSystem>,
+
+    /// Requests for the debug state of the source, which are declined as
+    /// there is no protocol state to report
+    debug_requests: mpsc::Receiver<oneshot::Sender<SourceDebugDump>>,
+
+    /// Stops the task once cancelled
+    cancel: CancellationToken,
+}
+
+/// Reads from the device if it is open, and never completes otherwise
+async fn read_device(device: &mut Option<File>, buf: &mut [u8]) -> std::io::Result<usize> {
+    match device {
+        Some(device) => device.read(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl<C> NmeaSourceTask<C>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn open_device(&mut self) {
+        match File::open(&self.path).await {
+            Ok(device) => {
+                info!("Opened NMEA device");
+                self.device = Some(device);
+            }
+            Err(error) => warn!(?error, "Could not open NMEA device"),
+        }
+    }
+
+    async fn update_system(&self, update: NtpSourceUpdate) {
+        self.msg_for_system_sender
+            .send(MsgForSystem::SourceUpdate(self.index, update))
+            .await
+            .ok();
+    }
+
+    async fn run(&mut self) {
+        let mut buf = [0_u8; 256];
+
+        let timeout = tokio::time::sleep(NMEA_TIMEOUT);
+        tokio::pin!(timeout);
+        let reopen = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(reopen);
+
+        loop {
+            enum SelectResult {
+                Read(std::io::Result<usize>),
+                Timeout,
+                Reopen,
+                DebugRequest(oneshot::Sender<SourceDebugDump>),
+                Cancelled,
+            }
+
+            let closed = self.device.is_none();
+            let cancel = self.cancel.clone();
+            let debug_requests = &mut self.debug_requests;
+            let device = &mut self.device;
+            let selected = tokio::select! {
+                () = cancel.cancelled() => {
+                    SelectResult::Cancelled
+                },
+                () = &mut timeout => {
+                    SelectResult::Timeout
+                },
+                () = &mut reopen, if closed => {
+                    SelectResult::Reopen
+                },
+                result = read_device(device, &mut buf) => {
+                    SelectResult::Read(result)
+                },
+                Some(reply) = debug_requests.recv() => {
+                    SelectResult::DebugRequest(reply)
+                },
+            };
+
+            match selected {
+                SelectResult::Cancelled => return,
+                SelectResult::DebugRequest(reply) => drop(reply),
+                SelectResult::Reopen => {
+                    self.open_device().await;
+                    reopen.as_mut().reset(Instant::now() + NMEA_REOPEN_INTERVAL);
+                }
+                SelectResult::Timeout => {
+                    let update = self.source.handle_timeout();
+                    self.update_system(update).await;
+                    timeout.as_mut().reset(Instant::now() + NMEA_TIMEOUT);
+                }
+                SelectResult::Read(Ok(0)) => {
+                    warn!("NMEA device closed");
+                    self.device = None;
+                }
+                SelectResult::Read(Ok(size)) => {
+                    let localtime = match self.clock.now() {
+                        Err(e) => {
+                            error!(error = ?e, "There was an error retrieving the current time");
+
+                            // report as no permissions, since this seems the most likely
+                            std::process::exit(exitcode::NOPERM);
+                        }
+                        Ok(ts) => ts,
+                    };
+
+                    if let Some(update) =
+                        self.source
+                            .handle_data(&buf[..size], localtime, NtpInstant::now())
+                    {
+                        self.update_system(update).await;
+                        timeout.as_mut().reset(Instant::now() + NMEA_TIMEOUT);
+                    }
+                }
+                SelectResult::Read(Err(error)) => {
+                    warn!(?error, "Could not read from NMEA device");
+                    self.device = None;
+                }
+            }
+        }
+    }
+
+    #[instrument(skip(clock, msg_for_system_sender, debug_requests, cancel))]
+    pub fn spawn(
+        index: SourceId,
+        path: PathBuf,
+        offset: NtpDuration,
+        clock: C,
+        msg_for_system_sender: mpsc::Sender<MsgForSystem>,
+        debug_requests: mpsc::Receiver<oneshot::Sender<SourceDebugDump>>,
+        cancel: CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let mut process = NmeaSourceTask {
+                    index,
+                    path,
+                    clock,
+                    device: None,
+                    source: NmeaSource::new(offset),
+                    msg_for_system_sender,
+                    debug_requests,
+                    cancel,
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{NtpLeapIndicator, NtpTimestamp};
+
+    use super::*;
+    use crate::daemon::util::EPOCH_OFFSET;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            let cur =
+                std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH)?;
+
+            Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                EPOCH_OFFSET.wrapping_add(cur.as_secs() as u32),
+                cur.subsec_nanos(),
+            ))
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reads_sentences_from_device() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join("ntp-test-nmea-1");
+        std::fs::write(
+            &path,
+            "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n",
+        )
+        .unwrap();
+
+        let (msg_for_system_sender, mut msg_for_system_receiver) = mpsc::channel(1);
+        let (_debug_sender, debug_requests) = mpsc::channel(1);
+        let cancel = CancellationToken::new();
+
+        let handle = NmeaSourceTask::spawn(
+            SourceId::new(),
+            path.clone(),
+            NtpDuration::ZERO,
+            TestClock {},
+            msg_for_system_sender,
+            debug_requests,
+            cancel.clone(),
+        );
+
+        let msg = msg_for_system_receiver.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::SourceUpdate(_, update) if update.has_measurement()));
+
+        cancel.cancel();
+        handle.await.unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
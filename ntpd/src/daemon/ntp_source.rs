@@ -1,20 +1,35 @@
-use std::{future::Future, marker::PhantomData, net::SocketAddr, pin::Pin};
+use std::{future::Future, marker::PhantomData, net::SocketAddr, pin::Pin, time::Duration};
 
 use ntp_proto::{
     NtpClock, NtpInstant, NtpSource, NtpSourceActionIterator, NtpSourceUpdate, NtpTimestamp,
     ProtocolVersion, SourceDefaultsConfig, SourceNtsData, SystemSnapshot,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 #[cfg(target_os = "linux")]
 use timestamped_socket::socket::open_interface_udp;
 use timestamped_socket::{
     interface::InterfaceName,
     socket::{connect_address, Connected, RecvResult, Socket},
 };
-use tracing::{debug, error, instrument, warn, Instrument, Span};
+use tracing::{debug, error, info, instrument, warn, Instrument, Span};
 
-use tokio::time::{Instant, Sleep};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, Sleep},
+};
+
+use super::{
+    config::{RouteLossAction, TimestampMode},
+    exitcode,
+    observer::SourceDebugDump,
+    spawn::SourceId,
+    util::{convert_net_timestamp, CancellationToken},
+};
 
-use super::{config::TimestampMode, exitcode, spawn::SourceId, util::convert_net_timestamp};
+/// Wait before the first probe of a lost route
+const ROUTE_PROBE_MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest wait between probes of a lost route
+const ROUTE_PROBE_MAX_INTERVAL: Duration = Duration::from_secs(256);
 
 /// Trait needed to allow injecting of futures other than `tokio::time::Sleep` for testing
 pub trait Wait: Future<Output = ()> {
@@ -27,6 +42,26 @@ impl Wait for Sleep {
     }
 }
 
+/// Randomize the poll interval a little to make it harder to predict poll requests
+fn randomize_poll_interval(rng: &mut impl Rng, poll_interval: Duration) -> Duration {
+    poll_interval.mul_f64(rng.gen_range(1.01..=1.05))
+}
+
+/// Wait until the next reachability probe, doubling on every failed probe
+fn next_route_probe_interval(current: Option<Duration>) -> Duration {
+    match current {
+        None => ROUTE_PROBE_MIN_INTERVAL,
+        Some(interval) => (interval * 2).min(ROUTE_PROBE_MAX_INTERVAL),
+    }
+}
+
+fn is_route_error(error: &std::io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EHOSTDOWN | libc::EHOSTUNREACH | libc::ENETDOWN | libc::ENETUNREACH)
+    )
+}
+
 #[derive(Debug, Clone)]
 #[allow(clippy::large_enum_variant)]
 pub enum MsgForSystem {
@@ -40,6 +75,18 @@ pub enum MsgForSystem {
     SourceUpdate(SourceId, NtpSourceUpdate),
 }
 
+impl MsgForSystem {
+    /// The source this message originates from
+    pub fn source_id(&self) -> SourceId {
+        match self {
+            MsgForSystem::MustDemobilize(id)
+            | MsgForSystem::NetworkIssue(id)
+            | MsgForSystem::Unreachable(id)
+            | MsgForSystem::SourceUpdate(id, _) => *id,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SourceChannels {
     pub msg_for_system_sender: tokio::sync::mpsc::Sender<MsgForSystem>,
@@ -58,18 +105,28 @@ pub(crate) struct SourceTask<C: 'static + NtpClock + Send, T: Wait> {
 
     source: NtpSource,
 
-    // we don't store the real origin timestamp in the packet, because that would leak our
-    // system time to the network (and could make attacks easier). So instead there is some
-    // garbage data in the origin_timestamp field, and we need to track and pass along the
-    // actual origin timestamp ourselves.
-    /// Timestamp of the last packet that we sent
-    last_send_timestamp: Option<NtpTimestamp>,
+    /// Source of randomness for the poll interval jitter, absent when the
+    /// poll interval is not randomized
+    rng: Option<StdRng>,
+
+    /// What to do when there is no route to the source
+    on_route_loss: RouteLossAction,
+    /// Wait between reachability probes while the route to the source is
+    /// lost, absent while the route is fine
+    route_probe: Option<Duration>,
+
+    /// Requests for the debug state of the source
+    debug_requests: mpsc::Receiver<oneshot::Sender<SourceDebugDump>>,
+
+    /// Stops the task once cancelled
+    cancel: CancellationToken,
 }
 
 #[derive(Debug)]
 enum SocketResult {
     Ok,
     Abort,
+    RouteLost,
 }
 
 impl<C, T> SourceTask<C, T>
@@ -94,6 +151,10 @@ where
 
         self.socket = match socket_res {
             Ok(socket) => Some(socket),
+            Err(error) if self.probes_route_loss() && is_route_error(&error) => {
+                debug!(?error, "No route to source");
+                return SocketResult::RouteLost;
+            }
             Err(error) => {
                 warn!(?error, "Could not open socket");
                 return SocketResult::Abort;
@@ -103,48 +164,100 @@ where
         SocketResult::Ok
     }
 
+    fn probes_route_loss(&self) -> bool {
+        self.on_route_loss == RouteLossAction::Probe
+    }
+
+    /// Stop polling until the route to the source is back, probing for it
+    /// with exponential backoff
+    fn wait_for_route(&mut self, poll_wait: Pin<&mut T>) {
+        if self.route_probe.is_none() {
+            warn!("Lost route to source, pausing polls until it returns");
+        }
+
+        let interval = next_route_probe_interval(self.route_probe);
+        self.route_probe = Some(interval);
+        poll_wait.reset(Instant::now() + interval);
+    }
+
+    /// State of the task and its source, the system adds what it knows
+    fn debug_dump(&self) -> SourceDebugDump {
+        SourceDebugDump {
+            source: self.source.debug_dump(),
+            last_send_timestamp: self.source.last_send_timestamp(),
+            route_lost: self.route_probe.is_some(),
+            timedata: None,
+            filter: None,
+            history: vec![],
+        }
+    }
+
+    /// Clean up after the task was cancelled
+    fn stop(&mut self) {
+        debug!("Source task cancelled, closing its socket");
+        self.socket = None;
+    }
+
     async fn run(&mut self, mut poll_wait: Pin<&mut T>) {
         loop {
+            // The task may have been cancelled while it was handling actions
+            if self.cancel.is_cancelled() {
+                self.stop();
+                return;
+            }
+
             let mut buf = [0_u8; 1024];
 
             enum SelectResult {
                 Timer,
                 Recv(Result<RecvResult<SocketAddr>, std::io::Error>),
+                DebugRequest(oneshot::Sender<SourceDebugDump>),
+                Cancelled,
             }
 
+            let cancel = self.cancel.clone();
+            let debug_requests = &mut self.debug_requests;
+            let socket = &mut self.socket;
             let selected = tokio::select! {
+                () = cancel.cancelled() => {
+                    SelectResult::Cancelled
+                },
                 () = &mut poll_wait => {
                     SelectResult::Timer
                 },
-                result = async { if let Some(ref mut socket) = self.socket { socket.recv(&mut buf).await } else { std::future::pending().await }} => {
+                result = async { if let Some(socket) = socket { socket.recv(&mut buf).await } else { std::future::pending().await }} => {
                     SelectResult::Recv(result)
                 },
+                Some(reply) = debug_requests.recv() => {
+                    SelectResult::DebugRequest(reply)
+                },
             };
 
             let actions = match selected {
+                SelectResult::Cancelled => {
+                    self.stop();
+                    return;
+                }
+                SelectResult::DebugRequest(reply) => {
+                    let _ = reply.send(self.debug_dump());
+                    continue;
+                }
                 SelectResult::Recv(result) => {
                     tracing::debug!("accept packet");
                     match accept_packet(result, &buf, &self.clock) {
                         AcceptResult::Accept(packet, recv_timestamp) => {
-                            let send_timestamp = match self.last_send_timestamp {
-                                Some(ts) => ts,
-                                None => {
-                                    debug!(
-                                        "we received a message without having sent one; discarding"
-                                    );
-                                    continue;
-                                }
-                            };
-
                             let system_snapshot = *self.channels.system_snapshot_receiver.borrow();
                             self.source.handle_incoming(
                                 system_snapshot,
                                 packet,
                                 NtpInstant::now(),
-                                send_timestamp,
                                 recv_timestamp,
                             )
                         }
+                        AcceptResult::NetworkGone if self.probes_route_loss() => {
+                            self.wait_for_route(poll_wait.as_mut());
+                            continue;
+                        }
                         AcceptResult::NetworkGone => {
                             self.channels
                                 .msg_for_system_sender
@@ -158,6 +271,27 @@ where
                 }
                 SelectResult::Timer => {
                     tracing::debug!("wait completed");
+
+                    // While the route is lost, only probe for it. Polling would
+                    // count as an unanswered request and get the source reset.
+                    if self.route_probe.is_some() {
+                        match self.setup_socket().await {
+                            SocketResult::Ok => {}
+                            SocketResult::RouteLost => {
+                                self.wait_for_route(poll_wait.as_mut());
+                                continue;
+                            }
+                            SocketResult::Abort => {
+                                self.channels
+                                    .msg_for_system_sender
+                                    .send(MsgForSystem::NetworkIssue(self.index))
+                                    .await
+                                    .ok();
+                                return;
+                            }
+                        }
+                    }
+
                     let system_snapshot = *self.channels.system_snapshot_receiver.borrow();
                     self.source.handle_timer(system_snapshot)
                 }
@@ -166,16 +300,23 @@ where
             for action in actions {
                 match action {
                     ntp_proto::NtpSourceAction::Send(packet) => {
-                        if matches!(self.setup_socket().await, SocketResult::Abort) {
-                            self.channels
-                                .msg_for_system_sender
-                                .send(MsgForSystem::NetworkIssue(self.index))
-                                .await
-                                .ok();
-                            return;
+                        match self.setup_socket().await {
+                            SocketResult::Ok => {}
+                            SocketResult::RouteLost => {
+                                self.wait_for_route(poll_wait.as_mut());
+                                continue;
+                            }
+                            SocketResult::Abort => {
+                                self.channels
+                                    .msg_for_system_sender
+                                    .send(MsgForSystem::NetworkIssue(self.index))
+                                    .await
+                                    .ok();
+                                return;
+                            }
                         }
 
-                        match self.clock.now() {
+                        let before_send = match self.clock.now() {
                             Err(e) => {
                                 // we cannot determine the origin_timestamp
                                 error!(error = ?e, "There was an error retrieving the current time");
@@ -183,35 +324,41 @@ where
                                 // report as no permissions, since this seems the most likely
                                 std::process::exit(exitcode::NOPERM);
                             }
-                            Ok(ts) => {
-                                self.last_send_timestamp = Some(ts);
-                            }
-                        }
+                            Ok(ts) => ts,
+                        };
 
                         match self.socket.as_mut().unwrap().send(&packet).await {
+                            Err(error) if self.probes_route_loss() && is_route_error(&error) => {
+                                debug!(?error, "poll message could not be sent");
+                                self.wait_for_route(poll_wait.as_mut());
+                            }
                             Err(error) => {
                                 warn!(?error, "poll message could not be sent");
 
-                                match error.raw_os_error() {
-                                    Some(libc::EHOSTDOWN)
-                                    | Some(libc::EHOSTUNREACH)
-                                    | Some(libc::ENETDOWN)
-                                    | Some(libc::ENETUNREACH) => {
-                                        self.channels
-                                            .msg_for_system_sender
-                                            .send(MsgForSystem::NetworkIssue(self.index))
-                                            .await
-                                            .ok();
-                                        return;
-                                    }
-                                    _ => {}
+                                if is_route_error(&error) {
+                                    self.channels
+                                        .msg_for_system_sender
+                                        .send(MsgForSystem::NetworkIssue(self.index))
+                                        .await
+                                        .ok();
+                                    return;
                                 }
                             }
                             Ok(opt_send_timestamp) => {
-                                // update the last_send_timestamp with the one given by the kernel, if available
-                                self.last_send_timestamp = opt_send_timestamp
-                                    .map(convert_net_timestamp)
-                                    .or(self.last_send_timestamp);
+                                if self.route_probe.take().is_some() {
+                                    info!("Route to source restored, resuming polls");
+                                }
+
+                                // We don't put the real send time in the packet, as that
+                                // would leak our system time to the network. The source
+                                // keeps it with the request, to use once the response
+                                // arrives. Prefer the timestamp given by the kernel, if
+                                // available, otherwise correct ours for the time it takes
+                                // to send.
+                                self.source.send_timestamp(
+                                    before_send,
+                                    opt_send_timestamp.map(convert_net_timestamp),
+                                );
                             }
                         }
                     }
@@ -222,9 +369,22 @@ where
                             .await
                             .ok();
                     }
+                    ntp_proto::NtpSourceAction::SetTimer(_)
+                    | ntp_proto::NtpSourceAction::SetPollTimer(_)
+                        if self.route_probe.is_some() =>
+                    {
+                        // keep waiting for the next reachability probe
+                    }
                     ntp_proto::NtpSourceAction::SetTimer(timeout) => {
                         poll_wait.as_mut().reset(Instant::now() + timeout)
                     }
+                    ntp_proto::NtpSourceAction::SetPollTimer(timeout) => {
+                        let timeout = match self.rng.as_mut() {
+                            Some(rng) => randomize_poll_interval(rng, timeout),
+                            None => timeout,
+                        };
+                        poll_wait.as_mut().reset(Instant::now() + timeout)
+                    }
                     ntp_proto::NtpSourceAction::Reset => {
                         self.channels
                             .msg_for_system_sender
@@ -263,6 +423,9 @@ where
         protocol_version: ProtocolVersion,
         config_snapshot: SourceDefaultsConfig,
         nts: Option<Box<SourceNtsData>>,
+        on_route_loss: RouteLossAction,
+        debug_requests: mpsc::Receiver<oneshot::Sender<SourceDebugDump>>,
+        cancel: CancellationToken,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(
             (async move {
@@ -272,7 +435,10 @@ where
                     NtpSource::new(source_addr, config_snapshot, protocol_version)
                 };
 
-                let poll_wait = tokio::time::sleep(std::time::Duration::default());
+                // Seeded once per source, so we don't hit the thread rng on every poll.
+                let mut rng = config_snapshot.randomize_poll.then(StdRng::from_entropy);
+
+                let poll_wait = tokio::time::sleep(Duration::default());
                 tokio::pin!(poll_wait);
 
                 for action in initial_actions {
@@ -286,6 +452,13 @@ where
                         ntp_proto::NtpSourceAction::SetTimer(timeout) => {
                             poll_wait.as_mut().reset(Instant::now() + timeout)
                         }
+                        ntp_proto::NtpSourceAction::SetPollTimer(timeout) => {
+                            let timeout = match rng.as_mut() {
+                                Some(rng) => randomize_poll_interval(rng, timeout),
+                                None => timeout,
+                            };
+                            poll_wait.as_mut().reset(Instant::now() + timeout)
+                        }
                         ntp_proto::NtpSourceAction::Reset => {
                             unreachable!("Should not be resetting from startup")
                         }
@@ -305,7 +478,11 @@ where
                     source_addr,
                     socket: None,
                     source,
-                    last_send_timestamp: None,
+                    rng,
+                    on_route_loss,
+                    route_probe: None,
+                    debug_requests,
+                    cancel,
                 };
 
                 process.run(poll_wait).await;
@@ -357,12 +534,10 @@ fn accept_packet<'a, C: NtpClock>(
         Err(receive_error) => {
             warn!(?receive_error, "could not receive packet");
 
-            match receive_error.raw_os_error() {
-                Some(libc::EHOSTDOWN)
-                | Some(libc::EHOSTUNREACH)
-                | Some(libc::ENETDOWN)
-                | Some(libc::ENETUNREACH) => AcceptResult::NetworkGone,
-                _ => AcceptResult::Ignore,
+            if is_route_error(&receive_error) {
+                AcceptResult::NetworkGone
+            } else {
+                AcceptResult::Ignore
             }
         }
     }
@@ -370,7 +545,7 @@ fn accept_packet<'a, C: NtpClock>(
 
 #[cfg(test)]
 mod tests {
-    use std::{io::Cursor, net::Ipv4Addr, sync::Arc, time::Duration};
+    use std::{io::Cursor, net::Ipv4Addr, sync::Arc};
 
     use ntp_proto::{NoCipher, NtpDuration, NtpLeapIndicator, NtpPacket, TimeSnapshot};
     use timestamped_socket::socket::{open_ip, GeneralTimestampMode, Open};
@@ -385,6 +560,10 @@ mod tests {
     }
 
     impl TestWaitSender {
+        fn deadline(&self) -> Option<Instant> {
+            self.state.lock().unwrap().deadline
+        }
+
         fn notify(&self) {
             let mut state = self.state.lock().unwrap();
             state.pending = true;
@@ -401,6 +580,7 @@ mod tests {
     struct TestWaitState {
         waker: Option<std::task::Waker>,
         pending: bool,
+        deadline: Option<Instant>,
     }
 
     impl Future for TestWait {
@@ -424,7 +604,9 @@ mod tests {
     }
 
     impl Wait for TestWait {
-        fn reset(self: Pin<&mut Self>, _deadline: Instant) {}
+        fn reset(self: Pin<&mut Self>, deadline: Instant) {
+            self.state.lock().unwrap().deadline = Some(deadline);
+        }
     }
 
     impl Drop for TestWait {
@@ -438,6 +620,7 @@ mod tests {
             let state = Arc::new(std::sync::Mutex::new(TestWaitState {
                 waker: None,
                 pending: false,
+                deadline: None,
             }));
 
             (
@@ -527,7 +710,11 @@ mod tests {
             timestamp_mode: TimestampMode::KernelRecv,
             socket: None,
             source,
-            last_send_timestamp: None,
+            rng: Some(StdRng::seed_from_u64(port_base.into())),
+            on_route_loss: RouteLossAction::default(),
+            route_probe: None,
+            debug_requests: mpsc::channel(1).1,
+            cancel: CancellationToken::new(),
         };
 
         (process, test_socket, msg_for_system_receiver)
@@ -554,6 +741,196 @@ mod tests {
         handle.abort();
     }
 
+    #[tokio::test]
+    async fn test_cancel_stops_task() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8016).await;
+        let cancel = process.cancel.clone();
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+            process
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        cancel.cancel();
+        let process = tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(process.socket.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_debug_request() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8018).await;
+        let (debug_requests, debug_requests_receiver) = mpsc::channel(1);
+        process.debug_requests = debug_requests_receiver;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        let (reply, response) = oneshot::channel();
+        debug_requests.send(reply).await.unwrap();
+        let dump = response.await.unwrap();
+        assert!(dump.last_send_timestamp.is_some());
+        assert!(!dump.route_lost);
+        assert!(dump.source.awaiting_response);
+        assert_eq!(dump.source.packets.sent, 1);
+
+        // the task keeps polling after answering
+        poll_send.notify();
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        handle.abort();
+    }
+
+    #[test]
+    fn test_randomize_poll_interval() {
+        let poll_interval = Duration::from_secs(16);
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let first: Vec<_> = (0..1000)
+            .map(|_| randomize_poll_interval(&mut rng, poll_interval))
+            .collect();
+
+        // the same seed gives the same sequence of poll intervals
+        let mut rng = StdRng::seed_from_u64(42);
+        let second: Vec<_> = (0..1000)
+            .map(|_| randomize_poll_interval(&mut rng, poll_interval))
+            .collect();
+        assert_eq!(first, second);
+
+        for interval in &first {
+            assert!(*interval >= poll_interval.mul_f64(1.01));
+            assert!(*interval <= poll_interval.mul_f64(1.05));
+        }
+
+        // the jitter is uniform, so on average we poll 3% later
+        let mean = first.iter().sum::<Duration>() / first.len() as u32;
+        assert!(mean > poll_interval.mul_f64(1.025));
+        assert!(mean < poll_interval.mul_f64(1.035));
+    }
+
+    #[tokio::test]
+    async fn test_poll_interval_uses_source_rng() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8012).await;
+
+        let poll_interval = process
+            .source
+            .current_poll_interval(SystemSnapshot::default())
+            .as_system_duration();
+        let expected = randomize_poll_interval(&mut StdRng::seed_from_u64(8012), poll_interval);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let before = Instant::now();
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        let deadline = loop {
+            if let Some(deadline) = poll_send.deadline() {
+                break deadline;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        let after = Instant::now();
+
+        assert!(deadline >= before + expected);
+        assert!(deadline <= after + expected);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_poll_interval_without_randomization() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8014).await;
+        process.rng = None;
+
+        let poll_interval = process
+            .source
+            .current_poll_interval(SystemSnapshot::default())
+            .as_system_duration();
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let before = Instant::now();
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        let deadline = loop {
+            if let Some(deadline) = poll_send.deadline() {
+                break deadline;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        let after = Instant::now();
+
+        assert!(deadline >= before + poll_interval);
+        assert!(deadline <= after + poll_interval);
+
+        handle.abort();
+    }
+
+    #[test]
+    fn test_route_probe_backoff() {
+        let mut interval = next_route_probe_interval(None);
+        assert_eq!(interval, ROUTE_PROBE_MIN_INTERVAL);
+
+        interval = next_route_probe_interval(Some(interval));
+        assert_eq!(interval, 2 * ROUTE_PROBE_MIN_INTERVAL);
+
+        for _ in 0..20 {
+            interval = next_route_probe_interval(Some(interval));
+        }
+        assert_eq!(interval, ROUTE_PROBE_MAX_INTERVAL);
+
+        assert!(is_route_error(&std::io::Error::from_raw_os_error(
+            libc::ENETUNREACH
+        )));
+        assert!(!is_route_error(&std::io::Error::from_raw_os_error(
+            libc::ECONNREFUSED
+        )));
+    }
+
     fn serialize_packet_unencryped(send_packet: &NtpPacket) -> [u8; 48] {
         let mut buf = [0; 48];
         let mut cursor = Cursor::new(buf.as_mut_slice());
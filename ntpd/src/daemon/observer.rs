@@ -1,21 +1,119 @@
-use super::server::ServerStats;
-use super::sockets::create_unix_socket_with_permissions;
+use super::server::{LatencyPercentiles, ServerStats};
+use super::sockets::{create_unix_socket_with_permissions, ObserverError};
 use super::spawn::SourceId;
-use super::system::ServerData;
-use ntp_proto::{ObservableSourceTimedata, PollInterval, SystemSnapshot};
+use super::system::{ServerData, SystemCommand};
+use ntp_proto::{
+    NtpDuration, NtpSourceDebugDump, NtpTimestamp, ObservableSourceFilter,
+    ObservableSourceTimedata, PacketCounters, PollInterval, ReferenceChange, SystemSnapshot,
+};
 use std::os::unix::fs::PermissionsExt;
-use std::{net::SocketAddr, time::Instant};
+use std::{
+    fmt::Display,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::warn;
 
 use serde::{Deserialize, Serialize};
 
+/// Responses larger than this (in bytes of json) are compressed when the
+/// client indicated it supports compression.
+const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// How long the observer waits for a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum size of an observation request, larger requests are rejected
+/// before they are read, so an unauthenticated client can't make the
+/// observer allocate much memory
+const MAX_REQUEST_SIZE: usize = 4 * 1024;
+
+/// How long the observer waits for a client to read its response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of tcp clients served at the same time, further
+/// connections are closed right away
+const MAX_TCP_CONNECTIONS: usize = 16;
+
+/// Maximum number of clients on the unix socket served at the same time,
+/// further connections are closed right away
+const MAX_UNIX_CONNECTIONS: usize = 16;
+
+/// How long a health check waits for the daemon to respond
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request sent by a client after connecting to the observation socket
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ObserveRequest {
+    /// Whether the client accepts a gzip compressed response
+    #[serde(default)]
+    pub compress: bool,
+    /// Also return the raw filter buffers of this source
+    #[serde(default)]
+    pub source_filter: Option<SourceId>,
+    /// Also return the recent measurement history of this source
+    #[serde(default)]
+    pub source_history: Option<SourceId>,
+    /// Also return the full internal state of this source, for bug reports
+    #[serde(default)]
+    pub source_debug: Option<SourceId>,
+    /// Only return the program data, such as the version of the daemon
+    #[serde(default)]
+    pub program_only: bool,
+    /// Token authenticating the client, required when observing over tcp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ObservableState {
     pub program: ProgramData,
     pub system: SystemSnapshot,
     pub sources: Vec<ObservableSourceState>,
     pub servers: Vec<ObservableServerState>,
+    /// Filter buffers of the source requested with `source_filter`, if it exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_filter: Option<ObservableSourceFilter>,
+    /// History of the source requested with `source_history`, if it exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_history: Option<Vec<SourceHistoryEntry>>,
+    /// State of the source requested with `source_debug`, if it exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_debug: Option<SourceDebugDump>,
+}
+
+/// Estimates of a source right after one of its measurements
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SourceHistoryEntry {
+    pub timestamp: NtpTimestamp,
+    pub offset: NtpDuration,
+    pub delay: NtpDuration,
+    pub jitter: NtpDuration,
+    pub poll_interval: PollInterval,
+}
+
+/// Everything the daemon knows about a source, to attach to bug reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceDebugDump {
+    /// State of the protocol handling of the source
+    pub source: NtpSourceDebugDump,
+    /// When the last request to the source was sent
+    pub last_send_timestamp: Option<NtpTimestamp>,
+    /// Whether polling is paused because there is no route to the source
+    pub route_lost: bool,
+    /// Estimates and selection outcome of the clock algorithm
+    pub timedata: Option<ObservableSourceTimedata>,
+    /// Filter buffers of the clock algorithm
+    pub filter: Option<ObservableSourceFilter>,
+    /// Estimates after the most recent measurements, oldest first
+    pub history: Vec<SourceHistoryEntry>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +122,9 @@ pub struct ProgramData {
     pub build_commit: String,
     pub build_commit_date: String,
     pub uptime_seconds: f64,
+    /// Cargo features the daemon was built with
+    #[serde(default)]
+    pub features: Vec<String>,
 }
 
 impl ProgramData {
@@ -42,14 +143,32 @@ impl Default for ProgramData {
             build_commit: env!("NTPD_RS_GIT_REV").to_owned(),
             build_commit_date: env!("NTPD_RS_GIT_DATE").to_owned(),
             uptime_seconds: 0.0,
+            features: enabled_features(),
         }
     }
 }
 
+fn enabled_features() -> Vec<String> {
+    [
+        (
+            "hardware-timestamping",
+            cfg!(feature = "hardware-timestamping"),
+        ),
+        ("unstable_ntpv5", cfg!(feature = "unstable_ntpv5")),
+        ("unstable_nts-pool", cfg!(feature = "unstable_nts-pool")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_owned())
+    .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ObservableServerState {
     pub address: SocketAddr,
     pub stats: ServerStats,
+    #[serde(default)]
+    pub response_latency: LatencyPercentiles,
 }
 
 impl From<&ServerData> for ObservableServerState {
@@ -57,14 +176,23 @@ impl From<&ServerData> for ObservableServerState {
         ObservableServerState {
             address: data.config.listen,
             stats: data.stats.clone(),
+            response_latency: LatencyPercentiles::from(&data.stats.response_latency),
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum ObservableSourceState {
     Nothing,
     Observable(ObservedSourceState),
+    /// Replies of the source could not be parsed with NTPv4 nor NTPv3, so it
+    /// is no longer polled
+    Incompatible {
+        name: String,
+        address: String,
+        id: SourceId,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -76,6 +204,101 @@ pub struct ObservedSourceState {
     pub name: String,
     pub address: String,
     pub id: SourceId,
+    #[serde(default)]
+    pub bogus_packets: u64,
+    #[serde(default)]
+    pub duplicate_packets: u64,
+    #[serde(default)]
+    pub packets: PacketCounters,
+    #[serde(default)]
+    pub last_reference_change: Option<ReferenceChange>,
+    /// The last request was timestamped by the kernel or network card when
+    /// it was sent
+    #[serde(default)]
+    pub send_timestamped: bool,
+    /// The minimum poll interval imposed by the server
+    #[serde(default)]
+    pub remote_min_poll_interval: PollInterval,
+    /// Percentage of the time over the last hour that the source answered
+    /// its polls, absent until the first poll interval completed
+    #[serde(default)]
+    pub reachability_pct_1h: Option<f64>,
+    /// Percentage of the time over the last day that the source answered
+    /// its polls
+    #[serde(default)]
+    pub reachability_pct_24h: Option<f64>,
+}
+
+/// Health of the daemon as seen through the observation socket, meant for
+/// service managers and container orchestration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The daemon is running and synchronized
+    Synchronized,
+    /// The daemon is running, but not synchronized (yet)
+    Unsynchronized,
+    /// The daemon could not be reached through the observation socket
+    Unreachable,
+}
+
+impl Health {
+    /// Process exit code for this status: 0 when synchronized, 1 when
+    /// running but not synchronized and 2 when the daemon cannot be reached
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Health::Synchronized => 0,
+            Health::Unsynchronized => 1,
+            Health::Unreachable => 2,
+        }
+    }
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Synchronized => write!(f, "synchronized"),
+            Health::Unsynchronized => write!(f, "running, but not synchronized"),
+            Health::Unreachable => write!(f, "cannot connect to the daemon"),
+        }
+    }
+}
+
+/// Request the current state of the daemon through its observation socket
+pub async fn observe(observe_socket: &Path) -> Result<ObservableState, ObserverError> {
+    let mut stream = tokio::net::UnixStream::connect(observe_socket).await?;
+    let request = ObserveRequest {
+        compress: true,
+        ..Default::default()
+    };
+    super::sockets::write_json(&mut stream, &request).await?;
+
+    let mut msg = Vec::new();
+    super::sockets::read_json(&mut stream, &mut msg).await
+}
+
+/// Request the version and build information of the daemon through its
+/// observation socket
+pub async fn version(observe_socket: &Path) -> Result<ProgramData, ObserverError> {
+    let mut stream = tokio::net::UnixStream::connect(observe_socket).await?;
+    let request = ObserveRequest {
+        program_only: true,
+        ..Default::default()
+    };
+    super::sockets::write_json(&mut stream, &request).await?;
+
+    let mut msg = Vec::new();
+    super::sockets::read_json(&mut stream, &mut msg).await
+}
+
+/// Check the health of the daemon through its observation socket
+pub async fn health(observe_socket: &Path) -> Health {
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, observe(observe_socket)).await {
+        Ok(Ok(state)) if state.system.time_snapshot.leap_indicator.is_synchronized() => {
+            Health::Synchronized
+        }
+        Ok(Ok(_)) => Health::Unsynchronized,
+        Ok(Err(_)) | Err(_) => Health::Unreachable,
+    }
 }
 
 pub async fn spawn(
@@ -83,10 +306,18 @@ pub async fn spawn(
     sources_reader: tokio::sync::watch::Receiver<Vec<ObservableSourceState>>,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
     system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
-) -> JoinHandle<std::io::Result<()>> {
+    command_sender: mpsc::Sender<SystemCommand>,
+) -> JoinHandle<Result<(), ObserverError>> {
     let config = config.clone();
     tokio::spawn(async move {
-        let result = observer(config, sources_reader, server_reader, system_reader).await;
+        let result = observer(
+            config,
+            sources_reader,
+            server_reader,
+            system_reader,
+            command_sender,
+        )
+        .await;
         if let Err(ref e) = result {
             warn!("Abnormal termination of the state observer: {e}");
             warn!("The state observer will not be available");
@@ -95,41 +326,300 @@ pub async fn spawn(
     })
 }
 
+/// State of the daemon reported to observers
+#[derive(Clone)]
+struct Observed {
+    start_time: Instant,
+    sources_reader: tokio::sync::watch::Receiver<Vec<ObservableSourceState>>,
+    server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
+    system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    command_sender: mpsc::Sender<SystemCommand>,
+}
+
 async fn observer(
     config: super::config::ObservabilityConfig,
     sources_reader: tokio::sync::watch::Receiver<Vec<ObservableSourceState>>,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
     system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
-) -> std::io::Result<()> {
-    let start_time = Instant::now();
+    command_sender: mpsc::Sender<SystemCommand>,
+) -> Result<(), ObserverError> {
+    let observed = Observed {
+        start_time: Instant::now(),
+        sources_reader,
+        server_reader,
+        system_reader,
+        command_sender,
+    };
 
-    let path = match config.observation_path {
-        Some(path) => path,
-        None => return Ok(()),
+    let unix_listener = match &config.observation_path {
+        Some(path) => {
+            // this binary needs to run as root to be able to adjust the system clock.
+            // by default, the socket inherits root permissions, but the client should not need
+            // elevated permissions to read from the socket. So we explicitly set the permissions
+            let permissions: std::fs::Permissions =
+                PermissionsExt::from_mode(config.observation_permissions);
+
+            Some(create_unix_socket_with_permissions(path, permissions)?)
+        }
+        None => None,
     };
 
-    // this binary needs to run as root to be able to adjust the system clock.
-    // by default, the socket inherits root permissions, but the client should not need
-    // elevated permissions to read from the socket. So we explicitly set the permissions
-    let permissions: std::fs::Permissions =
-        PermissionsExt::from_mode(config.observation_permissions);
+    // The tcp listener is reachable from the network, so clients must
+    // authenticate with the configured token
+    let tcp_listener = match config.observation_listen {
+        Some(address) => {
+            let token: Arc<str> = read_token(config.observation_token_path.as_deref())?.into();
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|error| ObserverError::Listen { address, error })?;
+            Some((listener, token))
+        }
+        None => None,
+    };
+    let tcp_connections = Arc::new(Semaphore::new(MAX_TCP_CONNECTIONS));
+    let unix_connections = Arc::new(Semaphore::new(MAX_UNIX_CONNECTIONS));
 
-    let sources_listener = create_unix_socket_with_permissions(&path, permissions)?;
+    if unix_listener.is_none() && tcp_listener.is_none() {
+        return Ok(());
+    }
 
+    // Every client is served on its own task, so a slow client can't hold
+    // up others
     loop {
-        let (mut stream, _addr) = sources_listener.accept().await?;
+        tokio::select! {
+            accepted = accept_unix(unix_listener.as_ref()) => {
+                let mut stream = accepted?;
+                let Ok(permit) = unix_connections.clone().try_acquire_owned() else {
+                    warn!("Too many observation clients, closing connection");
+                    continue;
+                };
+                let observed = observed.clone();
+                tokio::spawn(async move {
+                    let mut msg = Vec::new();
+                    if let Err(e) =
+                        handle_observation(&mut stream, None, &mut msg, &observed).await
+                    {
+                        warn!("Could not serve observation: {e}");
+                    }
+                    drop(permit);
+                });
+            }
+            accepted = accept_tcp(tcp_listener.as_ref().map(|(listener, _)| listener)) => {
+                let (mut stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // e.g. running out of file descriptors, which should
+                        // not stop observation for everyone
+                        warn!("Could not accept observation connection over tcp: {e}");
+                        continue;
+                    }
+                };
+                let Ok(permit) = tcp_connections.clone().try_acquire_owned() else {
+                    warn!(%peer, "Too many observation clients over tcp, closing connection");
+                    continue;
+                };
+                let token = tcp_listener.as_ref().map(|(_, token)| token.clone());
+                let observed = observed.clone();
+                tokio::spawn(async move {
+                    let mut msg = Vec::new();
+                    // a remote client going away should not stop the observer
+                    if let Err(e) =
+                        handle_observation(&mut stream, token.as_deref(), &mut msg, &observed).await
+                    {
+                        warn!(%peer, "Could not serve observation over tcp: {e}");
+                    }
+                    drop(permit);
+                });
+            }
+        }
+    }
+}
 
-        let observe = ObservableState {
-            program: ProgramData::with_uptime(start_time.elapsed().as_secs_f64()),
-            sources: sources_reader.borrow().to_owned(),
-            system: *system_reader.borrow(),
-            servers: server_reader.borrow().iter().map(|s| s.into()).collect(),
-        };
+fn read_token(path: Option<&Path>) -> Result<String, ObserverError> {
+    let Some(path) = path else {
+        return Err(ObserverError::Token {
+            path: PathBuf::new(),
+            error: std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "observation over tcp requires an observation token path",
+            ),
+        });
+    };
+
+    let token = std::fs::read_to_string(path).map_err(|error| ObserverError::Token {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(ObserverError::Token {
+            path: path.to_path_buf(),
+            error: std::io::Error::new(std::io::ErrorKind::InvalidData, "token is empty"),
+        });
+    }
+
+    Ok(token.to_owned())
+}
+
+async fn accept_unix(listener: Option<&UnixListener>) -> std::io::Result<UnixStream> {
+    match listener {
+        Some(listener) => Ok(listener.accept().await?.0),
+        None => std::future::pending().await,
+    }
+}
 
-        super::sockets::write_json(&mut stream, &observe).await?;
+async fn accept_tcp(listener: Option<&TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
     }
 }
 
+/// Compare tokens in time independent of where they differ, so the token
+/// can't be guessed byte by byte from response times
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Answer a single observation request. When `token` is set, the request
+/// must carry the same token to be answered.
+async fn handle_observation(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    token: Option<&str>,
+    msg: &mut Vec<u8>,
+    observed: &Observed,
+) -> Result<(), ObserverError> {
+    let request = match tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        super::sockets::read_json_with_limit::<ObserveRequest>(stream, msg, MAX_REQUEST_SIZE),
+    )
+    .await
+    {
+        Ok(Ok(request)) => request,
+        Ok(Err(e)) => {
+            warn!("Could not read observation request: {e}");
+            return Ok(());
+        }
+        Err(_) => {
+            warn!("Timed out waiting for observation request");
+            return Ok(());
+        }
+    };
+
+    if let Some(expected) = token {
+        let authenticated = request
+            .token
+            .as_deref()
+            .map_or(false, |given| token_matches(given, expected));
+        if !authenticated {
+            warn!("Rejected observation request with a missing or invalid token");
+            return Ok(());
+        }
+    }
+
+    let uptime = observed.start_time.elapsed().as_secs_f64();
+
+    if request.program_only {
+        let program = ProgramData::with_uptime(uptime);
+        return write_response(super::sockets::write_json(stream, &program)).await;
+    }
+
+    let source_filter = match request.source_filter {
+        Some(id) => observe_source_filter(&observed.command_sender, id).await,
+        None => None,
+    };
+
+    let source_history = match request.source_history {
+        Some(id) => observe_source_history(&observed.command_sender, id).await,
+        None => None,
+    };
+
+    let source_debug = match request.source_debug {
+        Some(id) => observe_source_debug(&observed.command_sender, id).await,
+        None => None,
+    };
+
+    let observe = ObservableState {
+        program: ProgramData::with_uptime(uptime),
+        sources: observed.sources_reader.borrow().to_owned(),
+        system: *observed.system_reader.borrow(),
+        servers: observed
+            .server_reader
+            .borrow()
+            .iter()
+            .map(|s| s.into())
+            .collect(),
+        source_filter,
+        source_history,
+        source_debug,
+    };
+
+    if request.compress {
+        write_response(super::sockets::write_json_compressed(
+            stream,
+            &observe,
+            COMPRESSION_THRESHOLD,
+        ))
+        .await
+    } else {
+        write_response(super::sockets::write_json(stream, &observe)).await
+    }
+}
+
+/// Write a response, giving up on clients that don't read it in time
+async fn write_response(
+    write: impl Future<Output = Result<(), ObserverError>>,
+) -> Result<(), ObserverError> {
+    match tokio::time::timeout(RESPONSE_TIMEOUT, write).await {
+        Ok(result) => result,
+        Err(_) => {
+            warn!("Timed out writing observation response");
+            Ok(())
+        }
+    }
+}
+
+async fn observe_source_filter(
+    command_sender: &mpsc::Sender<SystemCommand>,
+    id: SourceId,
+) -> Option<ObservableSourceFilter> {
+    let (reply, response) = oneshot::channel();
+    command_sender
+        .send(SystemCommand::ObserveSourceFilter { id, reply })
+        .await
+        .ok()?;
+    response.await.ok().flatten()
+}
+
+async fn observe_source_history(
+    command_sender: &mpsc::Sender<SystemCommand>,
+    id: SourceId,
+) -> Option<Vec<SourceHistoryEntry>> {
+    let (reply, response) = oneshot::channel();
+    command_sender
+        .send(SystemCommand::ObserveSourceHistory { id, reply })
+        .await
+        .ok()?;
+    response.await.ok().flatten()
+}
+
+async fn observe_source_debug(
+    command_sender: &mpsc::Sender<SystemCommand>,
+    id: SourceId,
+) -> Option<SourceDebugDump> {
+    let (reply, response) = oneshot::channel();
+    command_sender
+        .send(SystemCommand::ObserveSourceDebug { id, reply })
+        .await
+        .ok()?;
+    response.await.ok().flatten()
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "unstable_ntpv5")]
@@ -144,6 +634,7 @@ mod tests {
     };
     use tokio::{io::AsyncReadExt, net::UnixStream};
 
+    use super::super::sockets::{read_json, write_json};
     use super::*;
 
     #[derive(Debug, Clone, Default)]
@@ -202,6 +693,14 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id: SourceId::new(),
+                bogus_packets: 0,
+                duplicate_packets: 0,
+                packets: Default::default(),
+                send_timestamped: false,
+                remote_min_poll_interval: PollIntervalLimits::default().min,
+                reachability_pct_1h: None,
+                reachability_pct_24h: None,
+                last_reference_change: None,
             }),
         ]);
 
@@ -209,8 +708,19 @@ mod tests {
 
         let (_, system_reader) = tokio::sync::watch::channel(SystemSnapshot {
             stratum: 1,
+            raw_stratum: 1,
             reference_id: ReferenceId::NONE,
             accumulated_steps_threshold: None,
+            accumulated_steps_headroom: None,
+            clock_offset_correction: NtpDuration::ZERO,
+            holdover: Default::default(),
+            local_clock_fallback: false,
+            locked: false,
+            reference_timestamp: Default::default(),
+            upcoming_leap: None,
+            divergence_alarm: None,
+            convergence: None,
+            packets: Default::default(),
             time_snapshot: TimeSnapshot {
                 poll_interval: PollIntervalLimits::default().min,
                 precision: NtpDuration::from_seconds(1e-3),
@@ -218,6 +728,11 @@ mod tests {
                 root_dispersion: NtpDuration::ZERO,
                 leap_indicator: NtpLeapIndicator::Leap59,
                 accumulated_steps: NtpDuration::ZERO,
+                frequency_ppm: 0.0,
+                system_offset: NtpDuration::ZERO,
+                system_jitter: NtpDuration::ZERO,
+                system_source_choice: Default::default(),
+                slewing: false,
             },
             #[cfg(feature = "unstable_ntpv5")]
             bloom_filter: BloomFilter::new(),
@@ -226,18 +741,27 @@ mod tests {
         });
 
         let handle = tokio::spawn(async move {
-            observer(config, sources_reader, servers_reader, system_reader)
-                .await
-                .unwrap();
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
         });
 
         tokio::time::sleep(Duration::from_millis(10)).await;
 
         let mut reader = UnixStream::connect(path).await.unwrap();
 
+        write_json(&mut reader, &ObserveRequest::default())
+            .await
+            .unwrap();
+
         let mut buf = vec![];
-        while reader.read_buf(&mut buf).await.unwrap() != 0 {}
-        let result: ObservableState = serde_json::from_slice(&buf).unwrap();
+        let result: ObservableState = read_json(&mut reader, &mut buf).await.unwrap();
 
         // Deal with randomized order
         let mut count = 0;
@@ -272,6 +796,14 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id: SourceId::new(),
+                bogus_packets: 0,
+                duplicate_packets: 0,
+                packets: Default::default(),
+                send_timestamped: false,
+                remote_min_poll_interval: PollIntervalLimits::default().min,
+                reachability_pct_1h: None,
+                reachability_pct_24h: None,
+                last_reference_change: None,
             }),
         ]);
 
@@ -279,8 +811,19 @@ mod tests {
 
         let (mut system_writer, system_reader) = tokio::sync::watch::channel(SystemSnapshot {
             stratum: 1,
+            raw_stratum: 1,
             reference_id: ReferenceId::NONE,
             accumulated_steps_threshold: None,
+            accumulated_steps_headroom: None,
+            clock_offset_correction: NtpDuration::ZERO,
+            holdover: Default::default(),
+            local_clock_fallback: false,
+            locked: false,
+            reference_timestamp: Default::default(),
+            upcoming_leap: None,
+            divergence_alarm: None,
+            convergence: None,
+            packets: Default::default(),
             time_snapshot: TimeSnapshot {
                 poll_interval: PollIntervalLimits::default().min,
                 precision: NtpDuration::from_seconds(1e-3),
@@ -288,6 +831,11 @@ mod tests {
                 root_dispersion: NtpDuration::ZERO,
                 leap_indicator: NtpLeapIndicator::Leap59,
                 accumulated_steps: NtpDuration::ZERO,
+                frequency_ppm: 0.0,
+                system_offset: NtpDuration::ZERO,
+                system_jitter: NtpDuration::ZERO,
+                system_source_choice: Default::default(),
+                slewing: false,
             },
             #[cfg(feature = "unstable_ntpv5")]
             bloom_filter: BloomFilter::new(),
@@ -296,15 +844,25 @@ mod tests {
         });
 
         let handle = tokio::spawn(async move {
-            observer(config, sources_reader, servers_reader, system_reader)
-                .await
-                .unwrap();
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
         });
 
         tokio::time::sleep(Duration::from_millis(10)).await;
 
         let mut reader = UnixStream::connect(path).await.unwrap();
 
+        write_json(&mut reader, &ObserveRequest::default())
+            .await
+            .unwrap();
+
         // We do a small partial read of the data to test that whatever
         // happens, the observer doesnt keep a lock alive on either of
         // of the RwLocks.
@@ -319,4 +877,476 @@ mod tests {
 
         handle.abort();
     }
+
+    #[tokio::test]
+    async fn test_compressed_observation() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-13");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        // enough sources to push the response well past the compression threshold
+        let sources = (0..500)
+            .map(|i| {
+                ObservableSourceState::Observable(ObservedSourceState {
+                    timedata: Default::default(),
+                    unanswered_polls: Reach::default().unanswered_polls(),
+                    poll_interval: PollIntervalLimits::default().min,
+                    name: format!("127.0.{}.{}:123", i / 256, i % 256),
+                    address: format!("127.0.{}.{}:123", i / 256, i % 256),
+                    id: SourceId::new(),
+                    bogus_packets: 0,
+                    duplicate_packets: 0,
+                    packets: Default::default(),
+                    send_timestamped: false,
+                    remote_min_poll_interval: PollIntervalLimits::default().min,
+                    reachability_pct_1h: None,
+                    reachability_pct_24h: None,
+                    last_reference_change: None,
+                })
+            })
+            .collect();
+        let (_, sources_reader) = tokio::sync::watch::channel(sources);
+        let (_, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_, system_reader) = tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut reader = UnixStream::connect(path).await.unwrap();
+
+        write_json(
+            &mut reader,
+            &ObserveRequest {
+                compress: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // the frame header indicates a compressed payload
+        let mut raw = vec![];
+        while reader.read_buf(&mut raw).await.unwrap() != 0 {}
+        assert_eq!(raw[0], 0x01);
+        let length = u32::from_be_bytes(raw[1..5].try_into().unwrap()) as usize;
+        assert_eq!(raw.len(), 5 + length);
+        assert!(length < COMPRESSION_THRESHOLD * 4);
+
+        let mut json = vec![];
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&raw[5..]), &mut json)
+            .unwrap();
+        assert!(json.len() > COMPRESSION_THRESHOLD);
+        let result: ObservableState = serde_json::from_slice(&json).unwrap();
+        assert_eq!(result.sources.len(), 500);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_observation_in_fresh_directory() {
+        // be careful with copying: tests run concurrently and should use a unique directory name!
+        let directory = std::env::temp_dir().join("ntp-test-fresh-observe");
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory).unwrap();
+        }
+        std::fs::create_dir(&directory).unwrap();
+
+        // no socket exists yet at this path
+        let path = directory.join("observe");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (_, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_, system_reader) = tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(observer(
+            config,
+            sources_reader,
+            servers_reader,
+            system_reader,
+            mpsc::channel(1).0,
+        ));
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!handle.is_finished());
+
+        let mut reader = UnixStream::connect(&path).await.unwrap();
+        write_json(&mut reader, &ObserveRequest::default())
+            .await
+            .unwrap();
+
+        let mut buf = vec![];
+        let result: ObservableState = read_json(&mut reader, &mut buf).await.unwrap();
+        assert!(result.sources.is_empty());
+
+        handle.abort();
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-14");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (system_writer, system_reader) = tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(health(&path).await, Health::Unsynchronized);
+        assert_eq!(health(&path).await.exit_code(), 1);
+
+        system_writer.send_modify(|system| {
+            system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning
+        });
+        assert_eq!(health(&path).await, Health::Synchronized);
+        assert_eq!(health(&path).await.exit_code(), 0);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-19");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_system_writer, system_reader) =
+            tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let program = version(&path).await.unwrap();
+        assert_eq!(program.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(program.build_commit, env!("NTPD_RS_GIT_REV"));
+        assert_eq!(program.features, enabled_features());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_observation_requires_token() {
+        // be careful with copying: tests run concurrently and should use a unique address!
+        let address: SocketAddr = "127.0.0.1:19976".parse().unwrap();
+        let token_path = std::env::temp_dir().join("ntp-test-observation-token");
+        std::fs::write(&token_path, "secret\n").unwrap();
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_listen: Some(address),
+            observation_token_path: Some(token_path),
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_system_writer, system_reader) =
+            tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        for (token, accepted) in [
+            (None, false),
+            (Some("wrong"), false),
+            (Some("secret"), true),
+        ] {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let request = ObserveRequest {
+                program_only: true,
+                token: token.map(String::from),
+                ..Default::default()
+            };
+            write_json(&mut stream, &request).await.unwrap();
+
+            let mut buf = vec![];
+            let result = read_json::<ProgramData>(&mut stream, &mut buf).await;
+            assert_eq!(result.is_ok(), accepted);
+        }
+
+        // oversized requests are rejected before they are read
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = ObserveRequest {
+            program_only: true,
+            token: Some("x".repeat(MAX_REQUEST_SIZE)),
+            ..Default::default()
+        };
+        write_json(&mut stream, &request).await.unwrap();
+        let mut buf = vec![];
+        assert!(read_json::<ProgramData>(&mut stream, &mut buf)
+            .await
+            .is_err());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_observation_is_concurrent() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-20");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_system_writer, system_reader) =
+            tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // a client that never sends its request doesn't hold up the next one
+        let _stalled = UnixStream::connect(&path).await.unwrap();
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let request = ObserveRequest {
+            program_only: true,
+            ..Default::default()
+        };
+        write_json(&mut stream, &request).await.unwrap();
+
+        let mut buf = vec![];
+        let result = tokio::time::timeout(
+            REQUEST_TIMEOUT / 2,
+            read_json::<ProgramData>(&mut stream, &mut buf),
+        )
+        .await;
+        assert!(matches!(result, Ok(Ok(_))));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_observation_is_concurrent() {
+        // be careful with copying: tests run concurrently and should use a unique address!
+        let address: SocketAddr = "127.0.0.1:19977".parse().unwrap();
+        let token_path = std::env::temp_dir().join("ntp-test-observation-token-2");
+        std::fs::write(&token_path, "secret\n").unwrap();
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_listen: Some(address),
+            observation_token_path: Some(token_path),
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_system_writer, system_reader) =
+            tokio::sync::watch::channel(SystemSnapshot::default());
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                mpsc::channel(1).0,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // a client that never sends its request doesn't hold up the next one
+        let _stalled = TcpStream::connect(address).await.unwrap();
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = ObserveRequest {
+            program_only: true,
+            token: Some(String::from("secret")),
+            ..Default::default()
+        };
+        write_json(&mut stream, &request).await.unwrap();
+
+        let mut buf = vec![];
+        let result = tokio::time::timeout(
+            REQUEST_TIMEOUT / 2,
+            read_json::<ProgramData>(&mut stream, &mut buf),
+        )
+        .await;
+        assert!(matches!(result, Ok(Ok(_))));
+
+        // connections beyond the limit are closed without a response
+        let mut clients = vec![];
+        for _ in 0..MAX_TCP_CONNECTIONS {
+            clients.push(TcpStream::connect(address).await.unwrap());
+        }
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        write_json(&mut stream, &request).await.unwrap();
+        let mut buf = vec![];
+        let result = tokio::time::timeout(
+            REQUEST_TIMEOUT / 2,
+            read_json::<ProgramData>(&mut stream, &mut buf),
+        )
+        .await;
+        assert!(matches!(result, Ok(Err(_))));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_health_unreachable() {
+        let path = std::env::temp_dir().join("ntp-test-stream-does-not-exist");
+        assert_eq!(health(&path).await, Health::Unreachable);
+        assert_eq!(health(&path).await.exit_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_source_filter() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-16");
+        let config = super::super::config::ObservabilityConfig {
+            log_level: None,
+            observation_path: Some(path.clone()),
+            observation_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (_sources_writer, sources_reader) = tokio::sync::watch::channel(vec![]);
+        let (_servers_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_system_writer, system_reader) =
+            tokio::sync::watch::channel(SystemSnapshot::default());
+        let (command_sender, mut command_receiver) = mpsc::channel(1);
+
+        let source_id = SourceId::new();
+        let filter = ObservableSourceFilter {
+            initial: true,
+            delays: vec![1e-3, 2e-3],
+            offsets: vec![5e-4, 6e-4],
+        };
+        let expected = filter.clone();
+        let system = tokio::spawn(async move {
+            while let Some(command) = command_receiver.recv().await {
+                if let SystemCommand::ObserveSourceFilter { id, reply } = command {
+                    let _ = reply.send((id == source_id).then(|| filter.clone()));
+                }
+            }
+        });
+
+        let handle = tokio::spawn(async move {
+            observer(
+                config,
+                sources_reader,
+                servers_reader,
+                system_reader,
+                command_sender,
+            )
+            .await
+            .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut results = vec![];
+        for request in [None, Some(source_id), Some(SourceId::new())] {
+            let mut reader = UnixStream::connect(&path).await.unwrap();
+            write_json(
+                &mut reader,
+                &ObserveRequest {
+                    compress: false,
+                    source_filter: request,
+                    source_history: None,
+                    source_debug: None,
+                    program_only: false,
+                    token: None,
+                },
+            )
+            .await
+            .unwrap();
+
+            let mut buf = vec![];
+            let result: ObservableState = read_json(&mut reader, &mut buf).await.unwrap();
+            results.push(result.source_filter);
+        }
+
+        assert_eq!(results, [None, Some(expected), None]);
+
+        handle.abort();
+        system.abort();
+    }
 }
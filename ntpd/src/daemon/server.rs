@@ -1,24 +1,147 @@
 use std::{
+    collections::{BinaryHeap, HashMap},
+    net::{IpAddr, SocketAddr},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use ntp_proto::{
-    KeySet, NtpClock, Server, ServerReason, ServerResponse, ServerStatHandler, SystemSnapshot,
+    KeySet, NtpClock, NtpTimestamp, Server, ServerReason, ServerResponse, ServerStatHandler,
+    SystemSnapshot,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use timestamped_socket::socket::{open_ip, RecvResult};
-use tokio::task::JoinHandle;
+use timestamped_socket::socket::{open_ip, Connected, Open, RecvResult, Socket, Timestamp};
+use tokio::{sync::watch, task::JoinHandle};
 use tracing::{debug, instrument, warn};
 
-use super::{config::ServerConfig, util::convert_net_timestamp};
+use super::{
+    config::ServerConfig,
+    util::{convert_net_timestamp, CancellationToken},
+};
 
 // Maximum size of udp packet we handle
 const MAX_PACKET_SIZE: usize = 1024;
 
+// Maximum number of requests held back by the response jitter at any time
+const MAX_DELAYED_REQUESTS: usize = 1024;
+
+/// Socket of a server, either serving any client or connected to a single
+/// peer. A connected socket only receives packets from its peer, and the
+/// remote address of those is always the peer.
+enum ServerSocket {
+    Unconnected(Socket<SocketAddr, Open>),
+    Connected(Socket<SocketAddr, Connected>),
+}
+
+impl ServerSocket {
+    fn new(socket: Socket<SocketAddr, Open>, peer: Option<SocketAddr>) -> std::io::Result<Self> {
+        match peer {
+            Some(peer) => Ok(ServerSocket::Connected(socket.connect(peer)?)),
+            None => Ok(ServerSocket::Unconnected(socket)),
+        }
+    }
+
+    async fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<RecvResult<SocketAddr>> {
+        match self {
+            ServerSocket::Unconnected(socket) => socket.recv(buf).await,
+            ServerSocket::Connected(socket) => socket.recv(buf).await,
+        }
+    }
+
+    async fn send_to(&mut self, message: &[u8], addr: SocketAddr) -> std::io::Result<()> {
+        match self {
+            ServerSocket::Unconnected(socket) => socket.send_to(message, addr).await.map(|_| ()),
+            ServerSocket::Connected(socket) => socket.send(message).await.map(|_| ()),
+        }
+    }
+}
+
+/// Random delay before responding, uniformly distributed up to the configured
+/// maximum response jitter.
+fn response_delay(rng: &mut impl Rng, max_jitter: Duration) -> Duration {
+    max_jitter.mul_f64(rng.gen_range(0.0..=1.0))
+}
+
+/// A request that is held back by the response jitter until it is due. It is
+/// only handled once due, so the transmit timestamp of the response is taken
+/// after the delay.
+#[derive(Debug)]
+struct DelayedRequest {
+    due: tokio::time::Instant,
+    received: Instant,
+    recv_timestamp: NtpTimestamp,
+    from: SocketAddr,
+    message: Vec<u8>,
+}
+
+// Ordered such that the request that is due first is at the top of the heap
+impl Ord for DelayedRequest {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.due.cmp(&self.due)
+    }
+}
+
+impl PartialOrd for DelayedRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DelayedRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due
+    }
+}
+
+impl Eq for DelayedRequest {}
+
+/// Time a request waited in the receive queue of the socket before it is
+/// processed, measured from its kernel receive timestamp
+fn request_lag(timestamp: Timestamp, now: SystemTime) -> Duration {
+    let Ok(seconds) = u64::try_from(timestamp.seconds) else {
+        return Duration::ZERO;
+    };
+    let received = UNIX_EPOCH + Duration::new(seconds, timestamp.nanos);
+    now.duration_since(received).unwrap_or(Duration::ZERO)
+}
+
+/// Token bucket capping the number of requests processed per second. It
+/// holds at most a second worth of tokens, so bursts up to the rate pass.
+#[derive(Debug)]
+struct RequestRateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RequestRateLimiter {
+    fn new(rate: u32, now: Instant) -> Self {
+        RequestRateLimiter {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Whether a request received at the given time may be processed
+    fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ServerStats {
     pub received_packets: Counter,
@@ -32,6 +155,18 @@ pub struct ServerStats {
     pub nts_denied_packets: Counter,
     pub nts_rate_limited_packets: Counter,
     pub nts_nak_packets: Counter,
+    /// Requests dropped without a response because they waited too long
+    #[serde(default)]
+    pub shed_packets: Counter,
+    /// Requests dropped without a response because the server was over its
+    /// maximum request rate
+    #[serde(default)]
+    pub rate_capped_packets: Counter,
+    /// Requests refused by the allowlist or denylist, whatever the action
+    #[serde(default)]
+    pub access_denied_packets: Counter,
+    #[serde(skip)]
+    pub response_latency: LatencyHistogram,
 }
 
 impl ServerStatHandler for ServerStats {
@@ -48,15 +183,21 @@ impl ServerStatHandler for ServerStats {
             (ServerResponse::ProvideTime, _) => self.accepted_packets.inc(),
             (ServerResponse::Ignore, ServerReason::RateLimit) => self.rate_limited_packets.inc(),
             (ServerResponse::Ignore, _) => self.ignored_packets.inc(),
-            (ServerResponse::Deny, _) => self.denied_packets.inc(),
+            (ServerResponse::Deny | ServerResponse::Restrict, _) => self.denied_packets.inc(),
             (ServerResponse::NTSNak, _) => self.nts_nak_packets.inc(),
         }
 
+        if reason == ServerReason::Policy && response != ServerResponse::ProvideTime {
+            self.access_denied_packets.inc();
+        }
+
         if nts {
             self.nts_received_packets.inc();
             match (response, reason) {
                 (ServerResponse::ProvideTime, _) => self.nts_accepted_packets.inc(),
-                (ServerResponse::Deny, _) => self.nts_denied_packets.inc(),
+                (ServerResponse::Deny | ServerResponse::Restrict, _) => {
+                    self.nts_denied_packets.inc()
+                }
                 (ServerResponse::Ignore, ServerReason::RateLimit) => {
                     self.nts_rate_limited_packets.inc()
                 }
@@ -100,45 +241,338 @@ impl<'de> Deserialize<'de> for Counter {
     }
 }
 
+// Buckets are HDR-style: each power of two is split into 2^SUB_BUCKET_BITS
+// linear sub-buckets, giving a relative error of at most 1/8 over the whole
+// range. Values from 2^MAX_EXPONENT ns (about a second) upwards all end up in
+// the last bucket.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const MAX_EXPONENT: u32 = 30;
+const LATENCY_BUCKETS: usize = (MAX_EXPONENT - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+/// Histogram of request processing times, with a fixed number of buckets
+/// such that recording a value is a single atomic increment.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: Arc<[AtomicU64]>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: (0..LATENCY_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn bucket_index(nanos: u64) -> usize {
+        if nanos < SUB_BUCKETS as u64 {
+            return nanos as usize;
+        }
+
+        let exponent = u64::BITS - 1 - nanos.leading_zeros();
+        if exponent >= MAX_EXPONENT {
+            return LATENCY_BUCKETS - 1;
+        }
+
+        let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+        (exponent - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub_bucket
+    }
+
+    // Largest value (in nanoseconds) that ends up in the given bucket
+    fn bucket_upper_bound(index: usize) -> u64 {
+        if index < SUB_BUCKETS {
+            return index as u64;
+        }
+
+        let shift = (index / SUB_BUCKETS - 1) as u32;
+        let sub_bucket = (index % SUB_BUCKETS) as u64;
+        ((SUB_BUCKETS as u64 + sub_bucket + 1) << shift) - 1
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Latency below which the given fraction of recorded values fall, or
+    /// None if nothing was recorded yet. The result is the upper bound of the
+    /// bucket containing the percentile.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((fraction * total as f64).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        for (index, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_nanos(Self::bucket_upper_bound(index)));
+            }
+        }
+
+        unreachable!("rank is at most the total count")
+    }
+}
+
+/// Percentiles of the response latency of a server, in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub samples: u64,
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+impl From<&LatencyHistogram> for LatencyPercentiles {
+    fn from(histogram: &LatencyHistogram) -> Self {
+        let percentile = |fraction| {
+            histogram
+                .percentile(fraction)
+                .map(|latency| latency.as_secs_f64())
+        };
+
+        LatencyPercentiles {
+            samples: histogram.count(),
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+/// Listen addresses for a server that tracks interfaces: one for every local
+/// address of the same family as the (unspecified) configured address.
+fn per_address_listen(listen: SocketAddr, local_ips: &[IpAddr]) -> Vec<SocketAddr> {
+    local_ips
+        .iter()
+        .copied()
+        .filter(|ip| ip.is_ipv4() == listen.is_ipv4())
+        // Binding to a link-local IPv6 address requires its scope id, which we don't know
+        .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80))
+        .map(|ip| SocketAddr::new(ip, listen.port()))
+        .collect()
+}
+
+// Aborts the per address servers when the task managing them is aborted
+#[derive(Default)]
+struct PerAddressServers(HashMap<SocketAddr, (JoinHandle<()>, CancellationToken)>);
+
+impl PerAddressServers {
+    /// Cancel all per address servers, and wait for them to stop
+    async fn stop(&mut self) {
+        for (_, cancel) in self.0.values() {
+            cancel.cancel();
+        }
+        for (_, (task, _)) in self.0.drain() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for PerAddressServers {
+    fn drop(&mut self) {
+        for (task, _) in self.0.values() {
+            task.abort();
+        }
+    }
+}
+
+/// The state of the rest of the daemon that a server follows
+#[derive(Clone)]
+pub struct ServerChannels {
+    pub system_receiver: watch::Receiver<SystemSnapshot>,
+    pub keyset: watch::Receiver<Arc<KeySet>>,
+    /// Addresses of this machine, for the server to detect loops
+    pub local_ips: watch::Receiver<Arc<[IpAddr]>>,
+}
+
 pub struct ServerTask<C: 'static + NtpClock + Send> {
     config: ServerConfig,
     network_wait_period: std::time::Duration,
     system_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    /// Addresses of this machine, for the server to detect loops
+    local_ips: watch::Receiver<Arc<[IpAddr]>>,
     server: Server<C>,
     stats: ServerStats,
+    /// Source of randomness for the response jitter
+    rng: StdRng,
+    /// Requests waiting for their response jitter to pass
+    delayed: BinaryHeap<DelayedRequest>,
+    /// Caps the requests processed per second, when configured
+    request_limiter: Option<RequestRateLimiter>,
+    /// Stops the task once cancelled
+    cancel: CancellationToken,
 }
 
 impl<C: 'static + NtpClock + Send> ServerTask<C> {
     pub fn spawn(
         config: ServerConfig,
         stats: ServerStats,
-        mut system_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
-        mut keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+        channels: ServerChannels,
         clock: C,
         network_wait_period: Duration,
+        cancel: CancellationToken,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            let server = Server::new(
+            let ServerChannels {
+                mut system_receiver,
+                mut keyset,
+                mut local_ips,
+            } = channels;
+            let mut server = Server::new(
                 config.clone().into(),
                 clock,
                 *system_receiver.borrow_and_update(),
                 keyset.borrow_and_update().clone(),
             );
+            server.update_ip_list(local_ips.borrow_and_update().clone());
+
+            let request_limiter = config
+                .max_request_rate
+                .map(|rate| RequestRateLimiter::new(rate, Instant::now()));
 
             let mut process = ServerTask {
                 config,
                 network_wait_period,
                 system_receiver,
                 keyset,
+                local_ips,
                 server,
                 stats,
+                rng: StdRng::from_entropy(),
+                delayed: BinaryHeap::new(),
+                request_limiter,
+                cancel,
             };
 
             process.serve().await;
         })
     }
 
+    /// Serve on every local address separately, starting and stopping servers
+    /// as addresses come and go. Responses then always leave from the address
+    /// the request was sent to.
+    pub fn spawn_per_address(
+        config: ServerConfig,
+        stats: ServerStats,
+        mut channels: ServerChannels,
+        clock: C,
+        network_wait_period: Duration,
+        cancel: CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut servers = PerAddressServers::default();
+            loop {
+                let wanted =
+                    per_address_listen(config.listen, &channels.local_ips.borrow_and_update());
+
+                servers.0.retain(|listen, (_, server_cancel)| {
+                    let keep = wanted.contains(listen);
+                    if !keep {
+                        debug!(?listen, "Local address is gone, stopping server");
+                        server_cancel.cancel();
+                    }
+                    keep
+                });
+
+                for listen in wanted {
+                    servers.0.entry(listen).or_insert_with(|| {
+                        debug!(?listen, "New local address, starting server");
+                        let server_cancel = CancellationToken::new();
+                        let task = ServerTask::spawn(
+                            ServerConfig {
+                                listen,
+                                ..config.clone()
+                            },
+                            stats.clone(),
+                            channels.clone(),
+                            clock.clone(),
+                            network_wait_period,
+                            server_cancel.clone(),
+                        );
+                        (task, server_cancel)
+                    });
+                }
+
+                tokio::select! {
+                    () = cancel.cancelled() => {
+                        servers.stop().await;
+                        return;
+                    }
+                    changed = channels.local_ips.changed() => {
+                        if changed.is_err() {
+                            // No more changes, keep serving on the current addresses
+                            // until cancelled
+                            cancel.cancelled().await;
+                            servers.stop().await;
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    async fn send_response(
+        &mut self,
+        socket: &mut ServerSocket,
+        message: &[u8],
+        to: SocketAddr,
+        received: Instant,
+    ) {
+        if let Err(send_err) = socket.send_to(message, to).await {
+            self.stats.response_send_errors.inc();
+            debug!(error=?send_err, "Could not send response packet");
+        }
+        self.stats.response_latency.record(received.elapsed());
+    }
+
+    async fn handle_request(
+        &mut self,
+        socket: &mut ServerSocket,
+        message: &[u8],
+        from: SocketAddr,
+        recv_timestamp: NtpTimestamp,
+        received: Instant,
+    ) {
+        let mut send_buf = [0u8; MAX_PACKET_SIZE];
+        match self.server.handle(
+            from.ip(),
+            recv_timestamp,
+            message,
+            &mut send_buf[..message.len()],
+            &mut self.stats,
+        ) {
+            ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ }
+            ntp_proto::ServerAction::Respond { message } => {
+                self.send_response(socket, message, from, received).await;
+            }
+        }
+    }
+
     #[instrument(level = "debug", skip(self), fields(
         addr = debug(self.config.listen),
     ))]
@@ -153,13 +587,22 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                         let socket_res = open_ip(
                             self.config.listen,
                             timestamped_socket::socket::GeneralTimestampMode::SoftwareRecv,
-                        );
+                        )
+                        .and_then(|socket| ServerSocket::new(socket, self.config.connect));
 
                         match socket_res {
                             Ok(socket) => break socket,
                             Err(error) => {
-                                warn!(?error, ?self.config.listen, "Could not open server socket");
-                                tokio::time::sleep(self.network_wait_period).await;
+                                warn!(
+                                    ?error,
+                                    ?self.config.listen,
+                                    peer = ?self.config.connect,
+                                    "Could not open server socket"
+                                );
+                                tokio::select! {
+                                    () = self.cancel.cancelled() => return,
+                                    () = tokio::time::sleep(self.network_wait_period) => {}
+                                }
                             }
                         }
                     };
@@ -174,30 +617,77 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                 }
             };
 
-            let mut buf = [0_u8; MAX_PACKET_SIZE];
+            // One byte extra so that oversized packets can be told apart from
+            // packets of exactly the maximum size, instead of being truncated
+            let mut buf = [0_u8; MAX_PACKET_SIZE + 1];
+            let next_due = self.delayed.peek().map(|request| request.due);
             tokio::select! {
+                () = self.cancel.cancelled() => {
+                    debug!("Server task cancelled, closing its socket");
+                    return;
+                },
                 recv_res = socket.recv(&mut buf) => {
                     match recv_res {
+                        Ok(RecvResult {
+                            bytes_read: length,
+                            ..
+                        }) if length > MAX_PACKET_SIZE => {
+                            debug!("received an oversized packet");
+                            self.stats.register(0, false, ServerReason::ParseError, ServerResponse::Ignore);
+                        }
                         Ok(RecvResult {
                             bytes_read: length,
                             remote_addr: source_addr,
                             timestamp: Some(timestamp),
                         }) => {
-                            let mut send_buf = [0u8; MAX_PACKET_SIZE];
-                            match self.server.handle(source_addr.ip(), convert_net_timestamp(timestamp), &buf[..length], &mut send_buf[..length], &mut self.stats) {
-                                ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ },
-                                ntp_proto::ServerAction::Respond { message } => {
-                                    if let Err(send_err) = socket.send_to(message, source_addr).await {
-                                        self.stats.response_send_errors.inc();
-                                        debug!(error=?send_err, "Could not send response packet");
-                                    }
-                                },
+                            let received = Instant::now();
+                            if let Some(limiter) = &mut self.request_limiter {
+                                if !limiter.allow(received) {
+                                    self.stats.received_packets.inc();
+                                    self.stats.rate_capped_packets.inc();
+                                    continue;
+                                }
+                            }
+                            if let Some(max_lag) = self.config.max_request_lag {
+                                // The socket buffer is a bounded queue, with the oldest requests
+                                // at the front. When we fall behind, dropping those lets us catch
+                                // up, while the requests we do answer get an accurate response.
+                                if request_lag(timestamp, SystemTime::now()) > max_lag {
+                                    self.stats.received_packets.inc();
+                                    self.stats.shed_packets.inc();
+                                    continue;
+                                }
+                            }
+                            let recv_timestamp = convert_net_timestamp(timestamp);
+                            if self.config.response_jitter.is_zero() {
+                                self.handle_request(socket, &buf[..length], source_addr, recv_timestamp, received).await;
+                            } else if self.delayed.len() >= MAX_DELAYED_REQUESTS {
+                                self.stats.received_packets.inc();
+                                self.stats.shed_packets.inc();
+                            } else {
+                                // Hold the request back instead of waiting here, so other
+                                // requests are still handled in the meantime. It is only
+                                // handled once due, so that the transmit timestamp is
+                                // taken after the delay.
+                                let delay = response_delay(&mut self.rng, self.config.response_jitter);
+                                self.delayed.push(DelayedRequest {
+                                    due: tokio::time::Instant::now() + delay,
+                                    received,
+                                    recv_timestamp,
+                                    from: source_addr,
+                                    message: buf[..length].to_vec(),
+                                });
                             }
                         }
                         Ok(_) => {
                             debug!("received a packet without a timestamp");
                             self.stats.register(0, false, ServerReason::InternalError, ServerResponse::Ignore);
                         }
+                        Err(receive_error) if receive_error.kind() == std::io::ErrorKind::ConnectionRefused => {
+                            // Only connected sockets report this, when the peer
+                            // did not accept an earlier response
+                            debug!(?receive_error, "peer refused a response");
+                        }
                         Err(receive_error) => {
                             warn!(?receive_error, "could not receive packet");
 
@@ -215,12 +705,23 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                         }
                     }
                 },
+                () = tokio::time::sleep_until(next_due.unwrap_or_else(tokio::time::Instant::now)), if next_due.is_some() => {
+                    let now = tokio::time::Instant::now();
+                    while self.delayed.peek().map_or(false, |request| request.due <= now) {
+                        if let Some(request) = self.delayed.pop() {
+                            self.handle_request(socket, &request.message, request.from, request.recv_timestamp, request.received).await;
+                        }
+                    }
+                }
                 _ = self.system_receiver.changed(), if self.system_receiver.has_changed().is_ok() => {
                     self.server.update_system(*self.system_receiver.borrow_and_update());
                 }
                 _ = self.keyset.changed(), if self.keyset.has_changed().is_ok() => {
                     self.server.update_keyset(self.keyset.borrow_and_update().clone());
                 }
+                _ = self.local_ips.changed(), if self.local_ips.has_changed().is_ok() => {
+                    self.server.update_ip_list(self.local_ips.borrow_and_update().clone());
+                }
             }
         }
     }
@@ -298,10 +799,14 @@ mod tests {
         let join = ServerTask::spawn(
             config,
             Default::default(),
-            system_snapshots,
-            keyset,
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
             clock,
             Duration::from_secs(0),
+            CancellationToken::new(),
         );
 
         let socket = open_ip(
@@ -326,4 +831,446 @@ mod tests {
 
         join.abort();
     }
+
+    #[tokio::test]
+    async fn test_server_serves_with_response_jitter() {
+        let mut config = ServerConfig::try_from("127.0.0.1:9013").unwrap();
+        config.response_jitter = Duration::from_micros(1000);
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let join = ServerTask::spawn(
+            config,
+            Default::default(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
+            clock,
+            Duration::from_secs(0),
+            CancellationToken::new(),
+        );
+
+        // both requests are in flight at the same time, and are both answered
+        let mut clients = vec![];
+        for client in ["127.0.0.1:9014", "127.0.0.1:9015"] {
+            let socket =
+                open_ip(client.parse().unwrap(), GeneralTimestampMode::SoftwareRecv).unwrap();
+            let mut socket = socket.connect("127.0.0.1:9013".parse().unwrap()).unwrap();
+            let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+            socket
+                .send(&serialize_packet_unencryped(&packet))
+                .await
+                .unwrap();
+            clients.push((socket, id));
+        }
+
+        for (socket, id) in clients {
+            let mut buf = [0; 48];
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+            assert!(packet.valid_server_response(id, false));
+        }
+
+        join.abort();
+    }
+
+    #[tokio::test]
+    async fn test_connected_server_only_serves_peer() {
+        let mut config = ServerConfig::try_from("127.0.0.1:9010").unwrap();
+        config.connect = Some("127.0.0.1:9011".parse().unwrap());
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let join = ServerTask::spawn(
+            config,
+            Default::default(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
+            clock,
+            Duration::from_secs(0),
+            CancellationToken::new(),
+        );
+
+        let mut responses = vec![];
+        for (client, attempts) in [("127.0.0.1:9011", 50), ("127.0.0.1:9012", 3)] {
+            let socket =
+                open_ip(client.parse().unwrap(), GeneralTimestampMode::SoftwareRecv).unwrap();
+            let mut socket = socket.connect("127.0.0.1:9010".parse().unwrap()).unwrap();
+            let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+            let serialized = serialize_packet_unencryped(&packet);
+
+            // the peer retries until the server is up
+            let mut answered = false;
+            for _ in 0..attempts {
+                // sending fails while the server socket isn't open yet
+                let _ = socket.send(&serialized).await;
+
+                let mut buf = [0; 48];
+                let response =
+                    tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf)).await;
+                if response.map_or(false, |recv| {
+                    recv.is_ok()
+                        && NtpPacket::deserialize(&buf, &NoCipher)
+                            .unwrap()
+                            .0
+                            .valid_server_response(id, false)
+                }) {
+                    answered = true;
+                    break;
+                }
+            }
+            responses.push(answered);
+        }
+
+        // the kernel only delivers packets from the peer to a connected socket
+        assert_eq!(responses, [true, false]);
+
+        join.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_ignores_oversized() {
+        let config = ServerConfig::try_from("127.0.0.1:9002").unwrap();
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let stats = ServerStats::default();
+
+        let join = ServerTask::spawn(
+            config,
+            stats.clone(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
+            clock,
+            Duration::from_secs(0),
+            CancellationToken::new(),
+        );
+
+        let socket = open_ip(
+            "127.0.0.1:9003".parse().unwrap(),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        let mut socket = socket.connect("127.0.0.1:9002".parse().unwrap()).unwrap();
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencryped(&packet);
+
+        // The test runtime is single threaded, so the server only binds its
+        // socket once we yield to it. Packets sent before that would be lost.
+        tokio::task::yield_now().await;
+
+        // a packet that would be valid when truncated to the maximum size
+        let mut oversized = serialize_packet_unencryped(&packet);
+        let field_length = (MAX_PACKET_SIZE - oversized.len()) as u16;
+        oversized.extend_from_slice(&[0x7f, 0x00]);
+        oversized.extend_from_slice(&field_length.to_be_bytes());
+        oversized.resize(MAX_PACKET_SIZE + 1, 0);
+        socket.send(&oversized).await.unwrap();
+        socket.send(&serialized).await.unwrap();
+
+        // only the second packet gets a response
+        let mut buf = [0; 48];
+        tokio::time::timeout(Duration::from_millis(1000), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+
+        assert_eq!(stats.received_packets.get(), 2);
+        assert_eq!(stats.ignored_packets.get(), 1);
+        assert_eq!(stats.accepted_packets.get(), 1);
+
+        join.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_tracks_interfaces() {
+        let mut config = ServerConfig::try_from("0.0.0.0:9004").unwrap();
+        config.track_interfaces = true;
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let (ips_sender, local_ips) =
+            tokio::sync::watch::channel::<Arc<[IpAddr]>>(Arc::new(["::1".parse().unwrap()]));
+
+        let join = ServerTask::spawn_per_address(
+            config,
+            Default::default(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips,
+            },
+            clock,
+            Duration::from_secs(0),
+            CancellationToken::new(),
+        );
+
+        let socket = open_ip(
+            "127.0.0.1:9005".parse().unwrap(),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        let mut socket = socket.connect("127.0.0.1:9004".parse().unwrap()).unwrap();
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencryped(&packet);
+
+        // a newly appearing address is served, from that same address
+        ips_sender.send_replace(Arc::new(["127.0.0.1".parse().unwrap()]));
+        let mut buf = [0; 48];
+        loop {
+            socket.send(&serialized).await.unwrap();
+            let recv = tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf));
+            if recv.await.is_ok() {
+                break;
+            }
+        }
+        let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+
+        join.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_sheds_lagging_requests() {
+        let mut config = ServerConfig::try_from("127.0.0.1:9006").unwrap();
+        // every request has waited at least some time, so all are shed
+        config.max_request_lag = Some(Duration::ZERO);
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let stats = ServerStats::default();
+
+        let join = ServerTask::spawn(
+            config,
+            stats.clone(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
+            clock,
+            Duration::from_secs(0),
+            CancellationToken::new(),
+        );
+
+        let socket = open_ip(
+            "127.0.0.1:9007".parse().unwrap(),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        let mut socket = socket.connect("127.0.0.1:9006".parse().unwrap()).unwrap();
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencryped(&packet);
+
+        let mut buf = [0; 48];
+        while stats.shed_packets.get() == 0 {
+            socket.send(&serialized).await.unwrap();
+            let recv = tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf));
+            assert!(recv.await.is_err());
+        }
+
+        assert_eq!(stats.accepted_packets.get(), 0);
+        assert_eq!(stats.received_packets.get(), stats.shed_packets.get());
+
+        join.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_stops_when_cancelled() {
+        let config = ServerConfig::try_from("127.0.0.1:9008").unwrap();
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let cancel = CancellationToken::new();
+
+        let join = ServerTask::spawn(
+            config,
+            Default::default(),
+            ServerChannels {
+                system_receiver: system_snapshots,
+                keyset,
+                local_ips: tokio::sync::watch::channel(Arc::from([])).1,
+            },
+            clock,
+            Duration::from_secs(0),
+            cancel.clone(),
+        );
+
+        let socket = open_ip(
+            "127.0.0.1:9009".parse().unwrap(),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        let mut socket = socket.connect("127.0.0.1:9008".parse().unwrap()).unwrap();
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        socket
+            .send(&serialize_packet_unencryped(&packet))
+            .await
+            .unwrap();
+
+        let mut buf = [0; 48];
+        tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+
+        cancel.cancel();
+        tokio::time::timeout(Duration::from_secs(1), join)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the socket of the server is closed, so its address is free again
+        open_ip(
+            "127.0.0.1:9008".parse().unwrap(),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_request_lag() {
+        let now = UNIX_EPOCH + Duration::new(1000, 500);
+        let lag = |seconds, nanos| request_lag(Timestamp { seconds, nanos }, now);
+
+        assert_eq!(lag(1000, 0), Duration::from_nanos(500));
+        assert_eq!(lag(999, 500), Duration::from_secs(1));
+        // received after now, for example because the clock was stepped
+        assert_eq!(lag(1001, 0), Duration::ZERO);
+        assert_eq!(lag(-1, 0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_per_address_listen() {
+        let local_ips: Vec<IpAddr> = ["127.0.0.1", "192.168.1.2", "::1", "fe80::1", "2001:db8::1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            per_address_listen("0.0.0.0:123".parse().unwrap(), &local_ips),
+            vec![
+                "127.0.0.1:123".parse::<SocketAddr>().unwrap(),
+                "192.168.1.2:123".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            per_address_listen("[::]:123".parse().unwrap(), &local_ips),
+            vec![
+                "[::1]:123".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::1]:123".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_request_rate_limiter() {
+        let start = Instant::now();
+        let mut limiter = RequestRateLimiter::new(10, start);
+
+        // a burst of up to a second worth of requests passes
+        assert!((0..10).all(|_| limiter.allow(start)));
+        assert!(!limiter.allow(start));
+
+        // tokens come back at the configured rate
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.allow(later));
+        assert!(!limiter.allow(later));
+
+        // but never more than a second worth
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..20).filter(|_| limiter.allow(much_later)).count(), 10);
+    }
+
+    #[test]
+    fn test_response_delay() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let max_jitter = Duration::from_micros(500);
+
+        let delays: Vec<_> = (0..1000)
+            .map(|_| response_delay(&mut rng, max_jitter))
+            .collect();
+        assert!(delays.iter().all(|delay| *delay <= max_jitter));
+
+        let mean = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!(mean > Duration::from_micros(200) && mean < Duration::from_micros(300));
+
+        assert_eq!(response_delay(&mut rng, Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_latency_buckets() {
+        // every value falls within the bounds of its bucket
+        for nanos in (0..100_000).chain([1 << 20, 123_456_789, (1 << 30) - 1]) {
+            let index = LatencyHistogram::bucket_index(nanos);
+            assert!(nanos <= LatencyHistogram::bucket_upper_bound(index));
+            if index > 0 {
+                assert!(nanos > LatencyHistogram::bucket_upper_bound(index - 1));
+            }
+        }
+
+        // large values end up in the last bucket
+        assert_eq!(
+            LatencyHistogram::bucket_index(u64::MAX),
+            LATENCY_BUCKETS - 1
+        );
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.5), None);
+        assert_eq!(
+            LatencyPercentiles::from(&histogram),
+            LatencyPercentiles::default()
+        );
+
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.count(), 100);
+
+        // buckets are accurate to within 1/8th of the value
+        let p50 = histogram.percentile(0.5).unwrap().as_secs_f64();
+        assert!((50e-6..50e-6 * 1.125).contains(&p50));
+        let p99 = histogram.percentile(0.99).unwrap().as_secs_f64();
+        assert!((99e-6..99e-6 * 1.125).contains(&p99));
+
+        histogram.reset();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.percentile(0.5), None);
+    }
 }
@@ -1,62 +1,262 @@
+use std::fmt::Display;
 use std::fs::Permissions;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-pub async fn write_json<T>(stream: &mut UnixStream, value: &T) -> std::io::Result<()>
+/// Messages are framed as a one byte flags field, followed by the length of
+/// the payload as a big endian u32, followed by the payload itself.
+const HEADER_SIZE: usize = 5;
+
+/// The payload is gzip compressed
+const FLAG_GZIP: u8 = 0x01;
+
+/// Upper bound on the (decompressed) size of a single message, to prevent a
+/// misbehaving peer from making us allocate arbitrary amounts of memory.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ObserverError {
+    /// The socket could not be bound
+    Bind {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The tcp socket could not be bound
+    Listen {
+        address: SocketAddr,
+        error: std::io::Error,
+    },
+    /// The token authenticating clients over tcp could not be read
+    Token {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The socket path exists, but is something other than a socket
+    NotASocket(PathBuf),
+    /// The directory that should contain the socket does not exist
+    MissingParent(PathBuf),
+    /// Not allowed to create the socket or set its permissions
+    Permission {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// A message could not be serialized or deserialized
+    Json(serde_json::Error),
+    /// Any other io error, such as a connection reset or a malformed frame
+    Io(std::io::Error),
+}
+
+impl std::error::Error for ObserverError {}
+
+impl Display for ObserverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bind { path, error } => {
+                write!(f, "could not create socket at {}: {error}", path.display())
+            }
+            Self::Listen { address, error } => {
+                write!(f, "could not listen on {address}: {error}")
+            }
+            Self::Token { path, error } => {
+                write!(f, "could not read token from {}: {error}", path.display())
+            }
+            Self::NotASocket(path) => {
+                write!(f, "path {} exists but is not a socket", path.display())
+            }
+            Self::MissingParent(path) => write!(
+                f,
+                "could not create socket at {} because its parent directory does not exist",
+                path.display()
+            ),
+            Self::Permission { path, error } => write!(
+                f,
+                "insufficient permissions for socket at {}: {error}",
+                path.display()
+            ),
+            Self::Json(e) => write!(f, "invalid json message: {e}"),
+            Self::Io(e) => write!(f, "io error on socket: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ObserverError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for ObserverError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+fn invalid_data<E>(e: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    flags: u8,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    if payload.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("message exceeds the maximum message size"));
+    }
+
+    let mut header = [0; HEADER_SIZE];
+    header[0] = flags;
+    header[1..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+
+    stream.write_all(&header).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await
+}
+
+pub async fn write_json<T>(
+    stream: &mut (impl AsyncWrite + Unpin),
+    value: &T,
+) -> Result<(), ObserverError>
 where
     T: serde::Serialize,
 {
-    let bytes = serde_json::to_vec(value).unwrap();
-    stream.write_all(&bytes).await
+    let bytes = serde_json::to_vec(value)?;
+    Ok(write_frame(stream, 0, &bytes).await?)
+}
+
+/// Like [`write_json`], but gzip compresses the payload when the serialized
+/// value is larger than `threshold` bytes. [`read_json`] transparently handles
+/// both compressed and uncompressed messages.
+pub async fn write_json_compressed<T>(
+    stream: &mut (impl AsyncWrite + Unpin),
+    value: &T,
+    threshold: usize,
+) -> Result<(), ObserverError>
+where
+    T: serde::Serialize,
+{
+    let bytes = serde_json::to_vec(value)?;
+    if bytes.len() <= threshold {
+        return Ok(write_frame(stream, 0, &bytes).await?);
+    }
+
+    let mut encoder =
+        flate2::write::GzEncoder::new(Vec::with_capacity(bytes.len() / 4), Default::default());
+    encoder.write_all(&bytes)?;
+    let compressed = encoder.finish()?;
+
+    Ok(write_frame(stream, FLAG_GZIP, &compressed).await?)
 }
 
 pub async fn read_json<'a, T>(
-    stream: &mut UnixStream,
+    stream: &mut (impl AsyncRead + Unpin),
+    buffer: &'a mut Vec<u8>,
+) -> Result<T, ObserverError>
+where
+    T: serde::Deserialize<'a>,
+{
+    read_json_with_limit(stream, buffer, MAX_MESSAGE_SIZE).await
+}
+
+/// Like [`read_json`], but rejects messages larger than `max_size` bytes
+/// before reading them
+pub async fn read_json_with_limit<'a, T>(
+    stream: &mut (impl AsyncRead + Unpin),
     buffer: &'a mut Vec<u8>,
-) -> std::io::Result<T>
+    max_size: usize,
+) -> Result<T, ObserverError>
 where
     T: serde::Deserialize<'a>,
 {
     buffer.clear();
 
-    let n = stream.read_buf(buffer).await?;
-    buffer.truncate(n);
+    let mut header = [0; HEADER_SIZE];
+    stream.read_exact(&mut header).await?;
 
-    serde_json::from_slice(buffer)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
-}
+    let flags = header[0];
+    let length = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+
+    if flags & !FLAG_GZIP != 0 {
+        return Err(invalid_data(format!("unknown message flags {flags:#04x}")).into());
+    }
+
+    if length > max_size {
+        return Err(invalid_data("message exceeds the maximum message size").into());
+    }
+
+    if flags & FLAG_GZIP != 0 {
+        let mut compressed = vec![0; length];
+        stream.read_exact(&mut compressed).await?;
 
-fn other_error<T>(msg: String) -> std::io::Result<T> {
-    use std::io::{Error, ErrorKind};
-    Err(Error::new(ErrorKind::Other, msg))
+        let decoder = flate2::read::GzDecoder::new(compressed.as_slice());
+        decoder
+            .take(max_size as u64 + 1)
+            .read_to_end(buffer)
+            .map_err(invalid_data)?;
+
+        if buffer.len() > max_size {
+            return Err(invalid_data("message exceeds the maximum message size").into());
+        }
+    } else {
+        buffer.resize(length, 0);
+        stream.read_exact(buffer).await?;
+    }
+
+    Ok(serde_json::from_slice(buffer)?)
 }
 
 pub fn create_unix_socket_with_permissions(
     path: &Path,
     permissions: Permissions,
-) -> std::io::Result<tokio::net::UnixListener> {
+) -> Result<tokio::net::UnixListener, ObserverError> {
     let listener = create_unix_socket(path)?;
 
-    std::fs::set_permissions(path, permissions)?;
+    std::fs::set_permissions(path, permissions).map_err(|error| socket_error(path, error))?;
 
     Ok(listener)
 }
 
-fn create_unix_socket(path: &Path) -> std::io::Result<tokio::net::UnixListener> {
-    // must unlink path before the bind below (otherwise we get "address already in use")
-    if path.exists() {
-        use std::os::unix::fs::FileTypeExt;
+fn socket_error(path: &Path, error: std::io::Error) -> ObserverError {
+    let path = path.to_path_buf();
+    match error.kind() {
+        std::io::ErrorKind::PermissionDenied => ObserverError::Permission { path, error },
+        _ => ObserverError::Bind { path, error },
+    }
+}
 
-        let meta = std::fs::metadata(path)?;
-        if !meta.file_type().is_socket() {
-            return other_error(format!("path {path:?} exists but is not a socket"));
-        }
+/// Remove a stale socket left behind by a previous run. A socket that does
+/// not exist (yet) is not an error.
+fn remove_stale_socket(path: &Path) -> Result<(), ObserverError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(socket_error(path, error)),
+    };
 
-        std::fs::remove_file(path)?;
+    if !meta.file_type().is_socket() {
+        return Err(ObserverError::NotASocket(path.to_path_buf()));
     }
 
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            Err(socket_error(path, error))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn create_unix_socket(path: &Path) -> Result<tokio::net::UnixListener, ObserverError> {
+    // must unlink path before the bind below (otherwise we get "address already in use")
+    remove_stale_socket(path)?;
+
     // OS errors are terrible; let's try to do better
     let error = match tokio::net::UnixListener::bind(path) {
         Ok(listener) => return Ok(listener),
@@ -66,26 +266,17 @@ fn create_unix_socket(path: &Path) -> std::io::Result<tokio::net::UnixListener>
     // we don create parent directories
     if let Some(parent) = path.parent() {
         if !parent.exists() {
-            let msg = format!(
-                r"Could not create observe socket at {:?} because its parent directory does not exist",
-                &path
-            );
-            return other_error(msg);
+            return Err(ObserverError::MissingParent(path.to_path_buf()));
         }
     }
 
     // otherwise, just forward the OS error
-    let msg = format!(
-        "Could not create observe socket at {:?}: {:?}",
-        &path, error
-    );
-
-    other_error(msg)
+    Err(socket_error(path, error))
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::UnixListener;
+    use tokio::net::{UnixListener, UnixStream};
 
     use super::*;
 
@@ -117,7 +308,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn invalid_input_is_io_error() {
+    async fn invalid_input_is_json_error() {
         // be careful with copying: tests run concurrently and should use a unique socket name!
         let path = std::env::temp_dir().join("ntp-test-stream-5");
         if path.exists() {
@@ -128,18 +319,126 @@ mod tests {
 
         let (mut reader, _) = listener.accept().await.unwrap();
 
-        // write data that cannot be parsed
+        // write a frame with data that cannot be parsed
         let data = [0; 24];
-        writer.write_all(&data).await.unwrap();
+        write_frame(&mut writer, 0, &data).await.unwrap();
 
         let mut buf = Vec::new();
         let output = read_json::<Vec<usize>>(&mut reader, &mut buf)
             .await
             .unwrap_err();
 
-        assert_eq!(output.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(matches!(output, ObserverError::Json(_)));
 
         // the logic will automatically grow the buffer to the required size
         assert!(!buf.is_empty());
     }
+
+    #[tokio::test]
+    async fn large_compressed_message_roundtrips() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-11");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut writer = UnixStream::connect(&path).await.unwrap();
+
+        let (mut reader, _) = listener.accept().await.unwrap();
+
+        // much larger than both the socket buffer and any initial read buffer
+        let object: Vec<usize> = (0..200_000).collect();
+
+        let write = {
+            let object = object.clone();
+            tokio::spawn(async move {
+                write_json_compressed(&mut writer, &object, 0)
+                    .await
+                    .unwrap();
+                write_json(&mut writer, &object).await.unwrap();
+            })
+        };
+
+        let mut buf = Vec::with_capacity(16);
+        let output = read_json::<Vec<usize>>(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(object, output);
+
+        let output = read_json::<Vec<usize>>(&mut reader, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(object, output);
+
+        write.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-12");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut writer = UnixStream::connect(&path).await.unwrap();
+
+        let (mut reader, _) = listener.accept().await.unwrap();
+
+        let mut header = [0; HEADER_SIZE];
+        header[1..].copy_from_slice(&u32::MAX.to_be_bytes());
+        writer.write_all(&header).await.unwrap();
+
+        let mut buf = Vec::new();
+        let output = read_json::<Vec<usize>>(&mut reader, &mut buf)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(output, ObserverError::Io(ref e) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn socket_is_created_and_replaced() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-17");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        // nothing to remove the first time around
+        let listener = create_unix_socket(&path).unwrap();
+        drop(listener);
+
+        // but the stale socket of a previous run is replaced
+        let listener = create_unix_socket(&path).unwrap();
+        drop(listener);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_file_is_not_replaced() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-18");
+        std::fs::write(&path, b"not a socket").unwrap();
+
+        let output = create_unix_socket(&path).unwrap_err();
+        assert!(matches!(output, ObserverError::NotASocket(_)));
+        assert!(path.exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_parent_is_reported() {
+        let path = std::env::temp_dir()
+            .join("ntp-test-missing-directory")
+            .join("observe");
+
+        let output = create_unix_socket(&path).unwrap_err();
+        assert!(matches!(output, ObserverError::MissingParent(_)));
+    }
 }
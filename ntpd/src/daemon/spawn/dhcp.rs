@@ -0,0 +1,339 @@
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use ntp_proto::ProtocolVersion;
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{debug, info, warn};
+
+use super::super::config::{canonical_socket_addr, DhcpConfig, NormalizedAddress};
+
+use super::{
+    BasicSpawner, LookupLimiter, SourceId, SourceRemovalReason, SourceRemovedEvent,
+    SourceSpawnOptions, SpawnAction, SpawnEvent, SpawnerId,
+};
+
+/// Interval at which the servers file is read again, to pick up the servers
+/// of a renewed lease
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+struct DhcpSource {
+    id: SourceId,
+    address: NormalizedAddress,
+}
+
+pub struct DhcpSpawner {
+    config: DhcpConfig,
+    lookups: LookupLimiter,
+    id: SpawnerId,
+    /// Addresses of the statically configured sources, which we don't duplicate
+    static_addresses: Vec<NormalizedAddress>,
+    /// Servers listed in the servers file when it was last read
+    desired: Vec<NormalizedAddress>,
+    current_sources: Vec<DhcpSource>,
+    /// Addresses removed by the operator, these are never used again
+    removed: Vec<NormalizedAddress>,
+    next_reload: Instant,
+}
+
+#[derive(Debug)]
+pub enum DhcpSpawnError {
+    SendError(mpsc::error::SendError<SpawnEvent>),
+}
+
+impl Display for DhcpSpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SendError(e) => write!(f, "Channel send error: {e}"),
+        }
+    }
+}
+
+impl From<mpsc::error::SendError<SpawnEvent>> for DhcpSpawnError {
+    fn from(value: mpsc::error::SendError<SpawnEvent>) -> Self {
+        Self::SendError(value)
+    }
+}
+
+impl std::error::Error for DhcpSpawnError {}
+
+/// Parse the servers file written by the DHCP client. It lists one server per
+/// line, optionally prefixed by `server` as in an ntp.conf, and may contain
+/// comments starting with `#`. Anything after the address is ignored.
+fn parse_servers(contents: &str) -> Vec<NormalizedAddress> {
+    let mut servers = vec![];
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let address = match words.next() {
+            Some("server") => words.next(),
+            first => first,
+        };
+        let Some(address) = address else {
+            continue;
+        };
+
+        match NormalizedAddress::from_string_ntp(address.to_string()) {
+            Ok(address) if !servers.contains(&address) => servers.push(address),
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, address, "Ignoring invalid server from DHCP"),
+        }
+    }
+
+    servers
+}
+
+impl DhcpSpawner {
+    pub fn new(
+        config: DhcpConfig,
+        static_addresses: Vec<NormalizedAddress>,
+        lookups: LookupLimiter,
+    ) -> DhcpSpawner {
+        DhcpSpawner {
+            config,
+            lookups,
+            id: Default::default(),
+            static_addresses,
+            desired: Default::default(),
+            current_sources: Default::default(),
+            removed: Default::default(),
+            next_reload: Instant::now(),
+        }
+    }
+
+    fn is_candidate(&self, address: &NormalizedAddress) -> bool {
+        !self.current_sources.iter().any(|s| s.address == *address)
+            && !self.removed.contains(address)
+    }
+
+    async fn resolve(&self, address: &NormalizedAddress) -> Option<SocketAddr> {
+        // DHCP almost always provides addresses, which need no lookup
+        let ip = address
+            .server_name
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>();
+        if let Ok(ip) = ip {
+            return Some(canonical_socket_addr(SocketAddr::new(ip, address.port)));
+        }
+
+        match self
+            .lookups
+            .lookup_cached(&address.server_name, address.port, address.lookup_host())
+            .await
+        {
+            Ok(addresses) => addresses.first().copied(),
+            Err(e) => {
+                warn!(error = ?e, %address, "error while resolving DHCP server address, retrying");
+                None
+            }
+        }
+    }
+
+    /// Read the servers file again, and remove the sources for servers that
+    /// are no longer listed
+    async fn reload(&mut self, action_tx: &mpsc::Sender<SpawnEvent>) -> Result<(), DhcpSpawnError> {
+        let contents = match tokio::fs::read_to_string(&self.config.servers_file).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(path = ?self.config.servers_file, "No servers received through DHCP yet");
+                String::new()
+            }
+            Err(e) => {
+                // keep the current servers, the file may just be in the middle of an update
+                warn!(error = ?e, path = ?self.config.servers_file, "Could not read DHCP servers file");
+                return Ok(());
+            }
+        };
+
+        let desired: Vec<_> = parse_servers(&contents)
+            .into_iter()
+            .filter(|address| !self.static_addresses.contains(address))
+            .collect();
+        if desired == self.desired {
+            return Ok(());
+        }
+
+        info!(servers = ?desired.iter().map(|a| a.to_string()).collect::<Vec<_>>(), "DHCP servers changed");
+        self.desired = desired;
+
+        let (keep, outdated) = std::mem::take(&mut self.current_sources)
+            .into_iter()
+            .partition(|source| self.desired.contains(&source.address));
+        self.current_sources = keep;
+        for source in outdated {
+            action_tx
+                .send(SpawnEvent::new(self.id, SpawnAction::Remove(source.id)))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl BasicSpawner for DhcpSpawner {
+    type Error = DhcpSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), DhcpSpawnError> {
+        let candidates: Vec<_> = self
+            .desired
+            .iter()
+            .filter(|address| self.is_candidate(address))
+            .cloned()
+            .collect();
+
+        for address in candidates {
+            let Some(addr) = self.resolve(&address).await else {
+                continue;
+            };
+
+            let id = SourceId::new();
+            let action = SpawnAction::create(
+                id,
+                addr,
+                address.clone(),
+                ProtocolVersion::default(),
+                None,
+                SourceSpawnOptions::default(),
+            );
+            self.current_sources.push(DhcpSource { id, address });
+            action_tx.send(SpawnEvent::new(self.id, action)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        !self
+            .desired
+            .iter()
+            .any(|address| self.is_candidate(address))
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), DhcpSpawnError> {
+        if removed_source.reason == SourceRemovalReason::Removed {
+            if let Some(source) = self
+                .current_sources
+                .iter()
+                .find(|s| s.id == removed_source.id)
+            {
+                self.removed.push(source.address.clone());
+            }
+        }
+        self.current_sources.retain(|s| s.id != removed_source.id);
+        Ok(())
+    }
+
+    fn next_rotation(&self) -> Option<Instant> {
+        Some(self.next_reload)
+    }
+
+    async fn rotate(&mut self, action_tx: &mpsc::Sender<SpawnEvent>) -> Result<(), DhcpSpawnError> {
+        self.next_reload = Instant::now() + RELOAD_INTERVAL;
+        self.reload(action_tx).await?;
+        self.try_spawn(action_tx).await
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.servers_file.display().to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "dhcp"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{self, error::TryRecvError};
+
+    use crate::daemon::{
+        config::{DhcpConfig, NormalizedAddress},
+        spawn::{
+            dhcp::{parse_servers, DhcpSpawner},
+            tests::get_create_params,
+            BasicSpawner, LookupLimiter, SpawnAction,
+        },
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[test]
+    fn parses_servers_file() {
+        let servers = parse_servers(
+            "# received through DHCP\n\
+             192.0.2.1\n\
+             server 192.0.2.2 iburst\n\
+             \n\
+             [2001:db8::1]:1123\n\
+             192.0.2.1 # duplicate\n",
+        );
+
+        assert_eq!(
+            servers,
+            vec![
+                NormalizedAddress::new_unchecked("192.0.2.1", 123),
+                NormalizedAddress::new_unchecked("192.0.2.2", 123),
+                NormalizedAddress::new_unchecked("[2001:db8::1]", 1123),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reconciles_with_servers_file() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join("ntp-test-dhcp-servers");
+        std::fs::write(&path, "192.0.2.1\n192.0.2.2\n192.0.2.3\n").unwrap();
+
+        let mut spawner = DhcpSpawner::new(
+            DhcpConfig {
+                use_dhcp_ntp_servers: true,
+                servers_file: path.clone(),
+            },
+            vec![NormalizedAddress::new_unchecked("192.0.2.3", 123)],
+            LookupLimiter::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        // nothing to do before the file is read
+        assert!(spawner.is_complete());
+        spawner.rotate(&action_tx).await.unwrap();
+
+        // the statically configured server is not duplicated
+        let first = get_create_params(action_rx.try_recv().unwrap());
+        let second = get_create_params(action_rx.try_recv().unwrap());
+        assert_eq!(first.addr.to_string(), "192.0.2.1:123");
+        assert_eq!(second.addr.to_string(), "192.0.2.2:123");
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(spawner.is_complete());
+
+        // an unchanged file does nothing
+        spawner.rotate(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        // a renewed lease replaces only the servers that changed
+        std::fs::write(&path, "192.0.2.2\n192.0.2.4\n").unwrap();
+        spawner.rotate(&action_tx).await.unwrap();
+
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+        assert!(matches!(res.action, SpawnAction::Remove(id) if id == first.id));
+        let third = get_create_params(action_rx.try_recv().unwrap());
+        assert_eq!(third.addr.to_string(), "192.0.2.4:123");
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
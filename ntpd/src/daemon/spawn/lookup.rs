@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::Semaphore, time::Instant};
+
+use super::super::config::DnsConfig;
+
+/// Maximum number of names for which lookups are cached. Names of NTS-KE
+/// servers come from the network, so the cache must not grow without bound.
+const MAX_CACHED_LOOKUPS: usize = 256;
+
+/// Limits the DNS lookups done by the spawners
+///
+/// All spawners share a single limiter, so a configuration with many sources
+/// does not fire a burst of lookups at the resolver on startup. Every lookup is
+/// subject to a timeout, so a slow lookup for one source cannot hold on to its
+/// permit indefinitely.
+///
+/// The limiter also caches the results of lookups by name, so spawners that
+/// look up the same name within the cache time to live share a single lookup.
+/// The system resolver does not report the time to live of the records, so
+/// the configured time to live is used for every name.
+#[derive(Debug, Clone)]
+pub struct LookupLimiter {
+    permits: Option<Arc<Semaphore>>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<HashMap<(String, u16), CachedLookup>>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedLookup {
+    addresses: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+impl LookupLimiter {
+    pub fn new(config: &DnsConfig) -> LookupLimiter {
+        LookupLimiter {
+            permits: config
+                .max_concurrent_lookups
+                .map(|max| Arc::new(Semaphore::new(max.get().min(Semaphore::MAX_PERMITS)))),
+            timeout: Duration::from_secs(config.lookup_timeout),
+            cache_ttl: Duration::from_secs(config.cache_ttl),
+            cache: Default::default(),
+        }
+    }
+
+    /// Look up the addresses of a host, reusing the result of an earlier
+    /// lookup of the same host and port while it is cached. Only successful
+    /// lookups that found addresses are cached, so a transient failure is
+    /// retried on the next lookup.
+    pub async fn lookup_cached<I: Iterator<Item = SocketAddr>>(
+        &self,
+        host: &str,
+        port: u16,
+        lookup: impl Future<Output = std::io::Result<I>>,
+    ) -> std::io::Result<Vec<SocketAddr>> {
+        let key = (host.to_owned(), port);
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|cached| cached.expires > Instant::now())
+            .map(|cached| cached.addresses.clone());
+        if let Some(addresses) = cached {
+            return Ok(addresses);
+        }
+
+        let result = self.lookup(lookup).await.map(Iterator::collect::<Vec<_>>);
+
+        let mut cache = self.cache.lock().unwrap();
+        match &result {
+            Ok(addresses) if !addresses.is_empty() && !self.cache_ttl.is_zero() => {
+                if !cache.contains_key(&key) && cache.len() >= MAX_CACHED_LOOKUPS {
+                    let now = Instant::now();
+                    cache.retain(|_, cached| cached.expires > now);
+                    // still full, make room by dropping the entry that expires first
+                    if cache.len() >= MAX_CACHED_LOOKUPS {
+                        let first_to_expire = cache
+                            .iter()
+                            .min_by_key(|(_, cached)| cached.expires)
+                            .map(|(key, _)| key.clone());
+                        if let Some(first_to_expire) = first_to_expire {
+                            cache.remove(&first_to_expire);
+                        }
+                    }
+                }
+                cache.insert(
+                    key,
+                    CachedLookup {
+                        addresses: addresses.clone(),
+                        expires: Instant::now() + self.cache_ttl,
+                    },
+                );
+            }
+            _ => {
+                cache.remove(&key);
+            }
+        }
+
+        result
+    }
+
+    /// Forget the cached result for a host and port, so that the next lookup
+    /// of it asks the resolver again
+    pub fn invalidate(&self, host: &str, port: u16) {
+        self.cache.lock().unwrap().remove(&(host.to_owned(), port));
+    }
+
+    /// Run a lookup once a permit is available, queueing it otherwise
+    pub async fn lookup<T>(
+        &self,
+        lookup: impl Future<Output = std::io::Result<T>>,
+    ) -> std::io::Result<T> {
+        let _permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .acquire()
+                    .await
+                    .expect("Internal error: lookup semaphore closed"),
+            ),
+            None => None,
+        };
+
+        match tokio::time::timeout(self.timeout, lookup).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "DNS lookup timed out",
+            )),
+        }
+    }
+}
+
+impl Default for LookupLimiter {
+    fn default() -> Self {
+        Self::new(&DnsConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_concurrent_lookups() {
+        let limiter = LookupLimiter::new(&DnsConfig {
+            max_concurrent_lookups: NonZeroUsize::new(2),
+            ..Default::default()
+        });
+
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let lookup = || async {
+            let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+            max_active.fetch_max(now_active, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            active.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        };
+
+        let results = tokio::join!(
+            limiter.lookup(lookup()),
+            limiter.lookup(lookup()),
+            limiter.lookup(lookup()),
+            limiter.lookup(lookup()),
+            limiter.lookup(lookup()),
+        );
+
+        assert!(results.0.is_ok() && results.1.is_ok() && results.2.is_ok());
+        assert!(results.3.is_ok() && results.4.is_ok());
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_releases_permit() {
+        let limiter = LookupLimiter::new(&DnsConfig {
+            max_concurrent_lookups: NonZeroUsize::new(1),
+            // lookups that are not immediately ready time out
+            lookup_timeout: 0,
+            ..Default::default()
+        });
+
+        let (hanging, finished) = tokio::join!(
+            limiter.lookup(std::future::pending::<std::io::Result<()>>()),
+            limiter.lookup(async { Ok(()) }),
+        );
+
+        assert_eq!(hanging.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(finished.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_caches_lookups() {
+        let limiter = LookupLimiter::new(&DnsConfig {
+            cache_ttl: 60,
+            ..Default::default()
+        });
+
+        let lookups = AtomicUsize::new(0);
+        let address: SocketAddr = "127.0.0.1:123".parse().unwrap();
+        let lookup = |result: std::io::Result<Vec<SocketAddr>>| {
+            let lookups = &lookups;
+            async move {
+                lookups.fetch_add(1, Ordering::SeqCst);
+                result.map(Vec::into_iter)
+            }
+        };
+
+        // a second lookup of the same name uses the cached result
+        let first = limiter
+            .lookup_cached("example.com", 123, lookup(Ok(vec![address])))
+            .await;
+        let second = limiter
+            .lookup_cached("example.com", 123, lookup(Ok(vec![])))
+            .await;
+        assert_eq!(first.unwrap(), vec![address]);
+        assert_eq!(second.unwrap(), vec![address]);
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // but not a lookup for another port
+        let other = limiter
+            .lookup_cached("example.com", 456, lookup(Ok(vec![])))
+            .await;
+        assert!(other.unwrap().is_empty());
+
+        // a forced refresh asks the resolver again
+        limiter.invalidate("example.com", 123);
+        let refreshed = limiter
+            .lookup_cached("example.com", 123, lookup(Ok(vec![])))
+            .await;
+        assert!(refreshed.unwrap().is_empty());
+
+        // failures and empty results are not cached
+        let failed = limiter
+            .lookup_cached(
+                "example.com",
+                123,
+                lookup(Err(std::io::ErrorKind::NotFound.into())),
+            )
+            .await;
+        assert!(failed.is_err());
+        let recovered = limiter
+            .lookup_cached("example.com", 123, lookup(Ok(vec![address])))
+            .await;
+        assert_eq!(recovered.unwrap(), vec![address]);
+        assert_eq!(lookups.load(Ordering::SeqCst), 5);
+
+        // results expire after the time to live
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let expired = limiter
+            .lookup_cached("example.com", 123, lookup(Ok(vec![])))
+            .await;
+        assert!(expired.unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bounds_cache() {
+        let limiter = LookupLimiter::new(&DnsConfig {
+            cache_ttl: 60,
+            ..Default::default()
+        });
+
+        let address: SocketAddr = "127.0.0.1:123".parse().unwrap();
+        let lookup = || async move { Ok(vec![address].into_iter()) };
+
+        for port in 0..MAX_CACHED_LOOKUPS as u16 {
+            limiter
+                .lookup_cached("example.com", port, lookup())
+                .await
+                .unwrap();
+            tokio::time::advance(Duration::from_millis(1)).await;
+        }
+        assert_eq!(limiter.cache.lock().unwrap().len(), MAX_CACHED_LOOKUPS);
+
+        // the entry that expires first makes room for a new one
+        limiter
+            .lookup_cached("example.org", 123, lookup())
+            .await
+            .unwrap();
+        let cache = limiter.cache.lock().unwrap();
+        assert_eq!(cache.len(), MAX_CACHED_LOOKUPS);
+        assert!(!cache.contains_key(&("example.com".to_owned(), 0)));
+        assert!(cache.contains_key(&("example.org".to_owned(), 123)));
+    }
+}
@@ -1,22 +1,30 @@
-use std::{net::SocketAddr, sync::atomic::AtomicU64};
+use std::{net::SocketAddr, ops::RangeInclusive, sync::atomic::AtomicU64};
 
 use ntp_proto::{ProtocolVersion, SourceNtsData};
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::mpsc,
-    time::{timeout, Instant},
+    time::{timeout_at, Instant},
 };
 
-use super::{config::NormalizedAddress, system::NETWORK_WAIT_PERIOD};
+use super::{
+    config::{NormalizedAddress, RouteLossAction, SourceWeight},
+    system::NETWORK_WAIT_PERIOD,
+};
 
+pub mod dhcp;
 #[cfg(test)]
 pub mod dummy;
+mod lookup;
 pub mod nts;
 #[cfg(feature = "unstable_nts-pool")]
 pub mod nts_pool;
 pub mod pool;
+pub mod resolved;
 pub mod standard;
 
+pub use lookup::LookupLimiter;
+
 /// Unique identifier for a spawner.
 /// This is used to identify which spawner was used to create a source
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -103,14 +111,18 @@ pub enum SourceRemovalReason {
     Demobilized,
     NetworkIssue,
     Unreachable,
+    /// Removed at runtime through the control socket, should not be respawned
+    Removed,
+    /// Retired by its spawner after reaching its maximum lifetime
+    Expired,
 }
 
 /// The kind of action that the spawner requests to the system.
-/// Currently a spawner can only create sources
 #[derive(Debug)]
 pub enum SpawnAction {
     Create(SourceCreateParameters),
-    // Remove(()),
+    /// Remove a source previously created by the same spawner
+    Remove(SourceId),
 }
 
 impl SpawnAction {
@@ -120,6 +132,7 @@ impl SpawnAction {
         normalized_addr: NormalizedAddress,
         protocol_version: ProtocolVersion,
         nts: Option<Box<SourceNtsData>>,
+        options: SourceSpawnOptions,
     ) -> SpawnAction {
         SpawnAction::Create(SourceCreateParameters {
             id,
@@ -127,6 +140,7 @@ impl SpawnAction {
             normalized_addr,
             protocol_version,
             nts,
+            options,
         })
     }
 }
@@ -138,6 +152,21 @@ pub struct SourceCreateParameters {
     pub normalized_addr: NormalizedAddress,
     pub protocol_version: ProtocolVersion,
     pub nts: Option<Box<SourceNtsData>>,
+    pub options: SourceSpawnOptions,
+}
+
+/// How the system treats a source, as configured for it
+#[derive(Debug, Clone, Default)]
+pub struct SourceSpawnOptions {
+    pub weight: SourceWeight,
+    pub trust_leap: bool,
+    pub pin: bool,
+    pub no_select: bool,
+    pub no_step: bool,
+    pub ignore_root: bool,
+    /// Strata outside of which the source is not synchronized to
+    pub stratum_range: Option<RangeInclusive<u8>>,
+    pub on_route_loss: RouteLossAction,
 }
 
 #[cfg(test)]
@@ -158,6 +187,7 @@ impl SourceCreateParameters {
             .unwrap(),
             protocol_version: ProtocolVersion::default(),
             nts: None,
+            options: SourceSpawnOptions::default(),
         }
     }
 
@@ -246,6 +276,18 @@ pub trait BasicSpawner {
         Ok(())
     }
 
+    /// When the spawner next wants to replace one of its sources, if ever
+    fn next_rotation(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Replace sources that have reached the end of their lifetime
+    ///
+    /// This is called once the instant returned by `next_rotation` has passed.
+    async fn rotate(&mut self, _action_tx: &mpsc::Sender<SpawnEvent>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Get the id of the spawner
     fn get_id(&self) -> SpawnerId;
 
@@ -283,15 +325,24 @@ where
                 last_ticket_time = Instant::now();
             }
 
-            let event = if has_ticket {
-                system_notify.recv().await
-            } else {
-                timeout(
-                    NETWORK_WAIT_PERIOD - last_ticket_time.elapsed(),
-                    system_notify.recv(),
-                )
-                .await
-                .unwrap_or(Some(SystemEvent::Idle))
+            if self
+                .next_rotation()
+                .map_or(false, |rotation| rotation <= Instant::now())
+            {
+                self.rotate(&action_tx).await?;
+            }
+
+            let ticket_deadline = (!has_ticket).then(|| last_ticket_time + NETWORK_WAIT_PERIOD);
+            let deadline = match (ticket_deadline, self.next_rotation()) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+
+            let event = match deadline {
+                None => system_notify.recv().await,
+                Some(deadline) => timeout_at(deadline, system_notify.recv())
+                    .await
+                    .unwrap_or(Some(SystemEvent::Idle)),
             };
 
             let Some(event) = event else {
@@ -330,7 +381,9 @@ mod tests {
     use super::{SourceCreateParameters, SpawnAction, SpawnEvent};
 
     pub fn get_create_params(res: SpawnEvent) -> SourceCreateParameters {
-        let SpawnAction::Create(params) = res.action;
+        let SpawnAction::Create(params) = res.action else {
+            panic!("Expected a create action, got {:?}", res.action);
+        };
         params
     }
 }
@@ -7,10 +7,14 @@ use tracing::warn;
 
 use super::super::{config::NtsSourceConfig, keyexchange::key_exchange_client};
 
-use super::{BasicSpawner, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, SpawnerId};
+use super::{
+    BasicSpawner, LookupLimiter, SourceId, SourceRemovalReason, SourceRemovedEvent,
+    SourceSpawnOptions, SpawnAction, SpawnEvent, SpawnerId,
+};
 
 pub struct NtsSpawner {
     config: NtsSourceConfig,
+    lookups: LookupLimiter,
     id: SpawnerId,
     has_spawned: bool,
 }
@@ -36,10 +40,16 @@ impl From<mpsc::error::SendError<SpawnEvent>> for NtsSpawnError {
     }
 }
 
-pub(super) async fn resolve_addr(address: (&str, u16)) -> Option<SocketAddr> {
-    match tokio::net::lookup_host(address).await {
-        Ok(mut addresses) => match addresses.next() {
-            Some(address) => Some(address),
+pub(super) async fn resolve_addr(
+    lookups: &LookupLimiter,
+    address: (&str, u16),
+) -> Option<SocketAddr> {
+    match lookups
+        .lookup_cached(address.0, address.1, tokio::net::lookup_host(address))
+        .await
+    {
+        Ok(addresses) => match addresses.first() {
+            Some(address) => Some(*address),
             None => {
                 warn!("received unknown domain name from NTS-ke");
                 None
@@ -53,9 +63,10 @@ pub(super) async fn resolve_addr(address: (&str, u16)) -> Option<SocketAddr> {
 }
 
 impl NtsSpawner {
-    pub fn new(config: NtsSourceConfig) -> NtsSpawner {
+    pub fn new(config: NtsSourceConfig, lookups: LookupLimiter) -> NtsSpawner {
         NtsSpawner {
             config,
+            lookups,
             id: Default::default(),
             has_spawned: false,
         }
@@ -78,7 +89,9 @@ impl BasicSpawner for NtsSpawner {
         .await
         {
             Ok(ke) => {
-                if let Some(address) = resolve_addr((ke.remote.as_str(), ke.port)).await {
+                if let Some(address) =
+                    resolve_addr(&self.lookups, (ke.remote.as_str(), ke.port)).await
+                {
                     action_tx
                         .send(SpawnEvent::new(
                             self.id,
@@ -88,6 +101,16 @@ impl BasicSpawner for NtsSpawner {
                                 self.config.address.deref().clone(),
                                 ke.protocol_version,
                                 Some(ke.nts),
+                                SourceSpawnOptions {
+                                    weight: self.config.weight,
+                                    trust_leap: self.config.trust_leap,
+                                    pin: self.config.pin,
+                                    no_select: self.config.no_select,
+                                    no_step: self.config.no_step,
+                                    ignore_root: self.config.ignore_root,
+                                    stratum_range: self.config.stratum_range(),
+                                    ..Default::default()
+                                },
                             ),
                         ))
                         .await?;
@@ -108,9 +131,11 @@ impl BasicSpawner for NtsSpawner {
 
     async fn handle_source_removed(
         &mut self,
-        _removed_source: SourceRemovedEvent,
+        removed_source: SourceRemovedEvent,
     ) -> Result<(), NtsSpawnError> {
-        self.has_spawned = false;
+        if removed_source.reason != SourceRemovalReason::Removed {
+            self.has_spawned = false;
+        }
         Ok(())
     }
 
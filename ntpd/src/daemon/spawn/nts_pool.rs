@@ -8,7 +8,10 @@ use super::super::{
     config::NtsPoolSourceConfig, keyexchange::key_exchange_client_with_denied_servers,
 };
 
-use super::{BasicSpawner, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, SpawnerId};
+use super::{
+    BasicSpawner, LookupLimiter, SourceId, SourceRemovalReason, SourceRemovedEvent,
+    SourceSpawnOptions, SpawnAction, SpawnEvent, SpawnerId,
+};
 
 use super::nts::resolve_addr;
 
@@ -19,8 +22,11 @@ struct PoolSource {
 
 pub struct NtsPoolSpawner {
     config: NtsPoolSourceConfig,
+    lookups: LookupLimiter,
     id: SpawnerId,
     current_sources: Vec<PoolSource>,
+    /// Servers removed by the operator, these are never used again
+    removed: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -45,11 +51,13 @@ impl From<mpsc::error::SendError<SpawnEvent>> for NtsPoolSpawnError {
 }
 
 impl NtsPoolSpawner {
-    pub fn new(config: NtsPoolSourceConfig) -> NtsPoolSpawner {
+    pub fn new(config: NtsPoolSourceConfig, lookups: LookupLimiter) -> NtsPoolSpawner {
         NtsPoolSpawner {
             config,
+            lookups,
             id: Default::default(),
             current_sources: Default::default(),
+            removed: Default::default(),
             //known_ips: Default::default(),
         }
     }
@@ -58,6 +66,7 @@ impl NtsPoolSpawner {
         self.current_sources
             .iter()
             .any(|source| source.remote == domain)
+            || self.removed.iter().any(|remote| remote == domain)
     }
 }
 
@@ -76,12 +85,15 @@ impl BasicSpawner for NtsPoolSpawner {
                 &self.config.certificate_authorities,
                 self.current_sources
                     .iter()
-                    .map(|source| source.remote.clone()),
+                    .map(|source| source.remote.clone())
+                    .chain(self.removed.iter().cloned()),
             )
             .await
             {
                 Ok(ke) if !self.contains_source(&ke.remote) => {
-                    if let Some(address) = resolve_addr((ke.remote.as_str(), ke.port)).await {
+                    if let Some(address) =
+                        resolve_addr(&self.lookups, (ke.remote.as_str(), ke.port)).await
+                    {
                         let id = SourceId::new();
                         self.current_sources.push(PoolSource {
                             id,
@@ -96,6 +108,11 @@ impl BasicSpawner for NtsPoolSpawner {
                                     self.config.addr.deref().clone(),
                                     ke.protocol_version,
                                     Some(ke.nts),
+                                    SourceSpawnOptions {
+                                        weight: self.config.weight,
+                                        trust_leap: self.config.trust_leap,
+                                        ..Default::default()
+                                    },
                                 ),
                             ))
                             .await?;
@@ -123,6 +140,15 @@ impl BasicSpawner for NtsPoolSpawner {
         &mut self,
         removed_source: SourceRemovedEvent,
     ) -> Result<(), NtsPoolSpawnError> {
+        if removed_source.reason == SourceRemovalReason::Removed {
+            if let Some(source) = self
+                .current_sources
+                .iter()
+                .find(|p| p.id == removed_source.id)
+            {
+                self.removed.push(source.remote.clone());
+            }
+        }
         self.current_sources.retain(|p| p.id != removed_source.id);
         Ok(())
     }
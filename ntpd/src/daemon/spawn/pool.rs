@@ -1,24 +1,34 @@
 use std::fmt::Display;
+use std::time::Duration;
 use std::{net::SocketAddr, ops::Deref};
 
 use ntp_proto::ProtocolVersion;
-use tokio::sync::mpsc;
-use tracing::warn;
+use rand::{seq::SliceRandom, Rng};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{info, warn};
 
-use super::super::config::PoolSourceConfig;
+use super::super::config::{canonical_socket_addr, PoolSourceConfig};
 
-use super::{BasicSpawner, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, SpawnerId};
+use super::{
+    BasicSpawner, LookupLimiter, SourceId, SourceRemovalReason, SourceRemovedEvent,
+    SourceSpawnOptions, SpawnAction, SpawnEvent, SpawnerId,
+};
 
 struct PoolSource {
     id: SourceId,
     addr: SocketAddr,
+    /// When this source is replaced by a fresh address from the pool
+    expires: Option<Instant>,
 }
 
 pub struct PoolSpawner {
     config: PoolSourceConfig,
+    lookups: LookupLimiter,
     id: SpawnerId,
     current_sources: Vec<PoolSource>,
     known_ips: Vec<SocketAddr>,
+    /// Addresses removed by the operator, these are never used again
+    removed: Vec<SocketAddr>,
 }
 
 #[derive(Debug)]
@@ -33,14 +43,74 @@ impl Display for PoolSpawnError {
 impl std::error::Error for PoolSpawnError {}
 
 impl PoolSpawner {
-    pub fn new(config: PoolSourceConfig) -> PoolSpawner {
+    pub fn new(config: PoolSourceConfig, lookups: LookupLimiter) -> PoolSpawner {
         PoolSpawner {
             config,
+            lookups,
             id: Default::default(),
             current_sources: Default::default(),
             known_ips: Default::default(),
+            removed: Default::default(),
         }
     }
+
+    fn is_candidate(&self, addr: &SocketAddr) -> bool {
+        !self.current_sources.iter().any(|p| p.addr == *addr)
+            && !self.removed.contains(addr)
+            && !self
+                .config
+                .ignore
+                .iter()
+                .any(|ign| canonical_socket_addr(SocketAddr::new(*ign, 0)).ip() == addr.ip())
+    }
+
+    /// The lifetime of a new source. This is jittered between half and the
+    /// full configured lifetime, so sources created together don't all
+    /// expire at the same moment.
+    fn new_expiry(&self) -> Option<Instant> {
+        let lifetime = Duration::from_secs(self.config.max_association_lifetime?);
+        Some(Instant::now() + lifetime.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)))
+    }
+
+    /// Discard unused addresses beyond the configured maximum. Which ones are
+    /// kept is random, so we don't always fall back to the same part of the
+    /// pool.
+    fn limit_backups(&mut self) {
+        let Some(max_backups) = self.config.max_backups else {
+            return;
+        };
+        if self.known_ips.len() > max_backups {
+            self.known_ips = self
+                .known_ips
+                .choose_multiple(&mut rand::thread_rng(), max_backups)
+                .copied()
+                .collect();
+        }
+    }
+
+    async fn spawn_source(&mut self, addr: SocketAddr, action_tx: &mpsc::Sender<SpawnEvent>) {
+        let id = SourceId::new();
+        let expires = self.new_expiry();
+        self.current_sources.push(PoolSource { id, addr, expires });
+        let action = SpawnAction::create(
+            id,
+            addr,
+            self.config.addr.deref().clone(),
+            ProtocolVersion::default(),
+            None,
+            SourceSpawnOptions {
+                weight: self.config.weight,
+                trust_leap: self.config.trust_leap,
+                ..Default::default()
+            },
+        );
+        tracing::debug!(?action, "intending to spawn new pool source at");
+
+        action_tx
+            .send(SpawnEvent::new(self.id, action))
+            .await
+            .expect("Channel was no longer connected");
+    }
 }
 
 #[async_trait::async_trait]
@@ -57,15 +127,23 @@ impl BasicSpawner for PoolSpawner {
         }
 
         if self.known_ips.len() < self.config.count - self.current_sources.len() {
-            match self.config.addr.lookup_host().await {
+            // Not cached: pools rely on every lookup returning a fresh set of
+            // servers from round-robin DNS
+            match self.lookups.lookup(self.config.addr.lookup_host()).await {
                 Ok(addresses) => {
-                    // add the addresses looked up to our list of known ips
-                    self.known_ips.append(&mut addresses.collect());
+                    // add the addresses looked up to our list of known ips. These are
+                    // normalized, so IPv4-mapped IPv6 addresses equal their IPv4 form.
+                    for addr in addresses {
+                        if !self.known_ips.contains(&addr) {
+                            self.known_ips.push(addr);
+                        }
+                    }
                     // remove known ips that we are already connected to or that we want to ignore
-                    self.known_ips.retain(|ip| {
-                        !self.current_sources.iter().any(|p| p.addr == *ip)
-                            && !self.config.ignore.iter().any(|ign| *ign == ip.ip())
-                    });
+                    let known_ips = std::mem::take(&mut self.known_ips);
+                    self.known_ips = known_ips
+                        .into_iter()
+                        .filter(|ip| self.is_candidate(ip))
+                        .collect();
                 }
                 Err(e) => {
                     warn!(error = ?e, "error while resolving source address, retrying");
@@ -77,26 +155,14 @@ impl BasicSpawner for PoolSpawner {
         // Try and add sources to our pool
         while self.current_sources.len() < self.config.count {
             if let Some(addr) = self.known_ips.pop() {
-                let id = SourceId::new();
-                self.current_sources.push(PoolSource { id, addr });
-                let action = SpawnAction::create(
-                    id,
-                    addr,
-                    self.config.addr.deref().clone(),
-                    ProtocolVersion::default(),
-                    None,
-                );
-                tracing::debug!(?action, "intending to spawn new pool source at");
-
-                action_tx
-                    .send(SpawnEvent::new(self.id, action))
-                    .await
-                    .expect("Channel was no longer connected");
+                self.spawn_source(addr, action_tx).await;
             } else {
                 break;
             }
         }
 
+        self.limit_backups();
+
         Ok(())
     }
 
@@ -108,10 +174,66 @@ impl BasicSpawner for PoolSpawner {
         &mut self,
         removed_source: SourceRemovedEvent,
     ) -> Result<(), PoolSpawnError> {
+        if removed_source.reason == SourceRemovalReason::Removed {
+            if let Some(source) = self
+                .current_sources
+                .iter()
+                .find(|p| p.id == removed_source.id)
+            {
+                self.removed.push(source.addr);
+            }
+        }
         self.current_sources.retain(|p| p.id != removed_source.id);
         Ok(())
     }
 
+    fn next_rotation(&self) -> Option<Instant> {
+        self.current_sources.iter().filter_map(|p| p.expires).min()
+    }
+
+    async fn rotate(&mut self, action_tx: &mpsc::Sender<SpawnEvent>) -> Result<(), PoolSpawnError> {
+        let now = Instant::now();
+        let Some(index) = self
+            .current_sources
+            .iter()
+            .position(|p| p.expires.map_or(false, |expires| expires <= now))
+        else {
+            return Ok(());
+        };
+
+        // Only rotate a full pool, and only one source at a time. The
+        // replacement is created before the expired source is removed, so the
+        // pool never has fewer sources than configured.
+        let replacement = if self.current_sources.len() >= self.config.count {
+            match self.lookups.lookup(self.config.addr.lookup_host()).await {
+                Ok(addresses) => addresses.into_iter().find(|addr| self.is_candidate(addr)),
+                Err(e) => {
+                    warn!(error = ?e, "error while resolving source address for rotation");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let Some(addr) = replacement else {
+            // keep the current source for now and try again later
+            self.current_sources[index].expires = self.new_expiry();
+            return Ok(());
+        };
+
+        let expired = self.current_sources.remove(index);
+        info!(old = %expired.addr, new = %addr, "rotating pool source");
+        self.known_ips.retain(|ip| *ip != addr);
+        self.spawn_source(addr, action_tx).await;
+        action_tx
+            .send(SpawnEvent::new(self.id, SpawnAction::Remove(expired.id)))
+            .await
+            .expect("Channel was no longer connected");
+
+        Ok(())
+    }
+
     fn get_id(&self) -> SpawnerId {
         self.id
     }
@@ -127,13 +249,18 @@ impl BasicSpawner for PoolSpawner {
 
 #[cfg(test)]
 mod tests {
-    use tokio::sync::mpsc::{self, error::TryRecvError};
+    use std::time::Duration;
+
+    use tokio::{
+        sync::mpsc::{self, error::TryRecvError},
+        time::Instant,
+    };
 
     use crate::daemon::{
         config::{NormalizedAddress, PoolSourceConfig},
         spawn::{
-            pool::PoolSpawner, tests::get_create_params, BasicSpawner, SourceRemovalReason,
-            SourceRemovedEvent,
+            pool::PoolSpawner, tests::get_create_params, BasicSpawner, LookupLimiter,
+            SourceRemovalReason, SourceRemovedEvent, SpawnAction,
         },
         system::MESSAGE_BUFFER_SIZE,
     };
@@ -143,12 +270,19 @@ mod tests {
         let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
         let addresses = address_strings.map(|addr| addr.parse().unwrap());
 
-        let mut pool = PoolSpawner::new(PoolSourceConfig {
-            addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
-                .into(),
-            count: 2,
-            ignore: vec![],
-        });
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
         let spawner_id = pool.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -173,18 +307,79 @@ mod tests {
         assert!(pool.is_complete());
     }
 
+    #[tokio::test]
+    async fn limits_backups() {
+        let address_strings = [
+            "127.0.0.1:123",
+            "127.0.0.2:123",
+            "127.0.0.3:123",
+            "127.0.0.4:123",
+            "127.0.0.5:123",
+            "127.0.0.6:123",
+        ];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: Some(2),
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let first = get_create_params(action_rx.try_recv().unwrap());
+        let second = get_create_params(action_rx.try_recv().unwrap());
+        assert!(pool.is_complete());
+
+        // only two of the four unused addresses are kept
+        assert_eq!(pool.known_ips.len(), 2);
+        for addr in &pool.known_ips {
+            assert!(addresses.contains(addr));
+            assert_ne!(*addr, first.addr);
+            assert_ne!(*addr, second.addr);
+        }
+        let backups = pool.known_ips.clone();
+
+        // which are used to replace a lost source
+        pool.handle_source_removed(SourceRemovedEvent {
+            id: first.id,
+            reason: SourceRemovalReason::NetworkIssue,
+        })
+        .await
+        .unwrap();
+        pool.try_spawn(&action_tx).await.unwrap();
+        let replacement = get_create_params(action_rx.try_recv().unwrap());
+        assert!(backups.contains(&replacement.addr));
+        assert_eq!(pool.known_ips.len(), 1);
+    }
+
     #[tokio::test]
     async fn respect_ignores() {
         let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
         let addresses = address_strings.map(|addr| addr.parse().unwrap());
         let ignores = vec!["127.0.0.1".parse().unwrap()];
 
-        let mut pool = PoolSpawner::new(PoolSourceConfig {
-            addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
-                .into(),
-            count: 2,
-            ignore: ignores.clone(),
-        });
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: ignores.clone(),
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
         let spawner_id = pool.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -211,17 +406,97 @@ mod tests {
         assert!(pool.is_complete());
     }
 
+    #[tokio::test]
+    async fn ignores_ipv4_mapped_duplicates() {
+        let address_strings = ["127.0.0.1:123", "[::ffff:127.0.0.1]:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let params = get_create_params(action_rx.try_recv().unwrap());
+        assert_eq!(params.addr, "127.0.0.1:123".parse().unwrap());
+
+        // the mapped address is the same server, so no second source is created
+        let res = action_rx.try_recv().unwrap_err();
+        assert_eq!(res, TryRecvError::Empty);
+        assert!(!pool.is_complete());
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap_err();
+        assert_eq!(res, TryRecvError::Empty);
+    }
+
+    #[tokio::test]
+    async fn does_not_reuse_removed_sources() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        get_create_params(action_rx.try_recv().unwrap());
+        let params = get_create_params(action_rx.try_recv().unwrap());
+        assert!(pool.is_complete());
+
+        pool.handle_source_removed(SourceRemovedEvent {
+            id: params.id,
+            reason: SourceRemovalReason::Removed,
+        })
+        .await
+        .unwrap();
+
+        // the only address left is the one that was removed
+        assert!(!pool.is_complete());
+        pool.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap_err();
+        assert_eq!(res, TryRecvError::Empty);
+    }
+
     #[tokio::test]
     async fn refills_sources_upto_limit() {
         let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
         let addresses = address_strings.map(|addr| addr.parse().unwrap());
 
-        let mut pool = PoolSpawner::new(PoolSourceConfig {
-            addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
-                .into(),
-            count: 2,
-            ignore: vec![],
-        });
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
         assert!(!pool.is_complete());
@@ -256,13 +531,105 @@ mod tests {
         assert!(pool.is_complete());
     }
 
+    #[tokio::test]
+    async fn rotates_expired_sources() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: Some(3600),
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let first = get_create_params(action_rx.try_recv().unwrap());
+        let second = get_create_params(action_rx.try_recv().unwrap());
+        assert!(pool.is_complete());
+
+        // lifetimes are jittered, but never beyond the configured maximum
+        let rotation = pool.next_rotation().unwrap();
+        assert!(rotation > Instant::now() + Duration::from_secs(1799));
+        assert!(rotation <= Instant::now() + Duration::from_secs(3600));
+
+        // nothing has expired yet
+        pool.rotate(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        pool.current_sources
+            .iter_mut()
+            .find(|p| p.id == first.id)
+            .unwrap()
+            .expires = Some(Instant::now());
+        pool.rotate(&action_tx).await.unwrap();
+
+        // the replacement is created before the expired source is removed
+        let replacement = get_create_params(action_rx.try_recv().unwrap());
+        assert_ne!(replacement.addr, first.addr);
+        assert_ne!(replacement.addr, second.addr);
+        let SpawnAction::Remove(removed) = action_rx.try_recv().unwrap().action else {
+            panic!("Expected a remove action");
+        };
+        assert_eq!(removed, first.id);
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(pool.is_complete());
+    }
+
+    #[tokio::test]
+    async fn keeps_expired_sources_without_replacement() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: Some(3600),
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        get_create_params(action_rx.try_recv().unwrap());
+        get_create_params(action_rx.try_recv().unwrap());
+
+        // with no fresh address, the source is kept a while longer
+        pool.current_sources[0].expires = Some(Instant::now());
+        pool.rotate(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(pool.next_rotation().unwrap() > Instant::now());
+        assert!(pool.is_complete());
+    }
+
     #[tokio::test]
     async fn works_if_address_does_not_resolve() {
-        let mut pool = PoolSpawner::new(PoolSourceConfig {
-            addr: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![]).into(),
-            count: 2,
-            ignore: vec![],
-        });
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![]).into(),
+                count: 2,
+                ignore: vec![],
+                weight: Default::default(),
+                trust_leap: false,
+                max_association_lifetime: None,
+                max_backups: None,
+            },
+            LookupLimiter::default(),
+        );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
         assert!(!pool.is_complete());
         pool.try_spawn(&action_tx).await.unwrap();
@@ -0,0 +1,167 @@
+use std::fmt::Display;
+use std::net::SocketAddr;
+
+use ntp_proto::ProtocolVersion;
+use tokio::sync::mpsc;
+
+use super::super::config::NormalizedAddress;
+
+use super::{
+    BasicSpawner, SourceId, SourceRemovalReason, SourceRemovedEvent, SourceSpawnOptions,
+    SpawnAction, SpawnEvent, SpawnerId,
+};
+
+/// Spawns a single source for an address that was already resolved by the
+/// caller, for example by the service discovery of an embedding application.
+/// The address is never looked up again, also not when the source becomes
+/// unreachable.
+pub struct ResolvedSpawner {
+    id: SpawnerId,
+    address: NormalizedAddress,
+    addr: SocketAddr,
+    has_spawned: bool,
+}
+
+#[derive(Debug)]
+pub enum ResolvedSpawnError {
+    SendError(mpsc::error::SendError<SpawnEvent>),
+}
+
+impl Display for ResolvedSpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SendError(e) => write!(f, "Channel send error: {e}"),
+        }
+    }
+}
+
+impl From<mpsc::error::SendError<SpawnEvent>> for ResolvedSpawnError {
+    fn from(value: mpsc::error::SendError<SpawnEvent>) -> Self {
+        Self::SendError(value)
+    }
+}
+
+impl std::error::Error for ResolvedSpawnError {}
+
+impl ResolvedSpawner {
+    pub fn new(address: NormalizedAddress, addr: SocketAddr) -> ResolvedSpawner {
+        ResolvedSpawner {
+            id: Default::default(),
+            address,
+            addr,
+            has_spawned: false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BasicSpawner for ResolvedSpawner {
+    type Error = ResolvedSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), ResolvedSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::create(
+                    SourceId::new(),
+                    self.addr,
+                    self.address.clone(),
+                    ProtocolVersion::default(),
+                    None,
+                    SourceSpawnOptions::default(),
+                ),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), ResolvedSpawnError> {
+        if !matches!(
+            removed_source.reason,
+            SourceRemovalReason::Demobilized | SourceRemovalReason::Removed
+        ) {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        format!("{} ({})", self.address, self.addr)
+    }
+
+    fn get_description(&self) -> &str {
+        "resolved"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{self, error::TryRecvError};
+
+    use crate::daemon::{
+        config::NormalizedAddress,
+        spawn::{
+            resolved::ResolvedSpawner, tests::get_create_params, BasicSpawner, SourceRemovalReason,
+            SourceRemovedEvent,
+        },
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn recreates_without_lookup() {
+        let mut spawner = ResolvedSpawner::new(
+            NormalizedAddress::new_unchecked("time.example.com", 123),
+            "192.0.2.1:123".parse().unwrap(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+        let params = get_create_params(res);
+        assert_eq!(params.addr.to_string(), "192.0.2.1:123");
+        assert_eq!(params.normalized_addr.to_string(), "time.example.com:123");
+        assert!(spawner.is_complete());
+
+        // an unreachable source comes back at the same address
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Unreachable,
+            })
+            .await
+            .unwrap();
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_create_params(action_rx.try_recv().unwrap());
+        assert_eq!(params.addr.to_string(), "192.0.2.1:123");
+
+        // but a removed source does not come back
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Removed,
+            })
+            .await
+            .unwrap();
+        assert!(spawner.is_complete());
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+}
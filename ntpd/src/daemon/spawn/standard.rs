@@ -8,13 +8,14 @@ use tracing::warn;
 use super::super::config::StandardSource;
 
 use super::{
-    BasicSpawner, SourceId, SourceRemovalReason, SourceRemovedEvent, SpawnAction, SpawnEvent,
-    SpawnerId,
+    BasicSpawner, LookupLimiter, SourceId, SourceRemovalReason, SourceRemovedEvent,
+    SourceSpawnOptions, SpawnAction, SpawnEvent, SpawnerId,
 };
 
 pub struct StandardSpawner {
     id: SpawnerId,
     config: StandardSource,
+    lookups: LookupLimiter,
     resolved: Option<SocketAddr>,
     has_spawned: bool,
 }
@@ -41,10 +42,11 @@ impl From<mpsc::error::SendError<SpawnEvent>> for StandardSpawnError {
 impl std::error::Error for StandardSpawnError {}
 
 impl StandardSpawner {
-    pub fn new(config: StandardSource) -> StandardSpawner {
+    pub fn new(config: StandardSource, lookups: LookupLimiter) -> StandardSpawner {
         StandardSpawner {
             id: Default::default(),
             config,
+            lookups,
             resolved: None,
             has_spawned: false,
         }
@@ -54,14 +56,19 @@ impl StandardSpawner {
         if let (false, Some(addr)) = (force_resolve, self.resolved) {
             Some(addr)
         } else {
-            match self.config.address.lookup_host().await {
-                Ok(mut addresses) => match addresses.next() {
+            let address = &self.config.address;
+            match self
+                .lookups
+                .lookup_cached(&address.server_name, address.port, address.lookup_host())
+                .await
+            {
+                Ok(addresses) => match addresses.first() {
                     None => {
                         warn!("Could not resolve source address, retrying");
                         None
                     }
                     Some(first) => {
-                        self.resolved = Some(first);
+                        self.resolved = Some(*first);
                         self.resolved
                     }
                 },
@@ -94,6 +101,16 @@ impl BasicSpawner for StandardSpawner {
                     self.config.address.deref().clone(),
                     ProtocolVersion::default(),
                     None,
+                    SourceSpawnOptions {
+                        weight: self.config.weight,
+                        trust_leap: self.config.trust_leap,
+                        pin: self.config.pin,
+                        no_select: self.config.no_select,
+                        no_step: self.config.no_step,
+                        ignore_root: self.config.ignore_root,
+                        stratum_range: self.config.stratum_range(),
+                        on_route_loss: self.config.on_route_loss,
+                    },
                 ),
             ))
             .await?;
@@ -112,8 +129,13 @@ impl BasicSpawner for StandardSpawner {
         if removed_source.reason == SourceRemovalReason::Unreachable {
             // force new resolution
             self.resolved = None;
+            self.lookups
+                .invalidate(&self.config.address.server_name, self.config.address.port);
         }
-        if removed_source.reason != SourceRemovalReason::Demobilized {
+        if !matches!(
+            removed_source.reason,
+            SourceRemovalReason::Demobilized | SourceRemovalReason::Removed
+        ) {
             self.has_spawned = false;
         }
         Ok(())
@@ -139,22 +161,34 @@ mod tests {
     use crate::daemon::{
         config::{NormalizedAddress, StandardSource},
         spawn::{
-            standard::StandardSpawner, tests::get_create_params, BasicSpawner, SourceRemovalReason,
-            SourceRemovedEvent,
+            standard::StandardSpawner, tests::get_create_params, BasicSpawner, LookupLimiter,
+            SourceRemovalReason, SourceRemovedEvent,
         },
         system::MESSAGE_BUFFER_SIZE,
     };
 
     #[tokio::test]
     async fn creates_a_source() {
-        let mut spawner = StandardSpawner::new(StandardSource {
-            address: NormalizedAddress::with_hardcoded_dns(
-                "example.com",
-                123,
-                vec!["127.0.0.1:123".parse().unwrap()],
-            )
-            .into(),
-        });
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    vec!["127.0.0.1:123".parse().unwrap()],
+                )
+                .into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
+            },
+            LookupLimiter::default(),
+        );
         let spawner_id = spawner.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -171,14 +205,26 @@ mod tests {
 
     #[tokio::test]
     async fn recreates_a_source() {
-        let mut spawner = StandardSpawner::new(StandardSource {
-            address: NormalizedAddress::with_hardcoded_dns(
-                "example.com",
-                123,
-                vec!["127.0.0.1:123".parse().unwrap()],
-            )
-            .into(),
-        });
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    vec!["127.0.0.1:123".parse().unwrap()],
+                )
+                .into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
+            },
+            LookupLimiter::default(),
+        );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
         assert!(!spawner.is_complete());
@@ -203,19 +249,69 @@ mod tests {
         assert!(spawner.is_complete());
     }
 
+    #[tokio::test]
+    async fn does_not_recreate_a_removed_source() {
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    vec!["127.0.0.1:123".parse().unwrap()],
+                )
+                .into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
+            },
+            LookupLimiter::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_create_params(action_rx.try_recv().unwrap());
+
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Removed,
+            })
+            .await
+            .unwrap();
+
+        assert!(spawner.is_complete());
+    }
+
     #[tokio::test]
     async fn reresolves_on_unreachable() {
         let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
         let addresses = address_strings.map(|addr| addr.parse().unwrap());
 
-        let mut spawner = StandardSpawner::new(StandardSource {
-            address: NormalizedAddress::with_hardcoded_dns(
-                "europe.pool.ntp.org",
-                123,
-                addresses.to_vec(),
-            )
-            .into(),
-        });
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "europe.pool.ntp.org",
+                    123,
+                    addresses.to_vec(),
+                )
+                .into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
+            },
+            LookupLimiter::default(),
+        );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
         assert!(!spawner.is_complete());
@@ -265,9 +361,22 @@ mod tests {
 
     #[tokio::test]
     async fn works_if_address_does_not_resolve() {
-        let mut spawner = StandardSpawner::new(StandardSource {
-            address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![]).into(),
-        });
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![])
+                    .into(),
+                weight: Default::default(),
+                trust_leap: false,
+                pin: false,
+                no_select: false,
+                no_step: false,
+                ignore_root: false,
+                min_stratum: None,
+                max_stratum: None,
+                on_route_loss: Default::default(),
+            },
+            LookupLimiter::default(),
+        );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
         spawner.try_spawn(&action_tx).await.unwrap();
@@ -0,0 +1,192 @@
+//! On-disk format for persisted clock state
+//!
+//! The state file stores the frequency correction of every disciplined clock,
+//! keyed by the clock it belongs to, so that each clock can independently
+//! recover its frequency after a restart.
+//!
+//! Compatibility rules for the format:
+//! - New fields must have a default, so older files remain readable.
+//! - Unknown fields are ignored, so older versions of the daemon can read
+//!   files written by newer versions.
+//! - The version is only bumped for changes that cannot follow the rules
+//!   above. Files with a newer version than we understand are rejected.
+
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Latest version of the state file format
+pub const STATE_FILE_VERSION: u32 = 1;
+
+/// Identifies a clock within the state file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClockKey(String);
+
+impl ClockKey {
+    /// The system realtime clock
+    pub fn realtime() -> ClockKey {
+        ClockKey("realtime".into())
+    }
+
+    /// A clock opened through a device path, such as a PTP hardware clock
+    pub fn from_path(path: &Path) -> ClockKey {
+        ClockKey(path.display().to_string())
+    }
+}
+
+impl Default for ClockKey {
+    fn default() -> Self {
+        ClockKey::realtime()
+    }
+}
+
+impl std::fmt::Display for ClockKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Persisted state of a single clock
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ClockState {
+    /// Frequency offset applied to the clock, as a fraction (not ppm)
+    #[serde(default)]
+    pub frequency: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateFile {
+    pub version: u32,
+    #[serde(default)]
+    pub clocks: BTreeMap<ClockKey, ClockState>,
+}
+
+impl Default for StateFile {
+    fn default() -> Self {
+        StateFile {
+            version: STATE_FILE_VERSION,
+            clocks: BTreeMap::new(),
+        }
+    }
+}
+
+impl StateFile {
+    pub fn clock(&self, key: &ClockKey) -> Option<ClockState> {
+        self.clocks.get(key).copied()
+    }
+
+    pub fn set_clock(&mut self, key: ClockKey, state: ClockState) {
+        self.clocks.insert(key, state);
+    }
+
+    fn from_bytes(bytes: &[u8]) -> std::io::Result<StateFile> {
+        let state: StateFile = serde_json::from_slice(bytes)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+        if state.version > STATE_FILE_VERSION {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "state file version {} is newer than the supported version {}",
+                    state.version, STATE_FILE_VERSION
+                ),
+            ));
+        }
+
+        Ok(state)
+    }
+
+    /// Read the state file at `path`. A missing file results in an empty state.
+    pub async fn load(path: &Path) -> std::io::Result<StateFile> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => StateFile::from_bytes(&bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(StateFile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the state file to `path`. The file is written next to its final
+    /// location first and then moved in place, so a crash halfway through
+    /// never leaves a truncated file behind.
+    pub async fn store(&self, path: &Path) -> std::io::Result<()> {
+        let state = StateFile {
+            version: STATE_FILE_VERSION,
+            clocks: self.clocks.clone(),
+        };
+        let bytes = serde_json::to_vec_pretty(&state).unwrap();
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        tokio::fs::write(&tmp_path, bytes).await?;
+        tokio::fs::rename(&tmp_path, path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn store_then_load_is_identity() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join("ntp-test-state-1");
+
+        let mut state = StateFile::default();
+        state.set_clock(ClockKey::realtime(), ClockState { frequency: 12e-6 });
+        state.set_clock(
+            ClockKey::from_path(Path::new("/dev/ptp0")),
+            ClockState { frequency: -3e-6 },
+        );
+        state.store(&path).await.unwrap();
+
+        let loaded = StateFile::load(&path).await.unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(
+            loaded.clock(&ClockKey::realtime()),
+            Some(ClockState { frequency: 12e-6 })
+        );
+        assert_eq!(
+            loaded.clock(&ClockKey::from_path(Path::new("/dev/ptp1"))),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_file_is_empty_state() {
+        let path = std::env::temp_dir().join("ntp-test-state-does-not-exist");
+        let loaded = StateFile::load(&path).await.unwrap();
+        assert_eq!(loaded, StateFile::default());
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let state = StateFile::from_bytes(
+            br#"{
+                "version": 1,
+                "future-field": true,
+                "clocks": { "realtime": { "frequency": 1e-6, "future-field": 3 } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            state.clock(&ClockKey::realtime()),
+            Some(ClockState { frequency: 1e-6 })
+        );
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let error = StateFile::from_bytes(br#"{ "version": 2, "clocks": {} }"#).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let error = StateFile::from_bytes(b"not json").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,32 +1,54 @@
 #[cfg(feature = "unstable_nts-pool")]
 use super::spawn::nts_pool::NtsPoolSpawner;
 use super::{
-    config::{ClockConfig, NormalizedAddress, NtpSourceConfig, ServerConfig, TimestampMode},
+    config::{
+        canonical_socket_addr, ClockConfig, ClockStateConfig, Config, LeapSecondsConfig,
+        NmeaSourceConfig, NormalizedAddress, NtpSourceConfig, ServerConfig, TimestampMode,
+    },
+    nmea_source::NmeaSourceTask,
     ntp_source::{MsgForSystem, SourceChannels, SourceTask, Wait},
-    server::{ServerStats, ServerTask},
+    observer::{SourceDebugDump, SourceHistoryEntry},
+    server::{ServerChannels, ServerStats, ServerTask},
     spawn::{
-        nts::NtsSpawner, pool::PoolSpawner, standard::StandardSpawner, SourceCreateParameters,
-        SourceId, SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId, SystemEvent,
+        dhcp::DhcpSpawner, nts::NtsSpawner, pool::PoolSpawner, resolved::ResolvedSpawner,
+        standard::StandardSpawner, LookupLimiter, SourceCreateParameters, SourceId,
+        SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId, SystemEvent,
     },
+    state_file::{ClockKey, ClockState, StateFile},
+    util::CancellationToken,
     ObservableSourceState, ObservedSourceState,
 };
 
 use std::{
-    collections::HashMap, future::Future, marker::PhantomData, net::IpAddr, pin::Pin, sync::Arc,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
     time::Duration,
 };
 
 use ntp_proto::{
-    KeySet, NtpClock, SourceDefaultsConfig, SynchronizationConfig, System, SystemSnapshot,
+    KeySet, LeapSecondsFile, NtpClock, ObservableSourceFilter, SourceDefaultsConfig, SourceOptions,
+    StepThreshold, SynchronizationConfig, System, SystemSnapshot,
 };
 use timestamped_socket::interface::InterfaceName;
-use tokio::{sync::mpsc, task::JoinHandle};
-use tracing::{debug, info};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
 
 pub const NETWORK_WAIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
 
 pub const MESSAGE_BUFFER_SIZE: usize = 32;
 
+/// Timers further out than this are effectively never, and this keeps
+/// them from overflowing when added to the current time.
+const MAX_TIMER: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
 struct SingleshotSleep<T> {
     enabled: bool,
     sleep: Pin<Box<T>>,
@@ -74,17 +96,162 @@ pub struct DaemonChannels {
     pub source_snapshots_receiver: tokio::sync::watch::Receiver<Vec<ObservableSourceState>>,
     pub server_data_receiver: tokio::sync::watch::Receiver<Vec<ServerData>>,
     pub system_snapshot_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
+    pub command_sender: mpsc::Sender<SystemCommand>,
+    /// Stops the system task, including its sources and servers, once cancelled
+    pub(crate) cancel: CancellationToken,
+}
+
+/// Changes to the set of sources requested while the daemon is running
+#[derive(Debug)]
+pub enum SystemCommand {
+    /// Add sources from the given configuration. The reply is sent once the
+    /// first source for this configuration has been created, and is dropped
+    /// when the configuration could not be used.
+    AddSource {
+        config: NtpSourceConfig,
+        reply: oneshot::Sender<SourceId>,
+    },
+    /// Remove a source, the reply indicates whether the source existed
+    RemoveSource {
+        id: SourceId,
+        reply: oneshot::Sender<bool>,
+    },
+    /// Get the raw filter buffers of a source
+    ObserveSourceFilter {
+        id: SourceId,
+        reply: oneshot::Sender<Option<ObservableSourceFilter>>,
+    },
+    /// Get the estimates of a source after each of its recent measurements
+    ObserveSourceHistory {
+        id: SourceId,
+        reply: oneshot::Sender<Option<Vec<SourceHistoryEntry>>>,
+    },
+    /// Get everything known about a source, for bug reports
+    ObserveSourceDebug {
+        id: SourceId,
+        reply: oneshot::Sender<Option<SourceDebugDump>>,
+    },
+    /// Replace the single step panic threshold, replying once the controller
+    /// uses the new threshold
+    SetStepPanicThreshold {
+        threshold: StepThreshold,
+        reply: oneshot::Sender<()>,
+    },
+    /// Clear the running total of clock steps, replying once it is cleared
+    ResetAccumulatedSteps { reply: oneshot::Sender<()> },
+    /// Synchronize to only the given source, or return to automatic selection
+    /// for None. The reply indicates whether the source existed.
+    PinSource {
+        id: Option<SourceId>,
+        reply: oneshot::Sender<bool>,
+    },
+    /// Clear the response latency histograms of all servers
+    ResetServerLatency { reply: oneshot::Sender<()> },
 }
 
 /// Spawn the NTP daemon
 pub async fn spawn(
+    config: &Config,
+    clock_config: ClockConfig,
+    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+) -> std::io::Result<(JoinHandle<std::io::Result<()>>, DaemonChannels)> {
+    let (mut system, channels) = prepare(
+        config.synchronization,
+        config.source_defaults,
+        clock_config,
+        SystemStateConfig {
+            clock_state: &config.clock_state,
+            leap_seconds: &config.leap_seconds,
+            source_history_length: config.observability.source_history_length,
+        },
+        LookupLimiter::new(&config.dns),
+        keyset,
+    )
+    .await?;
+
+    for source_config in &config.sources {
+        system.add_source_config(source_config).map_err(|e| {
+            tracing::error!("Could not spawn source: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, e)
+        })?;
+    }
+
+    if config.dhcp.use_dhcp_ntp_servers {
+        let static_addresses = config
+            .sources
+            .iter()
+            .filter_map(|config| match config {
+                NtpSourceConfig::Standard(cfg) => Some(cfg.address.0.clone()),
+                _ => None,
+            })
+            .collect();
+        let spawner = DhcpSpawner::new(
+            config.dhcp.clone(),
+            static_addresses,
+            system.lookups.clone(),
+        );
+        system.add_spawner(spawner).map_err(|e| {
+            tracing::error!("Could not spawn DHCP sources: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, e)
+        })?;
+    }
+
+    Ok((start(system, &config.servers).await, channels))
+}
+
+/// What the system task keeps besides the synchronization state: the state
+/// it restores on startup, and the history it keeps for observation
+#[derive(Debug, Clone, Copy)]
+pub struct SystemStateConfig<'a> {
+    pub clock_state: &'a ClockStateConfig,
+    pub leap_seconds: &'a LeapSecondsConfig,
+    /// Number of measurements kept per source for the observer
+    pub source_history_length: usize,
+}
+
+/// Spawn the NTP daemon with sources for addresses that were already
+/// resolved, for applications that handle service discovery themselves.
+/// No DNS lookups are done for these sources, not even when they become
+/// unreachable. The daemon itself always uses [`spawn`].
+pub async fn spawn_with_resolved<C: NtpClock + Sync>(
     synchronization_config: SynchronizationConfig,
     source_defaults_config: SourceDefaultsConfig,
-    clock_config: ClockConfig,
-    source_configs: &[NtpSourceConfig],
+    clock_config: ClockConfig<C>,
+    state_config: SystemStateConfig<'_>,
+    resolved_sources: &[(NormalizedAddress, SocketAddr)],
     server_configs: &[ServerConfig],
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
 ) -> std::io::Result<(JoinHandle<std::io::Result<()>>, DaemonChannels)> {
+    let (mut system, channels) = prepare(
+        synchronization_config,
+        source_defaults_config,
+        clock_config,
+        state_config,
+        LookupLimiter::default(),
+        keyset,
+    )
+    .await?;
+
+    for (address, addr) in resolved_sources {
+        let spawner = ResolvedSpawner::new(address.clone(), canonical_socket_addr(*addr));
+        system.add_spawner(spawner).map_err(|e| {
+            tracing::error!("Could not spawn source: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, e)
+        })?;
+    }
+
+    Ok((start(system, server_configs).await, channels))
+}
+
+/// Create the system task, with its clock state and leap seconds loaded
+async fn prepare<C: NtpClock + Sync>(
+    synchronization_config: SynchronizationConfig,
+    source_defaults_config: SourceDefaultsConfig,
+    clock_config: ClockConfig<C>,
+    state_config: SystemStateConfig<'_>,
+    lookups: LookupLimiter,
+    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+) -> std::io::Result<(SystemTask<C, tokio::time::Sleep>, DaemonChannels)> {
     let ip_list = super::local_ip_provider::spawn()?;
 
     let (mut system, channels) = SystemTask::new(
@@ -96,57 +263,62 @@ pub async fn spawn(
         keyset,
         ip_list,
     );
-
-    for source_config in source_configs {
-        match source_config {
-            NtpSourceConfig::Standard(cfg) => {
-                system
-                    .add_spawner(StandardSpawner::new(cfg.clone()))
-                    .map_err(|e| {
-                        tracing::error!("Could not spawn source: {}", e);
-                        std::io::Error::new(std::io::ErrorKind::Other, e)
-                    })?;
-            }
-            NtpSourceConfig::Nts(cfg) => {
-                system
-                    .add_spawner(NtsSpawner::new(cfg.clone()))
-                    .map_err(|e| {
-                        tracing::error!("Could not spawn source: {}", e);
-                        std::io::Error::new(std::io::ErrorKind::Other, e)
-                    })?;
-            }
-            NtpSourceConfig::Pool(cfg) => {
-                system
-                    .add_spawner(PoolSpawner::new(cfg.clone()))
-                    .map_err(|e| {
-                        tracing::error!("Could not spawn source: {}", e);
-                        std::io::Error::new(std::io::ErrorKind::Other, e)
-                    })?;
-            }
-            #[cfg(feature = "unstable_nts-pool")]
-            NtpSourceConfig::NtsPool(cfg) => {
-                system
-                    .add_spawner(NtsPoolSpawner::new(cfg.clone()))
-                    .map_err(|e| {
-                        tracing::error!("Could not spawn source: {}", e);
-                        std::io::Error::new(std::io::ErrorKind::Other, e)
-                    })?;
+    system.lookups = lookups;
+    system.source_history_length = state_config.source_history_length;
+
+    if let Some(path) = &state_config.clock_state.storage_path {
+        match StateFile::load(path).await {
+            Ok(state) => system.set_clock_state(
+                ClockStateStorage {
+                    path: path.clone(),
+                    state,
+                    key: clock_config.clock_key,
+                },
+                Duration::from_secs(state_config.clock_state.store_interval),
+            ),
+            Err(e) => {
+                // Don't overwrite a file we could not read, it might be from a newer version
+                warn!(error = ?e, ?path, "Could not load clock state, clock state will not be stored");
             }
         }
     }
 
+    if let Some(path) = &state_config.leap_seconds.file {
+        match tokio::fs::read_to_string(path).await {
+            Ok(data) => match LeapSecondsFile::parse(&data) {
+                Ok(leap_seconds) => system.set_leap_seconds(leap_seconds),
+                Err(e) => warn!(error = %e, ?path, "Invalid leap seconds file, ignoring it"),
+            },
+            Err(e) => warn!(error = ?e, ?path, "Could not read leap seconds file"),
+        }
+    }
+
+    Ok((system, channels))
+}
+
+/// Start the servers and run the system task
+async fn start<C: NtpClock + Sync>(
+    mut system: SystemTask<C, tokio::time::Sleep>,
+    server_configs: &[ServerConfig],
+) -> JoinHandle<std::io::Result<()>> {
     for server_config in server_configs.iter() {
         system.add_server(server_config.to_owned()).await;
     }
 
-    let handle = tokio::spawn(async move {
+    tokio::spawn(async move {
         let sleep =
             SingleshotSleep::new_disabled(tokio::time::sleep_until(tokio::time::Instant::now()));
         tokio::pin!(sleep);
-        system.run(sleep).await
-    });
+        let result = system.run(sleep).await;
+        system.shutdown().await;
+        result
+    })
+}
 
-    Ok((handle, channels))
+struct ClockStateStorage {
+    path: PathBuf,
+    state: StateFile,
+    key: ClockKey,
 }
 
 struct SystemSpawnerData {
@@ -166,16 +338,32 @@ struct SystemTask<C: NtpClock, T: Wait> {
     ip_list: tokio::sync::watch::Receiver<Arc<[IpAddr]>>,
 
     msg_for_system_rx: mpsc::Receiver<MsgForSystem>,
+    command_rx: mpsc::Receiver<SystemCommand>,
     spawn_tx: mpsc::Sender<SpawnEvent>,
     spawn_rx: mpsc::Receiver<SpawnEvent>,
 
     sources: HashMap<SourceId, SourceState>,
     servers: Vec<ServerData>,
+    server_tasks: Vec<JoinHandle<()>>,
+    // stops all servers once cancelled
+    servers_cancel: CancellationToken,
+    // stops the task itself once cancelled
+    cancel: CancellationToken,
     spawners: Vec<SystemSpawnerData>,
+    // shared by all spawners, so the limit applies across all sources
+    lookups: LookupLimiter,
+    // sources added through a command, waiting for their first source
+    pending_adds: HashMap<SpawnerId, oneshot::Sender<SourceId>>,
 
     source_channels: SourceChannels,
     clock: C,
 
+    clock_state: Option<ClockStateStorage>,
+    clock_state_interval: Duration,
+
+    // number of measurements of each source kept in its history
+    source_history_length: usize,
+
     // which timestamps to use (this is a hint, OS or hardware may ignore)
     timestamp_mode: TimestampMode,
 
@@ -210,6 +398,8 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         let (msg_for_system_sender, msg_for_system_receiver) =
             tokio::sync::mpsc::channel(MESSAGE_BUFFER_SIZE);
         let (spawn_tx, spawn_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (command_sender, command_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let cancel = CancellationToken::new();
 
         // Build System and its channels
         (
@@ -225,17 +415,26 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                 ip_list,
 
                 msg_for_system_rx: msg_for_system_receiver,
+                command_rx,
                 spawn_rx,
                 spawn_tx,
 
                 sources: Default::default(),
                 servers: Default::default(),
+                server_tasks: Default::default(),
+                servers_cancel: CancellationToken::new(),
+                cancel: cancel.clone(),
                 spawners: Default::default(),
+                lookups: Default::default(),
+                pending_adds: Default::default(),
                 source_channels: SourceChannels {
                     msg_for_system_sender,
                     system_snapshot_receiver: system_snapshot_receiver.clone(),
                 },
                 clock,
+                clock_state: None,
+                clock_state_interval: MAX_TIMER,
+                source_history_length: 0,
                 timestamp_mode,
                 interface,
             },
@@ -243,10 +442,52 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                 source_snapshots_receiver,
                 server_data_receiver,
                 system_snapshot_receiver,
+                command_sender,
+                cancel,
             },
         )
     }
 
+    /// Announce leap seconds from the given leap seconds file
+    fn set_leap_seconds(&mut self, leap_seconds: LeapSecondsFile) {
+        match self.clock.now() {
+            Ok(now) if leap_seconds.is_expired(now) => {
+                warn!("Leap seconds file has expired, please update it. Until then the leap indicators of sources are used");
+            }
+            Ok(_) => info!("Announcing leap seconds from the leap seconds file"),
+            Err(e) => warn!(error = ?e, "Could not read the time to check the leap seconds file"),
+        }
+        self.system.set_leap_seconds(leap_seconds);
+    }
+
+    /// Restore the clock frequency from a persisted clock state, and store
+    /// the clock state every `interval` from now on.
+    fn set_clock_state(&mut self, storage: ClockStateStorage, interval: Duration) {
+        if let Some(ClockState { frequency }) = storage.state.clock(&storage.key) {
+            info!(clock = %storage.key, "restoring frequency from clock state");
+            self.system.restore_frequency(frequency);
+        }
+        self.clock_state = Some(storage);
+        self.clock_state_interval = interval.min(MAX_TIMER);
+    }
+
+    async fn store_clock_state(&mut self) {
+        let Some(frequency) = self.system.frequency() else {
+            // not controlling the clock (yet), nothing to store
+            return;
+        };
+        let Some(storage) = self.clock_state.as_mut() else {
+            return;
+        };
+
+        storage
+            .state
+            .set_clock(storage.key.clone(), ClockState { frequency });
+        if let Err(e) = storage.state.store(&storage.path).await {
+            warn!(error = ?e, path = ?storage.path, "Could not store clock state");
+        }
+    }
+
     fn add_spawner(
         &mut self,
         spawner: impl Spawner + Send + Sync + 'static,
@@ -261,9 +502,101 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         Ok(id)
     }
 
+    fn add_source_config(&mut self, config: &NtpSourceConfig) -> Result<SpawnerId, C::Error> {
+        let lookups = self.lookups.clone();
+        match config {
+            NtpSourceConfig::Standard(cfg) => {
+                self.add_spawner(StandardSpawner::new(cfg.clone(), lookups))
+            }
+            NtpSourceConfig::Nts(cfg) => self.add_spawner(NtsSpawner::new(cfg.clone(), lookups)),
+            NtpSourceConfig::Pool(cfg) => self.add_spawner(PoolSpawner::new(cfg.clone(), lookups)),
+            #[cfg(feature = "unstable_nts-pool")]
+            NtpSourceConfig::NtsPool(cfg) => {
+                self.add_spawner(NtsPoolSpawner::new(cfg.clone(), lookups))
+            }
+            NtpSourceConfig::Nmea(cfg) => self.add_nmea_source(cfg),
+        }
+    }
+
+    /// A receiver on a local device has nothing to resolve, so it gets no
+    /// spawner and its source is created right away. The returned id is not
+    /// that of a running spawner.
+    fn add_nmea_source(&mut self, config: &NmeaSourceConfig) -> Result<SpawnerId, C::Error> {
+        let spawner_id = SpawnerId::new();
+        let source_id = SourceId::new();
+        info!(source_id=?source_id, path=?config.path, "new NMEA source");
+        self.system.handle_source_create(
+            source_id,
+            SourceOptions {
+                weight: config.weight.get(),
+                ..SourceOptions::default()
+            },
+        )?;
+
+        let cancel = CancellationToken::new();
+        let (debug_requests, debug_requests_receiver) = mpsc::channel(1);
+        let task = NmeaSourceTask::spawn(
+            source_id,
+            config.path.clone(),
+            config.offset,
+            self.clock.clone(),
+            self.source_channels.msg_for_system_sender.clone(),
+            debug_requests_receiver,
+            cancel.clone(),
+        );
+        self.sources.insert(
+            source_id,
+            SourceState {
+                name: config.path.display().to_string(),
+                source_id,
+                spawner_id,
+                task,
+                cancel,
+                history: VecDeque::new(),
+                debug_requests,
+            },
+        );
+
+        // Don't care if there is no receiver
+        let _ = self
+            .source_snapshots_sender
+            .send(self.observe_sources().collect());
+
+        Ok(spawner_id)
+    }
+
     async fn run(&mut self, mut wait: Pin<&mut SingleshotSleep<T>>) -> std::io::Result<()> {
+        let clock_state_timer = tokio::time::sleep(self.clock_state_interval);
+        tokio::pin!(clock_state_timer);
+        let holdover_timer = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(holdover_timer);
+        let leap_seconds_timer = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(leap_seconds_timer);
+        let orphan_wait_timer = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(orphan_wait_timer);
+
         loop {
+            let holdover = self.system.holdover_timer();
+            if let Some(duration) = holdover {
+                holdover_timer
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + duration);
+            }
+            let leap_seconds = self.system.leap_seconds_timer().map(|d| d.min(MAX_TIMER));
+            if let Some(duration) = leap_seconds {
+                leap_seconds_timer
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + duration);
+            }
+            let orphan_wait = self.system.orphan_wait_timer();
+            if let Some(duration) = orphan_wait {
+                orphan_wait_timer
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + duration);
+            }
+
             tokio::select! {
+                () = self.cancel.cancelled() => break,
                 opt_msg_for_system = self.msg_for_system_rx.recv() => {
                     match opt_msg_for_system {
                         None => {
@@ -289,6 +622,9 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                         }
                     }
                 }
+                Some(command) = self.command_rx.recv() => {
+                    self.handle_command(command).await?;
+                }
                 _ = self.ip_list.changed(), if self.ip_list.has_changed().is_ok() => {
                     self.system.update_ip_list(self.ip_list.borrow_and_update().clone());
                 }
@@ -296,10 +632,31 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                     let timer = self.system.handle_timer();
                     self.handle_state_update(timer, &mut wait);
                 }
+                () = &mut holdover_timer, if holdover.is_some() => {
+                    self.system.handle_holdover_timer();
+                    self.handle_state_update(None, &mut wait);
+                }
+                () = &mut leap_seconds_timer, if leap_seconds.is_some() => {
+                    self.system.handle_leap_seconds_timer();
+                    self.handle_state_update(None, &mut wait);
+                }
+                () = &mut orphan_wait_timer, if orphan_wait.is_some() => {
+                    self.system
+                        .handle_orphan_wait_timer()
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    self.handle_state_update(None, &mut wait);
+                }
+                () = &mut clock_state_timer, if self.clock_state.is_some() => {
+                    self.store_clock_state().await;
+                    clock_state_timer
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + self.clock_state_interval);
+                }
             }
         }
 
-        // the channel closed and has no more messages in it
+        // the channel closed and has no more messages in it, or the task was
+        // cancelled
         Ok(())
     }
 
@@ -318,6 +675,110 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         }
     }
 
+    async fn handle_command(&mut self, command: SystemCommand) -> std::io::Result<()> {
+        match command {
+            SystemCommand::AddSource { config, reply } => {
+                // sources added at runtime get the same checks as configured
+                // ones, and dropping the reply tells the requester the source
+                // was not added
+                let issues = config.validate();
+                if !issues.is_empty() {
+                    for issue in issues {
+                        warn!("Rejected source: {}", issue.message);
+                    }
+                    return Ok(());
+                }
+                if config.is_pinned() && self.system.pinned_source().is_some() {
+                    warn!("Rejected source: another source is already pinned, at most one source can be pinned.");
+                    return Ok(());
+                }
+
+                self.prune_pending_adds();
+                match self.add_source_config(&config) {
+                    Ok(spawner_id) => {
+                        // sources without a spawner already exist
+                        match self.sources.values().find(|s| s.spawner_id == spawner_id) {
+                            Some(state) => {
+                                let _ = reply.send(state.source_id);
+                            }
+                            None => {
+                                self.pending_adds.insert(spawner_id, reply);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        // dropping the reply tells the requester the source was not added
+                        tracing::error!("Could not spawn source: {}", e);
+                    }
+                }
+            }
+            SystemCommand::RemoveSource { id, reply } => {
+                let removed = self
+                    .handle_source_removal(id, SourceRemovalReason::Removed)
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                let _ = reply.send(removed);
+            }
+            SystemCommand::ObserveSourceFilter { id, reply } => {
+                let _ = reply.send(self.system.observe_source_filter(id));
+            }
+            SystemCommand::ObserveSourceHistory { id, reply } => {
+                let history = self
+                    .sources
+                    .get(&id)
+                    .map(|state| state.history.iter().copied().collect());
+                let _ = reply.send(history);
+            }
+            SystemCommand::ObserveSourceDebug { id, reply } => {
+                self.observe_source_debug(id, reply);
+            }
+            SystemCommand::SetStepPanicThreshold { threshold, reply } => {
+                let previous = self.system.update_single_step_panic_threshold(threshold);
+                let loosened = match (previous.backward, threshold.backward) {
+                    (Some(previous), Some(new)) => new > previous,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                if loosened {
+                    warn!(
+                        ?threshold,
+                        ?previous,
+                        "Single step panic threshold now allows larger backward steps"
+                    );
+                } else {
+                    info!(?threshold, ?previous, "Single step panic threshold changed");
+                }
+                let _ = reply.send(());
+            }
+            SystemCommand::ResetAccumulatedSteps { reply } => {
+                info!("Accumulated step total reset");
+                self.system.reset_accumulated_steps();
+                let _ = self
+                    .system_snapshot_sender
+                    .send(self.system.system_snapshot());
+                let _ = reply.send(());
+            }
+            SystemCommand::PinSource { id, reply } => {
+                if id.map_or(false, |id| !self.sources.contains_key(&id)) {
+                    let _ = reply.send(false);
+                    return Ok(());
+                }
+                info!(source_id=?id, "Pinned source changed");
+                self.system
+                    .pin_source(id)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                let _ = reply.send(true);
+            }
+            SystemCommand::ResetServerLatency { reply } => {
+                for server in &self.servers {
+                    server.stats.response_latency.reset();
+                }
+                let _ = reply.send(());
+            }
+        }
+        Ok(())
+    }
+
     async fn handle_source_update(
         &mut self,
         msg: MsgForSystem,
@@ -325,6 +786,11 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
     ) -> std::io::Result<()> {
         tracing::debug!(?msg, "updating source");
 
+        if !self.sources.contains_key(&msg.source_id()) {
+            // the source was removed, but still had messages underway
+            return Ok(());
+        }
+
         match msg {
             MsgForSystem::MustDemobilize(index) => {
                 if let Err(e) = self.handle_source_demobilize(index).await {
@@ -332,10 +798,14 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                 };
             }
             MsgForSystem::SourceUpdate(index, update) => {
+                let has_measurement = update.has_measurement();
                 match self.system.handle_source_update(index, update) {
                     Err(e) => unreachable!("Could not process source measurement: {}", e),
                     Ok(timer) => self.handle_state_update(timer, wait),
                 }
+                if has_measurement {
+                    self.record_source_history(index);
+                }
             }
             MsgForSystem::NetworkIssue(index) => {
                 self.handle_source_network_issue(index).await?;
@@ -402,6 +872,37 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         Ok(())
     }
 
+    /// Remove a source on request of the operator or its spawner. Returns
+    /// whether the source existed.
+    async fn handle_source_removal(
+        &mut self,
+        index: SourceId,
+        reason: SourceRemovalReason,
+    ) -> Result<bool, C::Error> {
+        let Some(state) = self.sources.remove(&index) else {
+            return Ok(false);
+        };
+        info!(source_id=?index, name=%state.name, ?reason, "removing source");
+        state.cancel.cancel();
+        self.system.handle_source_remove(index)?;
+
+        let opt_spawner = self.spawners.iter().find(|s| s.id == state.spawner_id);
+        if let Some(spawner) = opt_spawner {
+            spawner
+                .notify_tx
+                .send(SystemEvent::source_removed(state.source_id, reason))
+                .await
+                .expect("Could not notify spawner");
+        }
+
+        // Don't care if there is no receiver
+        let _ = self
+            .source_snapshots_sender
+            .send(self.observe_sources().collect());
+
+        Ok(true)
+    }
+
     async fn handle_source_demobilize(&mut self, index: SourceId) -> Result<(), C::Error> {
         self.system.handle_source_remove(index)?;
 
@@ -430,17 +931,27 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
     ) -> Result<SourceId, C::Error> {
         let source_id = params.id;
         info!(source_id=?source_id, addr=?params.addr, spawner=?spawner_id, "new source");
-        self.sources.insert(
+        let options = &mut params.options;
+        self.system.handle_source_create(
             source_id,
-            SourceState {
-                source_address: params.normalized_addr.clone(),
-                source_id,
-                spawner_id,
+            SourceOptions {
+                weight: options.weight.get(),
+                trust_leap: options.trust_leap,
+                no_select: options.no_select,
+                no_step: options.no_step,
+                ignore_root: options.ignore_root,
             },
-        );
-        self.system.handle_source_create(source_id)?;
+        )?;
+        if options.pin {
+            self.system.pin_source(Some(source_id))?;
+        }
+        if let Some(range) = options.stratum_range.take() {
+            self.system.set_source_stratum_range(source_id, range);
+        }
 
-        SourceTask::spawn(
+        let cancel = CancellationToken::new();
+        let (debug_requests, debug_requests_receiver) = mpsc::channel(1);
+        let task = SourceTask::spawn(
             source_id,
             params.addr,
             self.interface,
@@ -450,8 +961,27 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
             params.protocol_version,
             self.source_defaults_config,
             params.nts.take(),
+            params.options.on_route_loss,
+            debug_requests_receiver,
+            cancel.clone(),
+        );
+        self.sources.insert(
+            source_id,
+            SourceState {
+                name: params.normalized_addr.to_string(),
+                source_id,
+                spawner_id,
+                task,
+                cancel,
+                history: VecDeque::new(),
+                debug_requests,
+            },
         );
 
+        if let Some(reply) = self.pending_adds.remove(&spawner_id) {
+            let _ = reply.send(source_id);
+        }
+
         // Don't care if there is no receiver
         let _ = self
             .source_snapshots_sender
@@ -470,10 +1000,25 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         Ok(source_id)
     }
 
+    /// Forget the add requests of requesters that stopped waiting for their
+    /// source, such as after their timeout
+    fn prune_pending_adds(&mut self) {
+        self.pending_adds.retain(|_, reply| !reply.is_closed());
+    }
+
     async fn handle_spawn_event(&mut self, event: SpawnEvent) -> Result<(), C::Error> {
+        self.prune_pending_adds();
         match event.action {
             SpawnAction::Create(params) => {
-                self.create_source(event.id, params).await?;
+                if let Err(e) = self.create_source(event.id, params).await {
+                    // dropping the reply tells the requester the source was not added
+                    self.pending_adds.remove(&event.id);
+                    return Err(e);
+                }
+            }
+            SpawnAction::Remove(id) => {
+                self.handle_source_removal(id, SourceRemovalReason::Expired)
+                    .await?;
             }
         }
         Ok(())
@@ -485,27 +1030,138 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
             stats: stats.clone(),
             config: config.clone(),
         });
-        ServerTask::spawn(
-            config,
-            stats,
-            self.source_channels.system_snapshot_receiver.clone(),
-            self.keyset.clone(),
-            self.clock.clone(),
-            NETWORK_WAIT_PERIOD,
-        );
+        let channels = ServerChannels {
+            system_receiver: self.source_channels.system_snapshot_receiver.clone(),
+            keyset: self.keyset.clone(),
+            local_ips: self.ip_list.clone(),
+        };
+        let task = if config.track_interfaces {
+            ServerTask::spawn_per_address(
+                config,
+                stats,
+                channels,
+                self.clock.clone(),
+                NETWORK_WAIT_PERIOD,
+                self.servers_cancel.clone(),
+            )
+        } else {
+            ServerTask::spawn(
+                config,
+                stats,
+                channels,
+                self.clock.clone(),
+                NETWORK_WAIT_PERIOD,
+                self.servers_cancel.clone(),
+            )
+        };
+        self.server_tasks.push(task);
         let _ = self.server_data_sender.send(self.servers.clone());
     }
 
+    /// Stop all sources and servers, waiting for them to clean up
+    async fn shutdown(&mut self) {
+        // Sources may be waiting to send us a message, which would keep them
+        // from noticing they were cancelled
+        self.msg_for_system_rx.close();
+
+        for state in self.sources.values() {
+            state.cancel.cancel();
+        }
+        self.servers_cancel.cancel();
+
+        for (_, state) in self.sources.drain() {
+            let _ = state.task.await;
+        }
+        for task in self.server_tasks.drain(..) {
+            let _ = task.await;
+        }
+    }
+
+    /// Remember the current estimates of a source after a measurement,
+    /// dropping the oldest entry once the history is full
+    fn record_source_history(&mut self, index: SourceId) {
+        if self.source_history_length == 0 {
+            return;
+        }
+        let Some((snapshot, timedata)) = self.system.observe_source(index) else {
+            return;
+        };
+        let Some(state) = self.sources.get_mut(&index) else {
+            return;
+        };
+
+        if state.history.len() >= self.source_history_length {
+            state.history.pop_front();
+        }
+        state.history.push_back(SourceHistoryEntry {
+            timestamp: timedata.last_update,
+            offset: timedata.offset,
+            delay: timedata.delay,
+            jitter: timedata.uncertainty,
+            poll_interval: snapshot.poll_interval,
+        });
+    }
+
+    /// Ask the task of a source for its state, and complete that with what
+    /// the system knows about the source. The reply is sent from a separate
+    /// task, as the source task may itself be waiting for the system.
+    fn observe_source_debug(&self, id: SourceId, reply: oneshot::Sender<Option<SourceDebugDump>>) {
+        let Some(state) = self.sources.get(&id) else {
+            let _ = reply.send(None);
+            return;
+        };
+        let (source_reply, source_response) = oneshot::channel();
+        if state.debug_requests.try_send(source_reply).is_err() {
+            let _ = reply.send(None);
+            return;
+        }
+
+        let timedata = self.system.observe_source(id).map(|(_, timedata)| timedata);
+        let filter = self.system.observe_source_filter(id);
+        let history = state.history.iter().copied().collect();
+        tokio::spawn(async move {
+            let dump = source_response.await.ok().map(|dump| SourceDebugDump {
+                timedata,
+                filter,
+                history,
+                ..dump
+            });
+            let _ = reply.send(dump);
+        });
+    }
+
+    /// Observable state of all sources, ordered by their id so consumers
+    /// see the same order on every update
     fn observe_sources(&self) -> impl Iterator<Item = ObservableSourceState> + '_ {
-        self.sources.iter().map(|(index, data)| {
-            if let Some((snapshot, timedata)) = self.system.observe_source(*index) {
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_unstable_by_key(|(index, _)| **index);
+        sources.into_iter().map(|(index, data)| {
+            if let Some(snapshot) = self
+                .system
+                .source_snapshot(*index)
+                .filter(|snapshot| snapshot.incompatible)
+            {
+                ObservableSourceState::Incompatible {
+                    name: data.name.clone(),
+                    address: snapshot.source_addr.to_string(),
+                    id: data.source_id,
+                }
+            } else if let Some((snapshot, timedata)) = self.system.observe_source(*index) {
                 ObservableSourceState::Observable(ObservedSourceState {
                     timedata,
                     unanswered_polls: snapshot.reach.unanswered_polls(),
                     poll_interval: snapshot.poll_interval,
-                    name: data.source_address.to_string(),
+                    name: data.name.clone(),
                     address: snapshot.source_addr.to_string(),
                     id: data.source_id,
+                    bogus_packets: snapshot.bogus_packets,
+                    duplicate_packets: snapshot.duplicate_packets,
+                    packets: snapshot.packets,
+                    last_reference_change: snapshot.last_reference_change,
+                    send_timestamped: snapshot.send_timestamped,
+                    remote_min_poll_interval: snapshot.remote_min_poll_interval,
+                    reachability_pct_1h: snapshot.reachability_pct_1h,
+                    reachability_pct_24h: snapshot.reachability_pct_24h,
                 })
             } else {
                 ObservableSourceState::Nothing
@@ -516,9 +1172,17 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
 
 #[derive(Debug)]
 struct SourceState {
-    source_address: NormalizedAddress,
+    /// Address or device the source was configured with
+    name: String,
     spawner_id: SpawnerId,
     source_id: SourceId,
+    task: JoinHandle<()>,
+    /// Stops the task of the source once cancelled
+    cancel: CancellationToken,
+    /// Estimates after the most recent measurements, oldest first
+    history: VecDeque<SourceHistoryEntry>,
+    /// Requests for the debug state of the source, handled by its task
+    debug_requests: mpsc::Sender<oneshot::Sender<SourceDebugDump>>,
 }
 
 #[derive(Debug, Clone)]
@@ -529,15 +1193,262 @@ pub struct ServerData {
 
 #[cfg(test)]
 mod tests {
+    use std::{io::Cursor, sync::Mutex};
+
     use ntp_proto::{
-        source_snapshot, KeySetProvider, Measurement, NtpDuration, NtpInstant, NtpLeapIndicator,
-        NtpSourceUpdate, NtpTimestamp,
+        source_snapshot, KeySetProvider, Measurement, NoCipher, NtpDuration, NtpInstant,
+        NtpLeapIndicator, NtpPacket, NtpSource, NtpSourceSnapshot, NtpSourceUpdate, NtpTimestamp,
+        ProtocolVersion, ReferenceId, SelectionStatus, TimeSnapshot,
     };
+    use tokio::time::Sleep;
 
     use super::super::spawn::dummy::DummySpawner;
 
     use super::*;
 
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum ClockCall {
+        SetFrequency(f64),
+        Step(NtpDuration),
+    }
+
+    #[derive(Debug, Default)]
+    struct MockClockState {
+        now: NtpTimestamp,
+        calls: Vec<ClockCall>,
+    }
+
+    /// Clock that only advances when told to, and records how it was steered
+    #[derive(Debug, Clone, Default)]
+    struct MockClock {
+        state: Arc<Mutex<MockClockState>>,
+    }
+
+    impl MockClock {
+        fn advance(&self, duration: NtpDuration) -> NtpTimestamp {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            state.now
+        }
+
+        fn calls(&self) -> Vec<ClockCall> {
+            self.state.lock().unwrap().calls.clone()
+        }
+    }
+
+    impl NtpClock for MockClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Ok(self.state.lock().unwrap().now)
+        }
+
+        fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(ClockCall::SetFrequency(freq));
+            Ok(state.now)
+        }
+
+        fn step_clock(&self, offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(ClockCall::Step(offset));
+            state.now += offset;
+            Ok(state.now)
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Runs a complete system task with mock sources, which don't poll
+    /// anything themselves. Tests script the messages of the sources, and
+    /// observe the system through its channels and the mock clock.
+    struct SystemHarness {
+        clock: MockClock,
+        channels: DaemonChannels,
+        msg_for_system_sender: mpsc::Sender<MsgForSystem>,
+        sources: Vec<SourceId>,
+        monotime: NtpInstant,
+        task: JoinHandle<()>,
+    }
+
+    /// Length of the source histories kept by the harness
+    const HARNESS_HISTORY_LENGTH: usize = 3;
+
+    impl SystemHarness {
+        async fn new(synchronization_config: SynchronizationConfig, sources: usize) -> Self {
+            let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+            let (_, ip_list) = tokio::sync::watch::channel([].into_iter().collect());
+            let clock = MockClock::default();
+
+            let (mut system, channels) = SystemTask::<_, Sleep>::new(
+                clock.clone(),
+                None,
+                TimestampMode::KernelRecv,
+                synchronization_config,
+                SourceDefaultsConfig::default(),
+                keyset,
+                ip_list,
+            );
+            system.source_history_length = HARNESS_HISTORY_LENGTH;
+            let msg_for_system_sender = system.source_channels.msg_for_system_sender.clone();
+
+            let spawner_id = system.add_spawner(DummySpawner::empty()).unwrap();
+            let sources = (0..sources)
+                .map(|i| {
+                    let id = SourceId::new();
+                    system
+                        .system
+                        .handle_source_create(id, SourceOptions::default())
+                        .unwrap();
+                    let (debug_requests, mut debug_requests_receiver) =
+                        mpsc::channel::<oneshot::Sender<SourceDebugDump>>(1);
+                    // stands in for the source task, answering debug requests
+                    let task = tokio::spawn(async move {
+                        while let Some(reply) = debug_requests_receiver.recv().await {
+                            let (source, _) = NtpSource::new(
+                                SocketAddr::from(([127, 0, 0, 1], 123)),
+                                SourceDefaultsConfig::default(),
+                                ProtocolVersion::default(),
+                            );
+                            let _ = reply.send(SourceDebugDump {
+                                source: source.debug_dump(),
+                                last_send_timestamp: None,
+                                route_lost: false,
+                                timedata: None,
+                                filter: None,
+                                history: vec![],
+                            });
+                        }
+                    });
+                    system.sources.insert(
+                        id,
+                        SourceState {
+                            name: format!("127.0.0.{i}:123"),
+                            spawner_id,
+                            source_id: id,
+                            task,
+                            cancel: CancellationToken::new(),
+                            history: VecDeque::new(),
+                            debug_requests,
+                        },
+                    );
+                    id
+                })
+                .collect();
+
+            let task = tokio::spawn(async move {
+                let wait = SingleshotSleep::new_disabled(tokio::time::sleep(Duration::ZERO));
+                tokio::pin!(wait);
+                system.run(wait).await.unwrap();
+            });
+
+            SystemHarness {
+                clock,
+                channels,
+                msg_for_system_sender,
+                sources,
+                monotime: NtpInstant::now(),
+                task,
+            }
+        }
+
+        /// Deliver a message to the system as if it came from a source, and
+        /// wait until the system has processed it.
+        async fn send(&mut self, msg: MsgForSystem) {
+            self.msg_for_system_sender.send(msg).await.unwrap();
+            self.channels
+                .source_snapshots_receiver
+                .changed()
+                .await
+                .unwrap();
+        }
+
+        /// A measurement of the given offset one second after the previous
+        fn measurement(&mut self, index: usize, offset: f64) -> MsgForSystem {
+            self.monotime = self.monotime + Duration::from_secs(1);
+            let localtime = self.clock.advance(NtpDuration::from_seconds(1.0));
+
+            MsgForSystem::SourceUpdate(
+                self.sources[index],
+                NtpSourceUpdate::measurement(
+                    NtpSourceSnapshot {
+                        stratum: 1,
+                        source_id: ReferenceId::from_ip(IpAddr::from([127, 0, 0, index as u8])),
+                        ..source_snapshot()
+                    },
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(offset),
+                        transmit_timestamp: NtpTimestamp::default(),
+                        receive_timestamp: NtpTimestamp::default(),
+                        localtime,
+                        monotime: self.monotime,
+
+                        stratum: 1,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                ),
+            )
+        }
+
+        fn system_snapshot(&self) -> SystemSnapshot {
+            *self.channels.system_snapshot_receiver.borrow()
+        }
+
+        fn source_snapshots(&self) -> Vec<ObservableSourceState> {
+            self.channels.source_snapshots_receiver.borrow().clone()
+        }
+
+        async fn source_history(&self, index: usize) -> Option<Vec<SourceHistoryEntry>> {
+            let (reply, response) = oneshot::channel();
+            self.channels
+                .command_sender
+                .send(SystemCommand::ObserveSourceHistory {
+                    id: self.sources[index],
+                    reply,
+                })
+                .await
+                .unwrap();
+            response.await.unwrap()
+        }
+
+        async fn source_debug(&self, index: usize) -> Option<SourceDebugDump> {
+            let (reply, response) = oneshot::channel();
+            self.channels
+                .command_sender
+                .send(SystemCommand::ObserveSourceDebug {
+                    id: self.sources[index],
+                    reply,
+                })
+                .await
+                .unwrap();
+            response.await.unwrap()
+        }
+    }
+
+    impl Drop for SystemHarness {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
+    }
+
     #[derive(Debug, Clone, Default)]
     struct TestClock {}
 
@@ -736,4 +1647,404 @@ mod tests {
             1
         );
     }
+
+    #[tokio::test]
+    async fn system_synchronizes_to_mock_source() {
+        let mut harness = SystemHarness::new(
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                ..SynchronizationConfig::default()
+            },
+            1,
+        )
+        .await;
+        assert_eq!(harness.system_snapshot().stratum, 16);
+
+        let msg = harness.measurement(0, 0.5);
+        harness.send(msg).await;
+
+        // the large offset at startup is stepped away
+        let steps: Vec<_> = harness
+            .clock
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                ClockCall::Step(offset) => Some(offset.to_seconds()),
+                ClockCall::SetFrequency(_) => None,
+            })
+            .collect();
+        assert_eq!(steps.len(), 1);
+        assert!(steps[0] > 0.4 && steps[0] <= 0.5);
+
+        let snapshot = harness.system_snapshot();
+        assert_eq!(snapshot.stratum, 2);
+        assert_eq!(
+            snapshot.reference_id,
+            ReferenceId::from_ip(IpAddr::from([127, 0, 0, 0]))
+        );
+
+        match harness.source_snapshots().as_slice() {
+            [ObservableSourceState::Observable(state)] => {
+                assert_eq!(state.timedata.selection, SelectionStatus::Selected);
+            }
+            other => panic!("unexpected source snapshots: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn spawn_with_resolved_polls_the_resolved_address() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        // this name would not resolve, so the source can only use the given address
+        let address =
+            NormalizedAddress::from_string_ntp(format!("ntp.invalid:{}", addr.port())).unwrap();
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let (task, channels) = spawn_with_resolved(
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            ClockConfig {
+                clock: MockClock::default(),
+                ..Default::default()
+            },
+            SystemStateConfig {
+                clock_state: &ClockStateConfig::default(),
+                leap_seconds: &LeapSecondsConfig::default(),
+                source_history_length: HARNESS_HISTORY_LENGTH,
+            },
+            &[(address.clone(), addr)],
+            &[],
+            keyset,
+        )
+        .await
+        .unwrap();
+
+        let mut buf = [0; 1024];
+        let (length, client) =
+            tokio::time::timeout(Duration::from_secs(5), server.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+        let request = NtpPacket::deserialize(&buf[..length], &NoCipher).unwrap().0;
+
+        // answer as a synchronized server, making the source observable
+        let system = SystemSnapshot {
+            stratum: 1,
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response =
+            NtpPacket::timestamp_response(&system, request, NtpTimestamp::default(), &TestClock {});
+        // large enough for responses in any version, such as NTPv5
+        let mut buf = [0; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        response.serialize(&mut cursor, &NoCipher, None).unwrap();
+        let length = cursor.position() as usize;
+        server.send_to(&buf[..length], client).await.unwrap();
+
+        let mut snapshots = channels.source_snapshots_receiver;
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            snapshots.wait_for(|snapshots| {
+                matches!(snapshots.as_slice(), [ObservableSourceState::Observable(_)])
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        match snapshots.borrow().as_slice() {
+            [ObservableSourceState::Observable(state)] => {
+                assert_eq!(state.name, address.to_string());
+                assert_eq!(state.address, addr.to_string());
+            }
+            other => panic!("unexpected source snapshots: {other:?}"),
+        }
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn system_resets_accumulated_steps() {
+        let mut harness = SystemHarness::new(
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                ..SynchronizationConfig::default()
+            },
+            2,
+        )
+        .await;
+
+        // only steps after the startup step are counted
+        for _ in 0..2 {
+            let msg = harness.measurement(0, 0.5);
+            harness.send(msg).await;
+        }
+        let steps = harness.system_snapshot().time_snapshot.accumulated_steps;
+        assert!(steps > NtpDuration::ZERO);
+
+        let (reply, response) = oneshot::channel();
+        harness
+            .channels
+            .command_sender
+            .send(SystemCommand::ResetAccumulatedSteps { reply })
+            .await
+            .unwrap();
+        response.await.unwrap();
+        assert_eq!(
+            harness.system_snapshot().time_snapshot.accumulated_steps,
+            NtpDuration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn system_pins_sources() {
+        let harness = SystemHarness::new(SynchronizationConfig::default(), 2).await;
+
+        for (id, existed) in [
+            (Some(SourceId::new()), false),
+            (Some(harness.sources[1]), true),
+            (None, true),
+        ] {
+            let (reply, response) = oneshot::channel();
+            harness
+                .channels
+                .command_sender
+                .send(SystemCommand::PinSource { id, reply })
+                .await
+                .unwrap();
+            assert_eq!(response.await.unwrap(), existed);
+        }
+    }
+
+    #[tokio::test]
+    async fn system_rejects_invalid_added_sources() {
+        let harness = SystemHarness::new(SynchronizationConfig::default(), 2).await;
+
+        let (reply, response) = oneshot::channel();
+        harness
+            .channels
+            .command_sender
+            .send(SystemCommand::PinSource {
+                id: Some(harness.sources[0]),
+                reply,
+            })
+            .await
+            .unwrap();
+        assert!(response.await.unwrap());
+
+        for config in [
+            // a second pinned source
+            r#"
+            mode = "server"
+            address = "127.0.0.1:123"
+            pin = true
+            "#,
+            r#"
+            mode = "server"
+            address = "127.0.0.1:123"
+            no-select = true
+            pin = true
+            "#,
+            r#"
+            mode = "pool"
+            address = "127.0.0.1:123"
+            count = 0
+            "#,
+        ] {
+            let config: NtpSourceConfig = toml::from_str(config).unwrap();
+            let (reply, response) = oneshot::channel();
+            harness
+                .channels
+                .command_sender
+                .send(SystemCommand::AddSource { config, reply })
+                .await
+                .unwrap();
+            assert!(response.await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn system_forgets_abandoned_added_sources() {
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let (_, ip_list) = tokio::sync::watch::channel([].into_iter().collect());
+        let (mut system, _) = SystemTask::<_, Sleep>::new(
+            TestClock {},
+            None,
+            TimestampMode::KernelRecv,
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            keyset,
+            ip_list,
+        );
+
+        let config: NtpSourceConfig = toml::from_str(
+            r#"
+            mode = "server"
+            address = "127.0.0.1:123"
+            "#,
+        )
+        .unwrap();
+        let (reply, response) = oneshot::channel();
+        system
+            .handle_command(SystemCommand::AddSource {
+                config: config.clone(),
+                reply,
+            })
+            .await
+            .unwrap();
+        assert_eq!(system.pending_adds.len(), 1);
+
+        // the requester timed out before the source was created
+        drop(response);
+        let (reply, _response) = oneshot::channel();
+        system
+            .handle_command(SystemCommand::AddSource { config, reply })
+            .await
+            .unwrap();
+        assert_eq!(system.pending_adds.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn system_drops_removed_mock_sources() {
+        let mut harness = SystemHarness::new(SynchronizationConfig::default(), 3).await;
+
+        for index in 0..3 {
+            let msg = harness.measurement(index, 0.0);
+            harness.send(msg).await;
+        }
+        assert_eq!(harness.source_snapshots().len(), 3);
+        assert_eq!(harness.system_snapshot().stratum, 2);
+
+        let source = harness.sources[0];
+        harness.send(MsgForSystem::MustDemobilize(source)).await;
+        assert_eq!(harness.source_snapshots().len(), 2);
+
+        let source = harness.sources[1];
+        harness.send(MsgForSystem::NetworkIssue(source)).await;
+        assert_eq!(harness.source_snapshots().len(), 1);
+
+        // the remaining source alone is not enough for a majority
+        let msg = harness.measurement(2, 0.0);
+        harness.send(msg).await;
+        match harness.source_snapshots().as_slice() {
+            [ObservableSourceState::Observable(state)] => {
+                assert_eq!(state.timedata.selection, SelectionStatus::NoMajority);
+            }
+            other => panic!("unexpected source snapshots: {other:?}"),
+        }
+        assert!(harness
+            .clock
+            .calls()
+            .iter()
+            .all(|call| !matches!(call, ClockCall::Step(_))));
+    }
+
+    #[tokio::test]
+    async fn system_observes_sources_in_stable_order() {
+        let mut harness = SystemHarness::new(SynchronizationConfig::default(), 8).await;
+
+        let observed_ids = |harness: &SystemHarness| -> Vec<SourceId> {
+            harness
+                .source_snapshots()
+                .iter()
+                .map(|state| match state {
+                    ObservableSourceState::Observable(state) => state.id,
+                    other => panic!("unexpected source snapshot: {other:?}"),
+                })
+                .collect()
+        };
+
+        for index in 0..8 {
+            let msg = harness.measurement(index, 0.0);
+            harness.send(msg).await;
+        }
+        let first = observed_ids(&harness);
+
+        let msg = harness.measurement(3, 0.0);
+        harness.send(msg).await;
+        let second = observed_ids(&harness);
+
+        let mut sorted = harness.sources.clone();
+        sorted.sort();
+        assert_eq!(first, sorted);
+        assert_eq!(second, first);
+    }
+
+    #[tokio::test]
+    async fn system_keeps_bounded_source_history() {
+        let mut harness = SystemHarness::new(SynchronizationConfig::default(), 2).await;
+        assert_eq!(harness.source_history(0).await, Some(vec![]));
+
+        for _ in 0..5 {
+            let msg = harness.measurement(0, 0.0);
+            harness.send(msg).await;
+        }
+
+        let history = harness.source_history(0).await.unwrap();
+        assert_eq!(history.len(), HARNESS_HISTORY_LENGTH);
+        assert!(history
+            .windows(2)
+            .all(|entries| entries[0].timestamp < entries[1].timestamp));
+        assert!(history.iter().all(|entry| entry.delay.to_seconds() > 0.0));
+
+        // other sources have their own history
+        assert_eq!(harness.source_history(1).await, Some(vec![]));
+
+        // removed sources have none
+        let source = harness.sources[0];
+        harness.send(MsgForSystem::MustDemobilize(source)).await;
+        assert_eq!(harness.source_history(0).await, None);
+    }
+
+    #[tokio::test]
+    async fn system_completes_source_debug_dump() {
+        let mut harness = SystemHarness::new(SynchronizationConfig::default(), 2).await;
+
+        for _ in 0..2 {
+            let msg = harness.measurement(0, 0.0);
+            harness.send(msg).await;
+        }
+
+        let dump = harness.source_debug(0).await.unwrap();
+        assert!(dump.timedata.is_some());
+        assert!(dump.filter.is_some());
+        assert_eq!(dump.history.len(), 2);
+
+        // removed sources have no state to dump
+        let source = harness.sources[0];
+        harness.send(MsgForSystem::MustDemobilize(source)).await;
+        assert!(harness.source_debug(0).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn system_alarms_on_divergent_sources() {
+        let mut harness = SystemHarness::new(
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                divergence_alarm_threshold: Some(NtpDuration::from_seconds(0.002)),
+                ..SynchronizationConfig::default()
+            },
+            2,
+        )
+        .await;
+
+        let msg = harness.measurement(0, 0.0);
+        harness.send(msg).await;
+        assert_eq!(harness.system_snapshot().divergence_alarm, None);
+
+        let msg = harness.measurement(1, 0.005);
+        harness.send(msg).await;
+        let alarm = harness.system_snapshot().divergence_alarm.unwrap();
+        assert!((alarm.divergence.to_seconds() - 0.005).abs() < 1e-6);
+
+        // the alarm clears once the sources no longer diverge
+        let source = harness.sources[1];
+        harness.send(MsgForSystem::MustDemobilize(source)).await;
+        let msg = harness.measurement(0, 0.0);
+        harness.send(msg).await;
+        assert_eq!(harness.system_snapshot().divergence_alarm, None);
+    }
 }
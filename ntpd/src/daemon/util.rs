@@ -1,4 +1,7 @@
+use std::sync::Arc;
+
 use ntp_proto::NtpTimestamp;
+use tokio::sync::watch;
 
 // Epoch offset between NTP and UNIX timescales
 pub(crate) const EPOCH_OFFSET: u32 = (70 * 365 + 17) * 86400;
@@ -16,3 +19,29 @@ pub(crate) fn convert_clock_timestamp(ts: clock_steering::Timestamp) -> NtpTimes
         ts.nanos,
     )
 }
+
+/// Asks a task to stop. Unlike aborting it, this lets the task stop at a point
+/// of its choosing and clean up first.
+#[derive(Debug, Clone)]
+pub(crate) struct CancellationToken(Arc<watch::Sender<bool>>);
+
+impl CancellationToken {
+    pub(crate) fn new() -> Self {
+        CancellationToken(Arc::new(watch::channel(false).0))
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once the token is cancelled. This is cancellation safe, so
+    /// it can be used as a branch of `tokio::select!`.
+    pub(crate) async fn cancelled(&self) {
+        // The sender lives as long as this token, so waiting can't fail
+        let _ = self.0.subscribe().wait_for(|cancelled| *cancelled).await;
+    }
+}
@@ -5,5 +5,7 @@ mod daemon;
 mod metrics;
 
 pub use ctl::main as ctl_main;
+// For applications that run the daemon themselves, with sources they resolved
 pub use daemon::main as daemon_main;
+pub use daemon::{config as daemon_config, spawn_with_resolved, DaemonChannels, SystemStateConfig};
 pub use metrics::exporter::main as metrics_exporter_main;
@@ -7,7 +7,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::daemon::{config::CliArg, initialize_logging_parse_config, ObservableState};
+use crate::daemon::{
+    config::CliArg, initialize_logging_parse_config, sockets::ObserverError, ObservableState,
+    ObserveRequest,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -183,14 +186,24 @@ async fn run(options: NtpMetricsExporterOptions) -> Result<(), Box<dyn std::erro
     }
 }
 
-async fn handler(buf: &mut String, observation_socket_path: &Path) -> std::io::Result<()> {
+async fn handler(buf: &mut String, observation_socket_path: &Path) -> Result<(), ObserverError> {
     let mut stream = tokio::net::UnixStream::connect(observation_socket_path).await?;
-    let mut msg = Vec::with_capacity(16 * 1024);
+    let request = ObserveRequest {
+        compress: true,
+        ..Default::default()
+    };
+    crate::daemon::sockets::write_json(&mut stream, &request).await?;
+
+    let mut msg = Vec::new();
     let observable_state: ObservableState =
         crate::daemon::sockets::read_json(&mut stream, &mut msg).await?;
 
-    format_response(buf, &observable_state)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "formatting error"))
+    format_response(buf, &observable_state).map_err(|_| {
+        ObserverError::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "formatting error",
+        ))
+    })
 }
 
 fn format_response(buf: &mut String, state: &ObservableState) -> std::fmt::Result {
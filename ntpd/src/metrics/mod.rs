@@ -155,6 +155,60 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         ),
     )?;
 
+    format_metric(
+        w,
+        "ntp_system_frequency",
+        "Frequency correction applied to the system clock in parts per million",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.system.time_snapshot.frequency_ppm),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_precision",
+        "Precision of the system clock",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(state.system.time_snapshot.precision.to_seconds()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_offset",
+        "Offset of the system clock from the combined sources at the last update",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(state.system.time_snapshot.system_offset.to_seconds()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_jitter",
+        "Uncertainty of the combined offset of the sources",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(state.system.time_snapshot.system_jitter.to_seconds()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_locked",
+        "Whether the system jitter stayed below the lock threshold for the lock duration",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.system.locked as i64),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_divergence_alarm",
+        "Whether the offsets of two sources differ by more than the divergence alarm threshold",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.system.divergence_alarm.is_some() as i64),
+    )?;
+
     format_metric(
         w,
         "ntp_system_accumulated_steps",
@@ -176,6 +230,27 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
             .unwrap_or(-1.0)),
     )?;
 
+    format_metric(
+        w,
+        "ntp_system_accumulated_steps_headroom",
+        "Amount of stepping left before the accumulated step threshold is reached (or -1 if no threshold was set)",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(state.system
+            .accumulated_steps_headroom
+            .map(|v| v.to_seconds())
+            .unwrap_or(-1.0)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_clock_offset_correction",
+        "Constant correction added to all measured offsets",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(state.system.clock_offset_correction.to_seconds()),
+    )?;
+
     format_metric(
         w,
         "ntp_system_leap_indicator",
@@ -221,6 +296,18 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_sources!(state, |p| p.poll_interval.as_duration().to_seconds()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_source_remote_min_poll_interval",
+        "Minimum time between polls imposed by the source",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_sources!(state, |p| p
+            .remote_min_poll_interval
+            .as_duration()
+            .to_seconds()),
+    )?;
+
     format_metric(
         w,
         "ntp_source_unanswered_polls",
@@ -275,6 +362,33 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_sources!(state, |p| p.timedata.remote_uncertainty.to_seconds()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_source_bogus_packets_total",
+        "Number of responses from the source that did not match our request",
+        MetricType::Counter,
+        None,
+        collect_sources!(state, |p| p.bogus_packets),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_duplicate_packets_total",
+        "Number of duplicate responses from the source to an already answered request",
+        MetricType::Counter,
+        None,
+        collect_sources!(state, |p| p.duplicate_packets),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_send_timestamped",
+        "Whether the last request to the source was timestamped by the kernel when sent",
+        MetricType::Gauge,
+        None,
+        collect_sources!(state, |p| p.send_timestamped as u8),
+    )?;
+
     format_metric(
         w,
         "ntp_server_received_packets_total",
@@ -320,6 +434,33 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_servers!(state, |s| s.stats.rate_limited_packets.get()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_server_shed_packets_total",
+        "Number of packets dropped because they waited too long to be processed",
+        MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.shed_packets.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_server_rate_capped_packets_total",
+        "Number of packets dropped because the server was over its maximum request rate",
+        MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.rate_capped_packets.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_server_access_denied_packets_total",
+        "Number of packets refused by the allowlist or denylist",
+        MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.access_denied_packets.get()),
+    )?;
+
     format_metric(
         w,
         "ntp_server_response_send_errors_total",
@@ -374,6 +515,34 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_servers!(state, |s| s.stats.nts_nak_packets.get()),
     )?;
 
+    let mut latencies = vec![];
+    for server in &state.servers {
+        let latency = server.response_latency;
+        for (quantile, value) in [
+            ("0.5", latency.p50),
+            ("0.95", latency.p95),
+            ("0.99", latency.p99),
+        ] {
+            if let Some(value) = value {
+                latencies.push(Measurement {
+                    labels: vec![
+                        ("listen_address", format!("{}", server.address)),
+                        ("quantile", quantile.to_owned()),
+                    ],
+                    value,
+                });
+            }
+        }
+    }
+    format_metric(
+        w,
+        "ntp_server_response_latency",
+        "Time between receiving a request and sending the response",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        latencies,
+    )?;
+
     w.write_str("# EOF\n")?;
     Ok(())
 }
@@ -1,5 +1,5 @@
 use std::{
-    io::Write,
+    io::{Read, Write},
     os::unix::net::UnixListener,
     process::{Command, Output},
     thread::spawn,
@@ -61,6 +61,19 @@ fn test_status() {
 
     spawn(move || {
         let (mut stream, _) = socket.accept().unwrap();
+
+        // read the request frame: flags, big endian length, json payload
+        let mut header = [0; 5];
+        stream.read_exact(&mut header).unwrap();
+        let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+        let mut request = vec![0; length as usize];
+        stream.read_exact(&mut request).unwrap();
+
+        // respond with an uncompressed frame
+        stream.write_all(&[0]).unwrap();
+        stream
+            .write_all(&(EXAMPLE_SOCKET_OUTPUT.len() as u32).to_be_bytes())
+            .unwrap();
         stream.write_all(EXAMPLE_SOCKET_OUTPUT.as_bytes()).unwrap();
     });
 